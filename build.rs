@@ -0,0 +1,45 @@
+//! Records the versions of the aitios crates that are built with, as locked
+//! in `Cargo.lock`, so runs can record them in their manifest.
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+fn main() {
+    let lock = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock.display());
+
+    // Built as a dependency, there is no lock file next to the manifest
+    let mut contents = String::new();
+    let _ = File::open(&lock).and_then(|mut lock| lock.read_to_string(&mut contents));
+
+    let versions: Vec<String> = contents
+        .split("[[package]]")
+        .filter_map(|package| {
+            let value = |key: &str| {
+                package
+                    .lines()
+                    .find(|line| line.starts_with(&format!("{} = ", key)))
+                    .map(|line| line[key.len() + 3..].trim_matches('"').to_string())
+            };
+            let name = value("name")?;
+            if !name.starts_with("aitios-") || name == env!("CARGO_PKG_NAME") {
+                return None;
+            }
+            let version = value("version")?;
+            // Git sources end with the locked commit after a hash sign
+            let revision = value("source")
+                .and_then(|source| source.find('#').map(|hash| source[hash + 1..].to_string()));
+            Some(match revision {
+                Some(revision) => format!("{} {} ({})", name, version, revision),
+                None => format!("{} {}", name, version),
+            })
+        })
+        .collect();
+
+    println!(
+        "cargo:rustc-env=AITIOS_CRATE_VERSIONS={}",
+        versions.join(";")
+    );
+}
@@ -0,0 +1,49 @@
+/*
+ * C API of aitios, available when building with the ffi feature:
+ *
+ *     cargo build --release --features ffi
+ *
+ * Functions that can fail return NULL or -1 and store an error message
+ * that can be obtained with aitios_last_error.
+ */
+#ifndef AITIOS_H
+#define AITIOS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct AitiosSimulation AitiosSimulation;
+
+/* Message of the last error on this thread, or NULL if the last call succeeded. */
+const char* aitios_last_error(void);
+
+/* Builds a simulation from a YAML spec, relative paths are resolved against
+ * the working directory. Returns NULL on failure. */
+AitiosSimulation* aitios_simulation_new(const char* spec_yaml);
+
+/* Performs the next iteration. Returns 1 if an iteration was performed,
+ * 0 if all iterations are done and -1 on failure. */
+int aitios_simulation_step(AitiosSimulation* simulation);
+
+/* Performs all remaining iterations. Returns 0 on success and -1 on failure. */
+int aitios_simulation_run(AitiosSimulation* simulation);
+
+/* Iteration performed last and the number of the last iteration. */
+uint32_t aitios_simulation_iteration(const AitiosSimulation* simulation);
+uint32_t aitios_simulation_last_iteration(const AitiosSimulation* simulation);
+
+/* Paths of the files written so far, valid until the simulation is freed. */
+size_t aitios_simulation_output_count(const AitiosSimulation* simulation);
+const char* aitios_simulation_output_path(const AitiosSimulation* simulation, size_t index);
+
+void aitios_simulation_free(AitiosSimulation* simulation);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -1,34 +1,39 @@
-use clap::{App, Arg};
+use app::bench_preset::BENCH_PRESET_NAMES;
+use app::init::PRESET_NAMES;
+use app::preview::preview_address;
+use app::progress::PROGRESS_FORMATS;
+use clap::{App, AppSettings, Arg, SubCommand};
+use spec::OVERWRITE_NAMES;
 
 pub fn new_app<'a, 'b>() -> App<'a, 'b> {
-    App::new("aitios")
+    let app = App::new("aitios")
         .version(crate_version!())
         .author("krachzack <hello@phstadler.com>")
         .about("Procedural weathering simulation on the command line with aitios")
+        // Spec files are only required when running a simulation, not for subcommands
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .arg(simulation_spec_file_arg().required_unless_one(&["generate-man", "explain-resolve"]))
+        .arg(inline_spec_arg())
         .arg(
-            Arg::with_name("SIMULATION_SPEC_FILE")
-                .help("Adds a new simulation specification fragment in a YAML file at the given path.")
-                .long_help("Adds a new simulation specification fragment in a YAML file at the given path. Multiple specs can be provided and later specs will add to or even override earlier specs, depending on the property. See --spec to provide an inline specification without a file.")
-                .required(true)
-                .validator(validate_simulation_spec)
-                .multiple(true)
-                .takes_value(true)
+            Arg::with_name("generate-man")
+                .long("generate-man")
+                .hidden(true)
+                .help("Prints a man page in roff format and exits.")
         )
         .arg(
-            Arg::with_name("spec")
-                .short("s")
-                .long("spec")
-                .multiple(true)
+            Arg::with_name("explain-resolve")
+                .long("explain-resolve")
                 .takes_value(true)
-                .help("Evaluates the given simulation spec directly")
-                .long_help("Evaluates the given simulation specification directly. It must be provided as a string in YAML format.")
-                .value_name("INLINE_SIMULATION_SPEC")
+                .value_name("PATH")
+                .help("Prints the paths that are tried in order when resolving the given path and exits.")
+                .long_help("Prints the paths that are tried in order when resolving the given path and exits. Paths are resolved relative to the working directory and to the directories of the given spec files, like relative paths in those spec files.")
         )
         .arg(
             Arg::with_name("verbose")
                 .short("v")
                 .long("verbose")
                 .multiple(true)
+                .global(true)
                 .help("Activates verbose output.")
         )
         .arg(
@@ -36,21 +41,333 @@ pub fn new_app<'a, 'b>() -> App<'a, 'b> {
                 .short("l")
                 .long("log")
                 .multiple(true)
+                .global(true)
                 .takes_value(true)
                 .min_values(0)
                 .max_values(64)
                 .value_name("LOG_FILE")
                 .help("Specifies a file in which to log simulation progress.")
         )
+        .arg(
+            Arg::with_name("log-filter")
+                .long("log-filter")
+                .global(true)
+                .takes_value(true)
+                .value_name("DIRECTIVES")
+                .help("Sets log levels by module, e.g. aitios_sim=debug,aitios_tex=warn.")
+                .long_help("Sets log levels by module with comma-separated directives, e.g. aitios_sim=debug,aitios_tex=warn. Directives apply to the named module and everything below it. Modules without a directive log at the level set with --verbose, or at the level of a directive without module name, e.g. info,aitios_tex=warn.")
+        )
         .arg(
             Arg::with_name("threads")
                 .short("t")
                 .long("threads")
+                .global(true)
                 .takes_value(true)
                 .value_name("THREAD_COUNT")
                 .validator(validate_thread_count)
                 .help("Overrides thread pool size from number of virtual processors to the given thread count.")
         )
+        .arg(
+            Arg::with_name("strict")
+                .long("strict")
+                .global(true)
+                .help("Fails on unknown keys in spec files, unknown {placeholders} in output patterns and surfel specs for materials that no scene uses, instead of ignoring them.")
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .global(true)
+                .takes_value(true)
+                .value_name("PROFILE_JSON_FILE")
+                .help("Records durations of loading, surfel sampling, table building, tracing and each effect into the given file in chrome tracing format.")
+        )
+        .arg(
+            Arg::with_name("stats")
+                .long("stats")
+                .global(true)
+                .takes_value(true)
+                .value_name("STATS_CSV_FILE")
+                .help("Writes the tons emitted by each source and the change of each substance after every iteration into the given CSV file.")
+        )
+        .arg(
+            Arg::with_name("resume-effects")
+                .long("resume-effects")
+                .global(true)
+                .help("Skips textures, OBJ and MTL files of effects that exist already, e.g. to continue an interrupted run with the same output paths.")
+        )
+        .arg(
+            Arg::with_name("audit")
+                .long("audit")
+                .global(true)
+                .help("Logs how the total of each substance changes while tracing and when swapping materials or injecting substances, and warns if tracing creates or destroys a substance that no source or rule explains.")
+        )
+        .arg(
+            Arg::with_name("overwrite")
+                .long("overwrite")
+                .global(true)
+                .takes_value(true)
+                .value_name("POLICY")
+                .possible_values(OVERWRITE_NAMES)
+                .help("Sets whether outputs that exist from before the run are replaced, fail the run or are kept by writing new outputs with a numeric suffix, overriding overwrite in the spec.")
+        )
+        .arg(
+            Arg::with_name("control")
+                .long("control")
+                .takes_value(true)
+                .value_name("ENDPOINT")
+                .help("Accepts commands to pause, resume, run effects, dump surfels or abort while running, on a unix socket with unix:PATH or on TCP with tcp:HOST:PORT.")
+                .long_help("Accepts commands while running, one per line, on a unix socket with unix:PATH or on TCP with tcp:HOST:PORT. Commands are pause, resume, effects to run the effects right away, dump PATTERN to dump surfels to an OBJ file, abort to run the effects a last time and stop, and status. All but status take effect after the current iteration. TCP connections must send token TOKEN first, with the token set with --control-token.")
+        )
+        .arg(
+            Arg::with_name("serve")
+                .long("serve")
+                .takes_value(true)
+                .value_name("[IP:]PORT")
+                .validator(validate_serve_address)
+                .help("Serves a page with the progress, the textures of the most recent iteration and the summary of the run on the given port of localhost while running, or on the given address, e.g. 0.0.0.0:8080 to serve it to other machines without authentication.")
+        )
+        .arg(
+            Arg::with_name("progress-format")
+                .long("progress-format")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(PROGRESS_FORMATS)
+                .default_value("text")
+                .help("Sets whether progress is only logged, or also printed to standard output as one JSON object per line with the phase, iteration, percent and eta_secs.")
+                .long_help("Sets whether progress is only logged, or also printed to standard output as one JSON object per line with the phase, iteration, percent and eta_secs, for job orchestrators to follow. With json-lines, terminal logging goes to standard error so that standard output only carries progress.")
+        )
+        .arg(
+            Arg::with_name("control-token")
+                .long("control-token")
+                .takes_value(true)
+                .value_name("TOKEN")
+                .requires("control")
+                .help("Sets the token that connections to a TCP control endpoint must send before any command.")
+        )
+        .arg(
+            Arg::with_name("output-root")
+                .long("output-root")
+                .global(true)
+                .takes_value(true)
+                .value_name("OUTPUT_DIR")
+                .help("Writes textures, scenes, logs and benchmarks with relative paths below the given directory, overriding output_root in the spec.")
+        )
+        .arg(
+            Arg::with_name("scene")
+                .long("scene")
+                .global(true)
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true)
+                .value_name("OBJ_FILE")
+                .help("Simulates the given scene instead of the scenes in the spec files. Can be given multiple times.")
+                .long_help("Simulates the given scene instead of the scenes in the spec files, so the same weathering can be applied to another asset without editing the spec. Can be given multiple times to simulate multiple scenes together. Paths and globs are resolved like scenes in spec files.")
+        )
+        .arg(
+            Arg::with_name("substances")
+                .long("substances")
+                .global(true)
+                .takes_value(true)
+                .use_delimiter(true)
+                .value_name("SUBSTANCES")
+                .help("Only transports the given comma-separated substances and only writes density and layer outputs for them, e.g. rust,moss.")
+                .long_help("Only transports the given comma-separated substances and only writes density and layer outputs for them, e.g. rust,moss, for faster debugging of a single weathering sign. Tons neither pick up nor deposit other substances, which keep their initial concentrations unless surfel rules change them.")
+        )
+        .subcommand(
+            SubCommand::with_name("inspect")
+                .about("Lists entities, their materials, applicable surfel specs and effects, and all substances, without running the simulation.")
+                .arg(simulation_spec_file_arg())
+                .arg(inline_spec_arg())
+        )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Runs the simulation, like running without a subcommand. Spec files ending in .aitios are bundles written with pack.")
+                .arg(simulation_spec_file_arg())
+                .arg(inline_spec_arg())
+        )
+        .subcommand(
+            SubCommand::with_name("effects")
+                .about("Only runs the effects of the spec on surfels restored from a snapshot, without tracing. Snapshots are written by dump_surfels effects with a .bin obj_pattern.")
+                .arg(simulation_spec_file_arg())
+                .arg(inline_spec_arg())
+                .arg(
+                    Arg::with_name("from-surfels")
+                        .long("from-surfels")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("SNAPSHOT_FILE")
+                        .help("Sets the surfel snapshot to run the effects on, e.g. surfels-30.bin.")
+                        .long_help("Sets the surfel snapshot to run the effects on, e.g. surfels-30.bin. The scenes and surfel sampling of the spec must be the same as when the snapshot was taken. Effects run as the iteration the snapshot was taken after.")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("golden")
+                .about("Runs the spec at low resolution with the output root in a temporary directory and compares the written textures against reference images, e.g. to validate spec refactors and crate upgrades.")
+                .arg(simulation_spec_file_arg())
+                .arg(inline_spec_arg())
+                .arg(
+                    Arg::with_name("references")
+                        .short("r")
+                        .long("references")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("REFERENCE_DIR")
+                        .help("Sets the directory with the reference images, at the same paths relative to it as the outputs relative to the output root.")
+                )
+                .arg(
+                    Arg::with_name("tolerance")
+                        .long("tolerance")
+                        .takes_value(true)
+                        .value_name("DELTA_E")
+                        .default_value("2.0")
+                        .validator(validate_tolerance)
+                        .help("Sets the largest mean CIE76 color difference of an image to its reference that still passes, about 2.3 is just noticeable.")
+                )
+                .arg(
+                    Arg::with_name("resolution")
+                        .long("resolution")
+                        .takes_value(true)
+                        .value_name("TEXELS")
+                        .default_value("64")
+                        .validator(validate_positive_count)
+                        .help("Limits synthesized textures to the given number of texels along each side.")
+                )
+                .arg(
+                    Arg::with_name("update")
+                        .long("update")
+                        .help("Writes the textures of the run as the new references instead of comparing.")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about("Times simulations of generated scenes, or works with benchmark files written by simulations.")
+                // The preset is only required when not comparing
+                .setting(AppSettings::SubcommandsNegateReqs)
+                .arg(
+                    Arg::with_name("preset")
+                        .short("p")
+                        .long("preset")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("PRESET")
+                        .possible_values(BENCH_PRESET_NAMES)
+                        .help("Generates a scene with a rain source and times a simulation of it, printing a summary of the durations.")
+                        .long_help("Generates a scene with a rain source and times a simulation of it, printing a summary of the durations. sphere is a sphere with SIZE rings, city a grid of SIZE by SIZE buildings and forest a grid of SIZE by SIZE trees, all on a patch of ground.")
+                )
+                .arg(
+                    Arg::with_name("size")
+                        .long("size")
+                        .takes_value(true)
+                        .value_name("SIZE")
+                        .default_value("8")
+                        .validator(validate_positive_count)
+                        .help("Sets the size of the generated scene, see --preset.")
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .value_name("DIR")
+                        .help("Writes the generated project and its benchmarks into the given directory instead of a temporary one, e.g. to compare them later with bench compare.")
+                )
+                .subcommand(
+                    SubCommand::with_name("compare")
+                        .about("Compares the mean durations of each phase in two benchmark files in CSV or NDJSON format and tests whether the difference is significant.")
+                        .arg(
+                            Arg::with_name("BEFORE")
+                                .required(true)
+                                .help("Benchmark file with the baseline durations.")
+                        )
+                        .arg(
+                            Arg::with_name("AFTER")
+                                .required(true)
+                                .help("Benchmark file with the durations after the change.")
+                        )
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .about("Checks OBJ scenes for missing normals, missing or degenerate texture coordinates, flipped winding and non-manifold edges, and reports which break surfel sampling or texture synthesis.")
+                .arg(
+                    Arg::with_name("OBJ_FILE")
+                        .required(true)
+                        .multiple(true)
+                        .help("Scene to check.")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("init")
+                .about("Writes a runnable starter project with a simulation spec, ton source and surfel specs, a scene and sample textures for a weathering preset.")
+                .arg(
+                    Arg::with_name("DIR")
+                        .default_value(".")
+                        .help("Directory to write the project into, created if missing.")
+                )
+                .arg(
+                    Arg::with_name("preset")
+                        .short("p")
+                        .long("preset")
+                        .takes_value(true)
+                        .value_name("PRESET")
+                        .possible_values(PRESET_NAMES)
+                        .default_value("rust")
+                        .help("Sets the kind of weathering the project starts out with.")
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Overwrites existing files instead of failing.")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("pack")
+                .about("Bundles the merged spec with all referenced scenes, surfel specs, ton source specs and textures into a zip archive that can be run with aitios run.")
+                .arg(simulation_spec_file_arg())
+                .arg(inline_spec_arg())
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("BUNDLE_FILE")
+                        .help("Sets the path of the archive to write, e.g. bundle.aitios.")
+                )
+        );
+
+    #[cfg(feature = "http")]
+    let app = app.arg(
+        Arg::with_name("download-cache")
+            .long("download-cache")
+            .global(true)
+            .takes_value(true)
+            .value_name("CACHE_DIR")
+            .help("Downloads http:// and https:// URLs in specs into the given directory instead of aitios-downloads in the temporary directory.")
+    );
+
+    app
+}
+
+fn simulation_spec_file_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("SIMULATION_SPEC_FILE")
+        .help("Adds a new simulation specification fragment in a YAML file at the given path.")
+        .long_help("Adds a new simulation specification fragment in a YAML file at the given path. Multiple specs can be provided and later specs will add to or even override earlier specs, depending on the property. Use - to read a specification from standard input. See --spec to provide an inline specification without a file.")
+        .required(true)
+        .validator(validate_simulation_spec)
+        .multiple(true)
+        .takes_value(true)
+}
+
+fn inline_spec_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("spec")
+        .short("s")
+        .long("spec")
+        .multiple(true)
+        .takes_value(true)
+        .help("Evaluates the given simulation spec directly")
+        .long_help("Evaluates the given simulation specification directly. It must be provided as a string in YAML format.")
+        .value_name("INLINE_SIMULATION_SPEC")
 }
 
 fn validate_simulation_spec(simulation_spec_file: String) -> Result<(), String> {
@@ -61,6 +378,34 @@ fn validate_simulation_spec(simulation_spec_file: String) -> Result<(), String>
     Ok(())
 }
 
+fn validate_serve_address(serve: String) -> Result<(), String> {
+    preview_address(&serve).map(|_| ())
+}
+
+fn validate_tolerance(tolerance: String) -> Result<(), String> {
+    match tolerance.parse::<f32>() {
+        Ok(tolerance) if tolerance >= 0.0 => Ok(()),
+        Ok(_) => Err(format!("Tolerance must not be negative: {}", tolerance)),
+        Err(e) => Err(format!(
+            "Invalid tolerance specified: {tolerance}\nCause: {cause}",
+            tolerance = tolerance,
+            cause = e
+        )),
+    }
+}
+
+fn validate_positive_count(count: String) -> Result<(), String> {
+    match count.parse::<usize>() {
+        Ok(count) if count > 0 => Ok(()),
+        Ok(_) => Err(String::from("Must be at least one")),
+        Err(e) => Err(format!(
+            "Invalid count specified: {count}\nCause: {cause}",
+            count = count,
+            cause = e
+        )),
+    }
+}
+
 fn validate_thread_count(thread_count: String) -> Result<(), String> {
     usize::from_str_radix(&thread_count, 10)
         .map(|_| ())
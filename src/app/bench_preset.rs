@@ -0,0 +1,435 @@
+use failure::{Error, ResultExt};
+use files::create_file_recursively;
+use std::f32::consts::PI;
+use std::fmt::Write as FmtWrite;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Distance between the centers of neighbouring buildings or trees.
+const SPACING: f32 = 3.0;
+
+/// Generated scene and source setup for timing simulations with
+/// `aitios bench --preset`, so no large assets need to be shipped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BenchPreset {
+    /// A sphere with `size` rings on a patch of ground.
+    Sphere,
+    /// A grid of `size` by `size` box buildings of varying height.
+    City,
+    /// A grid of `size` by `size` trees with a trunk and a conical crown.
+    Forest,
+}
+
+/// Names of the presets as accepted by `BenchPreset::from_name`.
+pub const BENCH_PRESET_NAMES: &'static [&'static str] = &["sphere", "city", "forest"];
+
+impl BenchPreset {
+    pub fn from_name(name: &str) -> Option<BenchPreset> {
+        match name {
+            "sphere" => Some(BenchPreset::Sphere),
+            "city" => Some(BenchPreset::City),
+            "forest" => Some(BenchPreset::Forest),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            BenchPreset::Sphere => "sphere",
+            BenchPreset::City => "city",
+            BenchPreset::Forest => "forest",
+        }
+    }
+
+    /// Generates the scene for the given size, with the half extent of its
+    /// ground.
+    fn scene(&self, size: usize) -> (Obj, f32) {
+        let mut obj = Obj::default();
+        let extent = match *self {
+            BenchPreset::Sphere => {
+                sphere(obj.entity("sphere", "stone"), size.max(3));
+                3.0
+            }
+            BenchPreset::City => {
+                for (x, z, center) in grid(size) {
+                    // Heights vary between 1 and 5 without a random generator
+                    let height = 1.0 + ((x * 7 + z * 13) % 9) as f32 / 2.0;
+                    cuboid(obj.entity("buildings", "concrete"), center, 1.0, height);
+                }
+                size as f32 * SPACING / 2.0
+            }
+            BenchPreset::Forest => {
+                for (_, _, center) in grid(size) {
+                    cuboid(obj.entity("trunks", "bark"), center, 0.3, 1.0);
+                    cone(obj.entity("crowns", "leaves"), center, 1.0, 2.5);
+                }
+                size as f32 * SPACING / 2.0
+            }
+        };
+        ground(obj.entity("ground", "soil"), extent);
+        (obj, extent)
+    }
+}
+
+/// Writes a simulation spec for the preset with its scene, surfel specs and
+/// a rain source into the given directory, and returns the path of the spec.
+///
+/// The spec writes benchmarks of setup, iterations, tracing and synthesis
+/// into `benchmarks` and their summary into `benchmarks/summary.csv`,
+/// relative to the output root.
+pub fn write_bench_project(dir: &Path, preset: BenchPreset, size: usize) -> Result<PathBuf, Error> {
+    let (obj, extent) = preset.scene(size);
+    let materials = obj.materials();
+
+    let mut texts = vec![
+        (
+            String::from("simulation.yml"),
+            simulation_yml(preset, size, &materials),
+        ),
+        (String::from("rain.yml"), rain_yml(extent)),
+        (String::from("scene/scene.obj"), obj.to_obj()),
+        (String::from("scene/scene.mtl"), mtl(&materials)),
+    ];
+    for material in materials.iter() {
+        texts.push((format!("{}.yml", material), surfel_yml(material)));
+    }
+
+    for &(ref name, ref text) in texts.iter() {
+        let path = dir.join(name);
+        create_file_recursively(&path)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .with_context(|_| format!("Could not write {}.", path.display()))?;
+    }
+
+    Ok(dir.join("simulation.yml"))
+}
+
+fn simulation_yml(preset: BenchPreset, size: usize, materials: &[&str]) -> String {
+    let surfels_by_material: String = materials
+        .iter()
+        .map(|m| format!("  {material}: \"{material}.yml\"\n", material = m))
+        .collect();
+
+    format!(
+        r#"name: "{name} benchmark of size {size}"
+description: "Generated by aitios bench --preset {name} --size {size}"
+scenes:
+  - "scene/scene.obj"
+iterations: 5
+surfel_distance: 0.1
+seed: 0
+overwrite: always
+sources:
+  - "rain.yml"
+surfels_by_material:
+{surfels_by_material}effects:
+  - density:
+      width: 256
+      height: 256
+      tex_pattern: "output/iteration-{{iteration}}/{{id}}-{{entity}}-{{substance}}.png"
+benchmark:
+  labeled: true
+  setup: "benchmarks/setup.csv"
+  iterations: "benchmarks/iterations.csv"
+  tracing: "benchmarks/tracing.csv"
+  synthesis: "benchmarks/synthesis.csv"
+  summary: "benchmarks/summary.csv"
+"#,
+        name = preset.name(),
+        size = size,
+        surfels_by_material = surfels_by_material,
+    )
+}
+
+fn rain_yml(extent: f32) -> String {
+    format!(
+        r#"name: Rain
+description: Rain falling onto the whole scene from above
+shape:
+  directional:
+    position: [0.0, 10.0, 0.0]
+    direction: [0.0, -1.0, 0.0]
+    radius: {radius:.2}
+emission_per_area: 1000.0
+p_straight: 0.0
+p_parabolic: 0.3
+p_flow: 0.7
+initial:
+  humidity: 1.0
+absorb:
+  humidity: 1.0
+interaction_radius: 0.1
+parabola_height: 0.07
+flow_distance: 0.17
+flow_direction: [0.0, -1.0, 0.0]
+"#,
+        // Covers the corners of the ground
+        radius = extent * 2.0f32.sqrt(),
+    )
+}
+
+fn surfel_yml(material: &str) -> String {
+    format!(
+        r#"name: {material}
+description: Surfel properties of {material} in the benchmark scene
+reflectance:
+  delta_straight: 0.0
+  delta_parabolic: 0.8
+  delta_flow: 0.2
+initial:
+  humidity: 0.0
+deposit:
+  humidity: 1.0
+rules:
+  # Evaporation reduces humidity
+  - from: humidity
+    factor: -0.5
+"#,
+        material = material,
+    )
+}
+
+fn mtl(materials: &[&str]) -> String {
+    materials.iter().fold(
+        String::from("# Materials of a scene written by aitios bench --preset\n"),
+        |mtl, material| format!("{}newmtl {}\nKd 0.6 0.6 0.6\nNs 10\n\n", mtl, material),
+    )
+}
+
+/// World space centers of a grid of `size` by `size` cells around the
+/// origin, together with the cell indexes.
+fn grid(size: usize) -> Vec<(usize, usize, [f32; 2])> {
+    let offset = (size as f32 - 1.0) * SPACING / 2.0;
+    (0..size)
+        .flat_map(|x| (0..size).map(move |z| (x, z)))
+        .map(|(x, z)| {
+            let center = [x as f32 * SPACING - offset, z as f32 * SPACING - offset];
+            (x, z, center)
+        })
+        .collect()
+}
+
+fn ground(entity: &mut Vec<Polygon>, extent: f32) {
+    entity.push(vec![
+        [-extent, 0.0, extent],
+        [extent, 0.0, extent],
+        [extent, 0.0, -extent],
+        [-extent, 0.0, -extent],
+    ]);
+}
+
+/// Appends a UV sphere of radius one resting on the ground, with the given
+/// number of rings and twice as many segments.
+fn sphere(entity: &mut Vec<Polygon>, rings: usize) {
+    let segments = rings * 2;
+    let point = |ring: usize, segment: usize| {
+        let polar = PI * ring as f32 / rings as f32;
+        let azimuth = 2.0 * PI * segment as f32 / segments as f32;
+        [
+            polar.sin() * azimuth.cos(),
+            1.0 + polar.cos(),
+            -polar.sin() * azimuth.sin(),
+        ]
+    };
+
+    for ring in 0..rings {
+        for segment in 0..segments {
+            let top = [point(ring, segment), point(ring, segment + 1)];
+            let bottom = [point(ring + 1, segment), point(ring + 1, segment + 1)];
+            // The poles are points, so the rings there are triangles
+            if ring == 0 {
+                entity.push(vec![top[0], bottom[0], bottom[1]]);
+            } else if ring == rings - 1 {
+                entity.push(vec![top[0], bottom[0], top[1]]);
+            } else {
+                entity.push(vec![top[0], bottom[0], bottom[1], top[1]]);
+            }
+        }
+    }
+}
+
+/// Appends the four sides and the top of a box standing on the ground.
+fn cuboid(entity: &mut Vec<Polygon>, center: [f32; 2], width: f32, height: f32) {
+    let r = width / 2.0;
+    let (x, z) = (center[0], center[1]);
+    let corners = [
+        [x - r, z + r],
+        [x + r, z + r],
+        [x + r, z - r],
+        [x - r, z - r],
+    ];
+
+    for side in 0..4 {
+        let a = corners[side];
+        let b = corners[(side + 1) % 4];
+        entity.push(vec![
+            [a[0], 0.0, a[1]],
+            [b[0], 0.0, b[1]],
+            [b[0], height, b[1]],
+            [a[0], height, a[1]],
+        ]);
+    }
+    entity.push(corners.iter().map(|c| [c[0], height, c[1]]).collect());
+}
+
+/// Appends a closed cone with eight sides and its base at the given height.
+fn cone(entity: &mut Vec<Polygon>, center: [f32; 2], base: f32, height: f32) {
+    const SIDES: usize = 8;
+    let radius = height / 3.0;
+    let apex = [center[0], base + height, center[1]];
+    let rim: Vec<[f32; 3]> = (0..SIDES)
+        .map(|side| {
+            let azimuth = 2.0 * PI * side as f32 / SIDES as f32;
+            [
+                center[0] + radius * azimuth.cos(),
+                base,
+                center[1] - radius * azimuth.sin(),
+            ]
+        })
+        .collect();
+
+    for side in 0..SIDES {
+        entity.push(vec![apex, rim[side], rim[(side + 1) % SIDES]]);
+    }
+    // Reversed to face down
+    entity.push(rim.iter().rev().cloned().collect());
+}
+
+/// Convex polygon with counter-clockwise winding when seen from outside.
+type Polygon = Vec<[f32; 3]>;
+
+/// Entities of a generated OBJ file, each with a name, a material and its
+/// polygons.
+#[derive(Debug, Default)]
+struct Obj {
+    entities: Vec<(String, String, Vec<Polygon>)>,
+}
+
+impl Obj {
+    /// Polygons of the entity with the given name, added if new.
+    fn entity(&mut self, name: &str, material: &str) -> &mut Vec<Polygon> {
+        let idx = match self.entities.iter().position(|e| e.0 == name) {
+            Some(idx) => idx,
+            None => {
+                let entity = (String::from(name), String::from(material), Vec::new());
+                self.entities.push(entity);
+                self.entities.len() - 1
+            }
+        };
+        &mut self.entities[idx].2
+    }
+
+    /// Names of the materials of the entities, in order of appearance.
+    fn materials(&self) -> Vec<&str> {
+        let mut materials: Vec<&str> = Vec::new();
+        for &(_, ref material, _) in self.entities.iter() {
+            if !materials.contains(&material.as_str()) {
+                materials.push(material);
+            }
+        }
+        materials
+    }
+
+    /// Writes the entities with flat normals, laying out the polygons of each
+    /// entity in a grid of texture space cells so that they do not overlap.
+    fn to_obj(&self) -> String {
+        let mut obj = String::from("# Scene written by aitios bench --preset\nmtllib scene.mtl\n");
+        let mut vertex_count = 0;
+        let mut normal_count = 0;
+
+        for &(ref name, ref material, ref polygons) in self.entities.iter() {
+            let cells = (polygons.len() as f32).sqrt().ceil().max(1.0) as usize;
+            let cell_size = 1.0 / cells as f32;
+            let margin = cell_size * 0.1;
+            let mut faces = String::new();
+
+            for (idx, polygon) in polygons.iter().enumerate() {
+                let u = (idx % cells) as f32 * cell_size;
+                let v = (idx / cells) as f32 * cell_size;
+                let (near, far) = (margin, cell_size - margin);
+                let texcoords = [[near, near], [far, near], [far, far], [near, far]];
+
+                let normal = normal(polygon);
+                writeln!(obj, "vn {} {} {}", normal[0], normal[1], normal[2]).unwrap();
+                normal_count += 1;
+
+                write!(faces, "f").unwrap();
+                for (position, texcoord) in polygon.iter().zip(texcoords.iter().cycle()) {
+                    writeln!(obj, "v {} {} {}", position[0], position[1], position[2]).unwrap();
+                    writeln!(obj, "vt {} {}", u + texcoord[0], v + texcoord[1]).unwrap();
+                    vertex_count += 1;
+                    write!(faces, " {0}/{0}/{1}", vertex_count, normal_count).unwrap();
+                }
+                writeln!(faces).unwrap();
+            }
+
+            write!(obj, "o {}\nusemtl {}\n{}", name, material, faces).unwrap();
+        }
+
+        obj
+    }
+}
+
+/// Unit normal of the plane of the first three vertices of the polygon.
+fn normal(polygon: &Polygon) -> [f32; 3] {
+    let (a, b, c) = (polygon[0], polygon[1], polygon[2]);
+    let u = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+    let v = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+    let n = [
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ];
+    let len = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+    [n[0] / len, n[1] / len, n[2] / len]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_yaml;
+    use spec::{SimulationSpec, SurfelSpec, TonSourceSpec};
+    use std::env::temp_dir;
+    use std::fs::{remove_dir_all, File};
+
+    #[test]
+    fn generated_projects_parse() {
+        for &name in BENCH_PRESET_NAMES {
+            let preset = BenchPreset::from_name(name).unwrap();
+            let dir = temp_dir().join(format!("aitios-bench-preset-test-{}", name));
+            let _ = remove_dir_all(&dir);
+
+            let spec_path = write_bench_project(&dir, preset, 2).unwrap();
+            let simulation: SimulationSpec =
+                serde_yaml::from_reader(File::open(&spec_path).unwrap()).unwrap();
+            assert!(simulation.benchmark.is_some());
+            let _: TonSourceSpec =
+                serde_yaml::from_reader(File::open(dir.join("rain.yml")).unwrap()).unwrap();
+            for surfels in simulation.surfels_by_material.values() {
+                let _: SurfelSpec =
+                    serde_yaml::from_reader(File::open(dir.join(surfels)).unwrap()).unwrap();
+            }
+
+            remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn sizes_scale_the_scene() {
+        let (small, _) = BenchPreset::City.scene(2);
+        let (large, extent) = BenchPreset::City.scene(4);
+        // Five faces per building and the ground
+        assert_eq!(4 * 5, small.entities[0].2.len());
+        assert_eq!(16 * 5, large.entities[0].2.len());
+        assert_eq!(6.0, extent);
+
+        let (sphere, _) = BenchPreset::Sphere.scene(8);
+        assert_eq!(8 * 16, sphere.entities[0].2.len());
+        // Faces of the sphere point away from its center
+        for polygon in sphere.entities[0].2.iter() {
+            let n = normal(polygon);
+            let p = polygon[1];
+            assert!(n[0] * p[0] + n[1] * (p[1] - 1.0) + n[2] * p[2] > 0.0);
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use failure::{Error, ResultExt};
+use runner::{Command, Observer};
+use std::fs::remove_file;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Where the control endpoint listens, `unix:PATH` for a unix socket or
+/// `tcp:HOST:PORT` for TCP.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoint {
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+impl Endpoint {
+    pub fn parse(endpoint: &str) -> Result<Endpoint, Error> {
+        if endpoint.starts_with("unix:") {
+            Ok(Endpoint::Unix(PathBuf::from(&endpoint["unix:".len()..])))
+        } else if endpoint.starts_with("tcp:") {
+            Ok(Endpoint::Tcp(endpoint["tcp:".len()..].to_string()))
+        } else {
+            bail!(
+                "Control endpoint {} is neither unix:PATH nor tcp:HOST:PORT.",
+                endpoint
+            )
+        }
+    }
+}
+
+/// Progress of the run as reported by `status`.
+#[derive(Debug, Clone, Default)]
+struct Status {
+    iteration: u32,
+    last_iteration: u32,
+    finished: bool,
+}
+
+/// Control endpoint accepting connections on a background thread. Removes
+/// its unix socket when dropped.
+pub struct Listening {
+    socket: Option<PathBuf>,
+    status: Arc<Mutex<Status>>,
+}
+
+impl Listening {
+    /// Observer that keeps the progress reported by `status` up to date.
+    pub fn status_observer(&self) -> Box<Observer> {
+        Box::new(StatusObserver(self.status.clone()))
+    }
+}
+
+impl Drop for Listening {
+    fn drop(&mut self) {
+        if let Some(ref socket) = self.socket {
+            let _ = remove_file(socket);
+        }
+    }
+}
+
+struct StatusObserver(Arc<Mutex<Status>>);
+
+impl Observer for StatusObserver {
+    fn iteration_started(&self, iteration: u32, last_iteration: u32) {
+        let mut status = self.0.lock().unwrap();
+        status.iteration = iteration;
+        status.last_iteration = last_iteration;
+    }
+
+    fn run_finished(&self, _error: Option<&Error>) {
+        self.0.lock().unwrap().finished = true;
+    }
+}
+
+/// Listens for connections at the given endpoint and passes on the commands
+/// they send, one per line, to the returned receiver.
+///
+/// TCP connections must send `token TOKEN` with the given token before any
+/// command, so that a token is required for TCP endpoints. Unix sockets are
+/// protected by their file permissions instead.
+pub fn listen(
+    endpoint: &Endpoint,
+    token: Option<&str>,
+) -> Result<(Receiver<Command>, Listening), Error> {
+    let (commands, received) = channel();
+    let status = Arc::new(Mutex::new(Status::default()));
+
+    let socket = match *endpoint {
+        #[cfg(unix)]
+        Endpoint::Unix(ref path) => {
+            let listener = UnixListener::bind(path).with_context(|_| {
+                format!(
+                    "Could not listen for control commands on {}, remove it if left over from an earlier run.",
+                    path.display()
+                )
+            })?;
+            let status = status.clone();
+            thread::spawn(move || {
+                accept(
+                    listener.incoming(),
+                    UnixStream::try_clone,
+                    None,
+                    commands,
+                    status,
+                )
+            });
+            Some(path.clone())
+        }
+        #[cfg(not(unix))]
+        Endpoint::Unix(_) => bail!("Unix socket control endpoints are only available on unix."),
+        Endpoint::Tcp(ref addr) => {
+            let token = match token {
+                Some(token) if !token.is_empty() => token.to_string(),
+                _ => bail!("TCP control endpoints need a token, set one with --control-token."),
+            };
+            let listener = TcpListener::bind(addr.as_str())
+                .with_context(|_| format!("Could not listen for control commands on {}.", addr))?;
+            let status = status.clone();
+            thread::spawn(move || {
+                accept(
+                    listener.incoming(),
+                    TcpStream::try_clone,
+                    Some(token),
+                    commands,
+                    status,
+                )
+            });
+            None
+        }
+    };
+
+    Ok((received, Listening { socket, status }))
+}
+
+/// Serves each incoming connection on a thread of its own, so that a client
+/// that stays connected does not lock out the others.
+fn accept<S, I>(
+    incoming: I,
+    try_clone: fn(&S) -> io::Result<S>,
+    token: Option<String>,
+    commands: Sender<Command>,
+    status: Arc<Mutex<Status>>,
+) where
+    S: Read + Write + Send + 'static,
+    I: Iterator<Item = io::Result<S>>,
+{
+    for stream in incoming.filter_map(Result::ok) {
+        let reader = match try_clone(&stream) {
+            Ok(reader) => reader,
+            Err(err) => {
+                warn!("Could not accept control connection: {}", err);
+                continue;
+            }
+        };
+        let (token, commands, status) = (token.clone(), commands.clone(), status.clone());
+        thread::spawn(move || {
+            serve(
+                reader,
+                stream,
+                token.as_ref().map(String::as_str),
+                &commands,
+                &status,
+            )
+        });
+    }
+}
+
+/// Answers the commands of one connection until it is closed.
+fn serve<R: Read, W: Write>(
+    reader: R,
+    mut writer: W,
+    token: Option<&str>,
+    commands: &Sender<Command>,
+    status: &Mutex<Status>,
+) {
+    let mut authenticated = token.is_none();
+    for line in BufReader::new(reader).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        let reply = if authenticated {
+            respond(line.trim(), commands, status)
+        } else if token.map_or(false, |t| line.trim() == format!("token {}", t)) {
+            authenticated = true;
+            String::from("ok")
+        } else {
+            // Refuse to read any further lines from unauthenticated connections
+            let _ = writeln!(writer, "error expected token first");
+            return;
+        };
+
+        if writeln!(writer, "{}", reply).is_err() {
+            return;
+        }
+    }
+}
+
+fn respond(line: &str, commands: &Sender<Command>, status: &Mutex<Status>) -> String {
+    let mut words = line.splitn(2, ' ');
+    let command = match (words.next().unwrap_or(""), words.next().map(str::trim)) {
+        ("pause", None) => Command::Pause,
+        ("resume", None) => Command::Resume,
+        ("effects", None) => Command::RunEffects,
+        ("dump", Some(pattern)) if !pattern.is_empty() => Command::DumpSurfels(pattern.to_string()),
+        ("abort", None) => Command::Abort,
+        ("status", None) => {
+            let status = status.lock().unwrap();
+            return format!(
+                "ok iteration {} of {}{}",
+                status.iteration,
+                status.last_iteration,
+                if status.finished { ", finished" } else { "" }
+            );
+        }
+        _ => {
+            return String::from(
+                "error expected pause, resume, effects, dump PATTERN, abort or status",
+            )
+        }
+    };
+
+    match commands.send(command) {
+        Ok(_) => String::from("ok after current iteration"),
+        Err(_) => String::from("error simulation is not running anymore"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn commands_need_token() {
+        let (commands, received) = channel();
+        let status = Mutex::new(Status::default());
+        let mut replies = Vec::new();
+
+        let session = "token secret\npause\ndump surfels-{iteration}.obj\njump\nstatus\n";
+        serve(
+            session.as_bytes(),
+            &mut replies,
+            Some("secret"),
+            &commands,
+            &status,
+        );
+        assert_eq!(
+            "ok\nok after current iteration\nok after current iteration\nerror expected pause, resume, effects, dump PATTERN, abort or status\nok iteration 0 of 0\n",
+            String::from_utf8(replies).unwrap()
+        );
+        assert_eq!(
+            vec![
+                Command::Pause,
+                Command::DumpSurfels(String::from("surfels-{iteration}.obj")),
+            ],
+            received.try_iter().collect::<Vec<_>>()
+        );
+
+        let mut replies = Vec::new();
+        serve(
+            "abort\n".as_bytes(),
+            &mut replies,
+            Some("secret"),
+            &commands,
+            &status,
+        );
+        assert_eq!(
+            "error expected token first\n",
+            String::from_utf8(replies).unwrap()
+        );
+        assert!(received.try_recv().is_err());
+
+        assert!(Endpoint::parse("http://localhost").is_err());
+        assert_eq!(
+            Endpoint::Tcp(String::from("127.0.0.1:7878")),
+            Endpoint::parse("tcp:127.0.0.1:7878").unwrap()
+        );
+    }
+}
@@ -0,0 +1,320 @@
+use failure::{Error, ResultExt};
+use files::create_file_recursively;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tex::{self, ImageRgba8, Rgba, RgbaImage};
+
+const SCENE_OBJ: &'static str = include_str!("presets/scene.obj");
+const SCENE_MTL: &'static str = include_str!("presets/scene.mtl");
+
+/// Side length of the generated sample textures.
+const SAMPLE_SIZE: u32 = 64;
+
+/// Starting point for a weathering project, written by `aitios init`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Preset {
+    /// Rain corrodes the metal pillar.
+    Rust,
+    /// Moisture lets moss grow on the concrete ground.
+    Moss,
+    /// Rain washes soot down over everything.
+    Soot,
+    /// Moisture carries salts out of the concrete, leaving white crusts.
+    Efflorescence,
+}
+
+/// Names of the presets as accepted by `Preset::from_name`.
+pub const PRESET_NAMES: &'static [&'static str] = &["rust", "moss", "soot", "efflorescence"];
+
+impl Preset {
+    pub fn from_name(name: &str) -> Option<Preset> {
+        match name {
+            "rust" => Some(Preset::Rust),
+            "moss" => Some(Preset::Moss),
+            "soot" => Some(Preset::Soot),
+            "efflorescence" => Some(Preset::Efflorescence),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Preset::Rust => "rust",
+            Preset::Moss => "moss",
+            Preset::Soot => "soot",
+            Preset::Efflorescence => "efflorescence",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match *self {
+            Preset::Rust => "Rain falls on a metal pillar, making it rust where water collects.",
+            Preset::Moss => "Rain keeps the ground around a pillar moist, letting moss grow on it.",
+            Preset::Soot => "Rain washes soot down a pillar and over the ground around it.",
+            Preset::Efflorescence => {
+                "Rain soaks into the concrete ground, leaving white salt crusts as it dries."
+            }
+        }
+    }
+
+    /// Substance that the layer effect synthesizes weathering from.
+    fn substance(&self) -> &'static str {
+        match *self {
+            Preset::Rust => "rust",
+            Preset::Moss => "moss",
+            Preset::Soot => "soot",
+            Preset::Efflorescence => "salt",
+        }
+    }
+
+    /// Materials of the starter scene that get weathered, `_` for all.
+    fn material(&self) -> &'static str {
+        match *self {
+            Preset::Rust => "metal",
+            Preset::Moss | Preset::Efflorescence => "concrete",
+            Preset::Soot => "_",
+        }
+    }
+
+    /// Amount of the substance carried by each drop of rain.
+    fn carried(&self) -> f32 {
+        match *self {
+            Preset::Soot => 0.5,
+            _ => 0.0,
+        }
+    }
+
+    /// Factor of humidity turning into the substance on the given material
+    /// in each iteration, if it does.
+    fn conversion(&self, material: &str) -> Option<f32> {
+        match (*self, material) {
+            (Preset::Rust, "metal") => Some(0.5),
+            (Preset::Moss, "concrete") => Some(0.1),
+            (Preset::Efflorescence, "concrete") => Some(0.2),
+            _ => None,
+        }
+    }
+
+    /// Colors of the samples for light and heavy weathering.
+    fn colors(&self) -> [[u8; 4]; 2] {
+        match *self {
+            Preset::Rust => [[150, 80, 40, 140], [110, 50, 20, 255]],
+            Preset::Moss => [[90, 120, 50, 140], [50, 90, 30, 255]],
+            Preset::Soot => [[40, 40, 40, 110], [15, 15, 15, 240]],
+            Preset::Efflorescence => [[235, 235, 225, 120], [250, 250, 245, 255]],
+        }
+    }
+}
+
+/// Writes a runnable project for the given preset into the given directory
+/// and returns the paths of the written files.
+///
+/// Fails without writing anything if any of the files exists already,
+/// unless `force` is set.
+pub fn init_project(dir: &Path, preset: Preset, force: bool) -> Result<Vec<PathBuf>, Error> {
+    let substance = preset.substance();
+    let colors = preset.colors();
+
+    let texts = vec![
+        ("simulation.yml", simulation_yml(preset)),
+        ("rain.yml", rain_yml(preset)),
+        ("iron.yml", surfel_yml(preset, "Iron", "metal")),
+        ("concrete.yml", surfel_yml(preset, "Concrete", "concrete")),
+        ("scene/scene.obj", String::from(SCENE_OBJ)),
+        ("scene/scene.mtl", String::from(SCENE_MTL)),
+    ];
+    let samples = vec![
+        (String::from("textures/clean.png"), [0, 0, 0, 0]),
+        (format!("textures/{}_light.png", substance), colors[0]),
+        (format!("textures/{}_heavy.png", substance), colors[1]),
+    ];
+
+    let paths: Vec<PathBuf> = texts
+        .iter()
+        .map(|&(name, _)| dir.join(name))
+        .chain(samples.iter().map(|&(ref name, _)| dir.join(name)))
+        .collect();
+
+    if !force {
+        if let Some(existing) = paths.iter().find(|p| p.exists()) {
+            bail!(
+                "{} exists already, use --force to overwrite it.",
+                existing.display()
+            );
+        }
+    }
+
+    for &(name, ref text) in texts.iter() {
+        let path = dir.join(name);
+        create_file_recursively(&path)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .with_context(|_| format!("Could not write {}.", path.display()))?;
+    }
+
+    for &(ref name, color) in samples.iter() {
+        let path = dir.join(name);
+        let mut file = create_file_recursively(&path)
+            .with_context(|_| format!("Could not create {}.", path.display()))?;
+        let sample = RgbaImage::from_pixel(SAMPLE_SIZE, SAMPLE_SIZE, Rgba { data: color });
+        ImageRgba8(sample)
+            .write_to(&mut file, tex::PNG)
+            .with_context(|_| format!("Could not write {}.", path.display()))?;
+    }
+
+    Ok(paths)
+}
+
+fn simulation_yml(preset: Preset) -> String {
+    let output = "output/{datetime}/iteration-{iteration}";
+    let substance = preset.substance();
+
+    format!(
+        r#"name: "{title} starter"
+description: "{description}"
+scenes:
+  - "scene/scene.obj"
+iterations: 20
+surfel_distance: 0.05
+sources:
+  - "rain.yml"
+# Maps MTL material names against surfel descriptions
+surfels_by_material:
+  metal: "iron.yml"
+  # catchall
+  _: "concrete.yml"
+effects:
+  # Grayscale concentration maps, darker where more of a substance is
+  - density:
+      width: 512
+      height: 512
+      tex_pattern: "{output}/{{id}}-{{entity}}-{{substance}}.png"
+  - layer:
+      materials: ["{material}"]
+      substance: "{substance}"
+      albedo:
+        width: 512
+        height: 512
+        tex_pattern: "{output}/{{id}}-{{entity}}-{{substance}}-albedo.png"
+        stops:
+          - sample: "textures/clean.png"
+            cenith: 0.0
+          - sample: "textures/{substance}_light.png"
+            cenith: 0.2
+          - sample: "textures/{substance}_heavy.png"
+            cenith: 0.6
+  - export:
+      obj_pattern: "{output}/weathered.obj"
+      mtl_pattern: "{output}/weathered.mtl"
+"#,
+        title = preset.name(),
+        description = preset.description(),
+        output = output,
+        material = preset.material(),
+        substance = substance,
+    )
+}
+
+fn rain_yml(preset: Preset) -> String {
+    format!(
+        r#"name: Rain
+description: Rain falling onto the scene from above
+shape:
+  directional:
+    position: [0.0, 4.0, 0.0]
+    direction: [0.0, -1.0, 0.0]
+    radius: 3.0
+emission_count: 20000
+p_straight: 0.0
+p_parabolic: 0.3
+p_flow: 0.7
+initial:
+  humidity: 1.0
+  {substance}: {carried:.1}
+absorb:
+  humidity: 1.0
+  {substance}: 0.2
+interaction_radius: 0.1
+parabola_height: 0.07
+flow_distance: 0.17
+flow_direction: [0.0, -1.0, 0.0]
+"#,
+        substance = preset.substance(),
+        carried = preset.carried(),
+    )
+}
+
+fn surfel_yml(preset: Preset, name: &str, material: &str) -> String {
+    let substance = preset.substance();
+    let conversion = match preset.conversion(material) {
+        Some(factor) => format!(
+            "  # Humidity turns into {substance}\n  - from: humidity\n    to: {substance}\n    factor: {factor:.1}\n",
+            substance = substance,
+            factor = factor,
+        ),
+        None => String::new(),
+    };
+
+    format!(
+        r#"name: {name}
+description: Surfel properties of the {material} in the starter scene
+reflectance:
+  delta_straight: 0.0
+  delta_parabolic: 0.8
+  delta_flow: 0.2
+initial:
+  humidity: 0.0
+  {substance}: 0.0
+deposit:
+  # Rate of absorption from tons to this type of surfel
+  humidity: 1.0
+  {substance}: 0.5
+rules:
+{conversion}  # Evaporation reduces humidity
+  - from: humidity
+    factor: -0.5
+"#,
+        name = name,
+        material = material,
+        substance = substance,
+        conversion = conversion,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_yaml;
+    use spec::{SimulationSpec, SurfelSpec, TonSourceSpec};
+    use std::env::temp_dir;
+    use std::fs::{remove_dir_all, File};
+
+    #[test]
+    fn scaffold_parses() {
+        for &name in PRESET_NAMES {
+            let preset = Preset::from_name(name).unwrap();
+            let dir = temp_dir().join(format!("aitios-init-test-{}", name));
+            let _ = remove_dir_all(&dir);
+
+            let written = init_project(&dir, preset, false).unwrap();
+            assert!(written.iter().all(|p| p.exists()));
+            assert!(
+                init_project(&dir, preset, false).is_err(),
+                "Overwrote {}",
+                name
+            );
+
+            let simulation: SimulationSpec =
+                serde_yaml::from_reader(File::open(dir.join("simulation.yml")).unwrap()).unwrap();
+            assert_eq!(1, simulation.scenes.len());
+            let _: TonSourceSpec =
+                serde_yaml::from_reader(File::open(dir.join("rain.yml")).unwrap()).unwrap();
+            for surfels in &["iron.yml", "concrete.yml"] {
+                let surfels: SurfelSpec =
+                    serde_yaml::from_reader(File::open(dir.join(surfels)).unwrap()).unwrap();
+                assert!(surfels.initial.contains_key(preset.substance()));
+            }
+
+            remove_dir_all(&dir).unwrap();
+        }
+    }
+}
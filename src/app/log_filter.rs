@@ -0,0 +1,144 @@
+use failure::Error;
+use log::{LevelFilter, Log, Metadata, Record};
+use simplelog::{Config, SharedLogger};
+
+/// Log levels by module, parsed from comma-separated directives like
+/// `aitios_sim=debug,aitios_tex=warn`.
+///
+/// A directive applies to the module with the given path and all modules
+/// below it, with the longest matching path taking precedence. Modules
+/// without a directive log at the default level, which can also be set with
+/// a directive that is only a level, e.g. `info,aitios_tex=warn`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFilter {
+    default: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl LogFilter {
+    /// Parses the given directives, using the given level for modules that
+    /// match no directive unless the directives set a default level.
+    pub fn parse(directives: &str, default: LevelFilter) -> Result<LogFilter, Error> {
+        let mut filter = LogFilter {
+            default,
+            modules: Vec::new(),
+        };
+
+        for directive in directives
+            .split(',')
+            .map(str::trim)
+            .filter(|d| !d.is_empty())
+        {
+            let mut parts = directive.splitn(2, '=');
+            // Can unwrap since splitn always yields at least one part
+            let module = parts.next().unwrap().trim();
+            match parts.next() {
+                Some(level) => filter
+                    .modules
+                    .push((module.to_string(), parse_level(level, directive)?)),
+                None => filter.default = parse_level(module, directive)?,
+            }
+        }
+
+        Ok(filter)
+    }
+
+    /// Level of the module with the given path.
+    pub fn level(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .filter(|&&(ref module, _)| {
+                target == module || target.starts_with(&format!("{}::", module))
+            })
+            .max_by_key(|&&(ref module, _)| module.len())
+            .map_or(self.default, |&(_, level)| level)
+    }
+
+    /// Most verbose level of any module.
+    pub fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|&(_, level)| level)
+            .fold(self.default, |max, level| max.max(level))
+    }
+}
+
+fn parse_level(level: &str, directive: &str) -> Result<LevelFilter, Error> {
+    level.trim().parse().map_err(|_| {
+        format_err!(
+            "Log filter directive \"{}\" has no valid level, expected one of off, error, warn, info, debug or trace.",
+            directive
+        )
+    })
+}
+
+/// Passes on records to the wrapped logger if the filter lets the level of
+/// their module through. The wrapped logger should accept the maximum level
+/// of the filter.
+pub struct FilteredLogger {
+    filter: LogFilter,
+    inner: Box<SharedLogger>,
+}
+
+impl FilteredLogger {
+    pub fn new(filter: LogFilter, inner: Box<SharedLogger>) -> Box<FilteredLogger> {
+        Box::new(FilteredLogger { filter, inner })
+    }
+}
+
+impl Log for FilteredLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level(metadata.target()) && self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+impl SharedLogger for FilteredLogger {
+    fn level(&self) -> LevelFilter {
+        self.filter.max_level()
+    }
+
+    fn config(&self) -> Option<&Config> {
+        self.inner.config()
+    }
+
+    fn as_log(self: Box<Self>) -> Box<Log> {
+        Box::new(*self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn module_levels() {
+        let filter = LogFilter::parse(
+            "aitios_sim=debug, aitios_sim::tracer=trace,aitios_tex=warn",
+            LevelFilter::Info,
+        )
+        .unwrap();
+
+        assert_eq!(LevelFilter::Debug, filter.level("aitios_sim"));
+        assert_eq!(LevelFilter::Debug, filter.level("aitios_sim::sim"));
+        assert_eq!(LevelFilter::Trace, filter.level("aitios_sim::tracer::ton"));
+        assert_eq!(LevelFilter::Warn, filter.level("aitios_tex::density"));
+        assert_eq!(LevelFilter::Info, filter.level("aitios_simulator"));
+        assert_eq!(LevelFilter::Trace, filter.max_level());
+
+        let quiet = LogFilter::parse("error,aitios_cli=info", LevelFilter::Info).unwrap();
+        assert_eq!(LevelFilter::Error, quiet.level("aitios_tex"));
+        assert_eq!(LevelFilter::Info, quiet.level("aitios_cli::runner"));
+
+        assert!(LogFilter::parse("aitios_sim=loud", LevelFilter::Info).is_err());
+    }
+}
@@ -0,0 +1,91 @@
+use app::new_app;
+use clap::Result as ClapResult;
+use spec::PLACEHOLDERS;
+use std::io::Write;
+
+/// Writes a roff man page for aitios into the given sink.
+///
+/// Sections are derived from the long help of the clap definitions,
+/// with an additional section documenting pattern placeholders.
+pub fn write_man_page<W: Write>(sink: &mut W) -> ClapResult<()> {
+    let mut app = new_app();
+
+    let mut help = Vec::new();
+    app.write_long_help(&mut help)?;
+    let help = String::from_utf8_lossy(&help);
+
+    writeln!(sink, ".TH AITIOS 1 \"\" \"aitios {}\"", crate_version!())?;
+    writeln!(sink, ".SH NAME")?;
+    writeln!(
+        sink,
+        "aitios \\- Procedural weathering simulation on the command line with aitios"
+    )?;
+
+    // Skip name, version, author and about until the first section heading
+    // and turn the all-caps headings clap emits into roff sections.
+    let mut in_section = false;
+    for line in help.lines().skip_while(|l| !is_section_heading(l)) {
+        if is_section_heading(line) {
+            if in_section {
+                writeln!(sink, ".fi")?;
+            }
+            writeln!(sink, ".SH {}", &line[..line.len() - 1])?;
+            writeln!(sink, ".nf")?;
+            in_section = true;
+        } else {
+            writeln!(sink, "{}", escape(line))?;
+        }
+    }
+    if in_section {
+        writeln!(sink, ".fi")?;
+    }
+
+    writeln!(sink, ".SH PATTERN PLACEHOLDERS")?;
+    writeln!(
+        sink,
+        "Output file patterns in simulation specs may contain the following placeholders:"
+    )?;
+    for &(placeholder, description) in PLACEHOLDERS {
+        writeln!(sink, ".TP")?;
+        writeln!(sink, ".B {}", escape(placeholder))?;
+        writeln!(sink, "{}", escape(description))?;
+    }
+
+    Ok(())
+}
+
+fn is_section_heading(line: &str) -> bool {
+    line.ends_with(':')
+        && !line.starts_with(' ')
+        && line
+            .chars()
+            .all(|c| c == ':' || c == ' ' || c.is_ascii_uppercase())
+}
+
+/// Escapes backslashes, hyphens and leading control characters for roff.
+fn escape(line: &str) -> String {
+    let line = line.replace('\\', "\\e").replace('-', "\\-");
+    if line.starts_with('.') || line.starts_with('\'') {
+        format!("\\&{}", line)
+    } else {
+        line
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn man_page_sections() {
+        let mut man = Vec::new();
+        write_man_page(&mut man).expect("Man page generation failed");
+        let man = String::from_utf8(man).unwrap();
+
+        assert!(man.starts_with(".TH AITIOS 1"));
+        assert!(man.contains("\n.SH USAGE\n"));
+        assert!(man.contains("\n.SH ARGS\n"));
+        assert!(man.contains("\\-\\-threads"));
+        assert!(man.contains("\n.B {iteration}\n"));
+    }
+}
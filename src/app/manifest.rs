@@ -0,0 +1,209 @@
+use failure::{Error, ResultExt};
+use files::{changed_inputs, write_atomically, InputHashes};
+use rayon;
+use runner::Observer;
+use serde_json::{self, Value};
+use spec::SimulationSpec;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::env::current_dir;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Versions of the aitios crates locked in `Cargo.lock` when building, as
+/// `name version (revision)` separated by semicolons.
+const CRATE_VERSIONS: &'static str = env!("AITIOS_CRATE_VERSIONS");
+
+/// What is needed to reproduce a run and what it wrote.
+#[derive(Debug, Serialize)]
+struct Manifest {
+    args: Vec<String>,
+    working_dir: Option<PathBuf>,
+    hostname: Option<String>,
+    threads: usize,
+    seed: u64,
+    datetime: String,
+    crates: BTreeMap<String, String>,
+    features: Vec<&'static str>,
+    /// The merged spec with all paths resolved.
+    spec: Value,
+    /// Content hashes of the scenes, surfel specs and samples by path.
+    inputs: InputHashes,
+    outputs: Vec<PathBuf>,
+    error: Option<String>,
+}
+
+/// Writes the manifest of a run to a JSON file once the run is over, with
+/// the files written by then and the error if the run failed.
+pub struct ManifestObserver {
+    path: PathBuf,
+    manifest: RefCell<Manifest>,
+}
+
+impl ManifestObserver {
+    pub fn new<P: Into<PathBuf>>(
+        path: P,
+        args: &[String],
+        spec: &SimulationSpec,
+        inputs: &InputHashes,
+        datetime: &str,
+    ) -> Result<Self, Error> {
+        let manifest = Manifest {
+            args: args.to_vec(),
+            working_dir: current_dir().ok(),
+            hostname: hostname(),
+            threads: rayon::current_num_threads(),
+            seed: spec.seed.unwrap_or(0),
+            datetime: String::from(datetime),
+            crates: crate_versions(),
+            features: features(),
+            spec: serde_json::to_value(spec).context("Could not record spec in manifest.")?,
+            inputs: inputs.clone(),
+            outputs: Vec::new(),
+            error: None,
+        };
+
+        Ok(ManifestObserver {
+            path: path.into(),
+            manifest: RefCell::new(manifest),
+        })
+    }
+}
+
+impl Observer for ManifestObserver {
+    fn file_written(&self, path: &Path) {
+        self.manifest.borrow_mut().outputs.push(path.to_path_buf());
+    }
+
+    fn run_finished(&self, error: Option<&Error>) {
+        let mut manifest = self.manifest.borrow_mut();
+        manifest.error = error.map(|err| err.to_string());
+
+        let written = write_atomically(&self.path, |file| -> Result<(), Error> {
+            serde_json::to_writer_pretty(file, &*manifest)?;
+            Ok(())
+        });
+        match written {
+            Ok(_) => info!("Wrote manifest to {}", self.path.display()),
+            Err(err) => warn!("Could not write manifest {}: {}", self.path.display(), err),
+        }
+    }
+}
+
+/// Fails if inputs recorded in the manifest of an earlier run at the given path
+/// changed since, so that resumed effects do not keep outputs made from other
+/// inputs than the ones they write anew. Only warns without such a manifest.
+pub fn check_resumable(path: &Path, inputs: &InputHashes) -> Result<(), Error> {
+    let recorded = File::open(path)
+        .ok()
+        .and_then(|file| serde_json::from_reader::<_, Value>(file).ok())
+        .and_then(|manifest| serde_json::from_value(manifest["inputs"].clone()).ok());
+
+    match recorded {
+        Some(recorded) => {
+            let changed = changed_inputs(&recorded, inputs);
+            if !changed.is_empty() {
+                bail!(
+                    "Cannot resume effects, inputs {:?} changed since the manifest {} was written. Remove the outputs of the earlier run or run without --resume-effects.",
+                    changed,
+                    path.display()
+                );
+            }
+        }
+        None => warn!(
+            "Resuming effects without input hashes in manifest {}, cannot check whether scenes, surfel specs or samples changed since the outputs were written.",
+            path.display()
+        ),
+    }
+
+    Ok(())
+}
+
+/// Version of this crate and the aitios crates it was built with.
+fn crate_versions() -> BTreeMap<String, String> {
+    let mut crates = BTreeMap::new();
+    crates.insert(
+        String::from(env!("CARGO_PKG_NAME")),
+        String::from(env!("CARGO_PKG_VERSION")),
+    );
+    for locked in CRATE_VERSIONS.split(';').filter(|l| !l.is_empty()) {
+        let mut words = locked.splitn(2, ' ');
+        if let (Some(name), Some(version)) = (words.next(), words.next()) {
+            crates.insert(String::from(name), String::from(version));
+        }
+    }
+    crates
+}
+
+fn features() -> Vec<&'static str> {
+    let features = [
+        ("native", cfg!(feature = "native")),
+        ("ffi", cfg!(feature = "ffi")),
+        ("http", cfg!(feature = "http")),
+    ];
+    features
+        .iter()
+        .filter(|&&(_, enabled)| enabled)
+        .map(|&(feature, _)| feature)
+        .collect()
+}
+
+#[cfg(unix)]
+fn hostname() -> Option<String> {
+    use libc;
+
+    let mut name = [0u8; 256];
+    if unsafe { libc::gethostname(name.as_mut_ptr() as *mut libc::c_char, name.len()) } != 0 {
+        return None;
+    }
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    String::from_utf8(name[..len].to_vec()).ok()
+}
+
+#[cfg(not(unix))]
+fn hostname() -> Option<String> {
+    use std::env::var;
+
+    var("COMPUTERNAME").ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use failure::err_msg;
+    use std::env::temp_dir;
+    use std::fs::{remove_file, File};
+
+    #[test]
+    fn record_outputs_and_error() {
+        let path = temp_dir().join("aitios-manifest-test.json");
+        let spec = SimulationSpec::default().seed(7);
+        let args = vec![String::from("aitios"), String::from("simulation.yml")];
+
+        let mut inputs = InputHashes::new();
+        inputs.insert(PathBuf::from("/scenes/park.obj"), String::from("af63dc4c8601ec8c"));
+
+        let observer =
+            ManifestObserver::new(&path, &args, &spec, &inputs, "2018-07-17T18_06_53").unwrap();
+        observer.file_written(Path::new("out/albedo.png"));
+        observer.run_finished(Some(&err_msg("Disk full")));
+
+        let manifest: Value = serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+        assert_eq!("simulation.yml", manifest["args"][1]);
+        assert_eq!(7, manifest["seed"]);
+        assert_eq!(7, manifest["spec"]["seed"]);
+        assert_eq!("out/albedo.png", manifest["outputs"][0]);
+        assert_eq!("Disk full", manifest["error"]);
+        assert_eq!("af63dc4c8601ec8c", manifest["inputs"]["/scenes/park.obj"]);
+
+        assert!(check_resumable(&path, &inputs).is_ok());
+        inputs.insert(PathBuf::from("/scenes/park.obj"), String::from("cbf29ce484222325"));
+        assert!(check_resumable(&path, &inputs).is_err());
+        assert_eq!(
+            env!("CARGO_PKG_VERSION"),
+            manifest["crates"][env!("CARGO_PKG_NAME")]
+        );
+
+        remove_file(&path).unwrap();
+    }
+}
@@ -3,7 +3,16 @@
 //! include functionality similar to the command line tool.
 
 mod app;
+mod bench_preset;
+mod control;
+mod init;
+mod log_filter;
+mod manifest;
+mod man;
+mod preview;
+mod progress;
 mod run;
 
 pub use self::app::new_app;
+pub use self::man::write_man_page;
 pub use self::run::{run, run_with_args};
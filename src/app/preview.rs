@@ -0,0 +1,281 @@
+use failure::{Error, ResultExt};
+use runner::Observer;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Seconds between reloads of the preview page.
+const REFRESH_SECS: u32 = 5;
+/// Seconds to wait for a request before closing the connection.
+const READ_TIMEOUT_SECS: u64 = 10;
+
+/// What the preview page shows, updated by the observer of the run.
+#[derive(Debug, Default)]
+struct Preview {
+    /// Description of the simulation, as logged before running.
+    summary: String,
+    iteration: u32,
+    last_iteration: u32,
+    finished: Option<String>,
+    /// Textures of the most recent iteration that wrote any.
+    textures: Vec<PathBuf>,
+    /// Textures written so far in the current iteration.
+    pending: Vec<PathBuf>,
+}
+
+/// Preview server running on background threads, showing the progress of
+/// the run it observes.
+pub struct PreviewServer {
+    preview: Arc<Mutex<Preview>>,
+}
+
+/// Address to serve the preview on for the value of `--serve`, either a port
+/// like `8080` on localhost, or an address like `0.0.0.0:8080` to serve it to
+/// other machines.
+pub fn preview_address(serve: &str) -> Result<SocketAddr, String> {
+    match serve.parse::<u16>() {
+        Ok(port) => Ok(SocketAddr::from((Ipv4Addr::new(127, 0, 0, 1), port))),
+        Err(_) => serve
+            .parse::<SocketAddr>()
+            .map_err(|_| format!("{} is neither a port nor an IP:PORT address.", serve)),
+    }
+}
+
+/// Serves a page with the summary, progress and most recent textures of the
+/// run on the given address.
+pub fn serve_preview(address: SocketAddr, summary: &str) -> Result<PreviewServer, Error> {
+    let listener = TcpListener::bind(address)
+        .with_context(|_| format!("Could not serve preview on {}.", address))?;
+    if !address.ip().is_loopback() {
+        warn!(
+            "Serving the preview on {} without authentication, anyone who can reach it sees \
+             the summary and textures of the run.",
+            address
+        );
+    }
+    let preview = Arc::new(Mutex::new(Preview {
+        summary: summary.to_string(),
+        ..Preview::default()
+    }));
+
+    let shared = preview.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming().filter_map(Result::ok) {
+            let preview = shared.clone();
+            thread::spawn(move || {
+                if let Err(err) = answer(stream, &preview) {
+                    debug!("Failed to answer preview request: {}", err);
+                }
+            });
+        }
+    });
+
+    Ok(PreviewServer { preview })
+}
+
+impl PreviewServer {
+    /// Observer that keeps the preview up to date.
+    pub fn observer(&self) -> Box<Observer> {
+        Box::new(PreviewObserver(self.preview.clone()))
+    }
+}
+
+struct PreviewObserver(Arc<Mutex<Preview>>);
+
+impl Observer for PreviewObserver {
+    fn iteration_started(&self, iteration: u32, last_iteration: u32) {
+        let mut preview = self.0.lock().unwrap();
+        preview.iteration = iteration;
+        preview.last_iteration = last_iteration;
+        preview.pending.clear();
+    }
+
+    fn file_written(&self, path: &Path) {
+        if path.extension().map_or(false, |e| e == "png") {
+            self.0.lock().unwrap().pending.push(path.to_path_buf());
+        }
+    }
+
+    fn iteration_finished(&self, _iteration: u32, _last_iteration: u32) {
+        let mut preview = self.0.lock().unwrap();
+        // Keep showing the last textures through iterations without effects
+        if !preview.pending.is_empty() {
+            preview.textures = preview.pending.drain(..).collect();
+        }
+    }
+
+    fn run_finished(&self, error: Option<&Error>) {
+        self.0.lock().unwrap().finished = Some(match error {
+            Some(error) => format!("failed: {}", error),
+            None => String::from("complete"),
+        });
+    }
+}
+
+fn answer(mut stream: TcpStream, preview: &Mutex<Preview>) -> Result<(), Error> {
+    // Clients that never finish their request would keep the thread forever
+    stream.set_read_timeout(Some(Duration::from_secs(READ_TIMEOUT_SECS)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Headers are not needed, but read so the client is not reset
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let (status, content_type, body) = respond(&request_line, preview);
+    write!(
+        stream,
+        "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+    Ok(())
+}
+
+/// Status, content type and body for the given request line. Only textures
+/// in the preview can be requested, by their index.
+fn respond(request_line: &str, preview: &Mutex<Preview>) -> (&'static str, &'static str, Vec<u8>) {
+    let mut words = request_line.split_whitespace();
+    let path = match (words.next(), words.next()) {
+        (Some("GET"), Some(path)) => path,
+        _ => {
+            return (
+                "405 Method Not Allowed",
+                "text/plain",
+                b"Only GET is supported.".to_vec(),
+            )
+        }
+    };
+
+    if path == "/" {
+        let page = page(&preview.lock().unwrap());
+        return ("200 OK", "text/html; charset=utf-8", page.into_bytes());
+    }
+
+    let texture = if path.starts_with("/texture/") {
+        path["/texture/".len()..]
+            .parse::<usize>()
+            .ok()
+            .and_then(|idx| preview.lock().unwrap().textures.get(idx).cloned())
+    } else {
+        None
+    };
+    let mut png = Vec::new();
+    match texture.map(|texture| File::open(texture).and_then(|mut f| f.read_to_end(&mut png))) {
+        Some(Ok(_)) => ("200 OK", "image/png", png),
+        _ => ("404 Not Found", "text/plain", b"Not found.".to_vec()),
+    }
+}
+
+fn page(preview: &Preview) -> String {
+    let progress = match preview.finished {
+        Some(ref finished) => format!("Run {}", finished),
+        None => format!(
+            "Iteration {} of {}",
+            preview.iteration, preview.last_iteration
+        ),
+    };
+    let textures: String = preview
+        .textures
+        .iter()
+        .enumerate()
+        .map(|(idx, texture)| {
+            format!(
+                "<figure><img src=\"/texture/{idx}\"><figcaption>{name}</figcaption></figure>\n",
+                idx = idx,
+                name = escape(&texture.display().to_string())
+            )
+        })
+        .collect();
+
+    format!(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<meta http-equiv=\"refresh\" content=\"{refresh}\">
+<title>aitios: {progress}</title>
+<style>img {{ max-width: 256px; }} figure {{ display: inline-block; }}</style>
+</head>
+<body>
+<h1>{progress}</h1>
+{textures}<pre>{summary}</pre>
+</body>
+</html>
+",
+        refresh = REFRESH_SECS,
+        progress = escape(&progress),
+        textures = textures,
+        summary = escape(&preview.summary)
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn serves_textures_of_last_iteration() {
+        let preview = Arc::new(Mutex::new(Preview {
+            summary: String::from("Name: <rust>"),
+            ..Preview::default()
+        }));
+        let observer = PreviewObserver(preview.clone());
+        observer.iteration_started(1, 2);
+        observer.file_written(Path::new("Cargo.toml"));
+        observer.file_written(Path::new("missing.png"));
+        observer.iteration_finished(1, 2);
+        observer.iteration_started(2, 2);
+        observer.iteration_finished(2, 2);
+
+        let (status, _, page) = respond("GET / HTTP/1.1\r\n", &preview);
+        let page = String::from_utf8(page).unwrap();
+        assert_eq!("200 OK", status);
+        assert!(page.contains("Iteration 2 of 2"));
+        assert!(page.contains("Name: &lt;rust&gt;"));
+        assert!(page.contains("<img src=\"/texture/0\">"));
+        assert!(!page.contains("/texture/1"));
+
+        // Listed, but not written to the file system
+        assert_eq!(
+            "404 Not Found",
+            respond("GET /texture/0 HTTP/1.1", &preview).0
+        );
+        assert_eq!(
+            "404 Not Found",
+            respond("GET /../Cargo.toml HTTP/1.1", &preview).0
+        );
+        assert_eq!(
+            "405 Method Not Allowed",
+            respond("POST / HTTP/1.1", &preview).0
+        );
+    }
+
+    #[test]
+    fn serve_on_localhost_unless_given_address() {
+        assert_eq!(
+            "127.0.0.1:8080".parse::<SocketAddr>(),
+            Ok(preview_address("8080").unwrap())
+        );
+        assert_eq!(
+            "0.0.0.0:8080".parse::<SocketAddr>(),
+            Ok(preview_address("0.0.0.0:8080").unwrap())
+        );
+        assert!(preview_address("localhost").is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use failure::Error;
+use runner::Observer;
+use serde_json;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::time::Instant;
+
+/// Values of `--progress-format`.
+pub const PROGRESS_FORMATS: &'static [&'static str] = &["text", "json-lines"];
+
+/// Progress of a run at one point in time, printed as one line of JSON, e.g.
+/// `{"phase":"tracing","iteration":3,"last_iteration":30,"percent":6.7,"eta_secs":81.0}`.
+#[derive(Debug, Serialize)]
+struct ProgressLine<'a> {
+    /// One of `started`, `tracing`, `effects`, `effect`,
+    /// `iteration_finished`, `complete` and `failed`.
+    phase: &'a str,
+    iteration: u32,
+    last_iteration: u32,
+    /// Share of the iterations of the run that are finished.
+    percent: f32,
+    /// Estimated seconds until the last iteration is finished, once the
+    /// first one is.
+    eta_secs: Option<f64>,
+    /// Type and index of the effect that was just performed, e.g. `layer#1`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    effect: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Progress {
+    /// First iteration of the run and when it started.
+    first: Option<(u32, Instant)>,
+    iteration: u32,
+    last_iteration: u32,
+    finished: u32,
+}
+
+impl Progress {
+    fn percent(&self) -> f32 {
+        match self.first {
+            Some((first, _)) if self.last_iteration >= first => {
+                let total = self.last_iteration - first + 1;
+                100.0 * self.finished as f32 / total as f32
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Remaining iterations times the mean duration of the finished ones.
+    fn eta_secs(&self) -> Option<f64> {
+        match self.first {
+            Some((first, started)) if self.finished > 0 => {
+                let elapsed = started.elapsed();
+                let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+                let remaining = (self.last_iteration + 1).saturating_sub(first + self.finished);
+                Some(elapsed / self.finished as f64 * remaining as f64)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Observer that prints the progress of a run as JSON lines, so that job
+/// orchestrators can follow it without parsing logs.
+pub struct JsonLinesProgress<W: Write> {
+    out: RefCell<W>,
+    progress: RefCell<Progress>,
+}
+
+impl<W: Write> JsonLinesProgress<W> {
+    pub fn new(out: W) -> Self {
+        JsonLinesProgress {
+            out: RefCell::new(out),
+            progress: RefCell::new(Progress::default()),
+        }
+    }
+
+    fn print(&self, phase: &str, effect: Option<&str>, error: Option<String>) {
+        let progress = self.progress.borrow();
+        let line = ProgressLine {
+            phase,
+            iteration: progress.iteration,
+            last_iteration: progress.last_iteration,
+            percent: progress.percent(),
+            eta_secs: progress.eta_secs(),
+            effect,
+            error,
+        };
+
+        let mut out = self.out.borrow_mut();
+        let printed = serde_json::to_writer(&mut *out, &line)
+            .map_err(io::Error::from)
+            .and_then(|_| writeln!(out))
+            .and_then(|_| out.flush());
+        if let Err(err) = printed {
+            warn!("Could not print progress: {}", err);
+        }
+    }
+}
+
+impl<W: Write> Observer for JsonLinesProgress<W> {
+    fn run_started(&self, last_iteration: u32) {
+        self.progress.borrow_mut().last_iteration = last_iteration;
+        self.print("started", None, None);
+    }
+
+    fn iteration_started(&self, iteration: u32, last_iteration: u32) {
+        {
+            let mut progress = self.progress.borrow_mut();
+            progress.first.get_or_insert((iteration, Instant::now()));
+            progress.iteration = iteration;
+            progress.last_iteration = last_iteration;
+        }
+        self.print("tracing", None, None);
+    }
+
+    fn tracing_finished(&self, _iteration: u32) {
+        self.print("effects", None, None);
+    }
+
+    fn effect_finished(&self, _iteration: u32, effect: &str) {
+        self.print("effect", Some(effect), None);
+    }
+
+    fn iteration_finished(&self, _iteration: u32, _last_iteration: u32) {
+        self.progress.borrow_mut().finished += 1;
+        self.print("iteration_finished", None, None);
+    }
+
+    fn run_finished(&self, error: Option<&Error>) {
+        match error {
+            None => self.print("complete", None, None),
+            Some(error) => self.print("failed", None, Some(error.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn one_json_object_per_line() {
+        let mut out = Vec::new();
+        {
+            let progress = JsonLinesProgress::new(&mut out);
+            progress.run_started(2);
+            for iteration in 1..3 {
+                progress.iteration_started(iteration, 2);
+                progress.tracing_finished(iteration);
+                progress.effect_finished(iteration, "density#0");
+                progress.iteration_finished(iteration, 2);
+            }
+            progress.run_finished(None);
+        }
+
+        let lines: Vec<Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let phases: Vec<&str> = lines.iter().map(|l| l["phase"].as_str().unwrap()).collect();
+        assert_eq!(
+            vec![
+                "started",
+                "tracing",
+                "effects",
+                "effect",
+                "iteration_finished",
+                "tracing",
+                "effects",
+                "effect",
+                "iteration_finished",
+                "complete",
+            ],
+            phases
+        );
+        assert_eq!(Value::Null, lines[1]["eta_secs"]);
+        assert_eq!("density#0", lines[3]["effect"]);
+        assert_eq!(50.0, lines[4]["percent"].as_f64().unwrap());
+        assert!(lines[4]["eta_secs"].as_f64().unwrap() >= 0.0);
+        assert_eq!(100.0, lines[9]["percent"].as_f64().unwrap());
+        assert_eq!(0.0, lines[9]["eta_secs"].as_f64().unwrap());
+    }
+}
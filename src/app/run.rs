@@ -1,16 +1,33 @@
-use app::new_app;
+use app::bench_preset::{write_bench_project, BenchPreset};
+use app::control::{listen, Endpoint};
+use app::init::{init_project, Preset};
+use app::preview::{preview_address, serve_preview};
+use app::progress::JsonLinesProgress;
+use app::log_filter::{FilteredLogger, LogFilter};
+use app::manifest::{check_resumable, ManifestObserver};
+use app::{new_app, write_man_page};
+use bencher::{read_benchmarks, Comparison};
+use chrono::DateTime;
 use builder::SimulationBuilder;
+use doctor::{diagnose, Impact};
+use golden::{compare_images, update_references};
 use clap::{ArgMatches, ErrorKind as ClapErrorKind, Result as ClapResult};
 use failure::{err_msg, Error, ResultExt};
-use files::{create_file_recursively, fs_timestamp};
+use files::{create_file_recursively, fs_timestamp, write_atomically, PatternValues};
+use profiler::Profiler;
 use rayon::ThreadPoolBuilder;
+use spec::Overwrite;
 use simplelog::{CombinedLogger, Config, LevelFilter, SharedLogger, TermLogger, WriteLogger};
 use std::collections::HashSet;
 use std::default::Default;
-use std::env::current_dir;
+use std::env::{args_os, current_dir, temp_dir};
 use std::ffi::OsString;
-use std::fs::create_dir_all;
+use std::fs::{create_dir_all, remove_dir_all, File};
+use std::io::{stderr, stdin, stdout, BufReader, BufWriter, Read};
 use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::time::UNIX_EPOCH;
 
 /// Runs with the specified arguments rather than `std::env::args()`.
 /// The first argument will be the executable name, the second will
@@ -20,45 +37,98 @@ where
     I: IntoIterator<Item = A>,
     A: Into<OsString> + Clone,
 {
-    let matches = new_app().get_matches_from_safe(iter);
-    run_with_matches(matches)
+    let args: Vec<OsString> = iter.into_iter().map(Into::into).collect();
+    let matches = new_app().get_matches_from_safe(args.iter().cloned());
+    // Recorded in manifests
+    let args: Vec<String> = args.iter().map(|a| a.to_string_lossy().into_owned()).collect();
+    run_with_matches(matches, &args)
 }
 
 /// Runs the applications with the arguments obtained from `std::env::args()`.
 pub fn run() -> Result<(), Error> {
-    let matches = new_app().get_matches_safe();
-    run_with_matches(matches)
+    run_with_args(args_os())
 }
 
-fn run_with_matches(matches: ClapResult<ArgMatches>) -> Result<(), Error> {
-    match matches {
-        // CLI arg parsing succeeded, unwrap the result and start loading and running simulation.
-        Ok(ref matched) => {
-            init_thread_pool(matched)?;
+fn run_with_matches(matches: ClapResult<ArgMatches>, args: &[String]) -> Result<(), Error> {
+    let result = dispatch(matches, args);
+
+    // Bundles are extracted into a temporary directory that is only needed while running
+    let bundles_dir = bundles_dir();
+    if bundles_dir.exists() {
+        if let Err(err) = remove_dir_all(&bundles_dir) {
+            warn!(
+                "Failed to remove extracted bundles in {}: {}",
+                bundles_dir.display(),
+                err
+            );
+        }
+    }
 
-            let builder = init_simulation_builder(matched)?;
+    result
+}
 
-            {
-                // Init logging after spec reading but before building
-                let spec = builder.spec();
-                init_logging(matched, &spec.log, &fs_timestamp(builder.creation_time()))?;
-            }
+fn dispatch(matches: ClapResult<ArgMatches>, args: &[String]) -> Result<(), Error> {
+    match matches {
+        // CLI arg parsing succeeded, unwrap the result and start loading and running simulation.
+        Ok(ref matched) if matched.is_present("generate-man") => {
+            write_man_page(&mut stdout())?;
+            Ok(())
+        }
+        Ok(ref matched) if matched.is_present("explain-resolve") => {
+            init_logging_fallback()?;
+            explain_resolve(matched)
+        }
+        Ok(ref matched) if matched.subcommand_matches("inspect").is_some() => {
+            init_logging_fallback()?;
 
-            info!("Simulation specification ready, preparing simulation...");
-            let mut runner = builder.build()?;
+            let inspect_matches = matched.subcommand_matches("inspect").unwrap();
+            let inspection = init_simulation_builder(inspect_matches)?.inspect()?;
+            println!("{}", inspection);
 
-            // Log the description line-wise
-            info!("Simulation ready.");
-            for line in format!("{}", runner).lines() {
-                info!("{}", line);
-            }
+            Ok(())
+        }
+        Ok(ref matched) if matched.subcommand_matches("pack").is_some() => {
+            init_logging_fallback()?;
 
-            info!("Simulation running...");
-            runner.run();
-            info!("Finished simulation, done.");
+            let pack_matches = matched.subcommand_matches("pack").unwrap();
+            // Can unwrap since output is required
+            let bundle_path = pack_matches.value_of("output").unwrap();
+            let builder = init_simulation_builder(pack_matches)?;
+            write_atomically(bundle_path, |bundle| builder.pack(bundle))
+                .context("Failed to write simulation bundle.")?;
+            info!("Packed simulation into {}", bundle_path);
 
             Ok(())
         }
+        Ok(ref matched) if matched.subcommand_matches("doctor").is_some() => {
+            init_logging_fallback()?;
+            doctor(matched.subcommand_matches("doctor").unwrap())
+        }
+        Ok(ref matched) if matched.subcommand_matches("init").is_some() => {
+            init_logging_fallback()?;
+            init(matched.subcommand_matches("init").unwrap())
+        }
+        Ok(ref matched) if matched.subcommand_matches("golden").is_some() => {
+            init_logging_fallback()?;
+            golden_test(matched.subcommand_matches("golden").unwrap())
+        }
+        Ok(ref matched) if matched.subcommand_matches("bench").is_some() => {
+            init_logging_fallback()?;
+
+            let bench_matches = matched.subcommand_matches("bench").unwrap();
+            if let Some(compare_matches) = bench_matches.subcommand_matches("compare") {
+                compare_benchmarks(compare_matches)
+            } else {
+                bench_preset(bench_matches)
+            }
+        }
+        Ok(ref matched) if matched.subcommand_matches("run").is_some() => {
+            run_simulation(matched.subcommand_matches("run").unwrap(), args)
+        }
+        Ok(ref matched) if matched.subcommand_matches("effects").is_some() => {
+            run_simulation(matched.subcommand_matches("effects").unwrap(), args)
+        }
+        Ok(ref matched) => run_simulation(matched, args),
         // CLI argument parsing either failed or the user just wanted help or version information
         Err(matches_error) => {
             init_logging_fallback()?;
@@ -82,6 +152,296 @@ fn run_with_matches(matches: ClapResult<ArgMatches>) -> Result<(), Error> {
     }
 }
 
+/// Prints a comparison of the benchmark files in the BEFORE and AFTER
+/// arguments.
+fn compare_benchmarks(matches: &ArgMatches) -> Result<(), Error> {
+    let read = |arg| -> Result<_, Error> {
+        // Can unwrap since both are required
+        let path = matches.value_of(arg).unwrap();
+        let file = File::open(path).with_context(|_| format!("Could not open {}.", path))?;
+        let benchmarks = read_benchmarks(BufReader::new(file))
+            .with_context(|_| format!("Could not read benchmarks from {}.", path))?;
+        Ok(benchmarks)
+    };
+
+    print!("{}", Comparison::new(&read("BEFORE")?, &read("AFTER")?));
+    Ok(())
+}
+
+/// Times a simulation of the scene generated for the preset and size in the
+/// given matches and prints the summary of its benchmarks.
+fn bench_preset(matches: &ArgMatches) -> Result<(), Error> {
+    // Can unwrap since the preset is required without compare and one of the possible values,
+    // and the size has a validated default
+    let preset = BenchPreset::from_name(matches.value_of("preset").unwrap()).unwrap();
+    let size: usize = matches.value_of("size").unwrap().parse().unwrap();
+    let (dir, keep) = match matches.value_of("output") {
+        Some(dir) => (PathBuf::from(dir), true),
+        None => (
+            temp_dir().join(format!("aitios-bench-{}", process::id())),
+            false,
+        ),
+    };
+
+    let result = run_bench_preset(&dir, preset, size);
+    if !keep && dir.exists() {
+        if let Err(err) = remove_dir_all(&dir) {
+            warn!("Failed to remove benchmark project in {}: {}", dir.display(), err);
+        }
+    }
+
+    result
+}
+
+fn run_bench_preset(dir: &Path, preset: BenchPreset, size: usize) -> Result<(), Error> {
+    let spec = write_bench_project(dir, preset, size)
+        .context("Failed to write benchmark project.")?;
+    let mut runner = SimulationBuilder::new()
+        .append_spec_fragment_file(&spec)?
+        .output_root(dir)
+        .build()?;
+    runner.run()?;
+
+    println!("Benchmark {} of size {}:", preset.name(), size);
+    let summary_path = dir.join("benchmarks").join("summary.csv");
+    let mut summary = String::new();
+    File::open(&summary_path)
+        .and_then(|mut file| file.read_to_string(&mut summary))
+        .with_context(|_| format!("Could not read summary {}.", summary_path.display()))?;
+    print!("{}", summary);
+
+    Ok(())
+}
+
+/// Prints a diagnosis of each OBJ file in the given matches and fails if any
+/// of them would break surfel sampling or texture synthesis.
+fn doctor(matches: &ArgMatches) -> Result<(), Error> {
+    let mut broken = false;
+    // Can unwrap since at least one file is required
+    for scene in matches.values_of("OBJ_FILE").unwrap() {
+        let diagnosis = diagnose(scene)?;
+        println!("{}", diagnosis);
+        broken = broken || diagnosis.has(Impact::Sampling) || diagnosis.has(Impact::Synthesis);
+    }
+
+    if broken {
+        Err(err_msg("Some scenes have issues that break surfel sampling or texture synthesis."))
+    } else {
+        Ok(())
+    }
+}
+
+/// Runs the spec in the given matches at low resolution into a temporary
+/// output root and compares the written images against the references, or
+/// replaces the references with them.
+fn golden_test(matches: &ArgMatches) -> Result<(), Error> {
+    let outputs = temp_dir().join(format!("aitios-golden-{}", process::id()));
+    let result = run_golden_test(matches, &outputs);
+
+    if outputs.exists() {
+        if let Err(err) = remove_dir_all(&outputs) {
+            warn!("Failed to remove test outputs in {}: {}", outputs.display(), err);
+        }
+    }
+
+    result
+}
+
+fn run_golden_test(matches: &ArgMatches, outputs: &Path) -> Result<(), Error> {
+    // Can unwrap since references are required and the others have validated defaults
+    let references = Path::new(matches.value_of("references").unwrap());
+    let tolerance: f32 = matches.value_of("tolerance").unwrap().parse().unwrap();
+    let resolution: usize = matches.value_of("resolution").unwrap().parse().unwrap();
+
+    // A fixed time keeps {datetime} in output paths the same as for the references
+    let mut runner = init_simulation_builder(matches)?
+        .created_at(DateTime::from(UNIX_EPOCH))
+        .limit_resolution(resolution)
+        .output_root(outputs)
+        .build()?;
+    runner.run()?;
+
+    if matches.is_present("update") {
+        let updated = update_references(outputs, references)?;
+        println!("Updated {} reference images in {}", updated.len(), references.display());
+        return Ok(());
+    }
+
+    let report = compare_images(outputs, references, tolerance)?;
+    println!("{}", report);
+    if report.passed() {
+        Ok(())
+    } else {
+        Err(err_msg("Some images differ from their references."))
+    }
+}
+
+/// Writes a starter project for the preset in the given matches.
+fn init(matches: &ArgMatches) -> Result<(), Error> {
+    // Can unwrap since both have defaults and the preset is one of the possible values
+    let dir = Path::new(matches.value_of("DIR").unwrap());
+    let preset = Preset::from_name(matches.value_of("preset").unwrap()).unwrap();
+
+    let written = init_project(dir, preset, matches.is_present("force"))
+        .context("Failed to write starter project.")?;
+    for path in written.iter() {
+        info!("Wrote {}", path.display());
+    }
+    info!(
+        "Run the {} starter with: aitios {}",
+        preset.name(),
+        dir.join("simulation.yml").display()
+    );
+
+    Ok(())
+}
+
+/// Builds and runs the simulation from the spec files and inline specs
+/// in the given matches, which were parsed from the given arguments.
+fn run_simulation(matched: &ArgMatches, args: &[String]) -> Result<(), Error> {
+    init_thread_pool(matched)?;
+
+    let profiler = matched.value_of("profile").map(|_| Rc::new(Profiler::new()));
+
+    let mut builder = init_simulation_builder(matched)?;
+    if let Some(ref profiler) = profiler {
+        builder = builder.profiler(Rc::clone(profiler));
+    }
+    // Only given to the effects subcommand
+    if let Some(snapshot) = matched.value_of("from-surfels") {
+        builder = builder.from_surfels(snapshot)?;
+    }
+    let snapshot_iteration = builder.snapshot_iteration();
+
+    // Init logging after spec reading but before building
+    let log_path = builder.log_path()?;
+    let datetime = fs_timestamp(builder.creation_time());
+    init_logging(matched, &log_path, &datetime)?;
+
+    info!("Simulation specification ready, preparing simulation...");
+    let mut runner = builder.build()?;
+    runner.set_resume_effects(matched.is_present("resume-effects"));
+    runner.set_audit(matched.is_present("audit"));
+    if let Some(overwrite) = matched.value_of("overwrite") {
+        // Can unwrap since clap only accepts the names of policies
+        runner.set_overwrite(Overwrite::from_name(overwrite).unwrap());
+    }
+
+    if let Some(stats_path) = matched.value_of("stats") {
+        let stats = create_file_recursively(stats_path)
+            .with_context(|_| format!("Failed to create iteration stats file {}.", stats_path))?;
+        runner.set_iteration_stats(Box::new(BufWriter::new(stats)));
+    }
+
+    if matched.is_present("resume-effects") && runner.spec().manifest.is_none() {
+        warn!("Resuming effects without a manifest in the spec, cannot check whether inputs changed since the outputs were written.");
+    }
+    if let Some(manifest) = runner.spec().manifest.clone() {
+        let manifest = PatternValues::new(&datetime).substitute(&manifest.to_string_lossy());
+        if matched.is_present("resume-effects") {
+            check_resumable(Path::new(&manifest), runner.input_hashes())?;
+        }
+        let observer =
+            ManifestObserver::new(manifest, args, runner.spec(), runner.input_hashes(), &datetime)?;
+        runner.add_observer(Box::new(observer));
+    }
+
+    // Kept until the run is over, so the socket is removed afterwards
+    let _control = match matched.value_of("control") {
+        Some(endpoint) => {
+            let (commands, listening) =
+                listen(&Endpoint::parse(endpoint)?, matched.value_of("control-token"))?;
+            info!("Accepting control commands on {}", endpoint);
+            runner.set_commands(commands);
+            runner.add_observer(listening.status_observer());
+            Some(listening)
+        }
+        None => None,
+    };
+
+    // Log the description line-wise
+    info!("Simulation ready.");
+    let summary = format!("{}", runner);
+    for line in summary.lines() {
+        info!("{}", line);
+    }
+
+    if let Some(serve) = matched.value_of("serve") {
+        // Can unwrap since the validator checks the address
+        let address = preview_address(serve).unwrap();
+        let preview = serve_preview(address, &summary)?;
+        info!("Serving preview on http://{}", address);
+        runner.add_observer(preview.observer());
+    }
+
+    if json_lines_progress(matched) {
+        runner.add_observer(Box::new(JsonLinesProgress::new(stdout())));
+    }
+
+    match snapshot_iteration {
+        Some(iteration) => {
+            info!("Running effects for iteration {} on surfel snapshot...", iteration);
+            runner.run_effects(iteration)?;
+        }
+        None => {
+            info!("Simulation running...");
+            runner.run()?;
+        }
+    }
+    info!("Finished simulation, done.");
+
+    if let (Some(profile_path), Some(profiler)) = (matched.value_of("profile"), profiler) {
+        persist_profile(profile_path, &profiler)?;
+    }
+
+    Ok(())
+}
+
+/// Prints the paths tried when resolving the path passed with `--explain-resolve`
+/// relative to the working directory and the directories of spec files.
+fn explain_resolve(matches: &ArgMatches) -> Result<(), Error> {
+    // Can unwrap since only called when present
+    let search_path = matches.value_of("explain-resolve").unwrap();
+
+    let mut builder = SimulationBuilder::new();
+    let spec_files = matches.values_of("SIMULATION_SPEC_FILE").into_iter().flat_map(|f| f);
+    // Standard input and URLs have no local directory
+    for spec_file in spec_files.filter(|f| *f != "-" && !f.contains("://")) {
+        match Path::new(spec_file).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => builder = builder.add_base_path(dir)?,
+            _ => (),
+        }
+    }
+
+    println!("Resolving {}, trying in order:", search_path);
+
+    let trace = builder.trace_resolve(search_path);
+    for (idx, &(ref candidate, ref resolved)) in trace.iter().enumerate() {
+        println!(
+            "{:>4}. {} ({})",
+            idx + 1,
+            candidate.display(),
+            if resolved.is_some() { "exists" } else { "not found" }
+        );
+    }
+
+    match trace.into_iter().filter_map(|(_, resolved)| resolved).next() {
+        Some(resolved) => println!("Resolves to {}", resolved.display()),
+        None => println!("Not found in any base path."),
+    }
+
+    Ok(())
+}
+
+fn persist_profile(profile_path: &str, profiler: &Profiler) -> Result<(), Error> {
+    write_atomically(profile_path, |profile| profiler.write_chrome_trace(profile))
+        .context("Failed to write profile.")?;
+
+    info!("Wrote profile to {}", profile_path);
+
+    Ok(())
+}
+
 fn init_thread_pool(matches: &ArgMatches) -> Result<(), Error> {
     if let Some(thread_count) = matches.value_of("THREAD_COUNT") {
         let thread_count = usize::from_str_radix(&thread_count, 10).unwrap(); // Can be unwrapped since validator checks this
@@ -112,7 +472,23 @@ fn init_simulation_builder(matches: &ArgMatches) -> Result<SimulationBuilder, Er
         ).peekable()
     });
 
-    let mut builder = SimulationBuilder::new();
+    let mut builder = SimulationBuilder::new().strict(matches.is_present("strict"));
+    if let Some(root) = matches.value_of("output-root") {
+        builder = builder.output_root(root);
+    }
+    if let Some(scenes) = matches.values_of("scene") {
+        builder = builder.scenes(scenes)?;
+    }
+    if let Some(substances) = matches.values_of("substances") {
+        builder = builder.substances(substances);
+    }
+
+    #[cfg(feature = "http")]
+    {
+        if let Some(cache) = matches.value_of("download-cache") {
+            builder = builder.download_cache(cache)?;
+        }
+    }
 
     loop {
         let advance_files = {
@@ -124,15 +500,25 @@ fn init_simulation_builder(matches: &ArgMatches) -> Result<SimulationBuilder, Er
                     // Smaller idx first
                     if file_idx < inline_idx {
                         // Advance iterators, so we can terminate some time later
-                        builder = builder.append_spec_fragment_file(spec_file)?;
+                        builder = append_spec_fragment_file_or_reader(
+                            builder,
+                            *file_idx,
+                            spec_file,
+                            stdin(),
+                        )?;
                         true
                     } else {
                         builder = builder.append_spec_fragment_str(spec_inline)?;
                         false
                     }
                 }
-                (Some((_, spec_file)), None) => {
-                    builder = builder.append_spec_fragment_file(spec_file)?;
+                (Some((file_idx, spec_file)), None) => {
+                    builder = append_spec_fragment_file_or_reader(
+                        builder,
+                        *file_idx,
+                        spec_file,
+                        stdin(),
+                    )?;
                     true
                 }
                 (None, Some((_, spec_inline))) => {
@@ -159,6 +545,36 @@ fn init_simulation_builder(matches: &ArgMatches) -> Result<SimulationBuilder, Er
     Ok(builder)
 }
 
+/// Appends the spec fragment file at the given path, or reads the spec
+/// fragment from the given reader if the path is `-`, which is standard
+/// input outside of tests.
+///
+/// Paths ending in `.aitios` are bundles written by `aitios pack` and get
+/// extracted into a temporary directory named after the argument index.
+fn append_spec_fragment_file_or_reader<R>(
+    builder: SimulationBuilder,
+    arg_idx: usize,
+    spec_file: &str,
+    stdin: R,
+) -> Result<SimulationBuilder, Error>
+where
+    R: Read,
+{
+    if spec_file == "-" {
+        Ok(builder.append_spec_fragment_reader(stdin)?)
+    } else if spec_file.ends_with(".aitios") {
+        let extract_to = bundles_dir().join(arg_idx.to_string());
+        Ok(builder.append_archive_file(spec_file, extract_to)?)
+    } else {
+        Ok(builder.append_spec_fragment_file(spec_file)?)
+    }
+}
+
+/// Directory that bundles are extracted into, removed after running.
+fn bundles_dir() -> PathBuf {
+    temp_dir().join(format!("aitios-bundles-{}", process::id()))
+}
+
 /// Initializes logging using the given argument matching result
 /// and an optional additional log path.
 ///
@@ -207,16 +623,33 @@ where
         _ => LevelFilter::Debug,
     };
 
-    let mut loggers: Vec<Box<SharedLogger>> = vec![
-        TermLogger::new(filter, Config::default())
-            .ok_or(err_msg("Failed to set up logging to terminal."))?,
-    ];
+    // Loggers take everything any module may log, the module filter does the rest
+    let module_filter = match arg_matches.value_of("log-filter") {
+        Some(directives) => Some(LogFilter::parse(directives, filter)?),
+        None => None,
+    };
+    let level = module_filter.as_ref().map_or(filter, LogFilter::max_level);
+    let filtered = |logger: Box<SharedLogger>| -> Box<SharedLogger> {
+        match module_filter {
+            Some(ref module_filter) => FilteredLogger::new(module_filter.clone(), logger),
+            None => logger,
+        }
+    };
+
+    // Standard output is reserved for progress lines if printing them
+    let terminal: Box<SharedLogger> = if json_lines_progress(arg_matches) {
+        WriteLogger::new(level, Config::default(), stderr())
+    } else {
+        TermLogger::new(level, Config::default())
+            .ok_or(err_msg("Failed to set up logging to terminal."))?
+    };
+    let mut loggers: Vec<Box<SharedLogger>> = vec![filtered(terminal)];
 
     let log_paths = canonical_log_file_paths(arg_matches, additional_logs, datetime)?;
     for log in log_paths.into_iter() {
         let log = create_file_recursively(log).context("Failed to create log file.")?;
 
-        loggers.push(WriteLogger::new(filter, Config::default(), log));
+        loggers.push(filtered(WriteLogger::new(level, Config::default(), log)));
     }
 
     CombinedLogger::init(loggers).context("Failed to set up combined logger.")?;
@@ -224,6 +657,11 @@ where
     Ok(())
 }
 
+/// Whether progress is printed to standard output as JSON lines.
+fn json_lines_progress(matches: &ArgMatches) -> bool {
+    matches.value_of("progress-format") == Some("json-lines")
+}
+
 fn canonical_log_file_paths<I, S>(
     arg_matches: &ArgMatches,
     additional_logs: I,
@@ -266,7 +704,7 @@ where
 
 fn log_arg_to_log_path(arg: &str, datetime: &str) -> Result<PathBuf, Error> {
     // Replace {datetime} pattern with filename safe timestamp
-    let arg = arg.replace("{datetime}", datetime);
+    let arg = PatternValues::new(datetime).substitute(arg);
     let path: &Path = arg.as_ref();
 
     if path.is_dir() {
@@ -502,6 +940,52 @@ mod test {
         assert_eq!(1, log_file_paths.len());
     }
 
+    #[test]
+    fn inspect_subcommand() {
+        let matches = new_app().get_matches_from(vec![
+            "aitios-cli",
+            "inspect",
+            "tests/examples/simulation.yml",
+        ]);
+
+        let inspect_matches = matches
+            .subcommand_matches("inspect")
+            .expect("Expected inspect to be recognized as subcommand");
+
+        assert_eq!(
+            Some("tests/examples/simulation.yml"),
+            inspect_matches.value_of("SIMULATION_SPEC_FILE")
+        );
+    }
+
+    #[test]
+    fn comma_separated_substances() {
+        let matches = new_app().get_matches_from(vec![
+            "aitios-cli",
+            "run",
+            "tests/examples/simulation.yml",
+            "--substances",
+            "rust,moss",
+        ]);
+
+        let run_matches = matches.subcommand_matches("run").unwrap();
+        assert_eq!(
+            vec!["rust", "moss"],
+            run_matches.values_of("substances").unwrap().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn dash_reads_spec_from_stdin() {
+        let matches = new_app().get_matches_from(vec!["aitios-cli", "-"]);
+        assert_eq!(Some("-"), matches.value_of("SIMULATION_SPEC_FILE"));
+
+        let spec: &[u8] = b"iterations: 7\n";
+        let builder =
+            append_spec_fragment_file_or_reader(SimulationBuilder::new(), 0, "-", spec).unwrap();
+        assert_eq!(Some(7), builder.spec().iterations);
+    }
+
     #[test]
     fn test_duplicate_log_file_removal() {
         let matches = new_app().get_matches_from(vec![
@@ -1,24 +1,42 @@
 use super::msg::Msg;
-use super::Benchmark;
-use std::io::Write;
+use super::{Benchmark, Label, Summary};
+use serde_json;
+use spec::{BenchFormat, BenchSpec};
+use std::cell::RefCell;
+use std::io::{self, Write};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
 
 pub struct Bencher {
+    layout: Layout,
+    /// Persisted benchmarks, kept for the summary.
+    records: RefCell<Vec<(Label, Duration)>>,
     tx: Sender<Msg>,
     worker_handle: Option<JoinHandle<()>>,
 }
 
 impl Bencher {
     /// Spawns a new bencher with a worker thread writing benchmarks to the
-    /// specified sink.
-    pub fn new<W>(sink: W) -> Self
+    /// specified sink in the given layout.
+    pub fn new<W>(sink: W, layout: Layout) -> Self
     where
         W: Write + Send + 'static,
     {
         let (tx, rx) = channel();
-        let worker_handle = Some(spawn(move || persist_benchmarks(rx, sink)));
-        Self { tx, worker_handle }
+        let worker_handle = Some(spawn(move || persist_benchmarks(rx, sink, layout)));
+        Self {
+            layout,
+            records: RefCell::new(Vec::new()),
+            tx,
+            worker_handle,
+        }
+    }
+
+    /// Checks whether labels are persisted, e.g. to only measure finer grained
+    /// benchmarks if they can be told apart from the others.
+    pub fn is_labeled(&self) -> bool {
+        self.layout != Layout::Durations
     }
 
     /// Measures a benchmark.
@@ -34,7 +52,20 @@ impl Bencher {
             panic!("Tried to benchmark but Bencher has already been flushed.")
         }
 
-        Benchmark::new(self.tx.clone())
+        Benchmark::new(self)
+    }
+
+    /// Hands a finished benchmark to the worker thread to persist it.
+    pub fn persist(&self, duration: Duration, label: Label) {
+        self.records.borrow_mut().push((label.clone(), duration));
+        self.tx
+            .send(Msg::Persist(duration, label))
+            .expect("Could not send benchmarked time to worker");
+    }
+
+    /// Statistics over the benchmarks persisted so far, by phase.
+    pub fn summarize(&self) -> Vec<Summary> {
+        Summary::of_records(&self.records.borrow())
     }
 
     /// Finishes the benchmark and makes sure everything has been
@@ -66,17 +97,128 @@ impl Drop for Bencher {
     }
 }
 
-fn persist_benchmarks<W>(rx: Receiver<Msg>, mut sink: W)
+/// How benchmarks are written to their sink.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Layout {
+    /// One duration in seconds per line.
+    Durations,
+    /// CSV with a header and the label of each benchmark before its duration,
+    /// followed by the throughput if known.
+    LabeledCsv,
+    /// One JSON object per line with the label and duration of a benchmark.
+    Ndjson,
+}
+
+impl Layout {
+    /// Layout of benchmarks configured in the given spec.
+    pub fn for_spec(spec: &BenchSpec) -> Self {
+        match spec.format {
+            Some(BenchFormat::Ndjson) => Layout::Ndjson,
+            _ if spec.is_labeled() => Layout::LabeledCsv,
+            _ => Layout::Durations,
+        }
+    }
+}
+
+/// A labeled benchmark in NDJSON benchmarks.
+#[derive(Serialize)]
+struct Record<'a> {
+    iteration: Option<u32>,
+    phase: &'a str,
+    entity: Option<&'a str>,
+    effect: Option<&'a str>,
+    seconds: f64,
+    processed: Option<u64>,
+    per_second: Option<f64>,
+}
+
+fn persist_benchmarks<W>(rx: Receiver<Msg>, mut sink: W, layout: Layout)
 where
     W: Write,
 {
-    while let Ok(Msg::Persist(duration)) = rx.recv() {
-        let secs = duration.as_secs();
-        let nanos = duration.subsec_nanos();
+    write_header(&mut sink, layout).expect("Could not write to benchmark sink.");
+
+    while let Ok(Msg::Persist(duration, label)) = rx.recv() {
+        write_row(&mut sink, layout, duration, &label)
+            .expect("Could not write to benchmark sink.");
+        // Flush so dashboards tailing the file see records as they come in
+        if layout == Layout::Ndjson {
+            sink.flush().expect("Could not write to benchmark sink.");
+        }
+    }
+}
+
+/// Writes the column names of labeled CSV benchmarks, other layouts have no
+/// header.
+pub fn write_header<W: Write>(sink: &mut W, layout: Layout) -> io::Result<()> {
+    match layout {
+        Layout::LabeledCsv => writeln!(
+            sink,
+            "iteration,phase,entity,effect,seconds,processed,per_second"
+        ),
+        Layout::Durations | Layout::Ndjson => Ok(()),
+    }
+}
 
+/// Writes a duration in seconds, with the given label and throughput unless
+/// the layout only has durations.
+pub fn write_row<W: Write>(
+    sink: &mut W,
+    layout: Layout,
+    duration: Duration,
+    label: &Label,
+) -> io::Result<()> {
+    let seconds = as_secs(duration);
+    let per_second = label.processed.map(|p| p as f64 / seconds);
+
+    match layout {
         // Pad nanos with zeros to nine digits to make
         // a number in seconds out of it.
-        writeln!(sink, "{}.{:09}", secs, nanos).expect("Could not write to benchmark sink.");
+        Layout::Durations => writeln!(
+            sink,
+            "{}.{:09}",
+            duration.as_secs(),
+            duration.subsec_nanos()
+        ),
+        Layout::LabeledCsv => writeln!(
+            sink,
+            "{},{},{},{},{}.{:09},{},{}",
+            label.iteration.map(|i| i.to_string()).unwrap_or_default(),
+            csv_field(&label.phase),
+            csv_field(label.entity.as_ref().map(String::as_str).unwrap_or("")),
+            csv_field(label.effect.as_ref().map(String::as_str).unwrap_or("")),
+            duration.as_secs(),
+            duration.subsec_nanos(),
+            label.processed.map(|p| p.to_string()).unwrap_or_default(),
+            per_second.map(|p| format!("{:.3}", p)).unwrap_or_default()
+        ),
+        Layout::Ndjson => {
+            let record = Record {
+                iteration: label.iteration,
+                phase: &label.phase,
+                entity: label.entity.as_ref().map(String::as_str),
+                effect: label.effect.as_ref().map(String::as_str),
+                seconds,
+                processed: label.processed,
+                per_second,
+            };
+            serde_json::to_writer(&mut *sink, &record).map_err(io::Error::from)?;
+            writeln!(sink)
+        }
+    }
+}
+
+/// Fractional seconds of a duration.
+pub fn as_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9
+}
+
+/// Quotes fields with separators or quotes in them, e.g. entity names.
+pub fn csv_field(field: &str) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
     }
 }
 
@@ -95,7 +237,7 @@ mod test {
 
         {
             let csv = File::create(csv_path).expect("Could not create test CSV for benchmarking.");
-            let bencher = Bencher::new(csv);
+            let bencher = Bencher::new(csv, Layout::Durations);
 
             {
                 let _benchmark_100 = bencher.bench();
@@ -161,4 +303,55 @@ mod test {
 
         remove_file(csv_path).expect("Could not remove test CSV file.");
     }
+
+    #[test]
+    fn labeled_rows() {
+        let label = Label {
+            iteration: Some(3),
+            phase: "entity".to_string(),
+            entity: Some("0-Buddha, bronze".to_string()),
+            effect: Some("layer#1".to_string()),
+            ..Label::default()
+        };
+        let tracing = Label {
+            iteration: Some(3),
+            phase: "tracing".to_string(),
+            processed: Some(1000),
+            ..Label::default()
+        };
+
+        let mut csv = Vec::new();
+        write_header(&mut csv, Layout::LabeledCsv).unwrap();
+        write_row(&mut csv, Layout::LabeledCsv, Duration::from_millis(1500), &label).unwrap();
+        write_row(&mut csv, Layout::LabeledCsv, Duration::from_millis(500), &tracing).unwrap();
+        write_row(&mut csv, Layout::Durations, Duration::from_millis(20), &label).unwrap();
+
+        assert_eq!(
+            "iteration,phase,entity,effect,seconds,processed,per_second\n\
+             3,entity,\"0-Buddha, bronze\",layer#1,1.500000000,,\n\
+             3,tracing,,,0.500000000,1000,2000.000\n\
+             0.020000000\n",
+            String::from_utf8(csv).unwrap()
+        );
+    }
+
+    #[test]
+    fn ndjson_rows() {
+        let label = Label {
+            iteration: Some(2),
+            phase: "tracing".to_string(),
+            processed: Some(1000),
+            ..Label::default()
+        };
+
+        let mut ndjson = Vec::new();
+        write_header(&mut ndjson, Layout::Ndjson).unwrap();
+        write_row(&mut ndjson, Layout::Ndjson, Duration::from_millis(250), &label).unwrap();
+
+        assert_eq!(
+            "{\"iteration\":2,\"phase\":\"tracing\",\"entity\":null,\"effect\":null,\"seconds\":0.25,\
+             \"processed\":1000,\"per_second\":4000.0}\n",
+            String::from_utf8(ndjson).unwrap()
+        );
+    }
 }
@@ -1,36 +1,65 @@
-use super::msg::Msg;
 use bencher::Bencher;
-use std::marker::PhantomData;
-use std::sync::mpsc::Sender;
 use std::time::SystemTime;
 
 /// A benchmark running in a bencher.
-/// There is not really a reference to the bencher,
-/// but the lifetime should make sure the benchmark
-/// is dropped before the bencher.
 pub struct Benchmark<'a> {
-    bencher: PhantomData<&'a Bencher>,
+    bencher: &'a Bencher,
     start_time: SystemTime,
-    tx: Sender<Msg>,
+    label: Label,
+}
+
+/// Describes what a benchmark measured. Only written to labeled benchmarks.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Label {
+    pub iteration: Option<u32>,
+    pub phase: String,
+    pub entity: Option<String>,
+    pub effect: Option<String>,
+    /// Amount of tons or surfels processed, for throughput.
+    pub processed: Option<u64>,
 }
 
 impl<'a> Benchmark<'a> {
-    pub fn new(tx: Sender<Msg>) -> Self {
+    pub fn new(bencher: &'a Bencher) -> Self {
         Self {
-            bencher: PhantomData,
+            bencher,
             start_time: SystemTime::now(),
-            tx,
+            label: Label::default(),
         }
     }
+
+    pub fn iteration(mut self, iteration: u32) -> Self {
+        self.label.iteration = Some(iteration);
+        self
+    }
+
+    pub fn phase<S: Into<String>>(mut self, phase: S) -> Self {
+        self.label.phase = phase.into();
+        self
+    }
+
+    /// Sets the entity identifier, formatted like `{id}-{entity}` in output
+    /// patterns.
+    pub fn entity(mut self, idx: usize, name: &str) -> Self {
+        self.label.entity = Some(format!("{}-{}", idx, name));
+        self
+    }
+
+    pub fn effect<S: Into<String>>(mut self, effect: S) -> Self {
+        self.label.effect = Some(effect.into());
+        self
+    }
+
+    pub fn processed(mut self, processed: u64) -> Self {
+        self.label.processed = Some(processed);
+        self
+    }
 }
 
 impl<'a> Drop for Benchmark<'a> {
     fn drop(&mut self) {
         match self.start_time.elapsed() {
-            Ok(elapsed) => self
-                .tx
-                .send(Msg::Persist(elapsed))
-                .expect("Could not send benchmarked time to worker"),
+            Ok(elapsed) => self.bencher.persist(elapsed, self.label.clone()),
             Err(err) => error!("Benchmarking failed {}", err),
         }
     }
@@ -0,0 +1,383 @@
+use super::Label;
+use failure::Error;
+use serde_json;
+use std::f64::consts::PI;
+use std::fmt;
+use std::io::BufRead;
+
+/// Significance level for marking changes in comparisons.
+const ALPHA: f64 = 0.05;
+
+/// Before and after statistics of each phase that occurs in both of two
+/// benchmark files.
+pub struct Comparison {
+    phases: Vec<PhaseComparison>,
+}
+
+struct PhaseComparison {
+    name: String,
+    before_mean: f64,
+    after_mean: f64,
+    /// Two-sided p-value of Welch's t-test, if both have at least two samples.
+    p_value: Option<f64>,
+}
+
+/// A labeled benchmark in NDJSON benchmarks.
+#[derive(Deserialize)]
+struct JsonRecord {
+    #[serde(flatten)]
+    label: Label,
+    seconds: f64,
+}
+
+/// Reads benchmarks written in any layout. Durations without labels are
+/// assigned the phase `durations`.
+pub fn read_benchmarks<R: BufRead>(reader: R) -> Result<Vec<(Label, f64)>, Error> {
+    let mut records = Vec::new();
+    let mut lines = reader.lines().enumerate().peekable();
+
+    // Labeled CSV has a header, skip it
+    let labeled_csv = match lines.peek() {
+        Some(&(_, Ok(ref header))) => header.starts_with("iteration,"),
+        _ => false,
+    };
+    if labeled_csv {
+        lines.next();
+    }
+
+    for (idx, line) in lines {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let record = if line.starts_with('{') {
+            serde_json::from_str::<JsonRecord>(line)
+                .ok()
+                .map(|r| (r.label, r.seconds))
+        } else if labeled_csv {
+            parse_labeled_csv(line)
+        } else {
+            line.parse().ok().map(|secs| {
+                let label = Label {
+                    phase: "durations".to_string(),
+                    ..Label::default()
+                };
+                (label, secs)
+            })
+        };
+
+        records.push(
+            record.ok_or_else(|| format_err!("Line {} is not a benchmark: {}", idx + 1, line))?,
+        );
+    }
+
+    Ok(records)
+}
+
+fn parse_labeled_csv(line: &str) -> Option<(Label, f64)> {
+    // Throughput columns are optional
+    let fields = split_csv(line);
+    if fields.len() < 5 {
+        return None;
+    }
+
+    let optional = |field: &String| {
+        if field.is_empty() {
+            None
+        } else {
+            Some(field.clone())
+        }
+    };
+
+    let label = Label {
+        iteration: match fields[0].as_str() {
+            "" => None,
+            iteration => Some(iteration.parse().ok()?),
+        },
+        phase: fields[1].clone(),
+        entity: optional(&fields[2]),
+        effect: optional(&fields[3]),
+        processed: match fields.get(5).map(String::as_str) {
+            None | Some("") => None,
+            Some(processed) => Some(processed.parse().ok()?),
+        },
+    };
+    Some((label, fields[4].parse().ok()?))
+}
+
+/// Splits a CSV line into fields, honoring quotes.
+fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+
+    fields
+}
+
+impl Comparison {
+    /// Compares phases, and effects of labeled synthesis benchmarks, that
+    /// occur both before and after, in the order they occur in before.
+    pub fn new(before: &[(Label, f64)], after: &[(Label, f64)]) -> Self {
+        let before = group(before);
+        let after = group(after);
+
+        let phases = before
+            .into_iter()
+            .filter_map(|(name, before)| {
+                let after = &after.iter().find(|&&(ref n, _)| *n == name)?.1;
+                Some(PhaseComparison {
+                    name,
+                    before_mean: mean(&before),
+                    after_mean: mean(after),
+                    p_value: welch_p_value(&before, after),
+                })
+            })
+            .collect();
+
+        Comparison { phases }
+    }
+}
+
+/// Groups samples by phase and effect, like summaries.
+fn group(records: &[(Label, f64)]) -> Vec<(String, Vec<f64>)> {
+    let mut groups: Vec<(String, Vec<f64>)> = Vec::new();
+
+    for &(ref label, secs) in records {
+        let name = match label.effect {
+            Some(ref effect) => format!("{} {}", label.phase, effect),
+            None => label.phase.clone(),
+        };
+
+        match groups.iter().position(|g| g.0 == name) {
+            Some(idx) => groups[idx].1.push(secs),
+            None => groups.push((name, vec![secs])),
+        }
+    }
+
+    groups
+}
+
+fn mean(samples: &[f64]) -> f64 {
+    samples.iter().sum::<f64>() / samples.len() as f64
+}
+
+fn variance(samples: &[f64]) -> f64 {
+    let mean = mean(samples);
+    samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (samples.len() - 1) as f64
+}
+
+/// Two-sided p-value for the means of the samples being equal, without
+/// assuming equal variances.
+fn welch_p_value(a: &[f64], b: &[f64]) -> Option<f64> {
+    if a.len() < 2 || b.len() < 2 {
+        return None;
+    }
+
+    let var_a = variance(a) / a.len() as f64;
+    let var_b = variance(b) / b.len() as f64;
+    if var_a + var_b == 0.0 {
+        // Identical constant samples are not different, anything else is
+        return Some(if mean(a) == mean(b) { 1.0 } else { 0.0 });
+    }
+
+    let t = (mean(a) - mean(b)) / (var_a + var_b).sqrt();
+    let dof = (var_a + var_b).powi(2)
+        / (var_a.powi(2) / (a.len() - 1) as f64 + var_b.powi(2) / (b.len() - 1) as f64);
+
+    // Two-sided tail probability of Student's t-distribution
+    Some(incomplete_beta(dof / 2.0, 0.5, dof / (dof + t * t)))
+}
+
+/// Regularized incomplete beta function I_x(a, b), evaluated with a continued
+/// fraction as in Numerical Recipes.
+fn incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+
+    // The continued fraction converges quickly only below this point
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_fraction(a, b, x) / a
+    } else {
+        1.0 - front * beta_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+fn beta_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const TINY: f64 = 1e-30;
+    let clamp = |v: f64| if v.abs() < TINY { TINY } else { v };
+
+    let mut c = 1.0;
+    let mut d = 1.0 / clamp(1.0 - (a + b) * x / (a + 1.0));
+    let mut fraction = d;
+
+    for m in 1..200 {
+        let m = f64::from(m);
+        let even = m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m));
+        d = 1.0 / clamp(1.0 + even * d);
+        c = clamp(1.0 + even / c);
+        fraction *= d * c;
+
+        let odd = -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0));
+        d = 1.0 / clamp(1.0 + odd * d);
+        c = clamp(1.0 + odd / c);
+        let delta = d * c;
+        fraction *= delta;
+
+        if (delta - 1.0).abs() < 1e-12 {
+            break;
+        }
+    }
+
+    fraction
+}
+
+/// Natural logarithm of the gamma function with the Lanczos approximation.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let series = COEFFICIENTS
+        .iter()
+        .enumerate()
+        .fold(1.000000000190015, |sum, (idx, c)| {
+            sum + c / (x + 1.0 + idx as f64)
+        });
+
+    -tmp + (2.0 * PI).sqrt().ln() + (series / x).ln()
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.phases.is_empty() {
+            return write!(f, "No phases in common.\n");
+        }
+
+        let rows: Vec<[String; 6]> = self
+            .phases
+            .iter()
+            .map(|p| {
+                let delta = p.after_mean - p.before_mean;
+                [
+                    p.name.clone(),
+                    format!("{:.3}s", p.before_mean),
+                    format!("{:.3}s", p.after_mean),
+                    format!("{:+.3}s", delta),
+                    format!("{:+.1}%", delta / p.before_mean * 100.0),
+                    match p.p_value {
+                        Some(p) if p < ALPHA => format!("{:.3} significant", p),
+                        Some(p) => format!("{:.3}", p),
+                        None => String::from("too few samples"),
+                    },
+                ]
+            })
+            .collect();
+
+        let header = [
+            String::from("Phase"),
+            String::from("Before"),
+            String::from("After"),
+            String::from("Delta"),
+            String::from("Change"),
+            String::from("p-value"),
+        ];
+
+        // Left-align the names, right-align the numbers, except the last column
+        let widths: Vec<usize> = (0..5)
+            .map(|col| {
+                rows.iter()
+                    .chain(Some(&header))
+                    .map(|r| r[col].chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        for row in Some(&header).into_iter().chain(rows.iter()) {
+            write!(
+                f,
+                "{:w0$}  {:>w1$}  {:>w2$}  {:>w3$}  {:>w4$}  {}\n",
+                row[0],
+                row[1],
+                row[2],
+                row[3],
+                row[4],
+                row[5],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2],
+                w3 = widths[3],
+                w4 = widths[4]
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_all_layouts() {
+        let csv = "iteration,phase,entity,effect,seconds\n\
+                   1,entity,\"0-a, b\",layer#1,0.5\n\
+                   1,tracing,,,1.25\n";
+        let records = read_benchmarks(csv.as_bytes()).unwrap();
+        assert_eq!(2, records.len());
+        assert_eq!(Some("0-a, b".to_string()), records[0].0.entity);
+        assert_eq!(1.25, records[1].1);
+
+        let ndjson = "{\"iteration\":1,\"phase\":\"tracing\",\"entity\":null,\"effect\":null,\
+                      \"seconds\":1.25}\n";
+        let records = read_benchmarks(ndjson.as_bytes()).unwrap();
+        assert_eq!("tracing", records[0].0.phase);
+        assert_eq!(1.25, records[0].1);
+
+        let durations = "0.500000000\n1.250000000\n";
+        let records = read_benchmarks(durations.as_bytes()).unwrap();
+        assert_eq!("durations", records[1].0.phase);
+        assert_eq!(1.25, records[1].1);
+    }
+
+    #[test]
+    fn significance() {
+        let before = [1.0, 1.1, 0.9, 1.05, 0.95];
+        let slower = [2.0, 2.1, 1.9, 2.05, 1.95];
+        let same = [1.02, 1.08, 0.92, 1.0, 0.98];
+
+        assert!(welch_p_value(&before, &slower).unwrap() < 0.001);
+        assert!(welch_p_value(&before, &same).unwrap() > 0.5);
+        // t = 2.0 with 10 degrees of freedom, from a t-table
+        let p = incomplete_beta(5.0, 0.5, 10.0 / 14.0);
+        assert!((p - 0.0734).abs() < 1e-3, "p-value was {}", p);
+    }
+}
@@ -1,6 +1,10 @@
 mod bencher;
 mod benchmark;
+mod compare;
 mod msg;
+mod summary;
 
-pub use self::bencher::Bencher;
-pub use self::benchmark::Benchmark;
+pub use self::bencher::{as_secs, csv_field, write_header, write_row, Bencher, Layout};
+pub use self::benchmark::{Benchmark, Label};
+pub use self::compare::{read_benchmarks, Comparison};
+pub use self::summary::{write_summaries, Summary};
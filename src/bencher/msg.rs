@@ -1,6 +1,7 @@
+use super::Label;
 use std::time::Duration;
 
 pub enum Msg {
     Done,
-    Persist(Duration),
+    Persist(Duration, Label),
 }
@@ -0,0 +1,133 @@
+use super::{as_secs, Label, Layout};
+use serde_json;
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// Statistics over all benchmarks of a phase, in seconds.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Summary {
+    pub phase: String,
+    /// Set for the effect and entity phases of labeled synthesis benchmarks,
+    /// which are summarized per effect.
+    pub effect: Option<String>,
+    pub count: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub p95: f64,
+}
+
+impl Summary {
+    /// Summarizes the given benchmarks, grouped by phase and effect, in the
+    /// order the groups were first measured.
+    pub fn of_records(records: &[(Label, Duration)]) -> Vec<Summary> {
+        let mut groups: Vec<(&str, Option<&str>, Vec<f64>)> = Vec::new();
+
+        for &(ref label, duration) in records {
+            let phase = label.phase.as_str();
+            let effect = label.effect.as_ref().map(String::as_str);
+            let secs = as_secs(duration);
+
+            match groups.iter().position(|g| g.0 == phase && g.1 == effect) {
+                Some(idx) => groups[idx].2.push(secs),
+                None => groups.push((phase, effect, vec![secs])),
+            }
+        }
+
+        groups
+            .into_iter()
+            .map(|(phase, effect, secs)| Summary::of_durations(phase, effect, secs))
+            .collect()
+    }
+
+    fn of_durations(phase: &str, effect: Option<&str>, mut secs: Vec<f64>) -> Summary {
+        secs.sort_by(|a, b| a.partial_cmp(b).expect("Durations are never NaN"));
+
+        let count = secs.len();
+        let mean = secs.iter().sum::<f64>() / count as f64;
+        let median = if count % 2 == 0 {
+            (secs[count / 2 - 1] + secs[count / 2]) / 2.0
+        } else {
+            secs[count / 2]
+        };
+        // Nearest rank, so with less than 20 samples this is the maximum
+        let p95 = secs[((count as f64 * 0.95).ceil() as usize).max(1) - 1];
+
+        Summary {
+            phase: phase.to_string(),
+            effect: effect.map(String::from),
+            count,
+            mean,
+            median,
+            p95,
+        }
+    }
+
+    /// Name of the phase and effect, if any, e.g. `effect layer#1`.
+    pub fn name(&self) -> String {
+        match self.effect {
+            Some(ref effect) => format!("{} {}", self.phase, effect),
+            None => self.phase.clone(),
+        }
+    }
+}
+
+/// Writes the given summaries as NDJSON if that is the layout, otherwise as
+/// CSV with a header.
+pub fn write_summaries<W: Write>(
+    sink: &mut W,
+    layout: Layout,
+    summaries: &[Summary],
+) -> io::Result<()> {
+    if layout != Layout::Ndjson {
+        writeln!(sink, "phase,effect,count,mean,median,p95")?;
+    }
+
+    for summary in summaries {
+        match layout {
+            Layout::Ndjson => {
+                serde_json::to_writer(&mut *sink, summary).map_err(io::Error::from)?;
+                writeln!(sink)?;
+            }
+            Layout::Durations | Layout::LabeledCsv => writeln!(
+                sink,
+                "{},{},{},{:.9},{:.9},{:.9}",
+                summary.phase,
+                summary.effect.as_ref().map(String::as_str).unwrap_or(""),
+                summary.count,
+                summary.mean,
+                summary.median,
+                summary.p95
+            )?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn summarize_by_phase() {
+        let label = |phase: &str| Label {
+            phase: phase.to_string(),
+            ..Label::default()
+        };
+        let mut records: Vec<_> = (1..21)
+            .map(|secs| (label("tracing"), Duration::from_secs(secs)))
+            .collect();
+        records.push((label("synthesis"), Duration::from_millis(500)));
+
+        let summaries = Summary::of_records(&records);
+
+        assert_eq!(2, summaries.len());
+        assert_eq!("tracing", summaries[0].name());
+        assert_eq!(20, summaries[0].count);
+        assert_eq!(10.5, summaries[0].mean);
+        assert_eq!(10.5, summaries[0].median);
+        assert_eq!(19.0, summaries[0].p95);
+        assert_eq!(0.5, summaries[1].median);
+        assert_eq!(0.5, summaries[1].p95);
+    }
+}
@@ -1,48 +1,96 @@
-use spec::{BenchSpec, SimulationSpec};
+use spec::{BenchSpec, SimulationSpec, SubstanceRange};
+use std::collections::HashMap;
+use std::fmt::Debug;
 use std::path::PathBuf;
 
 pub fn append(first: SimulationSpec, second: &SimulationSpec) -> SimulationSpec {
-    if first.iterations.is_some()
-        && second.iterations.is_some()
-        && first.iterations.unwrap() != second.iterations.unwrap()
-    {
-        warn!(
-            "Merging simulations specs, one has {first} iterations, but the second has {second} iterations. Using {second} for combined iterations",
-            first = first.iterations.unwrap(),
-            second = second.iterations.unwrap(),
-        )
-    }
-
     SimulationSpec {
         name: append_textual(&first.name, &second.name, "-"),
         description: append_textual(&first.description, &second.description, "\n\n"),
         scenes: append_list(first.scenes, second.scenes.iter()),
-        iterations: second.iterations.or(first.iterations),
-        effect_interval: second.effect_interval.or(first.effect_interval),
+        occluders: append_list(first.occluders, second.occluders.iter()),
+        scene_loading: append_setting("scene_loading", first.scene_loading, second.scene_loading),
+        iterations: append_setting("iterations", first.iterations, second.iterations),
+        effect_interval: append_setting(
+            "effect_interval",
+            first.effect_interval,
+            second.effect_interval,
+        ),
+        effects_at: append_setting("effects_at", first.effects_at, second.effects_at.clone()),
         log: append_log(first.log, &second.log),
-        surfel_distance: append_surfel_distance(first.surfel_distance, second.surfel_distance),
+        manifest: append_setting("manifest", first.manifest, second.manifest.clone()),
+        output_root: append_setting("output_root", first.output_root, second.output_root.clone()),
+        output_base: append_setting("output_base", first.output_base, second.output_base),
+        output_tree: append_setting("output_tree", first.output_tree, second.output_tree.clone()),
+        output_retry: append_setting("output_retry", first.output_retry, second.output_retry),
+        overwrite: append_setting("overwrite", first.overwrite, second.overwrite),
+        disk_space_check: append_setting(
+            "disk_space_check",
+            first.disk_space_check,
+            second.disk_space_check,
+        ),
+        surfel_distance: append_setting(
+            "surfel_distance",
+            first.surfel_distance,
+            second.surfel_distance,
+        ),
+        surfel_sampling: append_setting(
+            "surfel_sampling",
+            first.surfel_sampling,
+            second.surfel_sampling,
+        ),
         sources: append_list(first.sources, &second.sources),
-        surfels_by_material: {
-            let mut first = first.surfels_by_material;
-            let second = &second.surfels_by_material;
-            first.extend(second.clone().into_iter());
-            first
-        },
+        surfels_by_material: append_surfels_by_material(
+            first.surfels_by_material,
+            &second.surfels_by_material,
+        ),
+        material_map: append_material_map(first.material_map, &second.material_map),
+        entities: append_setting("entities", first.entities, second.entities.clone()),
+        substances: append_substance_ranges(first.substances, &second.substances),
         effects: append_list(first.effects, second.effects.iter()),
         benchmark: append_benchmark(&first.benchmark, &second.benchmark),
-        transport: second.transport.or(first.transport),
-        flat_filtering: second.flat_filtering.or(first.flat_filtering),
+        transport: append_setting("transport", first.transport, second.transport),
+        wind: append_setting("wind", first.wind, second.wind),
+        flat_filtering: append_setting(
+            "flat_filtering",
+            first.flat_filtering,
+            second.flat_filtering,
+        ),
+        mipmaps: append_setting("mipmaps", first.mipmaps, second.mipmaps),
+        normal_convention: append_setting(
+            "normal_convention",
+            first.normal_convention,
+            second.normal_convention,
+        ),
+        uv_overlaps: append_setting("uv_overlaps", first.uv_overlaps, second.uv_overlaps),
+        auto_unwrap: append_setting("auto_unwrap", first.auto_unwrap, second.auto_unwrap),
         rules: append_list(first.rules, second.rules.iter()),
+        material_swaps: append_list(first.material_swaps, second.material_swaps.iter()),
+        injections: append_list(first.injections, &second.injections),
+        initial_surfels: append_setting(
+            "initial_surfels",
+            first.initial_surfels,
+            second.initial_surfels.clone(),
+        ),
+        seed: append_setting("seed", first.seed, second.seed),
+        notify: append_setting("notify", first.notify, second.notify.clone()),
     }
 }
 
-fn append_surfel_distance(first: Option<f32>, second: Option<f32>) -> Option<f32> {
+/// Uses the second value if set, otherwise the first one, warning if both are
+/// set to different values.
+fn append_setting<T>(key: &str, first: Option<T>, second: Option<T>) -> Option<T>
+where
+    T: PartialEq + Debug,
+{
     match (first, second) {
         (Some(first), Some(second)) => {
             if first != second {
                 warn!(
-                    "Conflicting surfel distances from simulation fragments: {first} and {second}. Using {second}."
-                    ,first = first, second = second
+                    "Merging simulation specs and {key} has value {first:?} in one and {second:?} in the other. Using {second:?} in merged spec.",
+                    key = key,
+                    first = first,
+                    second = second
                 );
             }
             Some(second)
@@ -51,6 +99,63 @@ fn append_surfel_distance(first: Option<f32>, second: Option<f32>) -> Option<f32
     }
 }
 
+fn append_surfels_by_material(
+    mut first: HashMap<String, String>,
+    second: &HashMap<String, String>,
+) -> HashMap<String, String> {
+    for (material, second_spec) in second.iter() {
+        if let Some(first_spec) = first.insert(material.clone(), second_spec.clone()) {
+            if &first_spec != second_spec {
+                warn!(
+                    "Merging simulation specs and material {material} has surfel spec {first:?} in one and {second:?} in the other. Using {second:?} in merged spec.",
+                    material = material,
+                    first = first_spec,
+                    second = second_spec
+                );
+            }
+        }
+    }
+    first
+}
+
+fn append_material_map(
+    mut first: HashMap<String, String>,
+    second: &HashMap<String, String>,
+) -> HashMap<String, String> {
+    for (old_name, second_name) in second.iter() {
+        if let Some(first_name) = first.insert(old_name.clone(), second_name.clone()) {
+            if &first_name != second_name {
+                warn!(
+                    "Merging simulation specs and material {material} is renamed to {first:?} in one and {second:?} in the other. Using {second:?} in merged spec.",
+                    material = old_name,
+                    first = first_name,
+                    second = second_name
+                );
+            }
+        }
+    }
+    first
+}
+
+fn append_substance_ranges(
+    mut first: HashMap<String, SubstanceRange>,
+    second: &HashMap<String, SubstanceRange>,
+) -> HashMap<String, SubstanceRange> {
+    for (substance, second_range) in second.iter() {
+        if let Some(first_range) = first.insert(substance.clone(), *second_range) {
+            if &first_range != second_range {
+                warn!(
+                    "Merging simulation specs and substance {substance} has range {first:?} in one and {second:?} in the other. Using {second:?} in merged spec.",
+                    substance = substance,
+                    first = first_range,
+                    second = second_range
+                );
+            }
+        }
+    }
+    first
+}
+
 fn append_textual(first: &str, second: &str, delimiter: &str) -> String {
     match (first.trim(), second.trim()) {
         ("", "") => String::new(),
@@ -125,9 +230,34 @@ fn append_benchmark(first: &Option<BenchSpec>, second: &Option<BenchSpec>) -> Op
             tracing: second_or_first(&first.tracing, &second.tracing),
             synthesis: second_or_first(&first.synthesis, &second.synthesis),
             setup: second_or_first(&first.setup, &second.setup),
+            summary: second_or_first(&first.summary, &second.summary),
+            labeled: second.labeled.or(first.labeled),
+            format: second.format.or(first.format),
         }),
         (Some(spec), None) => Some(spec.clone()),
         (None, Some(spec)) => Some(spec.clone()),
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_yaml;
+    use spec::Transport;
+
+    #[test]
+    fn second_overrides_settings() {
+        let first: SimulationSpec =
+            serde_yaml::from_str("iterations: 3\ntransport: classic\nflat_filtering: true").unwrap();
+        let second: SimulationSpec =
+            serde_yaml::from_str("transport: conserving\neffect_interval: 2").unwrap();
+
+        let merged = append(first, &second);
+
+        assert_eq!(Some(3), merged.iterations);
+        assert_eq!(Some(2), merged.effect_interval);
+        assert_eq!(Some(Transport::Conserving), merged.transport);
+        assert_eq!(Some(true), merged.flat_filtering);
+    }
+}
@@ -1,13 +1,26 @@
-use builder::{append, canonicalize, instantiate, Error, ResolveErrorKind};
+use builder::canonicalize::resolve_scenes;
+use builder::instantiate::load_surfel_snapshot;
+use builder::output_tree::tree_outputs;
+use builder::parse::parse_spec;
+use builder::TonSourceFactory;
+use builder::{
+    append, canonicalize, canonicalize_outputs, inspect, instantiate, pack, unpack, Error,
+    Inspection, ResolveErrorKind,
+};
 use chrono::*;
-use files::Resolver;
-use runner::SimulationRunner;
-use serde_yaml;
-use spec::SimulationSpec;
+use files::{OutputResolver, Resolver};
+use profiler::Profiler;
+use runner::{SimulationRunner, SurfelSnapshot};
+use spec::{OutputBase, SceneSpec, SimulationSpec};
+use std::collections::HashMap;
 use std::default::Default;
 use std::env::current_dir;
+#[cfg(feature = "http")]
+use std::env::temp_dir;
 use std::fs::File;
-use std::path::Path;
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 pub struct SimulationBuilder {
     spec: SimulationSpec,
@@ -18,6 +31,17 @@ pub struct SimulationBuilder {
     ///    in the order they were added.
     resolv: Resolver,
     creation_time: DateTime<Local>,
+    profiler: Option<Rc<Profiler>>,
+    /// Overrides the output root of the spec if set.
+    output_root: Option<PathBuf>,
+    /// Replaces the scenes of all spec fragments if set.
+    scenes: Option<Vec<SceneSpec>>,
+    /// Substances to trace and synthesize, all if `None`.
+    substances: Option<Vec<String>>,
+    /// Surfel state to restore after sampling, with the path it was read from.
+    surfel_snapshot: Option<(PathBuf, SurfelSnapshot)>,
+    strict: bool,
+    source_factories: HashMap<String, Box<TonSourceFactory>>,
 }
 
 /// Builds simulations from specifications or specification fragments stored in files
@@ -31,6 +55,13 @@ impl SimulationBuilder {
             spec: Default::default(),
             resolv: local_resolver(),
             creation_time: Local::now(),
+            profiler: None,
+            output_root: None,
+            scenes: None,
+            substances: None,
+            surfel_snapshot: None,
+            strict: false,
+            source_factories: HashMap::new(),
         }
     }
 
@@ -45,7 +76,6 @@ impl SimulationBuilder {
     /// 2. relative to current working directory,
     /// 3. relative to directories added with this function,
     /// 4. relative to directory that contains current simulation spec fragment, if adding with a path.
-    #[allow(unused)]
     pub fn add_base_path<P>(mut self, base: P) -> Result<Self, Error>
     where
         P: AsRef<Path>,
@@ -86,20 +116,21 @@ impl SimulationBuilder {
     where
         P: AsRef<Path>,
     {
-        let simulation_spec_file = simulation_spec_file.as_ref();
-
-        // Resolve relative to cwd and relative to this spec.
-        let resolv = self.resolver_for(&simulation_spec_file)?;
-
-        // Allow relative files relative to parent of spec.
-        let spec_path = resolv
+        // Resolve the spec itself first, since it may be a URL that
+        // has no parent directory before downloading it.
+        let spec_path = self
+            .resolv
             .resolve(simulation_spec_file)
             .map_err(|e| Error::resolve(e, ResolveErrorKind::Simulation))?;
 
-        let spec = serde_yaml::from_reader(
+        // Resolve relative to cwd and relative to this spec.
+        let resolv = self.resolver_for(&spec_path)?;
+
+        let spec = parse_spec(
             // The resolved path should be always openable,
             // except with permission errors
             File::open(&spec_path)?,
+            self.strict,
         )?;
 
         // Resolve relative paths in the spec to absolute ones with a temporary
@@ -107,19 +138,80 @@ impl SimulationBuilder {
         // into account.
         // Not modifying self.resolv avoids hard to track down bugs when files
         // are resolved relative to some earlier spec.
-        let spec = canonicalize(spec, &resolv)?;
+        let mut spec = self.canonicalize_fragment(spec, &resolv)?;
+
+        // Output roots in files are relative to the file, outputs themselves
+        // are only resolved when building.
+        if let Some(spec_dir) = spec_path.parent() {
+            match spec.output_root {
+                Some(ref mut root) => *root = spec_dir.join(&root),
+                None if spec.output_base == Some(OutputBase::SpecDir) => {
+                    spec.output_root = Some(spec_dir.to_path_buf())
+                }
+                None => (),
+            }
+        }
+
+        self.append_spec_fragment(&spec)
+    }
+
+    /// Extracts a simulation archive written with `pack` into the given directory
+    /// and appends the contained spec.
+    ///
+    /// The extracted files must remain in place until the simulation has run.
+    pub fn append_archive_file<P, Q>(self, archive: P, extract_to: Q) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let archive = self
+            .resolv
+            .resolve(archive)
+            .map_err(|e| Error::resolve(e, ResolveErrorKind::Simulation))?;
 
+        let spec_path = unpack(File::open(archive)?, extract_to)?;
+        self.append_spec_fragment_file(spec_path)
+    }
+
+    /// Appends a simulation spec read from the given reader, e.g. standard input.
+    /// Relative paths are resolved like for inline specs, that is, not relative to
+    /// any spec file.
+    pub fn append_spec_fragment_reader<R>(self, spec: R) -> Result<Self, Error>
+    where
+        R: Read,
+    {
+        let spec = parse_spec(spec, self.strict)?;
+        let spec = self.canonicalize_fragment(spec, &self.resolv)?;
         self.append_spec_fragment(&spec)
     }
 
     pub fn append_spec_fragment_str(self, spec: &str) -> Result<Self, Error> {
-        let spec = serde_yaml::from_str(spec)?;
-        let spec = canonicalize(spec, &self.resolv)?;
+        let spec = parse_spec(spec.as_bytes(), self.strict)?;
+        let spec = self.canonicalize_fragment(spec, &self.resolv)?;
         self.append_spec_fragment(&spec)
     }
 
+    /// Makes paths in a spec fragment absolute, leaving out its scenes if they
+    /// are replaced anyway, so that they do not need to exist.
+    fn canonicalize_fragment(
+        &self,
+        mut spec: SimulationSpec,
+        resolver: &Resolver,
+    ) -> Result<SimulationSpec, Error> {
+        if self.scenes.is_some() {
+            spec.scenes.clear();
+        }
+        canonicalize(spec, resolver)
+    }
+
     pub fn append_spec_fragment(mut self, spec: &SimulationSpec) -> Result<Self, Error> {
+        if spec.output_base == Some(OutputBase::SpecDir) && spec.output_root.is_none() {
+            warn!("Spec with output_base: spec_dir is not read from a file, writing relative to the working directory.");
+        }
         self.spec = append(self.spec, spec);
+        if let Some(ref scenes) = self.scenes {
+            self.spec.scenes = scenes.clone();
+        }
         Ok(self)
     }
 
@@ -128,13 +220,182 @@ impl SimulationBuilder {
         &self.spec
     }
 
+    /// Downloads `http://` and `https://` scenes, specs and texture samples
+    /// referenced after this call into the given directory, instead of
+    /// `aitios-downloads` in the temporary directory of the system.
+    ///
+    /// Files that are already in the cache are not downloaded again.
+    #[cfg(feature = "http")]
+    pub fn download_cache<P>(mut self, cache: P) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        self.resolv
+            .set_download_cache(cache)
+            .map_err(|e| Error::resolve(e, ResolveErrorKind::DownloadCache))?;
+
+        Ok(self)
+    }
+
+    /// Records spans of loading, surfel sampling, surfel table building,
+    /// tracing and each effect with the given profiler, both while
+    /// building and while running the simulation.
+    pub fn profiler(mut self, profiler: Rc<Profiler>) -> Self {
+        self.profiler = Some(profiler);
+        self
+    }
+
+    /// In strict mode, unknown keys in simulation, surfel and ton source specs,
+    /// unknown placeholders in output patterns and surfel specs for materials
+    /// that no scene uses are errors instead of being ignored or warned about.
+    ///
+    /// Affects only spec fragments appended after enabling it.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Writes outputs relative to the given directory, overriding the
+    /// `output_root` of the spec. Relative roots are relative to the working
+    /// directory.
+    pub fn output_root<P: Into<PathBuf>>(mut self, root: P) -> Self {
+        self.output_root = Some(root.into());
+        self
+    }
+
+    /// Simulates the given scenes instead of the ones in the spec, also
+    /// replacing the scenes of spec fragments appended later. Relative paths
+    /// and globs are resolved like scenes in specs.
+    pub fn scenes<I, P>(mut self, scenes: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        let mut scenes = scenes
+            .into_iter()
+            .map(|path| SceneSpec::Path(path.into()))
+            .collect();
+        resolve_scenes(&mut scenes, &self.resolv)?;
+
+        self.spec.scenes = scenes.clone();
+        self.scenes = Some(scenes);
+        Ok(self)
+    }
+
+    /// Only transports the substances with the given names and only writes
+    /// density and layer outputs for them, e.g. to debug a single weathering
+    /// sign faster.
+    pub fn substances<I, S>(mut self, substances: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.substances = Some(substances.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Restores the concentrations of the surfels from a snapshot written by
+    /// `dump_surfels` into a `.bin` file, instead of starting with the
+    /// concentrations of the surfel specs or `initial_surfels` of the spec.
+    /// The scenes and surfel sampling must be the same as when the snapshot
+    /// was taken.
+    pub fn from_surfels<P: AsRef<Path>>(mut self, snapshot: P) -> Result<Self, Error> {
+        let path = self
+            .resolv
+            .resolve(snapshot)
+            .map_err(|e| Error::resolve(e, ResolveErrorKind::SurfelSnapshot))?;
+        let snapshot = load_surfel_snapshot(&path)?;
+        self.surfel_snapshot = Some((path, snapshot));
+        Ok(self)
+    }
+
+    /// Iteration after which the snapshot set with `from_surfels` was taken.
+    pub fn snapshot_iteration(&self) -> Option<u32> {
+        self.surfel_snapshot.as_ref().map(|&(_, ref s)| s.iteration)
+    }
+
+    /// Resolves outputs relative to the output root set on the builder, the
+    /// one in the spec, or the working directory, in this order.
+    fn output_resolver(&self) -> Result<OutputResolver, Error> {
+        let root = self.output_root.as_ref().or(self.spec.output_root.as_ref());
+        let outputs = match root {
+            Some(root) => OutputResolver::new(root)?,
+            None => OutputResolver::working_dir()?,
+        };
+        Ok(outputs)
+    }
+
+    /// Absolute path of the log file in the spec, if any, with the
+    /// `{datetime}` placeholder not yet substituted.
+    pub fn log_path(&self) -> Result<Option<PathBuf>, Error> {
+        let outputs = self.output_resolver()?;
+        Ok(self.spec.log.as_ref().map(|log| outputs.resolve(log)))
+    }
+
+    /// Uses the given factory to build ton sources from source specs with the
+    /// given `type`, e.g. `type: sphere`. Source specs without a type or with
+    /// `type: mesh` are built-in mesh-shaped sources.
+    pub fn source_factory<S, F>(mut self, kind: S, factory: F) -> Self
+    where
+        S: Into<String>,
+        F: TonSourceFactory + 'static,
+    {
+        self.source_factories.insert(kind.into(), Box::new(factory));
+        self
+    }
+
+    /// Gets the paths that are checked in order when resolving the given path
+    /// relative to the current working directory and added base paths, each
+    /// with its canonicalized form if it exists.
+    pub fn trace_resolve<P: AsRef<Path>>(&self, path: P) -> Vec<(PathBuf, Option<PathBuf>)> {
+        self.resolv.trace(path)
+    }
+
     /// Time of instantiation of this builder.
     pub fn creation_time(&self) -> DateTime<Local> {
         self.creation_time
     }
 
+    /// Uses the given time instead of the time of instantiation, e.g. for
+    /// the `{datetime}` placeholder in output patterns.
+    pub fn created_at(mut self, time: DateTime<Local>) -> Self {
+        self.creation_time = time;
+        self
+    }
+
+    /// Limits the textures synthesized by the effects appended so far to at
+    /// most `max` texels along each side, e.g. for fast regression tests.
+    pub fn limit_resolution(mut self, max: usize) -> Self {
+        for effect in self.spec.effects.iter_mut() {
+            effect.limit_resolution(max);
+        }
+        self
+    }
+
+    /// Loads the scenes and specs referenced so far and reports which surfel specs
+    /// and effects apply to which entity, without building the simulation.
+    pub fn inspect(&self) -> Result<Inspection, Error> {
+        inspect(&self.spec, &self.resolv)
+    }
+
+    /// Writes the spec merged so far into a zip archive, together with all
+    /// files it references, e.g. for bug reports or to run it on a render farm.
+    pub fn pack<W: Write + Seek>(&self, out: W) -> Result<(), Error> {
+        pack(&self.spec, &self.resolv, out)
+    }
+
     pub fn build(self) -> Result<SimulationRunner, Error> {
-        instantiate(self.spec, &self.resolv, self.creation_time)
+        let outputs = self.output_resolver()?;
+        instantiate(
+            canonicalize_outputs(tree_outputs(self.spec), &outputs),
+            &self.resolv,
+            self.creation_time,
+            self.profiler,
+            self.strict,
+            &self.source_factories,
+            self.substances.as_ref(),
+            self.surfel_snapshot.as_ref(),
+        )
     }
 }
 
@@ -151,6 +412,13 @@ fn local_resolver() -> Resolver {
         .add_base(current_dir().expect("Could not get current working directory."))
         .expect("Could not resolve current working directory.");
 
+    // If the temporary directory is not writable, resolving URLs fails
+    // later with a hint to configure a download cache.
+    #[cfg(feature = "http")]
+    {
+        resolv.set_download_cache(temp_dir().join("aitios-downloads")).ok();
+    }
+
     resolv
 }
 
@@ -166,4 +434,64 @@ mod test {
 
         assert_eq!("Funny Test Simulation", &builder.spec().name)
     }
+
+    #[test]
+    fn append_reader() {
+        let builder = SimulationBuilder::new()
+            .append_spec_fragment_reader("name: Piped Test Simulation".as_bytes())
+            .unwrap();
+
+        assert_eq!("Piped Test Simulation", &builder.spec().name)
+    }
+
+    #[test]
+    fn scenes_replace_spec_scenes() {
+        let builder = SimulationBuilder::new()
+            .scenes(vec!["tests/assets/sky.obj"])
+            .unwrap()
+            .append_spec_fragment_file("tests/examples/simulation.yml")
+            .unwrap();
+
+        assert_eq!(
+            vec![SceneSpec::Path(
+                current_dir().unwrap().join("tests/assets/sky.obj").canonicalize().unwrap()
+            )],
+            builder.spec().scenes
+        );
+        // Replaced scenes do not need to exist
+        assert!(builder.append_spec_fragment_str("scenes: [missing.obj]").is_ok());
+        assert!(SimulationBuilder::new().scenes(vec!["missing.obj"]).is_err());
+    }
+
+    #[test]
+    fn outputs_relative_to_spec_dir() {
+        use std::env::temp_dir;
+        use std::fs::{create_dir_all, remove_dir_all};
+
+        let dir = temp_dir().join("aitios-output-base-test");
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        let spec = dir.join("spec.yml");
+        File::create(&spec)
+            .unwrap()
+            .write_all(b"log: logs/run.log\noutput_base: spec_dir\n")
+            .unwrap();
+
+        let builder = SimulationBuilder::new()
+            .append_spec_fragment_file(&spec)
+            .unwrap();
+        assert_eq!(
+            Some(dir.canonicalize().unwrap().join("logs/run.log")),
+            builder.log_path().unwrap()
+        );
+
+        // Explicit output roots take precedence
+        let builder = builder.output_root("elsewhere");
+        assert_eq!(
+            Some(current_dir().unwrap().canonicalize().unwrap().join("elsewhere/logs/run.log")),
+            builder.log_path().unwrap()
+        );
+
+        remove_dir_all(&dir).unwrap();
+    }
 }
@@ -1,32 +1,90 @@
 use builder::{Error, ResolveErrorKind};
-use files::Resolver;
-use spec::{EffectSpec, SimulationSpec, Stop};
+use files::{OutputResolver, Resolver};
+use spec::{EffectSpec, MaterialSwapSpec, SceneSpec, SimulationSpec, Stop};
 use std::collections::HashMap;
 use std::path::PathBuf;
 
 /// Makes relative paths in the spec fragment absolute using the given resolver.
 ///
+/// Glob patterns in scenes, ton source specs and texture samples are expanded to
+/// all matching files.
+///
 /// Useful when combining specs with slightly different base paths for resolving.
 pub fn canonicalize(
     mut spec: SimulationSpec,
     resolver: &Resolver,
 ) -> Result<SimulationSpec, Error> {
     resolve_scenes(&mut spec.scenes, resolver)?;
+    resolve_scenes(&mut spec.occluders, resolver)?;
     resolve_ton_source_specs(&mut spec.sources, resolver)?;
     resolve_surfel_specs(&mut spec.surfels_by_material, resolver)?;
+    resolve_swap_surfel_specs(&mut spec.material_swaps, resolver)?;
     resolve_effect_spec_paths(&mut spec.effects, resolver)?;
-    // FIXME resolving outputs works differently
-    // resolve_benchmarks(&mut spec.benchmark, resolver)?;
+    resolve_injections(&mut spec.injections, resolver)?;
+    if let Some(ref mut initial_surfels) = spec.initial_surfels {
+        *initial_surfels = resolver
+            .resolve(&initial_surfels)
+            .map_err(|e| Error::resolve(e, ResolveErrorKind::SurfelSnapshot))?;
+    }
     Ok(spec)
 }
 
-fn resolve_scenes(scenes: &mut Vec<PathBuf>, resolver: &Resolver) -> Result<(), Error> {
-    for scene in scenes.iter_mut() {
-        *scene = resolver
-            .resolve(&scene)
-            .map_err(|e| Error::resolve(e, ResolveErrorKind::Scene))?;
+/// Makes output patterns of effects, the log file, the manifest and benchmark
+/// files absolute using the given output resolver.
+///
+/// Outputs are resolved once for the merged spec rather than per fragment, so
+/// that all of them end up relative to the same output root. Since resolved
+/// patterns are normalized, the collision check before running also catches
+/// outputs that are spelled differently, e.g. `out/a.png` and `./out/a.png`.
+pub fn canonicalize_outputs(mut spec: SimulationSpec, outputs: &OutputResolver) -> SimulationSpec {
+    let resolve = |path: &str| outputs.resolve(path).to_string_lossy().into_owned();
+
+    for effect in spec.effects.iter_mut() {
+        for pattern in effect.output_patterns_mut() {
+            *pattern = resolve(pattern);
+        }
+    }
+
+    if let Some(ref mut log) = spec.log {
+        *log = outputs.resolve(&log);
+    }
+
+    if let Some(ref mut manifest) = spec.manifest {
+        *manifest = outputs.resolve(&manifest);
     }
 
+    if let Some(ref mut benchmark) = spec.benchmark {
+        let benches = vec![
+            &mut benchmark.iterations,
+            &mut benchmark.tracing,
+            &mut benchmark.synthesis,
+            &mut benchmark.setup,
+            &mut benchmark.summary,
+        ];
+
+        for bench in benches.into_iter().filter_map(|b| b.as_mut()) {
+            *bench = outputs.resolve(&bench);
+        }
+    }
+
+    spec
+}
+
+/// Makes the scene paths absolute, with globs expanded to all matching files.
+pub fn resolve_scenes(scenes: &mut Vec<SceneSpec>, resolver: &Resolver) -> Result<(), Error> {
+    let mut resolved = Vec::with_capacity(scenes.len());
+    for scene in scenes.iter() {
+        // Every match of a glob gets the instances of the pattern
+        resolved.extend(
+            resolver
+                .resolve_glob(scene.path())
+                .map_err(|e| Error::resolve(e, ResolveErrorKind::Scene))?
+                .into_iter()
+                .map(|path| scene.with_path(path)),
+        );
+    }
+
+    *scenes = resolved;
     Ok(())
 }
 
@@ -34,12 +92,16 @@ fn resolve_ton_source_specs(
     source_spec_paths: &mut Vec<PathBuf>,
     resolver: &Resolver,
 ) -> Result<(), Error> {
-    for spec in source_spec_paths.iter_mut() {
-        *spec = resolver
-            .resolve(&spec)
-            .map_err(|e| Error::resolve(e, ResolveErrorKind::TonSourceSpec))?;
+    let mut resolved = Vec::with_capacity(source_spec_paths.len());
+    for spec in source_spec_paths.iter() {
+        resolved.extend(
+            resolver
+                .resolve_glob(&spec)
+                .map_err(|e| Error::resolve(e, ResolveErrorKind::TonSourceSpec))?,
+        );
     }
 
+    *source_spec_paths = resolved;
     Ok(())
 }
 
@@ -59,6 +121,30 @@ fn resolve_surfel_specs(
     Ok(())
 }
 
+fn resolve_swap_surfel_specs(
+    material_swaps: &mut Vec<MaterialSwapSpec>,
+    resolver: &Resolver,
+) -> Result<(), Error> {
+    for swap in material_swaps.iter_mut() {
+        swap.surfels = resolver
+            .resolve(&swap.surfels)
+            .map(|p| p.to_str().unwrap().to_string())
+            .map_err(|e| Error::resolve(e, ResolveErrorKind::SurfelSpec))?;
+    }
+
+    Ok(())
+}
+
+fn resolve_injections(injections: &mut Vec<PathBuf>, resolver: &Resolver) -> Result<(), Error> {
+    for injection in injections.iter_mut() {
+        *injection = resolver
+            .resolve(&injection)
+            .map_err(|e| Error::resolve(e, ResolveErrorKind::Injections))?;
+    }
+
+    Ok(())
+}
+
 fn resolve_effect_spec_paths(
     specs: &mut Vec<EffectSpec>,
     resolver: &Resolver,
@@ -95,18 +181,39 @@ fn resolve_effect_spec_paths(
     Ok(())
 }
 
+/// Resolves the samples of the stops. A pattern matching multiple samples
+/// turns into one stop per sample, with ceniths evenly spread from the cenith
+/// of the stop up to the cenith of the next stop, or up to 1.0 for the last stop.
 fn resolve_stop_list_paths(stops: &mut Vec<Stop>, resolver: &Resolver) -> Result<(), Error> {
-    for stop in stops.iter_mut() {
-        stop.sample = if let Some(sample) = stop.sample.as_ref() {
-            Some(
-                resolver
-                    .resolve(sample)
-                    .map_err(|e| Error::resolve(e, ResolveErrorKind::Layer))?,
-            )
-        } else {
-            None
+    let mut resolved = Vec::with_capacity(stops.len());
+
+    for (idx, stop) in stops.iter().enumerate() {
+        let samples = match stop.sample {
+            Some(ref sample) => resolver
+                .resolve_glob(sample)
+                .map_err(|e| Error::resolve(e, ResolveErrorKind::Layer))?,
+            None => {
+                resolved.push(stop.clone());
+                continue;
+            }
+        };
+
+        // The next stop keeps its cenith, the last one reaches 1.0 with its last sample
+        let (to, steps) = match stops.get(idx + 1) {
+            Some(next) => (next.cenith, samples.len()),
+            None => (1.0, samples.len().saturating_sub(1).max(1)),
+        };
+
+        for (step, sample) in samples.into_iter().enumerate() {
+            resolved.push(Stop {
+                sample: Some(sample),
+                sha256: stop.sha256.clone(),
+                cenith: stop.cenith + (to - stop.cenith) * step as f32 / steps as f32,
+            });
         }
     }
+
+    *stops = resolved;
     Ok(())
 }
 
@@ -135,3 +242,52 @@ fn resolve_stop_list_paths(stops: &mut Vec<Stop>, resolver: &Resolver) -> Result
     }
     Ok(())
 }*/
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use spec::Blend;
+    use std::env::current_dir;
+
+    #[test]
+    fn spread_globbed_stops() {
+        let mut resolver = Resolver::new();
+        resolver.add_base(current_dir().unwrap()).unwrap();
+
+        let spec = SimulationSpec::new().effect(
+            EffectSpec::layer(vec![], "rust").albedo(
+                Blend::new("albedo.png")
+                    .stop(0.0, Some(PathBuf::from("tests/examples/rust_stops/rust_*.png")))
+                    .stop(0.6, Some(PathBuf::from("tests/examples/black_pixel.png"))),
+            ),
+        );
+
+        let spec = canonicalize(spec, &resolver).unwrap();
+        let stops = match spec.effects[0] {
+            EffectSpec::Layer {
+                albedo: Some(ref albedo),
+                ..
+            } => &albedo.stops,
+            _ => unreachable!(),
+        };
+
+        let names: Vec<_> = stops
+            .iter()
+            .map(|s| s.sample.as_ref().unwrap().file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            vec![
+                "rust_alittle.png",
+                "rust_alittlemore.png",
+                "rust_nothing.png",
+                "black_pixel.png",
+            ],
+            names
+        );
+
+        let ceniths: Vec<_> = stops.iter().map(|s| s.cenith).collect();
+        for (expected, actual) in [0.0, 0.2, 0.4, 0.6].iter().zip(ceniths) {
+            assert!((expected - actual).abs() < 1e-6, "{:?}", stops);
+        }
+    }
+}
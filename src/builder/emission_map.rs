@@ -0,0 +1,136 @@
+use geom::{TupleTriangle, Vec2, Vertex};
+use tex::GrayImage;
+
+/// Levels that the luminance of emission maps is quantized to, which is also
+/// the most copies made of a single triangle.
+const WEIGHT_LEVELS: u32 = 8;
+/// Deepest subdivision of a triangle, into at most 4^MAX_DEPTH triangles.
+const MAX_DEPTH: u32 = 6;
+/// Longest edge in texels of a triangle that is not further subdivided.
+const MAX_TEXELS: f32 = 2.0;
+
+/// Triangles of an emission mesh, three vertices each, with emission weighted
+/// by the luminance of the given map at the texture coordinates.
+///
+/// Triangles are subdivided until they cover only a few texels and each part
+/// is repeated proportionally to the luminance at its center. Since tons are
+/// emitted proportionally to area, bright parts emit more tons, black parts
+/// are dropped and emit none.
+pub fn weight_by_map<I>(triangles: I, map: &GrayImage) -> Vec<Vertex>
+where
+    I: IntoIterator<Item = TupleTriangle<Vertex>>,
+{
+    let mut weighted = Vec::new();
+    for triangle in triangles {
+        subdivide(triangle, map, 0, &mut weighted);
+    }
+    weighted
+}
+
+fn subdivide(triangle: TupleTriangle<Vertex>, map: &GrayImage, depth: u32, out: &mut Vec<Vertex>) {
+    let TupleTriangle(a, b, c) = triangle;
+    let texels = |from: Vertex, to: Vertex| {
+        let delta = to.texcoords - from.texcoords;
+        let (x, y) = (delta.x * map.width() as f32, delta.y * map.height() as f32);
+        (x * x + y * y).sqrt()
+    };
+    let longest_edge = texels(a, b).max(texels(b, c)).max(texels(c, a));
+
+    if depth == MAX_DEPTH || longest_edge <= MAX_TEXELS {
+        let center = (a.texcoords + b.texcoords + c.texcoords) / 3.0;
+        for _ in 0..copies(map, center) {
+            out.extend(vec![a, b, c]);
+        }
+    } else {
+        let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+        // Same winding as the original triangle
+        for part in vec![
+            TupleTriangle(a, ab, ca),
+            TupleTriangle(ab, b, bc),
+            TupleTriangle(ca, bc, c),
+            TupleTriangle(ab, bc, ca),
+        ] {
+            subdivide(part, map, depth + 1, out);
+        }
+    }
+}
+
+/// Quantized luminance at the given texture coordinates.
+fn copies(map: &GrayImage, texcoords: Vec2) -> u32 {
+    (luminance(map, texcoords) * WEIGHT_LEVELS as f32).round() as u32
+}
+
+/// Luminance from zero to one of the texel at the given texture coordinates,
+/// which repeat outside of zero to one, with zero at the bottom of the map
+/// like in OBJ files.
+pub fn luminance(map: &GrayImage, texcoords: Vec2) -> f32 {
+    let wrap = |coord: f32, size: u32| {
+        let texel = (coord - coord.floor()) * size as f32;
+        (texel as u32).min(size - 1)
+    };
+    let x = wrap(texcoords.x, map.width());
+    let y = map.height() - 1 - wrap(texcoords.y, map.height());
+
+    f32::from(map.get_pixel(x, y)[0]) / 255.0
+}
+
+fn midpoint(a: Vertex, b: Vertex) -> Vertex {
+    let normal = (a.normal + b.normal) * 0.5;
+    let length = (normal.x * normal.x + normal.y * normal.y + normal.z * normal.z).sqrt();
+    Vertex {
+        position: (a.position + b.position) * 0.5,
+        // Opposite normals have no sensible middle, keep one of them
+        normal: if length > 0.0 { normal / length } else { a.normal },
+        texcoords: (a.texcoords + b.texcoords) * 0.5,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geom::Vec3;
+    use tex::{ImageBuffer, Luma};
+
+    fn vertex(u: f32, v: f32) -> Vertex {
+        Vertex {
+            position: Vec3::new(u, 0.0, v),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            texcoords: Vec2::new(u, v),
+        }
+    }
+
+    #[test]
+    fn emit_only_where_bright() {
+        // Left half black, right half white
+        let map = ImageBuffer::from_fn(8, 8, |x, _| Luma([if x < 4 { 0 } else { 255 }]));
+        let quad = vec![
+            TupleTriangle(vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(1.0, 1.0)),
+            TupleTriangle(vertex(0.0, 0.0), vertex(1.0, 1.0), vertex(0.0, 1.0)),
+        ];
+
+        let weighted = weight_by_map(quad, &map);
+        assert!(!weighted.is_empty());
+        assert_eq!(0, weighted.len() % (3 * WEIGHT_LEVELS as usize));
+        for triangle in weighted.chunks(3) {
+            let center = (triangle[0].texcoords.x + triangle[1].texcoords.x
+                + triangle[2].texcoords.x) / 3.0;
+            assert!(center >= 0.5, "Emitting from black texels at u = {}", center);
+        }
+    }
+
+    #[test]
+    fn luminance_repeats_from_bottom() {
+        // Top row black, bottom row white
+        let map = ImageBuffer::from_fn(2, 2, |_, y| Luma([if y == 0 { 0 } else { 255 }]));
+        assert_eq!(1.0, luminance(&map, Vec2::new(0.25, 0.25)));
+        assert_eq!(0.0, luminance(&map, Vec2::new(0.25, 0.75)));
+        assert_eq!(1.0, luminance(&map, Vec2::new(-0.75, 1.25)));
+    }
+
+    #[test]
+    fn black_map_emits_nothing() {
+        let map = ImageBuffer::from_pixel(4, 4, Luma([0]));
+        let triangle = TupleTriangle(vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(1.0, 1.0));
+        assert!(weight_by_map(vec![triangle], &map).is_empty());
+    }
+}
@@ -1,8 +1,16 @@
+#[cfg(feature = "native")]
 use asset::err::AssetError;
+#[cfg(feature = "native")]
+use tex::ImageError;
+#[cfg(feature = "native")]
+use zip::result::ZipError;
+use failure::{self, Compat};
 use files::ResolveError;
 use serde_yaml::Error as SerdeYamlError;
+use spec::{AdaptiveResolution, OutputRetry, Streaks, SurfelSampling, Wind};
 use std::fmt;
 use std::io;
+use std::path::PathBuf;
 
 #[derive(Fail, Debug)]
 pub enum Error {
@@ -17,7 +25,22 @@ pub enum Error {
     #[fail(display = "I/O error occurred during simulation loading.")]
     IO(#[cause] io::Error),
     #[fail(display = "Failed to load 3D assets for the simulation.")]
+    #[cfg(feature = "native")]
     Asset(#[cause] AssetError),
+    #[fail(display = "Line {} of scene {:?} could not be parsed, {}.", line, path, reason)]
+    ObjSyntax {
+        path: PathBuf,
+        line: usize,
+        reason: String,
+    },
+    #[fail(
+        display = "Effect {} synthesizes textures, which needs texture coordinates that scene_loading geometry drops.",
+        _0
+    )]
+    TexcoordsDropped(String),
+    #[fail(display = "Failed to read or write simulation archive.")]
+    #[cfg(feature = "native")]
+    Archive(#[cause] ZipError),
     #[fail(
         display = "Simulation spec did not specify a material to surfel specification mapping, surface properties unspecified."
     )]
@@ -34,8 +57,231 @@ pub enum Error {
         display = "No surfel or ton source specs mention any substance names, no substance transport possible."
     )]
     SubstancesMissing,
+    #[fail(
+        display = "Emission mesh scene {:?} of ton source {:?} does not contain any entities.",
+        mesh,
+        source_spec
+    )]
+    EmptyEmissionMesh { source_spec: PathBuf, mesh: PathBuf },
+    #[fail(
+        display = "Emission mesh {:?} of ton source {:?} has no surface area, no emission possible.",
+        mesh,
+        source_spec
+    )]
+    DegenerateEmissionMesh { source_spec: PathBuf, mesh: PathBuf },
+    #[fail(
+        display = "Ton source {:?} must specify either an emission mesh or a shape, but not both.",
+        _0
+    )]
+    AmbiguousEmissionShape(PathBuf),
+    #[fail(
+        display = "Emission shape of ton source {:?} has no surface area, check its direction, radius or angle.",
+        _0
+    )]
+    DegenerateEmissionShape(PathBuf),
+    #[fail(
+        display = "Ton source {:?} must specify either emission_count or emission_per_area, but not both.",
+        _0
+    )]
+    AmbiguousEmissionCount(PathBuf),
+    #[fail(
+        display = "Ton source {:?} has an emission cone of {} degrees, which needs to be between 0 and 180.",
+        _0,
+        _1
+    )]
+    InvalidEmissionCone(PathBuf, f32),
+    #[fail(display = "Ton source {:?} needs at least one burst.", _0)]
+    InvalidBursts(PathBuf),
+    #[fail(
+        display = "Ton source {:?} emits from a point without area, use emission_count instead of emission_per_area.",
+        _0
+    )]
+    EmissionPerAreaOfPoint(PathBuf),
+    #[fail(
+        display = "Emission map {:?} of ton source {:?} could not be loaded.",
+        map,
+        source_spec
+    )]
+    #[cfg(feature = "native")]
+    UnloadableEmissionMap {
+        source_spec: PathBuf,
+        map: PathBuf,
+        #[cause]
+        cause: ImageError,
+    },
+    #[fail(
+        display = "Emission map {:?} of ton source {:?} is black on all of the emission geometry, no emission possible.",
+        map,
+        source_spec
+    )]
+    BlackEmissionMap { source_spec: PathBuf, map: PathBuf },
+    #[fail(display = "Initial map {:?} of substance {} could not be loaded.", map, substance)]
+    #[cfg(feature = "native")]
+    UnloadableInitialMap {
+        substance: String,
+        map: PathBuf,
+        #[cause]
+        cause: ImageError,
+    },
+    #[fail(display = "Ton source spec has type {}, but expected a string.", _0)]
+    InvalidSourceType(String),
+    #[fail(
+        display = "Ton source spec {:?} has type {}, but no source factory has been registered for it.",
+        source_spec,
+        kind
+    )]
+    UnknownSourceType { source_spec: PathBuf, kind: String },
+    #[fail(display = "Custom ton source {:?} could not be built.", source_spec)]
+    CustomSource {
+        source_spec: PathBuf,
+        #[cause]
+        cause: Compat<failure::Error>,
+    },
+    #[fail(display = "Simulation could not be prepared for running.")]
+    RunnerSetup(#[cause] Compat<failure::Error>),
     #[fail(display = "Surfel distance has been set to {:?}", _0)]
     InvalidSurfelDistance(Option<f32>),
+    #[fail(display = "Surfel sampling {:?} yields no surfels.", _0)]
+    InvalidSurfelSampling(SurfelSampling),
+    #[fail(display = "Wind {:?} needs a direction and a non-negative strength.", _0)]
+    InvalidWind(Wind),
+    #[fail(
+        display = "Output retry {:?} needs at least one attempt and a non-negative backoff.",
+        _0
+    )]
+    InvalidOutputRetry(OutputRetry),
+    #[fail(display = "Effect schedule entry \"{}\" is malformed: {}.", entry, reason)]
+    InvalidEffectSchedule { entry: String, reason: String },
+    #[fail(display = "Effect {} has a wear mask with non-positive radius {}.", effect, radius)]
+    InvalidWearMask { effect: String, radius: f32 },
+    #[fail(
+        display = "Effect {} has streaks {:?}, which need a non-negative length, jitter between 0 and 1, positive falloff and a direction.",
+        effect,
+        streaks
+    )]
+    InvalidStreaks { effect: String, streaks: Streaks },
+    #[fail(
+        display = "Effect {} has a blend stop with cenith {}, which needs to be a finite number.",
+        effect,
+        cenith
+    )]
+    InvalidCenith { effect: String, cenith: f32 },
+    #[fail(
+        display = "Effect {} has adaptive resolution {:?}, which needs positive texels per unit and a minimum between 1 and the maximum.",
+        effect,
+        adaptive_resolution
+    )]
+    InvalidAdaptiveResolution {
+        effect: String,
+        adaptive_resolution: AdaptiveResolution,
+    },
+    #[fail(
+        display = "Inputs pinned with sha256 in the spec have other contents, an asset was modified since the spec was written maybe?\n{}",
+        _0
+    )]
+    PinnedInputsChanged(String),
+    #[fail(
+        display = "The entity filter of {} has patterns {}, which are no valid globs.",
+        owner,
+        patterns
+    )]
+    InvalidEntityPatterns { owner: String, patterns: String },
+    #[fail(
+        display = "Cannot notify {}, only http and https URLs are supported and aitios needs to be built with the http feature.",
+        url
+    )]
+    InvalidNotifyUrl { url: String },
+    #[fail(
+        display = "Effect {} references unknown substance \"{}\", known substances are: {}.",
+        effect,
+        substance,
+        known
+    )]
+    UnknownEffectSubstance {
+        /// Type and index of the effect, e.g. `layer#1`.
+        effect: String,
+        substance: String,
+        /// Comma-separated list of all substances mentioned in surfel and ton source specs.
+        known: String,
+    },
+    #[fail(
+        display = "Material swap #{} references unknown substance \"{}\", known substances are: {}.",
+        swap,
+        substance,
+        known
+    )]
+    UnknownSwapSubstance {
+        /// Index of the swap in `material_swaps`.
+        swap: usize,
+        substance: String,
+        known: String,
+    },
+    #[fail(display = "Substance injection schedule {:?} is invalid, {}.", path, reason)]
+    InvalidInjections { path: PathBuf, reason: String },
+    #[fail(display = "Surfel snapshot {:?} cannot be used, {}.", path, reason)]
+    InvalidSurfelSnapshot { path: PathBuf, reason: String },
+    #[fail(
+        display = "Range given for unknown substance \"{}\", known substances are: {}.",
+        substance,
+        known
+    )]
+    UnknownRangeSubstance { substance: String, known: String },
+    #[fail(
+        display = "Cannot restrict the run to unknown substance \"{}\", known substances are: {}.",
+        substance,
+        known
+    )]
+    UnknownFilterSubstance { substance: String, known: String },
+    #[fail(
+        display = "Material swap #{} swaps to material \"{}\", which is not in the loaded scenes. Available material names are: {}",
+        swap,
+        material,
+        available
+    )]
+    UnknownSwapMaterial {
+        swap: usize,
+        material: String,
+        available: String,
+    },
+    #[fail(display = "{} texture(s) could not be loaded:\n{}", count, list)]
+    UnloadableTextures {
+        count: usize,
+        /// One line per texture, with path, usage and the error.
+        list: String,
+    },
+    #[fail(display = "Strict mode forbids unknown keys in specs: {}", _0)]
+    UnknownSpecKeys(String),
+    #[fail(
+        display = "Strict mode forbids unknown or malformed placeholders in output patterns:\n{}",
+        _0
+    )]
+    UnknownPlaceholders(String),
+    #[fail(
+        display = "Strict mode forbids surfels_by_material entries {} that match no material in the loaded scenes. Available material names are: {}",
+        unmatched,
+        available
+    )]
+    UnmatchedSurfelSpecs { unmatched: String, available: String },
+    #[fail(
+        display = "{} output path(s) would be written by more than one output:\n{}",
+        count,
+        list
+    )]
+    OutputCollisions {
+        count: usize,
+        /// One line per path, with all outputs that would write it.
+        list: String,
+    },
+    #[fail(
+        display = "Outputs may not fit into the free space of {} volume(s), set disk_space_check to warn to run anyway:\n{}",
+        count,
+        list
+    )]
+    InsufficientDiskSpace {
+        count: usize,
+        /// One line per volume, with the estimated and the available space.
+        list: String,
+    },
 }
 
 impl Error {
@@ -44,17 +290,22 @@ impl Error {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum ResolveErrorKind {
     #[allow(unused)]
     BasePath,
     Simulation,
     TonSourceSpec,
     TonSourceMesh,
+    EmissionMap,
+    InitialMap,
     SurfelSpec,
     Scene,
     Layer,
     Benchmark,
+    Injections,
+    SurfelSnapshot,
+    DownloadCache,
 }
 
 impl fmt::Display for ResolveErrorKind {
@@ -67,10 +318,15 @@ impl fmt::Display for ResolveErrorKind {
                 &ResolveErrorKind::Simulation => "Simulation specification",
                 &ResolveErrorKind::TonSourceSpec => "Gammaton source specification",
                 &ResolveErrorKind::TonSourceMesh => "Gammaton source emission mesh",
+                &ResolveErrorKind::EmissionMap => "Gammaton source emission map",
+                &ResolveErrorKind::InitialMap => "Initial map of surfel specification",
                 &ResolveErrorKind::SurfelSpec => "Surfel specification",
                 &ResolveErrorKind::Scene => "Scene to simulate",
                 &ResolveErrorKind::Layer => "Texture sample referenced by layer effect",
                 &ResolveErrorKind::Benchmark => "Benchmarking CSV",
+                &ResolveErrorKind::Injections => "Substance injection schedule",
+                &ResolveErrorKind::SurfelSnapshot => "Surfel snapshot",
+                &ResolveErrorKind::DownloadCache => "Download cache directory",
             }
         )
     }
@@ -88,8 +344,16 @@ impl From<io::Error> for Error {
     }
 }
 
+#[cfg(feature = "native")]
 impl From<AssetError> for Error {
     fn from(error: AssetError) -> Self {
         Error::Asset(error)
     }
 }
+
+#[cfg(feature = "native")]
+impl From<ZipError> for Error {
+    fn from(error: ZipError) -> Self {
+        Error::Archive(error)
+    }
+}
@@ -0,0 +1,157 @@
+use builder::{Error, ResolveErrorKind};
+use files::Resolver;
+use runner::Injection;
+use serde_json;
+use spec::InjectionSpec;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Reads the injection schedules, checking that they only inject known
+/// substances in simulated iterations.
+///
+/// Schedules ending in `.json` are arrays of injection objects, others are
+/// CSV files with a header naming the iteration, substance, amount and
+/// optionally material columns.
+pub fn build_injections(
+    schedules: &[PathBuf],
+    iterations: u32,
+    unique_substance_names: &[String],
+    resolver: &Resolver,
+) -> Result<Vec<Injection>, Error> {
+    let mut injections = Vec::new();
+
+    for schedule in schedules {
+        let path = resolver
+            .resolve(schedule)
+            .map_err(|e| Error::resolve(e, ResolveErrorKind::Injections))?;
+        let invalid = |reason: String| Error::InvalidInjections {
+            path: path.clone(),
+            reason,
+        };
+
+        for spec in load_injections(&path).map_err(&invalid)? {
+            if spec.iteration < 1 || spec.iteration > iterations {
+                return Err(invalid(format!(
+                    "iteration {} is not between 1 and {}",
+                    spec.iteration, iterations
+                )));
+            }
+            let substance_idx = unique_substance_names
+                .iter()
+                .position(|n| *n == spec.substance)
+                .ok_or_else(|| {
+                    invalid(format!(
+                        "substance \"{}\" is unknown, known substances are: {}",
+                        spec.substance,
+                        unique_substance_names.join(", ")
+                    ))
+                })?;
+            injections.push(Injection {
+                spec,
+                substance_idx,
+            });
+        }
+    }
+
+    Ok(injections)
+}
+
+fn load_injections(path: &Path) -> Result<Vec<InjectionSpec>, String> {
+    let mut contents = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut contents))
+        .map_err(|e| e.to_string())?;
+
+    if path.extension().map_or(false, |e| e == "json") {
+        serde_json::from_str(&contents).map_err(|e| e.to_string())
+    } else {
+        parse_csv(&contents)
+    }
+}
+
+/// Parses CSV without quoting, with columns in any order as named by the
+/// header. Empty lines are skipped.
+fn parse_csv(csv: &str) -> Result<Vec<InjectionSpec>, String> {
+    let mut lines = csv
+        .lines()
+        .enumerate()
+        .filter(|&(_, line)| !line.trim().is_empty());
+    let header: Vec<&str> = match lines.next() {
+        Some((_, header)) => header.split(',').map(str::trim).collect(),
+        None => return Ok(Vec::new()),
+    };
+    let column = |name: &str| header.iter().position(|&c| c == name);
+    let required =
+        |name: &str| column(name).ok_or_else(|| format!("the header has no {} column", name));
+    let (iteration, substance, amount) = (
+        required("iteration")?,
+        required("substance")?,
+        required("amount")?,
+    );
+    let material = column("material");
+
+    lines
+        .map(|(idx, line)| {
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let field = |column: usize| fields.get(column).cloned().unwrap_or("");
+            let number_error = |name: &str| {
+                format!(
+                    "{} \"{}\" on line {} is not a number",
+                    name,
+                    field(column(name).unwrap()),
+                    idx + 1
+                )
+            };
+
+            Ok(InjectionSpec {
+                iteration: field(iteration)
+                    .parse()
+                    .map_err(|_| number_error("iteration"))?,
+                substance: field(substance).to_string(),
+                amount: field(amount).parse().map_err(|_| number_error("amount"))?,
+                material: material
+                    .map(field)
+                    .filter(|m| !m.is_empty())
+                    .map(String::from),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_csv_by_header() {
+        let csv =
+            "amount, iteration, substance, material\n0.5, 12, salt, steel\n\n-0.1, 3, humidity,\n";
+        let injections = parse_csv(csv).unwrap();
+
+        assert_eq!(
+            vec![
+                InjectionSpec {
+                    iteration: 12,
+                    substance: String::from("salt"),
+                    amount: 0.5,
+                    material: Some(String::from("steel")),
+                },
+                InjectionSpec {
+                    iteration: 3,
+                    substance: String::from("humidity"),
+                    amount: -0.1,
+                    material: None,
+                },
+            ],
+            injections
+        );
+        assert!(injections[1].affects_material("steel"));
+
+        assert_eq!(
+            "amount \"lots\" on line 2 is not a number",
+            parse_csv("iteration,substance,amount\n1,salt,lots").unwrap_err()
+        );
+        assert!(parse_csv("iteration,amount\n1,0.5").is_err());
+    }
+}
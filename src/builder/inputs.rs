@@ -0,0 +1,118 @@
+use builder::pack::obj_dependencies;
+use builder::Error;
+use files::{content_hash, sha256, InputHashes};
+use spec::{EffectSpec, SimulationSpec};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// Hashes the contents of the scenes with their materials and textures, the
+/// surfel specs and the texture samples of the given spec with resolved
+/// paths, so that outputs and snapshots of earlier runs can be checked for
+/// having been made from the same inputs.
+///
+/// Inputs that cannot be read are left out with a warning, building fails
+/// on them later if they are needed.
+pub fn input_hashes(spec: &SimulationSpec) -> InputHashes {
+    let mut inputs = surfel_inputs(spec);
+    for scene in spec.scenes.iter().chain(spec.occluders.iter()) {
+        inputs.extend(obj_dependencies(scene.path()).unwrap_or_default());
+    }
+    for effect in spec.effects.iter() {
+        if let &EffectSpec::Layer {
+            ref normal,
+            ref displacement,
+            ref albedo,
+            ref metallicity,
+            ref roughness,
+            ..
+        } = effect
+        {
+            let blends = [normal, displacement, albedo, metallicity, roughness];
+            for blend in blends.iter().filter_map(|b| b.as_ref()) {
+                inputs.extend(blend.stops.iter().filter_map(|s| s.sample.clone()));
+            }
+        }
+    }
+
+    inputs
+        .into_iter()
+        .filter_map(|path| match content_hash(&path) {
+            Ok(hash) => Some((path, hash)),
+            Err(err) => {
+                warn!("Could not hash input {}: {}", path.display(), err);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Inputs that decide where surfels are and how substances move between them,
+/// that is, the OBJ files of the scenes and the surfel specs. Materials and
+/// samples only matter for effects.
+pub fn surfel_inputs(spec: &SimulationSpec) -> BTreeSet<PathBuf> {
+    let mut inputs = BTreeSet::new();
+    for scene in spec.scenes.iter().chain(spec.occluders.iter()) {
+        inputs.insert(scene.path().to_path_buf());
+    }
+    inputs.extend(spec.surfels_by_material.values().map(PathBuf::from));
+    inputs.extend(spec.material_swaps.iter().map(|s| PathBuf::from(&s.surfels)));
+    inputs
+}
+
+/// Fails if scenes or samples pinned with a `sha256` digest in the spec have
+/// other contents, e.g. because a shared asset was modified after the spec
+/// was written. Reports all mismatches at once.
+pub fn check_pinned_inputs(spec: &SimulationSpec) -> Result<(), Error> {
+    let mut pinned: Vec<(&Path, &str)> = spec
+        .scenes
+        .iter()
+        .chain(spec.occluders.iter())
+        .filter_map(|scene| scene.sha256().map(|sha256| (scene.path(), sha256)))
+        .collect();
+    for effect in spec.effects.iter() {
+        if let &EffectSpec::Layer {
+            ref normal,
+            ref displacement,
+            ref albedo,
+            ref metallicity,
+            ref roughness,
+            ..
+        } = effect
+        {
+            let blends = [normal, displacement, albedo, metallicity, roughness];
+            for blend in blends.iter().filter_map(|b| b.as_ref()) {
+                pinned.extend(
+                    blend
+                        .stops
+                        .iter()
+                        .filter_map(|s| match (&s.sample, &s.sha256) {
+                            (&Some(ref sample), &Some(ref sha256)) => {
+                                Some((sample.as_path(), sha256.as_str()))
+                            }
+                            _ => None,
+                        }),
+                );
+            }
+        }
+    }
+
+    let mismatches: Vec<String> = pinned
+        .into_iter()
+        .filter_map(|(path, expected)| match sha256(path) {
+            Ok(ref actual) if actual.eq_ignore_ascii_case(expected) => None,
+            Ok(actual) => Some(format!(
+                "{} has sha256 {}, expected {}",
+                path.display(),
+                actual,
+                expected
+            )),
+            Err(err) => Some(format!("{} could not be read: {}", path.display(), err)),
+        })
+        .collect();
+
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::PinnedInputsChanged(mismatches.join("\n")))
+    }
+}
@@ -0,0 +1,316 @@
+use asset::obj;
+use builder::instantiate::{
+    load_source_specs, surfel_specs_by_material_name, unique_substance_names,
+};
+use builder::instance::instanced_entities;
+use builder::material_map::renamed_materials;
+use builder::uv::{separated_entities, unwrapped_entities};
+use builder::Error;
+use files::Resolver;
+use runner::{density_size, surface_areas};
+use scene::{Entity, Mesh};
+use spec::{EffectSpec, SimulationSpec, SurfelSampling, UvOverlaps};
+use std::fmt;
+
+/// Summary of what a simulation would do with the loaded scene, obtained
+/// without sampling surfels or tracing.
+///
+/// Useful to find out why entities remain unweathered, e.g. because no
+/// surfel spec matches their material or no effect touches them.
+pub struct Inspection {
+    entities: Vec<EntityInspection>,
+    unique_substance_names: Vec<String>,
+    /// Approximate distance between neighboring surfels, if the sampling
+    /// implies one.
+    surfel_spacing: Option<f32>,
+}
+
+struct EntityInspection {
+    name: String,
+    material: String,
+    /// Key in `surfels_by_material` that matched the material and the name of the
+    /// surfel spec, or `None` if the entity will be ignored by the simulation.
+    surfel_spec: Option<(String, String)>,
+    /// Effects that will touch the entity, as type and index in the effect list.
+    effects: Vec<String>,
+    texel_densities: Vec<TexelDensity>,
+}
+
+/// Resolution of the textures an effect writes for an entity, relative to the
+/// surface area of the entity.
+struct TexelDensity {
+    effect: String,
+    /// Width and height of the output textures, or `None` if they follow the
+    /// maps of the material or the blend samples.
+    size: Option<(usize, usize)>,
+    /// Fraction of the texture covered by the texture coordinates of the entity.
+    uv_coverage: f32,
+    /// Texels along one unit of the surface, if the size is known.
+    texels_per_unit: Option<f32>,
+}
+
+/// Loads scenes, surfel specs and ton source specs referenced in the given spec
+/// and determines for each entity which surfel spec and effects apply.
+pub fn inspect(spec: &SimulationSpec, resolver: &Resolver) -> Result<Inspection, Error> {
+    let surfel_specs_by_material_name = surfel_specs_by_material_name(spec, resolver, false)?;
+    let source_specs = load_source_specs(&spec.sources, resolver, false)?;
+    let unique_substance_names =
+        unique_substance_names(&surfel_specs_by_material_name, &source_specs);
+
+    let mut entities = Vec::new();
+    for scene in spec.scenes.iter() {
+        let mut scene_entities = unwrapped_entities(
+            instanced_entities(
+                renamed_materials(obj::load(scene.path())?, &spec.material_map),
+                scene.instances(),
+            ),
+            spec.auto_unwrap,
+        );
+        if spec.uv_overlaps == Some(UvOverlaps::Separate) {
+            scene_entities = separated_entities(scene_entities);
+        }
+
+        for entity in scene_entities {
+            let material = entity.material.name().to_string();
+
+            // Exact material name first, then the catchall, unless the
+            // entity filter of the simulation drops the entity
+            let simulated = spec.entities.as_ref().map_or(true, |f| f.admits(&entity.name));
+            let surfel_spec = [material.as_str(), "_"]
+                .iter()
+                .filter(|_| simulated)
+                .filter_map(|key| {
+                    surfel_specs_by_material_name
+                        .get(*key)
+                        .map(|surfel_spec| (key.to_string(), surfel_spec.name.clone()))
+                })
+                .next();
+
+            // Entities without surfel spec are dropped before effects run
+            let applied: Vec<(String, &EffectSpec)> = if surfel_spec.is_some() {
+                spec.effects
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, e)| e.affects_entity(&entity.name, &material))
+                    .map(|(idx, e)| (format!("{}#{}", e.kind(), idx), e))
+                    .collect()
+            } else {
+                Vec::new()
+            };
+
+            let (area, uv_area) = surface_areas(entity.mesh.triangles());
+            let texel_densities = applied
+                .iter()
+                .filter(|&&(_, effect)| is_synthesis(effect))
+                .map(|&(ref label, effect)| {
+                    let size = texture_size(effect, &entity);
+                    TexelDensity {
+                        effect: label.clone(),
+                        size,
+                        uv_coverage: uv_area,
+                        texels_per_unit: size.map(|(w, h)| texels_per_unit(w, h, uv_area, area)),
+                    }
+                })
+                .collect();
+
+            entities.push(EntityInspection {
+                name: entity.name.clone(),
+                material,
+                surfel_spec,
+                effects: applied.into_iter().map(|(label, _)| label).collect(),
+                texel_densities,
+            });
+        }
+    }
+
+    Ok(Inspection {
+        entities,
+        unique_substance_names,
+        surfel_spacing: surfel_spacing(spec),
+    })
+}
+
+/// Texels along one unit of a surface with the given area in world space and
+/// in texture space of a texture with the given size.
+fn texels_per_unit(width: usize, height: usize, uv_area: f32, area: f32) -> f32 {
+    if area > 0.0 {
+        ((width * height) as f32 * uv_area / area).sqrt()
+    } else {
+        0.0
+    }
+}
+
+/// Whether the effect writes textures for the entities it affects.
+fn is_synthesis(effect: &EffectSpec) -> bool {
+    match *effect {
+        EffectSpec::Density { .. } | EffectSpec::Layer { .. } | EffectSpec::Wear { .. } => true,
+        _ => false,
+    }
+}
+
+/// Size of the textures written by the effect for the entity, or the largest
+/// explicit size of the maps of a layer. `None` if the size follows textures
+/// that are only loaded when running.
+fn texture_size(effect: &EffectSpec, entity: &Entity) -> Option<(usize, usize)> {
+    match *effect {
+        EffectSpec::Density {
+            width,
+            height,
+            adaptive_resolution,
+            ..
+        } => Some(density_size(width, height, adaptive_resolution, entity)),
+        EffectSpec::Wear { width, height, .. } => Some((width, height)),
+        EffectSpec::Layer {
+            ref normal,
+            ref displacement,
+            ref albedo,
+            ref metallicity,
+            ref roughness,
+            ..
+        } => [normal, displacement, albedo, metallicity, roughness]
+            .iter()
+            .filter_map(|blend| blend.as_ref())
+            .filter_map(|blend| match (blend.width, blend.height) {
+                (Some(w), Some(h)) => Some((w, h)),
+                (Some(size), None) | (None, Some(size)) => Some((size, size)),
+                (None, None) => None,
+            })
+            .max_by_key(|&(w, h)| w * h),
+        _ => None,
+    }
+}
+
+fn surfel_spacing(spec: &SimulationSpec) -> Option<f32> {
+    match (spec.surfel_sampling, spec.surfel_distance) {
+        (Some(SurfelSampling::MinimumDistance(distance)), _) => Some(distance),
+        (Some(SurfelSampling::PerSqrUnit(count)), _) if count > 0 => {
+            Some(1.0 / (count as f32).sqrt())
+        }
+        (Some(_), _) => None,
+        (None, distance) => distance,
+    }
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rows: Vec<Vec<String>> = self
+            .entities
+            .iter()
+            .map(|e| {
+                vec![
+                    e.name.clone(),
+                    e.material.clone(),
+                    match e.surfel_spec {
+                        Some((ref key, ref name)) => format!("{} ({})", name, key),
+                        None => String::from("none, ignored"),
+                    },
+                    if e.effects.is_empty() {
+                        String::from("none")
+                    } else {
+                        e.effects.join(", ")
+                    },
+                ]
+            })
+            .collect();
+
+        write_table(f, &["Entity", "Material", "Surfel spec", "Effects"], &rows)?;
+
+        let density_rows: Vec<Vec<String>> = self
+            .entities
+            .iter()
+            .flat_map(|e| e.texel_densities.iter().map(move |d| (e, d)))
+            .map(|(e, d)| {
+                vec![
+                    e.name.clone(),
+                    d.effect.clone(),
+                    match d.size {
+                        Some((width, height)) => format!("{}x{}", width, height),
+                        None => String::from("from maps"),
+                    },
+                    format!("{:.0}%", d.uv_coverage * 100.0),
+                    match d.texels_per_unit {
+                        Some(texels) => format!("{:.1}", texels),
+                        None => String::from("-"),
+                    },
+                    match (d.texels_per_unit, self.surfel_spacing) {
+                        (Some(texels), Some(spacing)) => format!("{:.1}", texels * spacing),
+                        _ => String::from("-"),
+                    },
+                ]
+            })
+            .collect();
+
+        if !density_rows.is_empty() {
+            write!(f, "\n")?;
+            write_table(
+                f,
+                &[
+                    "Entity",
+                    "Effect",
+                    "Resolution",
+                    "UV coverage",
+                    "Texels per unit",
+                    "Texels between surfels",
+                ],
+                &density_rows,
+            )?;
+        }
+
+        write!(f, "\nSubstances: ")?;
+        if self.unique_substance_names.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", self.unique_substance_names.join(", "))
+        }
+    }
+}
+
+/// Writes rows below a header, padding all but the last column to the widest
+/// cell.
+fn write_table(f: &mut fmt::Formatter, header: &[&str], rows: &[Vec<String>]) -> fmt::Result {
+    let header: Vec<String> = header.iter().map(|h| h.to_string()).collect();
+    let widths: Vec<usize> = (0..header.len())
+        .map(|col| {
+            rows.iter()
+                .chain(Some(&header))
+                .map(|r| r[col].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    for row in Some(&header).into_iter().chain(rows.iter()) {
+        let last = row.len() - 1;
+        for (col, cell) in row.iter().enumerate().take(last) {
+            write!(f, "{:w$}  ", cell, w = widths[col])?;
+        }
+        write!(f, "{}\n", row[last])?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geom::{TupleTriangle, Vec2, Vec3, Vertex};
+
+    #[test]
+    fn texel_density_of_quad() {
+        let vertex = |x: f32, y: f32| Vertex {
+            position: Vec3::new(x * 2.0, y * 2.0, 0.0),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            // Only the lower left quarter of the texture is used
+            texcoords: Vec2::new(x * 0.5, y * 0.5),
+        };
+        let quad = vec![
+            TupleTriangle(vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(1.0, 1.0)),
+            TupleTriangle(vertex(0.0, 0.0), vertex(1.0, 1.0), vertex(0.0, 1.0)),
+        ];
+
+        let (area, uv_area) = surface_areas(quad);
+        assert_eq!((4.0, 0.25), (area, uv_area));
+        // 256 texels along the two units of each side of the quad
+        assert_eq!(128.0, texels_per_unit(512, 512, uv_area, area));
+    }
+}
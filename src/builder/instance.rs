@@ -0,0 +1,109 @@
+use builder::shape::vec3;
+use geom::{TupleTriangle, Vec3, Vertex};
+use scene::{Entity, Mesh};
+use spec::Transform;
+use std::rc::Rc;
+
+/// Entities of a scene, or copies of them for each of the given instances.
+///
+/// Copies share the material of the loaded entity, but get transformed meshes
+/// and names with the 1-based index of the instance appended, e.g.
+/// `bench-2`, so their surfels and outputs stay apart.
+pub fn instanced_entities(entities: Vec<Entity>, instances: Option<&[Transform]>) -> Vec<Entity> {
+    let instances = match instances {
+        Some(instances) => instances,
+        None => return entities,
+    };
+
+    instances
+        .iter()
+        .enumerate()
+        .flat_map(|(instance_idx, transform)| {
+            entities.iter().map(move |entity| {
+                let vertices = entity
+                    .mesh
+                    .triangles()
+                    .flat_map(|TupleTriangle(a, b, c)| vec![a, b, c])
+                    .map(|vertex| transformed_vertex(vertex, transform));
+
+                Entity {
+                    name: format!("{}-{}", entity.name, instance_idx + 1),
+                    material: Rc::clone(&entity.material),
+                    mesh: Rc::new(vertices.collect()),
+                }
+            })
+        })
+        .collect()
+}
+
+/// Scales, rotates and translates the position and rotates the normal.
+fn transformed_vertex(vertex: Vertex, transform: &Transform) -> Vertex {
+    let rotate = |vec: Vec3| match transform.rotate {
+        Some(degrees) => rotated(vec, degrees),
+        None => vec,
+    };
+
+    let scaled = vertex.position * transform.scale.unwrap_or(1.0);
+    let translation = transform.translate.map_or(Vec3::new(0.0, 0.0, 0.0), vec3);
+    let normal = rotate(vertex.normal);
+
+    Vertex {
+        position: rotate(scaled) + translation,
+        // Negative scale flips the surface, so the normal flips with it
+        normal: if transform.scale.map_or(false, |s| s < 0.0) {
+            -normal
+        } else {
+            normal
+        },
+        ..vertex
+    }
+}
+
+/// Rotates around the x, y and z axis in that order, by angles in degrees.
+fn rotated(vec: Vec3, degrees: [f32; 3]) -> Vec3 {
+    let (sin_x, cos_x) = degrees[0].to_radians().sin_cos();
+    let (sin_y, cos_y) = degrees[1].to_radians().sin_cos();
+    let (sin_z, cos_z) = degrees[2].to_radians().sin_cos();
+
+    let vec = Vec3::new(
+        vec.x,
+        cos_x * vec.y - sin_x * vec.z,
+        sin_x * vec.y + cos_x * vec.z,
+    );
+    let vec = Vec3::new(
+        cos_y * vec.x + sin_y * vec.z,
+        vec.y,
+        -sin_y * vec.x + cos_y * vec.z,
+    );
+    Vec3::new(
+        cos_z * vec.x - sin_z * vec.y,
+        sin_z * vec.x + cos_z * vec.y,
+        vec.z,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geom::Vec2;
+
+    #[test]
+    fn transform_vertex() {
+        let vertex = Vertex {
+            position: Vec3::new(1.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            texcoords: Vec2::new(0.5, 0.5),
+        };
+        let transform = Transform {
+            translate: Some([0.0, 0.0, 10.0]),
+            rotate: Some([0.0, 0.0, 90.0]),
+            scale: Some(2.0),
+        };
+
+        let transformed = transformed_vertex(vertex, &transform);
+        let close = |a: Vec3, b: Vec3| (a - b).x.abs() + (a - b).y.abs() + (a - b).z.abs() < 1e-5;
+        assert!(close(Vec3::new(0.0, 2.0, 10.0), transformed.position));
+        assert!(close(Vec3::new(-1.0, 0.0, 0.0), transformed.normal));
+        assert_eq!(Vec2::new(0.5, 0.5), transformed.texcoords);
+    }
+}
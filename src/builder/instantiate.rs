@@ -1,23 +1,55 @@
 use asset::obj;
+use builder::parse::parse_spec;
+use builder::source_factory::{SourceSpec, TonSourceFactory};
+use builder::placeholders::check_placeholders;
+use builder::emission_map::{luminance, weight_by_map};
+use builder::injections::build_injections;
+use builder::inputs::{check_pinned_inputs, input_hashes, surfel_inputs};
+use builder::instance::instanced_entities;
+use builder::material_map::{mapped_name, renamed_materials};
+use builder::uv::{separated_entities, unwrapped_entities};
+use builder::stream_obj::stream_obj;
+use builder::preflight::{
+    check_disk_space, check_output_collisions, check_textures, planned_outputs,
+};
+use builder::wind::{blown_flow_direction, blown_vertices};
+use builder::shape::{emitter_vertices, vec3};
+use builder::spread::spread_vertices;
+use bencher::{as_secs, write_header, write_row, Label, Layout};
 use builder::{Error, ResolveErrorKind};
 use chrono::*;
-use files::{create_file_recursively, fs_timestamp, Resolver};
-use geom::{TupleTriangle, Vec3, Vertex};
-use runner::SimulationRunner;
+use failure;
+use files::{
+    changed_inputs, fs_timestamp, write_atomically, InputHashes, PatternValues, Resolver,
+};
+use geom::{Triangle, TupleTriangle, Vertex};
+use profiler::Profiler;
+use serde_yaml;
+#[cfg(feature = "http")]
+use runner::HttpNotifier;
+use runner::{
+    explain_rule, sim_config, Budget, EntityRules, Injections, MaterialSwap, MaterialSwaps,
+    SimulationRunner, SourceGroup, SourceSchedule, StochasticRules, SurfelSnapshot,
+};
 use scene::DeinterleavedIndexedMeshBuf;
 use scene::{Entity, Mesh};
-use serde_yaml;
-use sim::{Config, Simulation, SurfelData, SurfelRule, TonSource, TonSourceBuilder, Transport};
-use spec::{BenchSpec, SimulationSpec, SurfelRuleSpec, SurfelSpec, TonSourceSpec, Transport::*};
+use sim::{Simulation, SurfelData, SurfelRule, TonSource, TonSourceBuilder};
+use spec::{
+    self, EffectSpec, EmitterShape, EntityFilter, MaterialSwapSpec, SceneLoading, SceneSpec,
+    ScheduleEntry, SimulationSpec,
+    SubstanceRange, SurfelRuleSpec, SurfelSpec, TonSourceSpec, Unwrap, UvOverlaps,
+    Wind,
+};
 use std::cmp::Eq;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs::File;
 use std::hash::Hash;
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::time::SystemTime;
 use surf::{Surface, SurfaceBuilder, Surfel, SurfelSampling};
+use tex::{self, GrayImage};
 
 /// Makes a simulation runner according to the given spec.
 ///
@@ -30,14 +62,39 @@ pub fn instantiate(
     spec: SimulationSpec,
     resolver: &Resolver,
     creation_time: DateTime<Local>,
+    profiler: Option<Rc<Profiler>>,
+    strict: bool,
+    source_factories: &HashMap<String, Box<TonSourceFactory>>,
+    substances: Option<&Vec<String>>,
+    surfel_snapshot: Option<&(PathBuf, SurfelSnapshot)>,
 ) -> Result<SimulationRunner, Error> {
     let load_start_time = SystemTime::now();
 
-    let surfel_specs_by_material_name = surfel_specs_by_material_name(&spec, &resolver)?;
+    // Before anything expensive, so jobs with modified assets fail fast
+    check_pinned_inputs(&spec)?;
 
-    let entities = load_entities(&spec.scenes, &surfel_specs_by_material_name)?;
+    let loading_span = profiler.as_ref().map(|p| p.span("loading", "setup"));
 
-    let source_specs = load_source_specs(&spec.sources, &resolver)?;
+    let mut surfel_specs_by_material_name =
+        surfel_specs_by_material_name(&spec, &resolver, strict)?;
+
+    let scene_loading = spec.scene_loading.unwrap_or_default();
+    // Before loading, which may take long for the scenes that need streaming
+    check_scene_loading(scene_loading, &spec.effects)?;
+    check_entity_filters(&spec)?;
+    let entities = load_entities(
+        &spec.scenes,
+        scene_loading,
+        &surfel_specs_by_material_name,
+        &spec.material_map,
+        spec.entities.as_ref(),
+        spec.uv_overlaps.unwrap_or_default(),
+        spec.auto_unwrap,
+        strict,
+    )?;
+    let occluders = load_occluders(&spec.occluders)?;
+
+    let mut source_specs = load_source_specs(&spec.sources, &resolver, strict)?;
 
     let unique_substance_names =
         unique_substance_names(&surfel_specs_by_material_name, &source_specs);
@@ -46,124 +103,744 @@ pub fn instantiate(
         return Err(Error::SubstancesMissing);
     }
 
+    if let Some(substances) = substances {
+        check_filter_substances(substances, &unique_substance_names)?;
+        exclude_from_transport(
+            substances,
+            &mut surfel_specs_by_material_name,
+            &mut source_specs,
+        );
+    }
+
     if spec.effects.is_empty() {
         return Err(Error::EffectsMissing);
     }
 
-    //let surfel_rules = build_surfel_rules(&surfel_specs_by_material_name, &unique_substance_names);
-    let sources = build_sources(&source_specs, &unique_substance_names, &resolver)?;
-
-    let surfel_distance = spec.surfel_distance;
-    if surfel_distance.is_none() || surfel_distance.unwrap() <= 0.0 {
-        return Err(Error::InvalidSurfelDistance(surfel_distance));
+    check_effect_substances(&spec.effects, &unique_substance_names)?;
+    check_range_substances(&spec.substances, &unique_substance_names)?;
+    check_effect_schedule(&spec.effects_at, spec.iterations.unwrap_or(1))?;
+    check_wear_masks(&spec.effects)?;
+    check_streaks(&spec.effects)?;
+    check_ceniths(&spec.effects)?;
+    check_adaptive_resolutions(&spec.effects)?;
+    if let Some(output_retry) = spec.output_retry {
+        if !output_retry.is_valid() {
+            return Err(Error::InvalidOutputRetry(output_retry));
+        }
+    }
+    if strict {
+        check_placeholders(&spec.effects)?;
     }
-    let surface = build_surface(
+    let texture_sizes = check_textures(&spec.effects, &entities)?;
+    let outputs = planned_outputs(
+        &spec.effects,
         &entities,
+        substances.unwrap_or(&unique_substance_names),
+        &texture_sizes,
+    );
+    check_output_collisions(&outputs)?;
+    let material_swaps = build_material_swaps(
+        &spec.material_swaps,
+        &entities,
+        &unique_substance_names,
+        resolver,
+        strict,
+    )?;
+    let global_rules = entity_rules(&spec.rules, &unique_substance_names);
+    let substance_budgets = substance_budgets(
+        &source_specs,
+        &global_rules,
         &surfel_specs_by_material_name,
+        &material_swaps,
+        &unique_substance_names,
+    );
+    let stochastic_rules = build_stochastic_rules(
+        spec.seed.unwrap_or(0),
+        &global_rules,
+        &entities,
+        &surfel_specs_by_material_name,
+        &material_swaps,
         &unique_substance_names,
-        surfel_distance.unwrap(),
     );
 
-    let simulation = {
-        let has_fallback_surfel_spec = surfel_specs_by_material_name.contains_key("_");
+    let iterations = spec.iterations.unwrap_or(1);
+    let injections = Injections::new(build_injections(
+        &spec.injections,
+        iterations,
+        &unique_substance_names,
+        resolver,
+    )?);
 
-        // Ignoring geometry where the corresponding material has no surfel specification
-        // and hence has no surfels generated, unless a fallback surfel spec is provided
-        let all_triangles = entities
+    //let surfel_rules = build_surfel_rules(&surfel_specs_by_material_name, &unique_substance_names);
+    // Sources are built for the first iteration and whenever the active
+    // sources or emission counts change, emission meshes are only loaded once.
+    // Material swaps, stochastic rules, injections and sources with different
+    // transports or bursts set up the simulation again in any iteration and
+    // sources cannot be reused, so they are built for every iteration then.
+    if let Some(wind) = spec.wind {
+        if !wind.is_valid() {
+            return Err(Error::InvalidWind(wind));
+        }
+    }
+    let multiple_groups = source_transports(&source_specs, spec.transport).len() > 1
+        || source_specs
             .iter()
-            .filter(|e| {
-                has_fallback_surfel_spec
-                    || surfel_specs_by_material_name.contains_key(e.material.name())
-            })
-            .flat_map(|e| e.mesh.triangles());
+            .filter_map(|s| s.mesh())
+            .any(|s| s.bursts() > 1);
+    let builds = source_builds(
+        source_phases(&source_specs, iterations),
+        iterations,
+        !material_swaps.is_empty()
+            || stochastic_rules.is_some()
+            || !injections.is_empty()
+            || multiple_groups,
+    );
+    let mut meshes = HashMap::new();
+    let mut source_emissions = Vec::new();
+    let mut scheduled = builds
+        .into_iter()
+        .map(|(iteration, changed, emission_counts)| {
+            let (sources, emissions) = build_sources(
+                &spec.sources,
+                &source_specs,
+                &emission_counts,
+                spec.transport,
+                spec.wind.as_ref(),
+                &unique_substance_names,
+                &resolver,
+                source_factories,
+                &mut meshes,
+            )?;
+            source_emissions.push((iteration, emissions));
+            Ok((iteration, changed, sources))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    // With multiple transports or bursts, the schedule sets up the first
+    // iteration too, since the simulation is set up again after tracing each
+    // group
+    let first_group = if multiple_groups {
+        None
+    } else {
+        scheduled.remove(0).2.pop()
+    };
+    let (transport, sources) = match first_group {
+        Some(group) => (group.transport, group.sources),
+        None => (spec.transport, Vec::new()),
+    };
 
-        let transport = match spec.transport {
-            Some(Classic) => Transport::classic(),
-            Some(Consistent) => Transport::consistent(),
-            Some(Conserving) => Transport::conserving(),
-            Some(Differential) | None => Transport::differential(),
-        };
+    let initial_maps = load_initial_maps(&surfel_specs_by_material_name, &unique_substance_names)?;
 
-        let config = Config { transport };
+    drop(loading_span);
 
-        let rules = spec
-            .rules
-            .iter()
-            .map(|r| rule_by_spec(r, &unique_substance_names))
-            .collect();
+    let sampling = surfel_sampling(&spec)?;
+    let sampling_start = SystemTime::now();
+    let mut surface = {
+        let _sampling_span = profiler.as_ref().map(|p| p.span("surfel sampling", "setup"));
+
+        build_surface(
+            &entities,
+            &surfel_specs_by_material_name,
+            &unique_substance_names,
+            &initial_maps,
+            sampling,
+        )
+    };
+    if let Some(ref stochastic_rules) = stochastic_rules {
+        stochastic_rules.draw(1, &mut surface);
+    }
+    injections.apply(1, &entities, &mut surface);
+    // A snapshot passed to the builder takes precedence over the one in the spec
+    let initial_surfels = match (surfel_snapshot, spec.initial_surfels.as_ref()) {
+        (None, Some(path)) => Some((path.clone(), load_surfel_snapshot(path)?)),
+        _ => None,
+    };
+    let restored = surfel_snapshot.or(initial_surfels.as_ref());
+    // Only hashed when recorded or compared, since scenes can be large
+    let inputs = if needs_input_hashes(&spec, restored.is_some()) {
+        input_hashes(&spec)
+    } else {
+        InputHashes::new()
+    };
+    if let Some(&(ref path, ref snapshot)) = restored {
+        check_snapshot_inputs(path, snapshot, &inputs, &surfel_inputs(&spec))?;
+        snapshot
+            .restore(&mut surface, &unique_substance_names)
+            .map_err(|e| Error::InvalidSurfelSnapshot {
+                path: path.clone(),
+                reason: e.to_string(),
+            })?;
+    }
+    let sampling_duration = sampling_start.elapsed().unwrap();
+    let surfel_count = surface.samples.len();
+    let sampling_secs = as_secs(sampling_duration);
+    info!(
+        "Sampled {} surfels in {:.3}s, {:.0} surfels per second.",
+        surfel_count,
+        sampling_secs,
+        surfel_count as f64 / sampling_secs
+    );
+
+    let effect_iterations = (0..iterations + 1).filter(|&i| spec.runs_effects(i)).count();
+    check_disk_space(
+        &outputs,
+        surfel_count,
+        effect_iterations as u64,
+        spec.disk_space_check.unwrap_or_default(),
+    )?;
+
+    let (simulation, source_schedule) = {
+        let has_fallback_surfel_spec = surfel_specs_by_material_name.contains_key("_");
+
+        // Ignoring geometry where the corresponding material has no surfel specification
+        // and hence has no surfels generated, unless a fallback surfel spec is provided.
+        // Occluders only block tons and never have surfels.
+        let all_triangles = || {
+            entities
+                .iter()
+                .filter(|e| {
+                    has_fallback_surfel_spec
+                        || surfel_specs_by_material_name.contains_key(e.material.name())
+                })
+                .chain(occluders.iter())
+                .flat_map(|e| e.mesh.triangles())
+        };
+
+        let source_schedule = if scheduled.is_empty() {
+            None
+        } else {
+            Some(SourceSchedule::new(
+                all_triangles().collect(),
+                global_rules.always.clone(),
+                spec.transport,
+                scheduled,
+            ))
+        };
 
         // No global surfel rules, only per substance type mapped from material
-        Simulation::new_with_config(config, sources, all_triangles, surface, rules)
+        let simulation = Simulation::new_with_config(
+            sim_config(transport),
+            sources,
+            all_triangles(),
+            surface,
+            global_rules.always,
+        );
+        (simulation, source_schedule)
     };
 
     let datetime = fs_timestamp(creation_time);
-    let runner = SimulationRunner::new(
+    let mut runner = SimulationRunner::new(
         spec,
         unique_substance_names,
         simulation,
         entities,
         &datetime,
-    );
+        profiler,
+    ).map_err(|e| Error::RunnerSetup(e.compat()))?;
+    if let Some(source_schedule) = source_schedule {
+        runner.set_source_schedule(source_schedule);
+    }
+    runner.set_source_emissions(source_emissions);
+    if !material_swaps.is_empty() {
+        runner.set_material_swaps(MaterialSwaps::new(material_swaps));
+    }
+    if let Some(stochastic_rules) = stochastic_rules {
+        runner.set_stochastic_rules(stochastic_rules);
+    }
+    runner.set_substance_budgets(substance_budgets);
+    runner.set_input_hashes(inputs);
+    if !injections.is_empty() {
+        runner.set_injections(injections);
+    }
+    if let Some(substances) = substances {
+        runner.set_synthesized_substances(substances.clone());
+    }
+    if let Some(notify) = runner.spec().notify.clone() {
+        add_notifier(&mut runner, &notify.url)?;
+    }
 
-    if let Some(BenchSpec {
-        setup: Some(ref setup_csv),
-        ..
-    }) = runner.spec().benchmark
-    {
-        let elapsed = load_start_time.elapsed().unwrap();
-        let secs = elapsed.as_secs();
-        let nanos = elapsed.subsec_nanos();
+    if let Some(ref benchmark) = runner.spec().benchmark {
+        if let Some(ref setup_csv) = benchmark.setup {
+            let elapsed = load_start_time.elapsed().unwrap();
 
-        let mut setup_csv = create_file_recursively(
-            setup_csv.to_str().unwrap().replace("{datetime}", &datetime),
-        ).expect("Could not write to benchmark sink.");
+            let setup_csv = PatternValues::new(&datetime).substitute(setup_csv.to_str().unwrap());
 
-        writeln!(setup_csv, "{}.{:09}", secs, nanos).expect("Could not write to benchmark sink.");
+            let label = Label {
+                phase: "setup".to_string(),
+                ..Label::default()
+            };
+            let sampling_label = Label {
+                phase: "surfel sampling".to_string(),
+                processed: Some(surfel_count as u64),
+                ..Label::default()
+            };
+            let layout = Layout::for_spec(benchmark);
+
+            write_atomically(setup_csv, |csv| {
+                write_header(csv, layout)?;
+                write_row(csv, layout, elapsed, &label)?;
+                // Only labeled rows can be told apart from the whole setup
+                match layout {
+                    Layout::Durations => Ok(()),
+                    _ => write_row(csv, layout, sampling_duration, &sampling_label),
+                }
+            }).expect("Could not write to benchmark sink.");
+        }
     }
 
     Ok(runner)
 }
 
+/// Checks that all substances referenced by effects are mentioned in some
+/// surfel or ton source spec, so the simulation fails before running rather
+/// than when synthesizing textures.
+fn check_effect_substances(
+    effects: &[EffectSpec],
+    unique_substance_names: &[String],
+) -> Result<(), Error> {
+    for (idx, effect) in effects.iter().enumerate() {
+        if let &EffectSpec::Layer { ref substance, .. } = effect {
+            if !unique_substance_names.contains(substance) {
+                return Err(Error::UnknownEffectSubstance {
+                    effect: format!("{}#{}", effect.kind(), idx),
+                    substance: substance.clone(),
+                    known: unique_substance_names.join(", "),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that only substances that occur in the simulation are selected
+/// for tracing and synthesis.
+fn check_filter_substances(
+    substances: &[String],
+    unique_substance_names: &[String],
+) -> Result<(), Error> {
+    match substances
+        .iter()
+        .find(|s| !unique_substance_names.contains(s))
+    {
+        Some(substance) => Err(Error::UnknownFilterSubstance {
+            substance: substance.clone(),
+            known: unique_substance_names.join(", "),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Whether the inputs of the spec are hashed, to record them in the manifest
+/// or in surfel snapshots, or to compare them with a restored snapshot.
+fn needs_input_hashes(spec: &SimulationSpec, restores_snapshot: bool) -> bool {
+    let dumps_snapshots = spec.effects.iter().any(|effect| match effect {
+        &EffectSpec::DumpSurfels { ref obj_pattern } => obj_pattern.ends_with(".bin"),
+        _ => false,
+    });
+    restores_snapshot || dumps_snapshots || spec.manifest.is_some()
+}
+
+/// Fails if the scenes or surfel specs changed since the snapshot was taken,
+/// which would restore concentrations onto surfels they do not belong to.
+/// Changed materials and samples are fine, e.g. to run tweaked effects.
+fn check_snapshot_inputs(
+    path: &Path,
+    snapshot: &SurfelSnapshot,
+    inputs: &InputHashes,
+    surfel_inputs: &BTreeSet<PathBuf>,
+) -> Result<(), Error> {
+    if snapshot.inputs.is_empty() {
+        warn!(
+            "Surfel snapshot {} records no input hashes, cannot check whether scenes or surfel specs changed since it was taken.",
+            path.display()
+        );
+        return Ok(());
+    }
+
+    let changed: Vec<&Path> = changed_inputs(&snapshot.inputs, inputs)
+        .into_iter()
+        .filter(|input| surfel_inputs.contains(*input))
+        .collect();
+    if changed.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::InvalidSurfelSnapshot {
+            path: path.to_path_buf(),
+            reason: format!("inputs {:?} changed since it was taken", changed),
+        })
+    }
+}
+
+/// Reads a surfel snapshot written by a `dump_surfels` effect.
+pub fn load_surfel_snapshot(path: &Path) -> Result<SurfelSnapshot, Error> {
+    File::open(path)
+        .map_err(failure::Error::from)
+        .and_then(|file| SurfelSnapshot::read(&mut BufReader::new(file)))
+        .map_err(|e| Error::InvalidSurfelSnapshot {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        })
+}
+
+/// Stops tons from picking up and depositing substances other than the given
+/// ones, so that only those are transported. Sources of custom types are
+/// built by their factories and keep their absorption rates.
+fn exclude_from_transport(
+    substances: &[String],
+    surfel_specs_by_material_name: &mut HashMap<String, SurfelSpec>,
+    source_specs: &mut [SourceSpec],
+) {
+    for surfel_spec in surfel_specs_by_material_name.values_mut() {
+        surfel_spec.deposit.retain(|s, _| substances.contains(s));
+    }
+
+    for source_spec in source_specs.iter_mut() {
+        if let &mut SourceSpec::Mesh(ref mut source_spec) = source_spec {
+            source_spec.absorb.retain(|s, _| substances.contains(s));
+        }
+    }
+}
+
+/// Checks that ranges are only given for substances that occur in the
+/// simulation, which would otherwise likely be misspelled.
+fn check_range_substances(
+    ranges: &HashMap<String, SubstanceRange>,
+    unique_substance_names: &[String],
+) -> Result<(), Error> {
+    // Sorted so the first unknown substance is reported consistently
+    let mut substances: Vec<&String> = ranges.keys().collect();
+    substances.sort();
+
+    match substances
+        .into_iter()
+        .find(|s| !unique_substance_names.contains(s))
+    {
+        Some(substance) => Err(Error::UnknownRangeSubstance {
+            substance: substance.clone(),
+            known: unique_substance_names.join(", "),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Checks that the expressions in `effects_at` are well-formed, since
+/// malformed expressions would silently never run the effects.
+fn check_effect_schedule(
+    effects_at: &Option<Vec<ScheduleEntry>>,
+    iterations: u32,
+) -> Result<(), Error> {
+    for entry in effects_at.iter().flat_map(|e| e.iter()) {
+        if let &ScheduleEntry::Expression(ref expression) = entry {
+            entry
+                .range(iterations)
+                .map_err(|reason| Error::InvalidEffectSchedule {
+                    entry: expression.clone(),
+                    reason,
+                })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that wear masks have a neighborhood to look for edges or cavities in.
+fn check_wear_masks(effects: &[EffectSpec]) -> Result<(), Error> {
+    for (idx, effect) in effects.iter().enumerate() {
+        let mask = match effect {
+            &EffectSpec::Wear { ref mask, .. } => Some(mask),
+            &EffectSpec::Layer { ref wear, .. } => wear.as_ref(),
+            _ => None,
+        };
+
+        if let Some(mask) = mask {
+            if !(mask.radius > 0.0) {
+                return Err(Error::InvalidWearMask {
+                    effect: format!("{}#{}", effect.kind(), idx),
+                    radius: mask.radius,
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that no effect synthesizes textures if texture coordinates are
+/// dropped while loading.
+fn check_scene_loading(loading: SceneLoading, effects: &[EffectSpec]) -> Result<(), Error> {
+    if loading != SceneLoading::Geometry {
+        return Ok(());
+    }
+
+    for (idx, effect) in effects.iter().enumerate() {
+        match effect {
+            &EffectSpec::Density { .. } | &EffectSpec::Layer { .. } | &EffectSpec::Wear { .. } => {
+                return Err(Error::TexcoordsDropped(format!("{}#{}", effect.kind(), idx)))
+            }
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+fn check_streaks(effects: &[EffectSpec]) -> Result<(), Error> {
+    for (idx, effect) in effects.iter().enumerate() {
+        if let &EffectSpec::Layer {
+            ref normal,
+            ref displacement,
+            ref albedo,
+            ref metallicity,
+            ref roughness,
+            ..
+        } = effect
+        {
+            let blends = [normal, displacement, albedo, metallicity, roughness];
+            let streaks = blends.iter().filter_map(|b| b.as_ref()).filter_map(|b| b.streaks);
+
+            for streaks in streaks {
+                if !streaks.is_valid() {
+                    return Err(Error::InvalidStreaks {
+                        effect: format!("{}#{}", effect.kind(), idx),
+                        streaks,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Blending sorts stops by their ceniths, which needs them to be numbers.
+fn check_ceniths(effects: &[EffectSpec]) -> Result<(), Error> {
+    for (idx, effect) in effects.iter().enumerate() {
+        if let &EffectSpec::Layer {
+            ref normal,
+            ref displacement,
+            ref albedo,
+            ref metallicity,
+            ref roughness,
+            ..
+        } = effect
+        {
+            let blends = [normal, displacement, albedo, metallicity, roughness];
+            let ceniths = blends
+                .iter()
+                .filter_map(|b| b.as_ref())
+                .flat_map(|b| b.stops.iter().map(|s| s.cenith));
+
+            for cenith in ceniths {
+                if !cenith.is_finite() {
+                    return Err(Error::InvalidCenith {
+                        effect: format!("{}#{}", effect.kind(), idx),
+                        cenith,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn check_entity_filters(spec: &SimulationSpec) -> Result<(), Error> {
+    let simulation = spec
+        .entities
+        .as_ref()
+        .map(|filter| (String::from("the simulation"), filter));
+    let effects = spec
+        .effects
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, effect)| {
+            effect
+                .entities()
+                .map(|filter| (format!("effect {}#{}", effect.kind(), idx), filter))
+        });
+
+    for (owner, filter) in simulation.into_iter().chain(effects) {
+        let invalid = filter.invalid_patterns();
+        if !invalid.is_empty() {
+            return Err(Error::InvalidEntityPatterns {
+                owner,
+                patterns: format!("{:?}", invalid),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn check_adaptive_resolutions(effects: &[EffectSpec]) -> Result<(), Error> {
+    for (idx, effect) in effects.iter().enumerate() {
+        if let &EffectSpec::Density {
+            adaptive_resolution: Some(adaptive_resolution),
+            ..
+        } = effect
+        {
+            if !adaptive_resolution.is_valid() {
+                return Err(Error::InvalidAdaptiveResolution {
+                    effect: format!("{}#{}", effect.kind(), idx),
+                    adaptive_resolution,
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
 fn load_entities(
-    paths: &Vec<PathBuf>,
+    scenes: &Vec<SceneSpec>,
+    loading: SceneLoading,
     surfel_specs_by_material_name: &HashMap<String, SurfelSpec>,
+    material_map: &HashMap<String, String>,
+    entity_filter: Option<&EntityFilter>,
+    uv_overlaps: UvOverlaps,
+    auto_unwrap: Option<Unwrap>,
+    strict: bool,
 ) -> Result<Vec<Entity>, Error> {
     let mut all_entities = Vec::new();
+    let mut all_material_names = BTreeSet::new();
 
-    for scene_path in paths.iter() {
-        let mut entities = obj::load(&scene_path)?;
-
-        // Throw out all entitites which have no mapped surfel spec,
-        // unless there is a fallback material named "_".
-        // This ignoring affects intersection test and surfel generation,
-        // potentially providing a massive speedup if many objects ignored.
-        if !surfel_specs_by_material_name.contains_key("_") {
-            entities.retain(|e| {
-                surfel_specs_by_material_name
-                    .keys()
-                    .any(|n| n == e.material.name())
-            });
+    // Throw out all entitites which have no mapped surfel spec,
+    // unless there is a fallback material named "_".
+    // This ignoring affects intersection test and surfel generation,
+    // potentially providing a massive speedup if many objects ignored.
+    // Surfel specs are keyed by the names from the material map.
+    let simulated = |material_name: &str| {
+        surfel_specs_by_material_name.contains_key("_")
+            || surfel_specs_by_material_name.contains_key(mapped_name(material_map, material_name))
+    };
+
+    for scene in scenes.iter() {
+        let entities = match loading {
+            SceneLoading::Full => {
+                let mut entities = obj::load(scene.path())?;
+                all_material_names.extend(
+                    entities
+                        .iter()
+                        .map(|e| mapped_name(material_map, e.material.name()).to_string()),
+                );
+                entities.retain(|e| simulated(e.material.name()));
+                entities
+            }
+            // Streaming skips unsimulated entities before they take up memory
+            SceneLoading::Streaming | SceneLoading::Geometry => {
+                let texcoords = loading == SceneLoading::Streaming;
+                let streamed = stream_obj(scene.path(), texcoords, &simulated)?;
+                all_material_names.extend(
+                    streamed
+                        .material_names
+                        .iter()
+                        .map(|n| mapped_name(material_map, n).to_string()),
+                );
+                streamed.entities
+            }
+        };
+        let mut entities = instanced_entities(
+            renamed_materials(entities, material_map),
+            scene.instances(),
+        );
+
+        // Without texture coordinates, there is nothing to warn about or unwrap
+        if loading != SceneLoading::Geometry {
+            entities = unwrapped_entities(entities, auto_unwrap);
+        }
+        if uv_overlaps == UvOverlaps::Separate {
+            entities = separated_entities(entities);
+        }
+        // After instancing and separation, so effects see the same names
+        if let Some(filter) = entity_filter {
+            entities.retain(|e| filter.admits(&e.name));
         }
 
         all_entities.extend(entities);
     }
 
+    let unmatched = unmatched_material_names(surfel_specs_by_material_name, &all_material_names);
+    if !unmatched.is_empty() && strict {
+        return Err(Error::UnmatchedSurfelSpecs {
+            unmatched: format!("{:?}", unmatched),
+            available: format!("{:?}", all_material_names),
+        });
+    } else if !unmatched.is_empty() {
+        warn!(
+            "The surfels_by_material entries {unmatched:?} match no material in the loaded scenes, renamed materials maybe? Available material names are: {available:?}",
+            unmatched = unmatched,
+            available = all_material_names,
+        );
+    }
+
     Ok(all_entities)
 }
 
+#[cfg(feature = "http")]
+fn add_notifier(runner: &mut SimulationRunner, url: &str) -> Result<(), Error> {
+    let notifier = HttpNotifier::new(url, &runner.spec().name).ok_or_else(|| {
+        Error::InvalidNotifyUrl {
+            url: url.to_string(),
+        }
+    })?;
+    runner.add_observer(Box::new(notifier));
+    Ok(())
+}
+
+#[cfg(not(feature = "http"))]
+fn add_notifier(_runner: &mut SimulationRunner, url: &str) -> Result<(), Error> {
+    Err(Error::InvalidNotifyUrl {
+        url: url.to_string(),
+    })
+}
+
+/// Loads the entities of occluder scenes, which only take part in intersection
+/// and are kept regardless of their materials.
+fn load_occluders(scenes: &Vec<SceneSpec>) -> Result<Vec<Entity>, Error> {
+    let mut occluders = Vec::new();
+    for scene in scenes.iter() {
+        occluders.extend(instanced_entities(
+            obj::load(scene.path())?,
+            scene.instances(),
+        ));
+    }
+    Ok(occluders)
+}
+
+/// Finds the keys of surfel specs by material name, except the catchall, that
+/// are not in the given set of material names. The result is sorted.
+fn unmatched_material_names<'a, V>(
+    surfel_specs_by_material_name: &'a HashMap<String, V>,
+    material_names: &BTreeSet<String>,
+) -> Vec<&'a String> {
+    let mut unmatched: Vec<&String> = surfel_specs_by_material_name
+        .keys()
+        .filter(|k| *k != "_" && !material_names.contains(*k))
+        .collect();
+
+    unmatched.sort();
+    unmatched
+}
+
 /// For faster substance access, each substance name gets an ID which is an
 /// index into the returned vector. Names can occur in sources, and surfels
 /// as initial values and as absorption/deposition rates
-fn unique_substance_names(
+pub fn unique_substance_names(
     surfel_specs: &HashMap<String, SurfelSpec>,
-    source_specs: &Vec<TonSourceSpec>,
+    source_specs: &Vec<SourceSpec>,
 ) -> Vec<String> {
     let unique_substance_names: HashSet<&String> = surfel_specs
         .values()
-        .flat_map(|s| s.initial.keys().chain(s.deposit.keys()))
+        .flat_map(|s| {
+            s.initial
+                .keys()
+                .chain(s.deposit.keys())
+                .chain(s.initial_maps.keys())
+        })
         .chain(
             source_specs
                 .iter()
+                .filter_map(|s| s.mesh())
                 .flat_map(|s| s.initial.keys().chain(s.absorb.keys())),
         )
         .collect();
@@ -171,108 +848,471 @@ fn unique_substance_names(
     unique_substance_names.into_iter().cloned().collect()
 }
 
-fn load_source_specs(
+pub fn load_source_specs(
     sources: &Vec<PathBuf>,
     resolver: &Resolver,
-) -> Result<Vec<TonSourceSpec>, Error> {
+    strict: bool,
+) -> Result<Vec<SourceSpec>, Error> {
     /*if sources.is_empty() {
         return Err(Error::SourcesMissing);
     }*/
 
     sources
         .iter()
-        .map(|s| load_source_spec(s, resolver))
+        .map(|s| load_source_spec(s, resolver, strict))
         .collect()
 }
 
-fn load_source_spec(path: &PathBuf, resolver: &Resolver) -> Result<TonSourceSpec, Error> {
+fn load_source_spec(
+    path: &PathBuf,
+    resolver: &Resolver,
+    strict: bool,
+) -> Result<SourceSpec, Error> {
     let path = resolver
         .resolve(path)
         .map_err(|e| Error::resolve(e, ResolveErrorKind::TonSourceSpec))?;
 
     let spec_file = &mut File::open(path)?;
 
-    let spec: TonSourceSpec = serde_yaml::from_reader(spec_file)?;
+    // Custom source types may have arbitrary keys, so only check
+    // unknown keys after determining the type
+    let document = serde_yaml::from_reader(spec_file)?;
+
+    SourceSpec::from_document(document, strict)
+}
+
+/// Groups iterations with the same active sources and emission counts,
+/// returning the first iteration of each group and the emission count of
+/// each source in it, or `None` for inactive sources.
+///
+/// Custom sources are always active and determine their own emission count,
+/// their count is always zero. The same goes for sources with an emission
+/// count per area, which is only known after loading the emission geometry.
+fn source_phases(sources: &[SourceSpec], iterations: u32) -> Vec<(u32, Vec<Option<usize>>)> {
+    let emission_counts = |iteration| -> Vec<Option<usize>> {
+        sources
+            .iter()
+            .map(|s| match s.mesh() {
+                Some(spec) => match spec.active_iterations {
+                    Some(ref active) if !active.contains(iteration) => None,
+                    _ => Some(
+                        spec.emission_count
+                            .as_ref()
+                            .map_or(0, |count| count.at(iteration, iterations)),
+                    ),
+                },
+                None => Some(0),
+            })
+            .collect()
+    };
+
+    let mut phases = vec![(1, emission_counts(1))];
+    for iteration in 2..(iterations + 1) {
+        let emission_counts = emission_counts(iteration);
+        if emission_counts != phases.last().unwrap().1 {
+            phases.push((iteration, emission_counts));
+        }
+    }
+
+    phases
+}
+
+/// Iterations to build sources for, whether the sources change in them and
+/// their emission counts, from phases of `source_phases`. Sources are only
+/// built for the first iteration of each phase, unless `every_iteration` is
+/// set.
+fn source_builds(
+    phases: Vec<(u32, Vec<Option<usize>>)>,
+    iterations: u32,
+    every_iteration: bool,
+) -> Vec<(u32, bool, Vec<Option<usize>>)> {
+    if !every_iteration {
+        return phases
+            .into_iter()
+            .map(|(first_iteration, emission_counts)| (first_iteration, true, emission_counts))
+            .collect();
+    }
+
+    let mut phases = phases.into_iter().peekable();
+    let mut emission_counts = Vec::new();
+    let mut builds = Vec::new();
+    for iteration in 1..(iterations.max(1) + 1) {
+        let changed = phases.peek().map_or(false, |p| p.0 == iteration);
+        if changed {
+            emission_counts = phases.next().unwrap().1;
+        }
+        builds.push((iteration, changed, emission_counts.clone()));
+    }
+
+    builds
+}
 
-    Ok(spec)
+/// Distinct transports of the given sources, where sources without a
+/// transport of their own use the given transport of the simulation.
+fn source_transports(
+    sources: &[SourceSpec],
+    transport: Option<spec::Transport>,
+) -> Vec<Option<spec::Transport>> {
+    let mut transports = Vec::new();
+    for source in sources {
+        let source_transport = source.mesh().and_then(|s| s.transport).or(transport);
+        if !transports.contains(&source_transport) {
+            transports.push(source_transport);
+        }
+    }
+    transports
 }
 
+/// Emission meshes by path, emission map and the bits of the emission cone
+/// angle, with the surface area of the mesh before weighting it with the map.
+type MeshCache =
+    HashMap<(PathBuf, Option<PathBuf>, Option<u32>), (Rc<DeinterleavedIndexedMeshBuf>, f32)>;
+
+/// Builds ton sources from the given specs, which were loaded from the paths
+/// in `source_spec_paths` with the same index, with the emission counts from
+/// `source_phases`, skipping inactive sources. The sources are grouped by
+/// burst and then by their transport, or `transport` if they have none, in
+/// order of appearance. Sources with multiple bursts are built once per
+/// burst, each with its share of the emission count.
+///
+/// Also returns the tons that each built-in source emits per iteration by
+/// its name, custom sources determine that themselves.
+///
+/// Emission meshes are looked up in `meshes` by path and emission map before
+/// loading them and are added after loading them.
 fn build_sources(
-    sources: &Vec<TonSourceSpec>,
+    source_spec_paths: &Vec<PathBuf>,
+    sources: &Vec<SourceSpec>,
+    emission_counts: &[Option<usize>],
+    transport: Option<spec::Transport>,
+    wind: Option<&Wind>,
     unique_substance_names: &Vec<String>,
     resolver: &Resolver,
-) -> Result<Vec<TonSource>, Error> {
-    sources
+    source_factories: &HashMap<String, Box<TonSourceFactory>>,
+    meshes: &mut MeshCache,
+) -> Result<(Vec<SourceGroup>, Vec<(String, usize)>), Error> {
+    let mut groups: Vec<SourceGroup> = Vec::new();
+    let mut emissions = Vec::new();
+    let active = source_spec_paths
         .iter()
-        .map(|spec| {
-            let mesh_scene = resolver
-                .resolve(&spec.mesh)
-                .map_err(|e| Error::resolve(e, ResolveErrorKind::TonSourceMesh))?;
+        .zip(sources.iter())
+        .zip(emission_counts)
+        .filter_map(|(source, &count)| count.map(|count| (source, count)));
 
-            let mesh_scene = &obj::load(&mesh_scene)?;
+    for ((spec_path, spec), emission_count) in active {
+        let bursts = spec.mesh().map_or(1, |s| s.bursts());
+        if bursts == 0 {
+            return Err(Error::InvalidBursts(spec_path.clone()));
+        }
 
-            let mesh = if mesh_scene.len() == 0 {
-                panic!("Emission mesh scene does not contain any entities")
-            } else if mesh_scene.len() == 1 {
-                Rc::clone(&mesh_scene.into_iter().next().unwrap().mesh)
-            } else {
-                // Combine everything in the source mesh scene into a megamesh
-                // when encountering more than one entity
-                Rc::new(
-                    mesh_scene
-                        .iter()
-                        .flat_map(|m| {
-                            m.mesh.triangles().flat_map(|t| {
-                                let TupleTriangle(v0, v1, v2) = t;
-                                vec![v0, v1, v2].into_iter()
-                            })
+        let mut emitted = 0;
+        for burst in 0..bursts {
+            let source = match spec {
+                &SourceSpec::Mesh(ref spec) => build_mesh_source(
+                    spec_path,
+                    spec,
+                    emission_count,
+                    burst,
+                    wind,
+                    unique_substance_names,
+                    resolver,
+                    meshes,
+                ).map(|(source, burst_count)| {
+                    emitted += burst_count;
+                    source
+                }),
+                &SourceSpec::Custom { ref kind, ref spec } => {
+                    let factory = source_factories.get(kind).ok_or_else(|| {
+                        Error::UnknownSourceType {
+                            source_spec: spec_path.clone(),
+                            kind: kind.clone(),
+                        }
+                    })?;
+
+                    factory
+                        .build(spec, spec_path, unique_substance_names)
+                        .map_err(|e| Error::CustomSource {
+                            source_spec: spec_path.clone(),
+                            cause: e.compat(),
                         })
-                        .collect::<DeinterleavedIndexedMeshBuf>(),
-                )
-            };
+                }
+            }?;
+
+            let source_transport = spec.mesh().and_then(|s| s.transport).or(transport);
+            let group = groups
+                .iter()
+                .position(|g| g.burst == burst && g.transport == source_transport);
+            match group {
+                Some(idx) => groups[idx].sources.push(source),
+                None => groups.push(SourceGroup {
+                    transport: source_transport,
+                    burst,
+                    sources: vec![source],
+                }),
+            }
+        }
+
+        if let Some(spec) = spec.mesh() {
+            emissions.push((spec.name.clone(), emitted));
+        }
+    }
+
+    // Stable, so groups of a burst stay in order of appearance
+    groups.sort_by_key(|g| g.burst);
+    Ok((groups, emissions))
+}
 
-            let mut builder = TonSourceBuilder::new();
+/// Resolves a file referenced by a ton source or surfel spec loaded from
+/// `spec_path`, i.e. an emission mesh, emission map or initial map.
+pub fn resolve_source_file(
+    spec_path: &Path,
+    file: &Path,
+    kind: ResolveErrorKind,
+    resolver: &Resolver,
+) -> Result<PathBuf, Error> {
+    // Files may also be relative to the source spec, e.g. on the
+    // server the spec was downloaded from.
+    let mut resolver = resolver.clone();
+    if let Some(spec_dir) = spec_path.parent() {
+        resolver
+            .add_base(spec_dir)
+            .map_err(|e| Error::resolve(e, kind))?;
+    }
+
+    resolver.resolve(file).map_err(|e| Error::resolve(e, kind))
+}
+
+/// Loads an emission mesh, combining all of the objects in it, and returns
+/// it with its surface area.
+fn load_emission_mesh(
+    spec_path: &PathBuf,
+    mesh_path: &PathBuf,
+) -> Result<(Rc<DeinterleavedIndexedMeshBuf>, f32), Error> {
+    let mesh_scene = &obj::load(mesh_path)?;
+
+    let mesh = if mesh_scene.len() == 0 {
+        return Err(Error::EmptyEmissionMesh {
+            source_spec: spec_path.clone(),
+            mesh: mesh_path.clone(),
+        });
+    } else if mesh_scene.len() == 1 {
+        Rc::clone(&mesh_scene.into_iter().next().unwrap().mesh)
+    } else {
+        // Combine everything in the source mesh scene into a megamesh
+        // when encountering more than one entity
+        Rc::new(
+            mesh_scene
+                .iter()
+                .flat_map(|m| {
+                    m.mesh.triangles().flat_map(|t| {
+                        let TupleTriangle(v0, v1, v2) = t;
+                        vec![v0, v1, v2].into_iter()
+                    })
+                })
+                .collect::<DeinterleavedIndexedMeshBuf>(),
+        )
+    };
+
+    // Diffuse emission picks origins on the triangles weighted by area,
+    // which is impossible without any area
+    let area: f32 = mesh.triangles().map(|t| t.area()).sum();
+    if !(area > 0.0) {
+        return Err(Error::DegenerateEmissionMesh {
+            source_spec: spec_path.clone(),
+            mesh: mesh_path.clone(),
+        });
+    }
+
+    Ok((mesh, area))
+}
+
+/// Emission mesh made from the given triangles with emission weighted by the
+/// luminance of an emission map.
+fn weighted_mesh<I>(
+    spec_path: &PathBuf,
+    triangles: I,
+    map_path: &PathBuf,
+) -> Result<Rc<DeinterleavedIndexedMeshBuf>, Error>
+where
+    I: IntoIterator<Item = TupleTriangle<Vertex>>,
+{
+    let map = tex::open(map_path)
+        .map_err(|cause| Error::UnloadableEmissionMap {
+            source_spec: spec_path.clone(),
+            map: map_path.clone(),
+            cause,
+        })?
+        .to_luma();
+
+    let vertices = weight_by_map(triangles, &map);
+    if vertices.is_empty() {
+        return Err(Error::BlackEmissionMap {
+            source_spec: spec_path.clone(),
+            map: map_path.clone(),
+        });
+    }
 
-            if let Some(ref direction_arr) = spec.flow_direction {
-                builder = builder.flow_direction_static(Vec3::new(
-                    direction_arr[0],
-                    direction_arr[1],
-                    direction_arr[2],
-                ));
+    Ok(Rc::new(vertices.into_iter().collect()))
+}
+
+/// Tons emitted in the given burst of `bursts` when emitting `emission_count`
+/// per iteration, with the remainder in the first bursts.
+fn burst_share(emission_count: usize, burst: u32, bursts: u32) -> usize {
+    let (burst, bursts) = (burst as usize, bursts as usize);
+    emission_count / bursts + if burst < emission_count % bursts { 1 } else { 0 }
+}
+
+/// Emission mesh with copies of the triangles tilted into the directions of
+/// the emission cone, if any.
+fn spread_mesh(
+    mesh: Rc<DeinterleavedIndexedMeshBuf>,
+    cone: Option<f32>,
+) -> Rc<DeinterleavedIndexedMeshBuf> {
+    match cone {
+        Some(degrees) => Rc::new(spread_vertices(mesh.triangles(), degrees).into_iter().collect()),
+        None => mesh,
+    }
+}
+
+/// Emission mesh with normals tilted into the wind, if any.
+fn blown_mesh(
+    mesh: Rc<DeinterleavedIndexedMeshBuf>,
+    wind: Option<&Wind>,
+) -> Rc<DeinterleavedIndexedMeshBuf> {
+    match wind {
+        Some(wind) => {
+            let vertices = mesh.triangles().flat_map(|TupleTriangle(a, b, c)| vec![a, b, c]);
+            Rc::new(blown_vertices(vertices, wind).into_iter().collect())
+        }
+        None => mesh,
+    }
+}
+
+/// Builds a source for the given burst, returned with the tons it emits.
+fn build_mesh_source(
+    spec_path: &PathBuf,
+    spec: &TonSourceSpec,
+    emission_count: usize,
+    burst: u32,
+    wind: Option<&Wind>,
+    unique_substance_names: &Vec<String>,
+    resolver: &Resolver,
+    meshes: &mut MeshCache,
+) -> Result<(TonSource, usize), Error> {
+    let emission_map = match spec.emission_map {
+        Some(ref map) => Some(resolve_source_file(
+            spec_path,
+            map,
+            ResolveErrorKind::EmissionMap,
+            resolver,
+        )?),
+        None => None,
+    };
+    let cone = spec.emission_cone_degrees;
+    if let Some(degrees) = cone {
+        if !(degrees >= 0.0 && degrees <= 180.0) {
+            return Err(Error::InvalidEmissionCone(spec_path.clone(), degrees));
+        }
+    }
+
+    let (mesh, area) = match (&spec.mesh, &spec.shape) {
+        (&Some(ref mesh), &None) => {
+            let mesh_path =
+                resolve_source_file(spec_path, mesh, ResolveErrorKind::TonSourceMesh, resolver)?;
+            let key = (mesh_path, emission_map, cone.map(f32::to_bits));
+            match meshes.get(&key).cloned() {
+                Some(mesh) => mesh,
+                None => {
+                    let (mesh, area) = load_emission_mesh(spec_path, &key.0)?;
+                    let mesh = match key.1 {
+                        Some(ref map) => weighted_mesh(spec_path, mesh.triangles(), map)?,
+                        None => mesh,
+                    };
+                    let mesh = blown_mesh(spread_mesh(mesh, cone), wind);
+                    meshes.insert(key, (Rc::clone(&mesh), area));
+                    (mesh, area)
+                }
             }
+        }
+        (&None, &Some(ref shape)) => {
+            let vertices = emitter_vertices(shape)
+                .ok_or_else(|| Error::DegenerateEmissionShape(spec_path.clone()))?;
+            let triangles = vertices
+                .chunks(3)
+                .map(|t| TupleTriangle(t[0], t[1], t[2]));
+            let area = triangles.clone().map(|t| t.area()).sum();
+            let mesh = match emission_map {
+                Some(ref map) => weighted_mesh(spec_path, triangles, map)?,
+                None => Rc::new(vertices.iter().cloned().collect()),
+            };
+            (blown_mesh(spread_mesh(mesh, cone), wind), area)
+        }
+        _ => return Err(Error::AmbiguousEmissionShape(spec_path.clone())),
+    };
 
-            let source = builder
-                .mesh_shaped(&mesh, spec.diffuse)
-                .emission_count(spec.emission_count)
-                .p_straight(spec.p_straight)
-                .p_parabolic(spec.p_parabolic)
-                .p_flow(spec.p_flow)
-                .substances(&extract_keys(&spec.initial, unique_substance_names, 0.0))
-                .pickup_rates(extract_keys(&spec.absorb, unique_substance_names, 0.0))
-                .interaction_radius(spec.interaction_radius)
-                .parabola_height(spec.parabola_height)
-                .flow_distance(spec.flow_distance)
-                .build();
-
-            Ok(source)
-        })
-        .collect()
+    let emission_count = match (&spec.emission_count, spec.emission_per_area, &spec.shape) {
+        (&Some(_), None, _) => emission_count,
+        (&None, Some(_), &Some(EmitterShape::Point { .. }))
+        | (&None, Some(_), &Some(EmitterShape::Cone { .. })) => {
+            return Err(Error::EmissionPerAreaOfPoint(spec_path.clone()))
+        }
+        (&None, Some(per_area), _) => {
+            let emission_count = (per_area * area).round() as usize;
+            info!(
+                "{} emits {} tons per iteration from an area of {}.",
+                spec.name, emission_count, area
+            );
+            emission_count
+        }
+        _ => return Err(Error::AmbiguousEmissionCount(spec_path.clone())),
+    };
+    let emission_count = burst_share(emission_count, burst, spec.bursts());
+    // Generated shapes emit along their normals, randomizing would defeat them,
+    // and spread emission is tilted around the normals instead
+    let diffuse = spec.diffuse && spec.shape.is_none() && cone.is_none();
+
+    let mut builder = TonSourceBuilder::new();
+
+    let flow_direction = match wind {
+        Some(wind) => blown_flow_direction(spec.flow_direction, wind),
+        None => spec.flow_direction.map(vec3),
+    };
+    if let Some(flow_direction) = flow_direction {
+        builder = builder.flow_direction_static(flow_direction);
+    }
+
+    let source = builder
+        .mesh_shaped(&mesh, diffuse)
+        .emission_count(emission_count)
+        .p_straight(spec.p_straight)
+        .p_parabolic(spec.p_parabolic)
+        .p_flow(spec.p_flow)
+        .substances(&extract_keys(&spec.initial, unique_substance_names, 0.0))
+        .pickup_rates(extract_keys(&spec.absorb, unique_substance_names, 0.0))
+        .interaction_radius(spec.interaction_radius)
+        .parabola_height(spec.parabola_height)
+        .flow_distance(spec.flow_distance)
+        .build();
+
+    Ok((source, emission_count))
 }
 
-fn surfel_specs_by_material_name(
+pub fn surfel_specs_by_material_name(
     spec: &SimulationSpec,
     resolver: &Resolver,
+    strict: bool,
 ) -> Result<HashMap<String, SurfelSpec>, Error> {
     let mut specs = HashMap::with_capacity(spec.surfels_by_material.len());
 
     for (material_name, surfel_spec) in spec.surfels_by_material.iter() {
-        let surfel_spec = &mut File::open(
-            resolver
-                .resolve(surfel_spec)
-                .map_err(|e| Error::resolve(e, ResolveErrorKind::SurfelSpec))?,
-        )?;
+        let path = resolver
+            .resolve(surfel_spec)
+            .map_err(|e| Error::resolve(e, ResolveErrorKind::SurfelSpec))?;
 
-        let surfel_spec: SurfelSpec = serde_yaml::from_reader(surfel_spec)?;
+        let mut surfel_spec: SurfelSpec = parse_spec(&mut File::open(&path)?, strict)?;
+        for map in surfel_spec.initial_maps.values_mut() {
+            *map = resolve_source_file(&path, map, ResolveErrorKind::InitialMap, resolver)?;
+        }
 
         specs.insert(material_name.clone(), surfel_spec);
     }
@@ -284,23 +1324,149 @@ fn surfel_specs_by_material_name(
     }
 }
 
+/// Loads the surfel specs and materials of material swaps and checks their
+/// substances.
+fn build_material_swaps(
+    specs: &[MaterialSwapSpec],
+    entities: &[Entity],
+    unique_substance_names: &Vec<String>,
+    resolver: &Resolver,
+    strict: bool,
+) -> Result<Vec<MaterialSwap>, Error> {
+    specs
+        .iter()
+        .enumerate()
+        .map(|(idx, spec)| {
+            let substance_idx = unique_substance_names
+                .iter()
+                .position(|n| *n == spec.substance)
+                .ok_or_else(|| Error::UnknownSwapSubstance {
+                    swap: idx,
+                    substance: spec.substance.clone(),
+                    known: unique_substance_names.join(", "),
+                })?;
+
+            let surfel_spec: SurfelSpec = parse_spec(
+                &mut File::open(
+                    resolver
+                        .resolve(&spec.surfels)
+                        .map_err(|e| Error::resolve(e, ResolveErrorKind::SurfelSpec))?,
+                )?,
+                strict,
+            )?;
+
+            let material = match spec.material {
+                Some(ref name) => Some(
+                    entities
+                        .iter()
+                        .find(|e| e.material.name() == name)
+                        .map(|e| Rc::clone(&e.material))
+                        .ok_or_else(|| Error::UnknownSwapMaterial {
+                            swap: idx,
+                            material: name.clone(),
+                            available: entities
+                                .iter()
+                                .map(|e| e.material.name())
+                                .collect::<BTreeSet<_>>()
+                                .into_iter()
+                                .collect::<Vec<_>>()
+                                .join(", "),
+                        })?,
+                ),
+                None => None,
+            };
+
+            Ok(MaterialSwap {
+                spec: spec.clone(),
+                substance_idx,
+                // Entity is ignored, the surfels keep theirs
+                surfel: proto_surfel(0, &surfel_spec, unique_substance_names),
+                rules: entity_rules(&surfel_spec.rules, unique_substance_names),
+                material,
+            })
+        })
+        .collect()
+}
+
+/// Surfel properties from a surfel spec with initial concentrations.
+fn proto_surfel(
+    entity_idx: usize,
+    surfel_spec: &SurfelSpec,
+    unique_substance_names: &Vec<String>,
+) -> SurfelData {
+    let default_substance_concentration = 0.0;
+    let default_deposition_rate = 0.0;
+
+    // Rules with a probability are drawn for each iteration
+    let rules = entity_rules(&surfel_spec.rules, unique_substance_names).always;
+
+    SurfelData {
+        entity_idx,
+        delta_straight: surfel_spec.reflectance.delta_straight,
+        delta_parabolic: surfel_spec.reflectance.delta_parabolic,
+        delta_flow: surfel_spec.reflectance.delta_flow,
+        substances: extract_keys(
+            &surfel_spec.initial,
+            &unique_substance_names,
+            default_substance_concentration,
+        ),
+        /// Weights for the transport of substances from a settled ton to a surfel
+        deposition_rates: extract_keys(
+            &surfel_spec.deposit,
+            &unique_substance_names,
+            default_deposition_rate,
+        ),
+        rules,
+    }
+}
+
+/// Loads the initial maps of the surfel specs as grayscale images, by the
+/// material name the surfel spec is mapped to and the substance index.
+fn load_initial_maps(
+    surfel_specs_by_material_name: &HashMap<String, SurfelSpec>,
+    unique_substance_names: &Vec<String>,
+) -> Result<HashMap<String, Vec<(usize, GrayImage)>>, Error> {
+    let mut maps_by_material_name = HashMap::new();
+
+    for (material_name, surfel_spec) in surfel_specs_by_material_name.iter() {
+        let mut maps = Vec::with_capacity(surfel_spec.initial_maps.len());
+        for (substance, map_path) in surfel_spec.initial_maps.iter() {
+            let substance_idx = unique_substance_names
+                .iter()
+                .position(|n| n == substance)
+                .expect("Substances of initial maps are unique substances");
+            let map = tex::open(map_path)
+                .map_err(|cause| Error::UnloadableInitialMap {
+                    substance: substance.clone(),
+                    map: map_path.clone(),
+                    cause,
+                })?
+                .to_luma();
+            maps.push((substance_idx, map));
+        }
+
+        if !maps.is_empty() {
+            maps_by_material_name.insert(material_name.clone(), maps);
+        }
+    }
+
+    Ok(maps_by_material_name)
+}
+
 fn build_surface(
     entities: &Vec<Entity>,
     surfel_specs_by_material_name: &HashMap<String, SurfelSpec>,
     unique_substance_names: &Vec<String>,
-    surfel_distance: f32,
+    initial_maps: &HashMap<String, Vec<(usize, GrayImage)>>,
+    sampling: spec::SurfelSampling,
 ) -> Surface<Surfel<Vertex, SurfelData>> {
     let catchall_surfel_spec = surfel_specs_by_material_name.get("_");
-    let default_substance_concentration = 0.0;
-    let default_deposition_rate = 0.0;
 
-    entities
+    let mut surface = entities
         .iter()
         .enumerate()
         .fold(
-            SurfaceBuilder::new()
-                // TODO make this configurable
-                .sampling(SurfelSampling::MinimumDistance(surfel_distance)),
+            SurfaceBuilder::new(),
             |b, (entity_idx, ent)| {
                 let material_name = ent.material.name();
 
@@ -309,44 +1475,228 @@ fn build_surface(
                     .or(catchall_surfel_spec);
 
                 if let Some(surfel_spec) = surfel_spec {
-                    let rules = surfel_spec
-                        .rules
-                        .iter()
-                        .map(|r| rule_by_spec(r, &unique_substance_names))
-                        .collect();
-
-                    let proto_surfel = SurfelData {
-                        entity_idx,
-                        delta_straight: surfel_spec.reflectance.delta_straight,
-                        delta_parabolic: surfel_spec.reflectance.delta_parabolic,
-                        delta_flow: surfel_spec.reflectance.delta_flow,
-                        substances: extract_keys(
-                            &surfel_spec.initial,
-                            &unique_substance_names,
-                            default_substance_concentration,
-                        ),
-                        /// Weights for the transport of substances from a settled ton to a surfel
-                        deposition_rates: extract_keys(
-                            &surfel_spec.deposit,
-                            &unique_substance_names,
-                            default_deposition_rate,
-                        ),
-                        rules,
-                    };
+                    let proto_surfel =
+                        proto_surfel(entity_idx, surfel_spec, unique_substance_names);
 
                     info!(
-                        "Sampling entity \"{}\" into surfel representation, 2r={}…",
-                        ent.name, surfel_distance
+                        "Sampling entity \"{}\" into surfel representation, {:?}…",
+                        ent.name, sampling
                     );
 
-                    b.sample_triangles(ent.mesh.triangles(), &proto_surfel)
+                    sampling_batches(ent.mesh.triangles(), sampling)
+                        .into_iter()
+                        .fold(b, |b, (sampling, triangles)| {
+                            b.sampling(sampling).sample_triangles(triangles, &proto_surfel)
+                        })
                 } else {
                     // If no surfel spec is defined in the YAML, ignore the entity for the simulation
                     b
                 }
             },
         )
-        .build()
+        .build();
+
+    if !initial_maps.is_empty() {
+        // Initial maps of the surfel spec that each entity uses, if any
+        let maps_by_entity: Vec<_> = entities
+            .iter()
+            .map(|e| {
+                let material_name = e.material.name();
+                if surfel_specs_by_material_name.contains_key(material_name) {
+                    initial_maps.get(material_name)
+                } else {
+                    initial_maps.get("_")
+                }
+            })
+            .collect();
+
+        for surfel in surface.samples.iter_mut() {
+            let texcoords = surfel.vertex().texcoords;
+            let data = surfel.data_mut();
+            if let Some(maps) = maps_by_entity[data.entity_idx] {
+                for &(substance_idx, ref map) in maps.iter() {
+                    data.substances[substance_idx] = luminance(map, texcoords);
+                }
+            }
+        }
+    }
+
+    surface
+}
+
+/// Surfel sampling of the spec, where `surfel_sampling` takes precedence over
+/// the older `surfel_distance`.
+fn surfel_sampling(spec: &SimulationSpec) -> Result<spec::SurfelSampling, Error> {
+    match (spec.surfel_sampling, spec.surfel_distance) {
+        (Some(sampling), _) if sampling.is_valid() => Ok(sampling),
+        (Some(sampling), _) => Err(Error::InvalidSurfelSampling(sampling)),
+        (None, Some(distance)) if distance > 0.0 => {
+            Ok(spec::SurfelSampling::MinimumDistance(distance))
+        }
+        (None, distance) => Err(Error::InvalidSurfelDistance(distance)),
+    }
+}
+
+/// Groups the given triangles by the sampling they are sampled with.
+///
+/// Samplings that depend on the triangle are approximated with dart throwing
+/// on each triangle, with a minimum distance that fits the desired amount of
+/// surfels into it if they were packed hexagonally. Triangles without area or
+/// surfels are skipped.
+fn sampling_batches<I>(
+    triangles: I,
+    sampling: spec::SurfelSampling,
+) -> Vec<(SurfelSampling, Vec<TupleTriangle<Vertex>>)>
+where
+    I: IntoIterator<Item = TupleTriangle<Vertex>>,
+{
+    // Surfels on each triangle, or on the whole texture for per texel sampling
+    let (surfels, per_texture) = match sampling {
+        spec::SurfelSampling::MinimumDistance(distance) => {
+            let triangles = triangles.into_iter().collect();
+            return vec![(SurfelSampling::MinimumDistance(distance), triangles)];
+        }
+        spec::SurfelSampling::PerSqrUnit(count) => {
+            let triangles = triangles.into_iter().collect();
+            return vec![(SurfelSampling::PerSqrUnit(count), triangles)];
+        }
+        spec::SurfelSampling::PerTriangle(count) => (count as f32, false),
+        spec::SurfelSampling::PerTexel { width, height } => ((width * height) as f32, true),
+    };
+
+    triangles
+        .into_iter()
+        .filter_map(|triangle| {
+            let area = triangle.area();
+            let count = if per_texture {
+                surfels * texcoord_area(&triangle)
+            } else {
+                surfels
+            };
+
+            if area > 0.0 && count > 0.0 {
+                // Hexagonally packed surfels with distance d cover sqrt(3)/2 d² each
+                let distance = (2.0 * area / (3.0f32.sqrt() * count)).sqrt();
+                Some((SurfelSampling::MinimumDistance(distance), vec![triangle]))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Area of the triangle in texture space, where the whole texture has area one.
+fn texcoord_area(triangle: &TupleTriangle<Vertex>) -> f32 {
+    let TupleTriangle(ref a, ref b, ref c) = *triangle;
+    let (ab, ac) = (b.texcoords - a.texcoords, c.texcoords - a.texcoords);
+    0.5 * (ab.x * ac.y - ab.y * ac.x).abs()
+}
+
+/// Stochastic rules of each entity and the global rules, or `None` if no rule
+/// has a probability, not even after swapping materials.
+fn build_stochastic_rules(
+    seed: u64,
+    global_rules: &EntityRules,
+    entities: &[Entity],
+    surfel_specs_by_material_name: &HashMap<String, SurfelSpec>,
+    material_swaps: &[MaterialSwap],
+    unique_substance_names: &[String],
+) -> Option<StochasticRules> {
+    let catchall_surfel_spec = surfel_specs_by_material_name.get("_");
+    let by_entity = entities
+        .iter()
+        .map(|e| {
+            surfel_specs_by_material_name
+                .get(e.material.name())
+                .or(catchall_surfel_spec)
+                .map_or_else(EntityRules::default, |s| {
+                    entity_rules(&s.rules, unique_substance_names)
+                })
+        })
+        .collect::<Vec<_>>();
+
+    let stochastic = global_rules.is_stochastic()
+        || by_entity.iter().any(EntityRules::is_stochastic)
+        || material_swaps.iter().any(|s| s.rules.is_stochastic());
+
+    if stochastic {
+        Some(StochasticRules::new(
+            seed,
+            by_entity,
+            global_rules.sometimes.clone(),
+        ))
+    } else {
+        None
+    }
+}
+
+/// Changes of substance totals while tracing that the sources and rules
+/// explain, for `--audit`. Tons carry substances to surfels and pick them up,
+/// custom sources may do either with any substance.
+fn substance_budgets(
+    source_specs: &[SourceSpec],
+    global_rules: &EntityRules,
+    surfel_specs_by_material_name: &HashMap<String, SurfelSpec>,
+    material_swaps: &[MaterialSwap],
+    unique_substance_names: &[String],
+) -> Vec<Budget> {
+    let mut budgets = vec![Budget::default(); unique_substance_names.len()];
+    let substance_idx = |name: &String| unique_substance_names.iter().position(|n| n == name);
+
+    for source_spec in source_specs.iter() {
+        match source_spec {
+            &SourceSpec::Mesh(ref spec) => {
+                for (name, _) in spec.initial.iter().filter(|&(_, &c)| c > 0.0) {
+                    if let Some(idx) = substance_idx(name) {
+                        budgets[idx].may_grow = true;
+                    }
+                }
+                for (name, _) in spec.absorb.iter().filter(|&(_, &r)| r > 0.0) {
+                    if let Some(idx) = substance_idx(name) {
+                        budgets[idx].may_shrink = true;
+                    }
+                }
+            }
+            &SourceSpec::Custom { .. } => {
+                for budget in budgets.iter_mut() {
+                    *budget = Budget::unbounded();
+                }
+            }
+        }
+    }
+
+    let surfel_rules = surfel_specs_by_material_name
+        .values()
+        .map(|s| entity_rules(&s.rules, unique_substance_names));
+    let swap_rules = material_swaps.iter().map(|s| s.rules.clone());
+    for rules in Some(global_rules.clone())
+        .into_iter()
+        .chain(surfel_rules)
+        .chain(swap_rules)
+    {
+        for rule in rules
+            .always
+            .iter()
+            .chain(rules.sometimes.iter().map(|&(ref rule, _)| rule))
+        {
+            explain_rule(&mut budgets, rule);
+        }
+    }
+
+    budgets
+}
+
+/// Surfel rules from the given specs, split by whether they have a probability.
+fn entity_rules(specs: &[SurfelRuleSpec], unique_substance_names: &[String]) -> EntityRules {
+    let mut rules = EntityRules::default();
+    for spec in specs {
+        let rule = rule_by_spec(spec, unique_substance_names);
+        match spec.probability() {
+            Some(probability) => rules.sometimes.push((rule, probability)),
+            None => rules.always.push(rule),
+        }
+    }
+    rules
 }
 
 fn rule_by_spec(spec: &SurfelRuleSpec, unique_substance_names: &[String]) -> SurfelRule {
@@ -355,6 +1705,7 @@ fn rule_by_spec(spec: &SurfelRuleSpec, unique_substance_names: &[String]) -> Sur
             ref from,
             ref to,
             factor,
+            ..
         } => SurfelRule::Transfer {
             source_substance_idx: unique_substance_names
                 .iter()
@@ -371,7 +1722,9 @@ fn rule_by_spec(spec: &SurfelRuleSpec, unique_substance_names: &[String]) -> Sur
             ),
             factor,
         },
-        &SurfelRuleSpec::Deteriorate { ref from, factor } => SurfelRule::Deteriorate {
+        &SurfelRuleSpec::Deteriorate {
+            ref from, factor, ..
+        } => SurfelRule::Deteriorate {
             substance_idx: unique_substance_names
                 .iter()
                 .position(|n| n == from)
@@ -381,7 +1734,7 @@ fn rule_by_spec(spec: &SurfelRuleSpec, unique_substance_names: &[String]) -> Sur
                 )),
             factor,
         },
-        &SurfelRuleSpec::Deposit { ref to, amount } => SurfelRule::Deposit {
+        &SurfelRuleSpec::Deposit { ref to, amount, .. } => SurfelRule::Deposit {
             substance_idx: unique_substance_names
                 .iter()
                 .position(|n| n == to)
@@ -401,3 +1754,226 @@ fn extract_keys<K: Eq + Hash, V: Clone>(map: &HashMap<K, V>, keys: &Vec<K>, defa
         .map(|k| map.get(k).unwrap_or(&default).clone())
         .collect()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geom::{Vec2, Vec3};
+    use serde_yaml;
+
+    #[test]
+    fn unknown_layer_substance() {
+        let effects: Vec<EffectSpec> = serde_yaml::from_str(
+            "- layer:
+                materials: []
+                substance: rsut",
+        ).unwrap();
+        let substances = vec![String::from("humidity"), String::from("rust")];
+
+        match check_effect_substances(&effects, &substances) {
+            Err(Error::UnknownEffectSubstance {
+                effect,
+                substance,
+                known,
+            }) => {
+                assert_eq!("layer#0", effect);
+                assert_eq!("rsut", substance);
+                assert_eq!("humidity, rust", known);
+            }
+            _ => panic!("Expected unknown substance to be detected"),
+        }
+
+        let effects: Vec<EffectSpec> = serde_yaml::from_str(
+            "- layer:
+                materials: []
+                substance: rust",
+        ).unwrap();
+        assert!(check_effect_substances(&effects, &substances).is_ok());
+    }
+
+    #[test]
+    fn exclude_substances_from_transport() {
+        let known = vec![String::from("humidity"), String::from("rust")];
+        let rust = vec![String::from("rust")];
+        assert!(check_filter_substances(&rust, &known).is_ok());
+        match check_filter_substances(&[String::from("moss")], &known) {
+            Err(Error::UnknownFilterSubstance { substance, .. }) => assert_eq!("moss", substance),
+            _ => panic!("Expected unknown substance to be rejected"),
+        }
+
+        let surfel_spec: SurfelSpec = serde_yaml::from_str(
+            "
+            name: iron
+            description: Rusts where humid
+            reflectance: { delta_straight: 0.1, delta_parabolic: 0.1, delta_flow: 0.1 }
+            initial: { humidity: 0.5, rust: 0.1 }
+            deposit: { humidity: 1.0, rust: 1.0 }",
+        ).unwrap();
+        let mut surfel_specs = HashMap::new();
+        surfel_specs.insert(String::from("_"), surfel_spec);
+        exclude_from_transport(&rust, &mut surfel_specs, &mut []);
+
+        let surfel_spec = &surfel_specs["_"];
+        assert_eq!(vec![&String::from("rust")], surfel_spec.deposit.keys().collect::<Vec<_>>());
+        // Excluded substances keep their initial concentrations
+        assert_eq!(2, surfel_spec.initial.len());
+    }
+
+    #[test]
+    fn wear_mask_radius() {
+        let effects: Vec<EffectSpec> = serde_yaml::from_str(
+            "
+            - wear:
+                width: 512
+                height: 512
+                radius: 0.05
+                kind: cavities
+                tex_pattern: out/{entity}-wear.png
+            - layer:
+                materials: []
+                substance: rust
+                wear: { radius: 0.0 }",
+        ).unwrap();
+
+        match check_wear_masks(&effects) {
+            Err(Error::InvalidWearMask { effect, radius }) => {
+                assert_eq!("layer#1", effect);
+                assert_eq!(0.0, radius);
+            }
+            _ => panic!("Expected wear mask without radius to be rejected"),
+        }
+        assert!(check_wear_masks(&effects[..1]).is_ok());
+    }
+
+    #[test]
+    fn phases_of_scheduled_sources() {
+        let source = |active_iterations: &str| {
+            let mut spec: TonSourceSpec =
+                serde_yaml::from_reader(File::open("tests/examples/rain.yml").unwrap()).unwrap();
+            spec.active_iterations = serde_yaml::from_str(active_iterations).unwrap();
+            SourceSpec::Mesh(spec)
+        };
+        let rain = source("{ from: 1, to: 3 }");
+        let dust = source("{ from: 4 }");
+        let gusts = source("[2, 6]");
+        let rain_count = Some(100000);
+
+        assert_eq!(
+            vec![
+                (1, vec![rain_count, None, None]),
+                (2, vec![rain_count, None, rain_count]),
+                (3, vec![rain_count, None, None]),
+                (4, vec![None, rain_count, None]),
+                (6, vec![None, rain_count, rain_count]),
+            ],
+            source_phases(&[rain, dust, gusts], 6)
+        );
+
+        let mut tapering = source("{ from: 1 }");
+        if let SourceSpec::Mesh(ref mut spec) = tapering {
+            spec.emission_count = serde_yaml::from_str("{ from: 100, to: 0 }").unwrap();
+        }
+        assert_eq!(
+            vec![(1, vec![Some(100)]), (2, vec![Some(50)]), (3, vec![Some(0)])],
+            source_phases(&[tapering], 3)
+        );
+    }
+
+    #[test]
+    fn transports_of_sources() {
+        let source = |transport: Option<spec::Transport>| {
+            let mut spec: TonSourceSpec =
+                serde_yaml::from_reader(File::open("tests/examples/rain.yml").unwrap()).unwrap();
+            spec.transport = transport;
+            SourceSpec::Mesh(spec)
+        };
+        let classic = Some(spec::Transport::Classic);
+        let conserving = Some(spec::Transport::Conserving);
+        let sources = [source(None), source(classic), source(conserving)];
+
+        assert_eq!(
+            vec![conserving, classic],
+            source_transports(&sources, conserving)
+        );
+        assert_eq!(
+            vec![None, classic, conserving],
+            source_transports(&sources, None)
+        );
+    }
+
+    #[test]
+    fn sources_for_every_iteration() {
+        let phases = vec![(1, vec![Some(10)]), (3, vec![None])];
+
+        assert_eq!(
+            vec![(1, true, vec![Some(10)]), (3, true, vec![None])],
+            source_builds(phases.clone(), 4, false)
+        );
+        assert_eq!(
+            vec![
+                (1, true, vec![Some(10)]),
+                (2, false, vec![Some(10)]),
+                (3, true, vec![None]),
+                (4, false, vec![None]),
+            ],
+            source_builds(phases, 4, true)
+        );
+    }
+
+    #[test]
+    fn split_emission_into_bursts() {
+        let shares: Vec<usize> = (0..3).map(|burst| burst_share(10, burst, 3)).collect();
+        assert_eq!(vec![4, 3, 3], shares);
+        assert_eq!(10, burst_share(10, 0, 1));
+    }
+
+    #[test]
+    fn sampling_per_texel() {
+        let vertex = |x: f32, y: f32, u: f32, v: f32| Vertex {
+            position: Vec3::new(x, y, 0.0),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            texcoords: Vec2::new(u, v),
+        };
+        let mapped = TupleTriangle(
+            vertex(0.0, 0.0, 0.0, 0.0),
+            vertex(2.0, 0.0, 1.0, 0.0),
+            vertex(0.0, 2.0, 0.0, 1.0),
+        );
+        let unmapped = TupleTriangle(
+            vertex(0.0, 0.0, 0.0, 0.0),
+            vertex(2.0, 0.0, 0.0, 0.0),
+            vertex(0.0, 2.0, 0.0, 0.0),
+        );
+
+        let sampling = spec::SurfelSampling::PerTexel {
+            width: 4,
+            height: 4,
+        };
+        let batches = sampling_batches(vec![mapped, unmapped], sampling);
+        assert_eq!(1, batches.len());
+        match batches[0].0 {
+            // Eight texels on an area of two
+            SurfelSampling::MinimumDistance(distance) => {
+                let expected = (4.0 / (3.0f32.sqrt() * 8.0)).sqrt();
+                assert!((distance - expected).abs() < 1e-6)
+            }
+            _ => panic!("Expected dart throwing on each triangle"),
+        }
+    }
+
+    #[test]
+    fn unmatched_surfel_spec_materials() {
+        let mut surfels_by_material = HashMap::new();
+        surfels_by_material.insert(String::from("bronze"), ());
+        surfels_by_material.insert(String::from("Bronze.001"), ());
+        surfels_by_material.insert(String::from("zinc"), ());
+        surfels_by_material.insert(String::from("_"), ());
+
+        let material_names = ["bronze", "stone"].iter().map(|n| n.to_string()).collect();
+
+        assert_eq!(
+            vec!["Bronze.001", "zinc"],
+            unmatched_material_names(&surfels_by_material, &material_names)
+        );
+    }
+}
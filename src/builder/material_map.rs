@@ -0,0 +1,56 @@
+use scene::{Entity, Material, MaterialBuilder};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Name that the material with the given name in a scene goes by in the
+/// spec, the name itself if the material map does not rename it.
+pub fn mapped_name<'a>(material_map: &'a HashMap<String, String>, name: &'a str) -> &'a str {
+    material_map.get(name).map_or(name, |n| n.as_str())
+}
+
+/// Replaces the materials of entities that the material map renames with
+/// copies that only differ in name. Entities that shared a material share
+/// the renamed copy.
+pub fn renamed_materials(
+    mut entities: Vec<Entity>,
+    material_map: &HashMap<String, String>,
+) -> Vec<Entity> {
+    if material_map.is_empty() {
+        return entities;
+    }
+
+    let mut renamed: HashMap<String, Rc<Material>> = HashMap::new();
+    for entity in entities.iter_mut() {
+        let new_name = match material_map.get(entity.material.name()) {
+            Some(new_name) => new_name,
+            None => continue,
+        };
+        let material = renamed
+            .entry(entity.material.name().to_string())
+            .or_insert_with(|| {
+                Rc::new(
+                    MaterialBuilder::from(&*entity.material)
+                        .name(new_name.as_str())
+                        .build(),
+                )
+            })
+            .clone();
+        entity.material = material;
+    }
+
+    entities
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unmapped_names_stay() {
+        let mut material_map = HashMap::new();
+        material_map.insert(String::from("Material.001_bronze"), String::from("bronze"));
+
+        assert_eq!("bronze", mapped_name(&material_map, "Material.001_bronze"));
+        assert_eq!("stone", mapped_name(&material_map, "stone"));
+    }
+}
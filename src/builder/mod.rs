@@ -1,11 +1,55 @@
 mod append;
+#[cfg(feature = "native")]
 mod builder;
 mod canonicalize;
+#[cfg(feature = "native")]
+mod emission_map;
 mod err;
+#[cfg(feature = "native")]
+mod injections;
+#[cfg(feature = "native")]
+mod inputs;
+#[cfg(feature = "native")]
+mod inspect;
+#[cfg(feature = "native")]
+mod instance;
+#[cfg(feature = "native")]
 mod instantiate;
+#[cfg(feature = "native")]
+mod material_map;
+#[cfg(feature = "native")]
+mod output_tree;
+#[cfg(feature = "native")]
+mod pack;
+mod parse;
+mod placeholders;
+#[cfg(feature = "native")]
+mod preflight;
+#[cfg(feature = "native")]
+mod shape;
+#[cfg(feature = "native")]
+mod source_factory;
+#[cfg(feature = "native")]
+mod spread;
+#[cfg(feature = "native")]
+mod stream_obj;
+#[cfg(feature = "native")]
+mod uv;
+#[cfg(feature = "native")]
+mod wind;
 
 pub use self::append::append;
+#[cfg(feature = "native")]
 pub use self::builder::SimulationBuilder;
-pub use self::canonicalize::canonicalize;
+pub use self::canonicalize::{canonicalize, canonicalize_outputs};
 pub use self::err::{Error, ResolveErrorKind};
+#[cfg(feature = "native")]
+pub use self::inspect::{inspect, Inspection};
+#[cfg(feature = "native")]
 pub use self::instantiate::instantiate;
+#[cfg(feature = "native")]
+pub use self::pack::{pack, unpack};
+pub use self::parse::{parse_spec, parse_spec_value};
+pub use self::placeholders::check_placeholders;
+#[cfg(feature = "native")]
+pub use self::source_factory::{SourceSpec, TonSourceFactory};
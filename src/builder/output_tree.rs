@@ -0,0 +1,146 @@
+use spec::{EffectSpec, SimulationSpec};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Replaces the texture patterns of layer, density and wear effects with
+/// patterns in a tree under the `output_tree` directory of the spec, one
+/// directory for each entity, e.g. `out/entity/Statue/albedo_3.png`. The
+/// directories are named with `{entity_dir}`, which keeps entities with the
+/// same name apart.
+///
+/// Extensions of the replaced patterns are kept, so that encodings still
+/// follow them. Channels that several effects write are suffixed with the
+/// index of the effect, e.g. `albedo-2`. Without `output_tree`, the spec is
+/// returned as it is.
+pub fn tree_outputs(mut spec: SimulationSpec) -> SimulationSpec {
+    let root = match spec.output_tree {
+        Some(ref root) => root.clone(),
+        None => return spec,
+    };
+
+    let mut channel_counts: HashMap<&'static str, usize> = HashMap::new();
+    for effect in spec.effects.iter_mut() {
+        for (channel, _) in entity_outputs(effect) {
+            *channel_counts.entry(channel).or_insert(0) += 1;
+        }
+    }
+
+    for (idx, effect) in spec.effects.iter_mut().enumerate() {
+        for (channel, pattern) in entity_outputs(effect) {
+            let channel = if channel_counts[channel] > 1 {
+                format!("{}-{}", channel, idx)
+            } else {
+                channel.to_string()
+            };
+            *pattern = tree_pattern(&root, &channel, pattern);
+        }
+    }
+
+    spec
+}
+
+/// Pattern for the texture of the given channel of each entity in each
+/// iteration.
+fn tree_pattern(root: &str, channel: &str, replaced: &str) -> String {
+    let extension = Path::new(replaced)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("png");
+    Path::new(root)
+        .join(format!(
+            "entity/{{entity_dir}}/{}_{{iteration}}.{}",
+            channel, extension
+        ))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Channel names and patterns of the outputs of the given effect that are
+/// written once for each entity.
+fn entity_outputs(effect: &mut EffectSpec) -> Vec<(&'static str, &mut String)> {
+    match effect {
+        &mut EffectSpec::Layer {
+            ref mut normal,
+            ref mut displacement,
+            ref mut albedo,
+            ref mut metallicity,
+            ref mut roughness,
+            ref mut exr_pattern,
+            ..
+        } => vec![
+            ("normal", normal),
+            ("displacement", displacement),
+            ("albedo", albedo),
+            ("metallicity", metallicity),
+            ("roughness", roughness),
+        ]
+        .into_iter()
+        .filter_map(|(channel, blend)| blend.as_mut().map(|b| (channel, &mut b.tex_pattern)))
+        .chain(exr_pattern.as_mut().map(|p| ("layers", p)))
+        .collect(),
+        &mut EffectSpec::Density {
+            ref mut tex_pattern,
+            ..
+        } => vec![("density-{substance}", tex_pattern)],
+        &mut EffectSpec::Wear {
+            ref mut tex_pattern,
+            ..
+        } => vec![("wear", tex_pattern)],
+        &mut EffectSpec::Export { .. } | &mut EffectSpec::DumpSurfels { .. } => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use spec::Blend;
+
+    #[test]
+    fn one_directory_per_entity() {
+        let untouched = SimulationSpec::default().effect(EffectSpec::density(
+            8,
+            8,
+            "out/{entity}.png",
+            None,
+            None,
+        ));
+        assert_eq!(
+            vec!["out/{entity}.png"],
+            tree_outputs(untouched).effects[0].output_patterns()
+        );
+
+        let spec = SimulationSpec::default()
+            .output_tree("out/")
+            .effect(
+                EffectSpec::layer(vec![], "rust")
+                    .albedo(Blend::new("{id}-albedo.ktx2"))
+                    .roughness(Blend::new("{id}-roughness.png")),
+            )
+            .effect(EffectSpec::layer(vec![], "moss").albedo(Blend::new("{id}-moss")))
+            .effect(EffectSpec::density(
+                8,
+                8,
+                "d.png",
+                Some(String::from("d.obj")),
+                None,
+            ));
+
+        let spec = tree_outputs(spec);
+        let patterns: Vec<Vec<&str>> = spec.effects.iter().map(|e| e.output_patterns()).collect();
+        // Layer maps are written in the order of the fields of the effect
+        assert_eq!(
+            vec![
+                vec![
+                    "out/entity/{entity_dir}/albedo-0_{iteration}.ktx2",
+                    "out/entity/{entity_dir}/roughness_{iteration}.png",
+                ],
+                vec!["out/entity/{entity_dir}/albedo-1_{iteration}.png"],
+                vec![
+                    "out/entity/{entity_dir}/density-{substance}_{iteration}.png",
+                    "d.obj",
+                ],
+            ],
+            patterns
+        );
+    }
+}
@@ -0,0 +1,375 @@
+use builder::instantiate::{load_source_specs, resolve_source_file};
+use builder::parse::parse_spec;
+use builder::{Error, ResolveErrorKind, SourceSpec};
+use files::Resolver;
+use serde_yaml;
+use spec::{EffectSpec, SimulationSpec, SurfelSpec};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+use zip::result::ZipError;
+use zip::write::FileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+/// Name of the merged simulation spec in archives.
+const SPEC_NAME: &str = "simulation.yml";
+/// Directory in archives that holds all referenced files.
+const FILES_DIR: &str = "files";
+
+/// Writes a zip archive with the given canonicalized spec and every scene,
+/// material library, texture, surfel spec, initial map, ton source spec,
+/// emission mesh, emission map and texture sample it references, so the simulation can be
+/// run on another machine with `unpack`.
+///
+/// Referenced files keep their location relative to each other, so relative
+/// references in OBJ and MTL files still work. Paths in the spec, meshes
+/// and emission maps of ton source specs and initial maps of surfel specs
+/// are rewritten to point into the archive.
+pub fn pack<W: Write + Seek>(
+    spec: &SimulationSpec,
+    resolver: &Resolver,
+    out: W,
+) -> Result<(), Error> {
+    let mut spec = spec.clone();
+    // Outputs go to the working directory of whoever runs the archive
+    spec.output_root = None;
+    spec.output_base = None;
+    let source_specs = load_source_specs(&spec.sources, resolver, false)?;
+
+    let mut files = BTreeSet::new();
+    // Built-in source specs with their mesh and emission map made relative to the spec
+    let mut rewritten_sources = HashMap::new();
+
+    for scene in spec.scenes.iter().chain(spec.occluders.iter()) {
+        files.insert(scene.path().to_path_buf());
+        files.extend(obj_dependencies(scene.path())?);
+    }
+
+    for (path, source) in spec.sources.iter().zip(source_specs) {
+        files.insert(path.clone());
+
+        // Custom sources are packed as they are, their referenced files are unknown
+        if let SourceSpec::Mesh(mut source) = source {
+            let spec_dir = path.parent().unwrap_or(Path::new(""));
+
+            if let Some(mesh) = source.mesh.take() {
+                let mesh =
+                    resolve_source_file(path, &mesh, ResolveErrorKind::TonSourceMesh, resolver)?;
+                source.mesh = Some(relative_path(spec_dir, &mesh));
+                files.insert(mesh.clone());
+                files.extend(obj_dependencies(&mesh)?);
+            }
+
+            if let Some(map) = source.emission_map.take() {
+                let map =
+                    resolve_source_file(path, &map, ResolveErrorKind::EmissionMap, resolver)?;
+                source.emission_map = Some(relative_path(spec_dir, &map));
+                files.insert(map);
+            }
+
+            rewritten_sources.insert(path.clone(), source);
+        }
+    }
+
+    // Surfel specs with their initial maps made relative to the spec
+    let mut rewritten_surfel_specs = HashMap::new();
+    for path in spec.surfels_by_material.values().map(PathBuf::from) {
+        files.insert(path.clone());
+
+        let mut surfel_spec: SurfelSpec = parse_spec(File::open(&path)?, false)?;
+        if surfel_spec.initial_maps.is_empty() {
+            continue;
+        }
+
+        let spec_dir = path.parent().unwrap_or(Path::new(""));
+        for map in surfel_spec.initial_maps.values_mut() {
+            let resolved =
+                resolve_source_file(&path, map, ResolveErrorKind::InitialMap, resolver)?;
+            *map = relative_path(spec_dir, &resolved);
+            files.insert(resolved);
+        }
+        rewritten_surfel_specs.insert(path, surfel_spec);
+    }
+    files.extend(spec.material_swaps.iter().map(|s| PathBuf::from(&s.surfels)));
+    files.extend(spec.injections.iter().cloned());
+    files.extend(spec.initial_surfels.iter().cloned());
+    files.extend(
+        samples_mut(&mut spec.effects)
+            .into_iter()
+            .map(|s| s.clone()),
+    );
+
+    let root = common_ancestor(&files);
+    let entry_name = |path: &Path| {
+        path.strip_prefix(&root).unwrap_or(path).components().fold(
+            String::from(FILES_DIR),
+            |name, component| match component {
+                Component::Normal(c) => format!("{}/{}", name, c.to_string_lossy()),
+                _ => name,
+            },
+        )
+    };
+
+    for scene in spec.scenes.iter_mut().chain(spec.occluders.iter_mut()) {
+        *scene.path_mut() = PathBuf::from(entry_name(scene.path()));
+    }
+    for source in spec.sources.iter_mut() {
+        *source = PathBuf::from(entry_name(source));
+    }
+    for surfel_spec in spec.surfels_by_material.values_mut() {
+        *surfel_spec = entry_name(Path::new(surfel_spec));
+    }
+    for swap in spec.material_swaps.iter_mut() {
+        swap.surfels = entry_name(Path::new(&swap.surfels));
+    }
+    for injections in spec.injections.iter_mut() {
+        *injections = PathBuf::from(entry_name(injections));
+    }
+    if let Some(ref mut initial_surfels) = spec.initial_surfels {
+        *initial_surfels = PathBuf::from(entry_name(initial_surfels));
+    }
+    for sample in samples_mut(&mut spec.effects) {
+        *sample = PathBuf::from(entry_name(sample));
+    }
+
+    let mut zip = ZipWriter::new(out);
+
+    zip.start_file(SPEC_NAME, FileOptions::default())?;
+    zip.write_all(
+        serde_yaml::to_string(&spec)
+            .expect("Simulation specs only have string keys and are always serializable")
+            .as_bytes(),
+    )?;
+
+    for file in files.iter() {
+        zip.start_file(entry_name(file), FileOptions::default())?;
+        match (rewritten_sources.get(file), rewritten_surfel_specs.get(file)) {
+            (Some(source), _) => zip.write_all(
+                serde_yaml::to_string(source)
+                    .expect("Ton source specs only have string keys and are always serializable")
+                    .as_bytes(),
+            )?,
+            (None, Some(surfel_spec)) => zip.write_all(
+                serde_yaml::to_string(surfel_spec)
+                    .expect("Surfel specs only have string keys and are always serializable")
+                    .as_bytes(),
+            )?,
+            (None, None) => {
+                io::copy(&mut File::open(file)?, &mut zip)?;
+            }
+        }
+    }
+
+    zip.finish()?;
+    Ok(())
+}
+
+/// Extracts an archive written with `pack` into the given directory and
+/// returns the path of the extracted simulation spec.
+pub fn unpack<R, P>(archive: R, dir: P) -> Result<PathBuf, Error>
+where
+    R: Read + Seek,
+    P: AsRef<Path>,
+{
+    let dir = dir.as_ref();
+    let mut archive = ZipArchive::new(archive)?;
+
+    for idx in 0..archive.len() {
+        let mut entry = archive.by_index(idx)?;
+
+        // Absolute paths or .. would escape the extraction directory
+        let path = match entry.enclosed_name() {
+            Some(name) => dir.join(name),
+            None => {
+                return Err(Error::Archive(ZipError::InvalidArchive(
+                    "Archive entry points outside of the archive",
+                )))
+            }
+        };
+
+        if entry.is_dir() {
+            fs::create_dir_all(&path)?;
+        } else {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            io::copy(&mut entry, &mut File::create(&path)?)?;
+        }
+    }
+
+    let spec_path = dir.join(SPEC_NAME);
+    if spec_path.exists() {
+        Ok(spec_path)
+    } else {
+        Err(Error::Archive(ZipError::FileNotFound))
+    }
+}
+
+/// Texture sample paths of all layer effects.
+fn samples_mut(effects: &mut Vec<EffectSpec>) -> Vec<&mut PathBuf> {
+    let mut samples = Vec::new();
+
+    for effect in effects.iter_mut() {
+        if let EffectSpec::Layer {
+            ref mut normal,
+            ref mut displacement,
+            ref mut albedo,
+            ref mut metallicity,
+            ref mut roughness,
+            ..
+        } = *effect
+        {
+            for blend in vec![normal, displacement, albedo, metallicity, roughness] {
+                if let Some(blend) = blend.as_mut() {
+                    samples.extend(blend.stops.iter_mut().filter_map(|s| s.sample.as_mut()));
+                }
+            }
+        }
+    }
+
+    samples
+}
+
+/// Material libraries referenced by an OBJ file and the textures referenced in
+/// those, as far as they exist.
+pub fn obj_dependencies(obj: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut dependencies = Vec::new();
+
+    for line in BufReader::new(File::open(obj)?).lines() {
+        let line = line?;
+        let mut tokens = line.split_whitespace();
+        if tokens.next() == Some("mtllib") {
+            for mtl in tokens.filter_map(|mtl| referenced_file(obj, mtl)) {
+                dependencies.extend(mtl_dependencies(&mtl)?);
+                dependencies.push(mtl);
+            }
+        }
+    }
+
+    Ok(dependencies)
+}
+
+fn mtl_dependencies(mtl: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut textures = Vec::new();
+
+    for line in BufReader::new(File::open(mtl)?).lines() {
+        let line = line?;
+        let mut tokens = line.split_whitespace();
+        let is_map = match tokens.next() {
+            Some(key) => key.starts_with("map_") || key == "bump" || key == "disp" || key == "norm",
+            None => false,
+        };
+
+        // Options come first, the file name last
+        if let (true, Some(texture)) = (is_map, tokens.last()) {
+            if Path::new(texture).is_absolute() {
+                warn!(
+                    "Texture {} in {} is absolute and will only be found on the same machine.",
+                    texture,
+                    mtl.display()
+                );
+            }
+            textures.extend(referenced_file(mtl, texture));
+        }
+    }
+
+    Ok(textures)
+}
+
+/// Canonicalized path of a file referenced relative to the given file, if it exists.
+fn referenced_file(referencing: &Path, reference: &str) -> Option<PathBuf> {
+    referencing.parent()?.join(reference).canonicalize().ok()
+}
+
+/// Deepest directory that contains all of the given files.
+fn common_ancestor(files: &BTreeSet<PathBuf>) -> PathBuf {
+    let mut files = files.iter();
+    let mut ancestor = match files.next().and_then(|f| f.parent()) {
+        Some(parent) => parent.to_path_buf(),
+        None => return PathBuf::new(),
+    };
+
+    for file in files {
+        while !file.starts_with(&ancestor) {
+            ancestor.pop();
+        }
+    }
+
+    ancestor
+}
+
+/// Path to `path` relative to the directory `dir`, both absolute.
+fn relative_path(dir: &Path, path: &Path) -> PathBuf {
+    let dir: Vec<_> = dir.components().collect();
+    let path: Vec<_> = path.components().collect();
+    let common = dir
+        .iter()
+        .zip(path.iter())
+        .take_while(|&(a, b)| a == b)
+        .count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..dir.len() {
+        relative.push("..");
+    }
+    for component in path[common..].iter() {
+        relative.push(component.as_os_str());
+    }
+
+    relative
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use builder::SimulationBuilder;
+    use spec::TonSourceSpec;
+    use std::env::temp_dir;
+    use std::io::Cursor;
+
+    #[test]
+    fn relative_paths() {
+        assert_eq!(
+            PathBuf::from("../assets/sky.obj"),
+            relative_path(Path::new("/a/examples"), Path::new("/a/assets/sky.obj"))
+        );
+        assert_eq!(
+            PathBuf::from("sky.obj"),
+            relative_path(Path::new("/a"), Path::new("/a/sky.obj"))
+        );
+    }
+
+    #[test]
+    fn pack_and_unpack_example() {
+        let builder = SimulationBuilder::new()
+            .append_spec_fragment_file("tests/examples/simulation.yml")
+            .unwrap();
+
+        let mut archive = Cursor::new(Vec::new());
+        builder.pack(&mut archive).unwrap();
+        archive.set_position(0);
+
+        let dir = temp_dir().join("aitios-pack-test");
+        let spec_path = unpack(archive, &dir).unwrap();
+
+        let spec: SimulationSpec =
+            serde_yaml::from_reader(File::open(&spec_path).unwrap()).unwrap();
+        let scene = dir.join(spec.scenes[0].path());
+        assert!(scene.exists(), "Scene missing in archive");
+        assert!(
+            scene.with_extension("mtl").exists(),
+            "MTL missing in archive"
+        );
+
+        let source = dir.join(&spec.sources[0]);
+        let source_spec: TonSourceSpec =
+            serde_yaml::from_reader(File::open(&source).unwrap()).unwrap();
+        assert!(
+            source.parent().unwrap().join(source_spec.mesh.unwrap()).exists(),
+            "Emission mesh missing in archive"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
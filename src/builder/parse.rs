@@ -0,0 +1,59 @@
+use builder::Error;
+use serde::de::DeserializeOwned;
+use serde_ignored;
+use serde_yaml::{self, Value};
+use std::io::Read;
+
+/// Deserializes a simulation, surfel or ton source spec from YAML.
+///
+/// Unknown keys are silently ignored, unless in strict mode, where they
+/// are reported with their path in the document, e.g. `effects.0.layer.albdeo`.
+pub fn parse_spec<T, R>(reader: R, strict: bool) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+    R: Read,
+{
+    if !strict {
+        return Ok(serde_yaml::from_reader(reader)?);
+    }
+
+    parse_spec_value(serde_yaml::from_reader(reader)?, strict)
+}
+
+/// Like `parse_spec`, but with an already parsed YAML document.
+pub fn parse_spec_value<T>(document: Value, strict: bool) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    if !strict {
+        return Ok(serde_yaml::from_value(document)?);
+    }
+
+    let mut unknown_keys = Vec::new();
+    let spec = serde_ignored::deserialize(document, |path| unknown_keys.push(path.to_string()))?;
+
+    if unknown_keys.is_empty() {
+        Ok(spec)
+    } else {
+        Err(Error::UnknownSpecKeys(unknown_keys.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use spec::SimulationSpec;
+
+    #[test]
+    fn strict_rejects_typos() {
+        let spec = "iteratoins: 5\nsurfel_distance: 0.1";
+
+        let lenient: SimulationSpec = parse_spec(spec.as_bytes(), false).unwrap();
+        assert_eq!(None, lenient.iterations);
+
+        match parse_spec::<SimulationSpec, _>(spec.as_bytes(), true) {
+            Err(Error::UnknownSpecKeys(keys)) => assert_eq!("iteratoins", keys),
+            _ => panic!("Expected unknown key to be reported in strict mode"),
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use builder::Error;
+use files::{placeholders, unformatted};
+use spec::{EffectSpec, PLACEHOLDERS};
+
+/// Reports all `{placeholder}` tokens in output patterns that are not known
+/// placeholders, e.g. `{entitiy}`, or that have a format their value does not
+/// support, e.g. `{entity:04}`. These would otherwise end up verbatim in the
+/// written file names.
+pub fn check_placeholders(effects: &[EffectSpec]) -> Result<(), Error> {
+    let mut unknown = Vec::new();
+
+    for (idx, effect) in effects.iter().enumerate() {
+        for pattern in effect.output_patterns() {
+            for token in placeholders(pattern) {
+                let (name, well_formed) = unformatted(token);
+                if !well_formed || !PLACEHOLDERS.iter().any(|&(known, _)| known == name) {
+                    unknown.push(format!("{} in {}#{}: {}", token, effect.kind(), idx, pattern));
+                }
+            }
+        }
+    }
+
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::UnknownPlaceholders(unknown.join("\n")))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_yaml;
+
+    #[test]
+    fn report_unknown_placeholders() {
+        let effects: Vec<EffectSpec> = serde_yaml::from_str(
+            "
+            - export:
+                obj_pattern: out/{iteration}-{entitiy}.obj
+                mtl_pattern: out/{iteration:04}-{substance:04}.mtl
+            - dump_surfels:
+                obj_pattern: out/{datetime}-{iteratoin}.obj",
+        ).unwrap();
+
+        match check_placeholders(&effects) {
+            Err(Error::UnknownPlaceholders(list)) => {
+                assert_eq!(
+                    "{entitiy} in export#0: out/{iteration}-{entitiy}.obj\n\
+                     {substance:04} in export#0: out/{iteration:04}-{substance:04}.mtl\n\
+                     {iteratoin} in dump_surfels#1: out/{datetime}-{iteratoin}.obj",
+                    list
+                );
+            }
+            _ => panic!("Expected misspelled placeholders to be reported"),
+        }
+    }
+}
@@ -0,0 +1,604 @@
+use builder::Error;
+use files::{entity_dirs, existing_dir, has_placeholder, volume, PatternValues, Volume};
+use runner::density_size;
+use scene::{Entity, Material, Mesh};
+use spec::{Blend, DiskSpaceCheck, EffectSpec};
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use tex::{self, GenericImage};
+
+/// Bytes per texel of uncompressed RGBA, so estimates of written PNG
+/// textures are upper bounds.
+const BYTES_PER_TEXEL: u64 = 4;
+/// Rough size of the position, texture coordinate, normal and face lines
+/// that an exported OBJ has for each triangle.
+const OBJ_BYTES_PER_TRIANGLE: u64 = 300;
+/// Rough size of a material in an exported MTL, including map paths.
+const MTL_BYTES_PER_ENTITY: u64 = 512;
+/// Rough size of a surfel in a surfel dump.
+const OBJ_BYTES_PER_SURFEL: u64 = 64;
+
+/// Width and height of textures by path.
+pub type TextureSizes = BTreeMap<PathBuf, (u32, u32)>;
+
+/// Opens and decodes every blend stop sample and every original material map
+/// that layer effects will blend over, so missing or corrupt textures are
+/// reported all at once before the simulation starts, rather than failing
+/// hours into a run. Returns the sizes of the textures.
+pub fn check_textures(
+    effects: &[EffectSpec],
+    entities: &[Entity],
+) -> Result<TextureSizes, Error> {
+    // Sorted and deduplicated for a stable report
+    let mut textures = BTreeMap::new();
+
+    for (idx, effect) in effects.iter().enumerate() {
+        if let &EffectSpec::Layer {
+            ref normal,
+            ref displacement,
+            ref albedo,
+            ref metallicity,
+            ref roughness,
+            ..
+        } = effect
+        {
+            let effect_name = format!("{}#{}", effect.kind(), idx);
+
+            for blend in [normal, displacement, albedo, metallicity, roughness]
+                .iter()
+                .filter_map(|b| b.as_ref())
+            {
+                for sample in blend.stops.iter().filter_map(|s| s.sample.as_ref()) {
+                    textures
+                        .entry(sample.clone())
+                        .or_insert_with(|| format!("blend stop of {}", effect_name));
+                }
+            }
+
+            let affected_materials = entities
+                .iter()
+                .filter(|e| effect.affects_entity(&e.name, e.material.name()))
+                .map(|e| &e.material);
+
+            for material in affected_materials {
+                for (_, _, map) in layer_maps(effect, material) {
+                    if let Some(map) = map {
+                        textures.entry(map.clone()).or_insert_with(|| {
+                            format!(
+                                "map of material {} used by {}",
+                                material.name(),
+                                effect_name
+                            )
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    let mut sizes = TextureSizes::new();
+    let mut failures = Vec::new();
+    for (path, usage) in textures {
+        match tex::open(&path) {
+            Ok(texture) => {
+                sizes.insert(path, texture.dimensions());
+            }
+            Err(err) => failures.push(format!("{:?} ({}): {}", path, usage, err)),
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(sizes)
+    } else {
+        Err(Error::UnloadableTextures {
+            count: failures.len(),
+            list: failures.join("\n"),
+        })
+    }
+}
+
+/// Output that an effect writes whenever it runs, with an upper bound of its
+/// size.
+pub struct Output {
+    /// Path with `{iteration}` and `{datetime}` left unexpanded.
+    pub path: String,
+    /// Effect writing the output and the entity or substance it is for.
+    pub description: String,
+    pub bytes: u64,
+    /// Bytes added for each surfel, since surfels are only sampled after
+    /// outputs are planned.
+    pub bytes_per_surfel: u64,
+}
+
+/// Expands the output patterns of all effects for every entity and substance
+/// they will be written for, skipping layer effects for substances that are
+/// not in the given ones.
+///
+/// All outputs of one iteration share the same `{iteration}` and `{datetime}`,
+/// so these placeholders are left unexpanded. Texture sizes are used for layer
+/// maps without an explicit size.
+pub fn planned_outputs(
+    effects: &[EffectSpec],
+    entities: &[Entity],
+    substances: &[String],
+    texture_sizes: &TextureSizes,
+) -> Vec<Output> {
+    let iteration_values = PatternValues::new("{datetime}");
+    let entity_dirs = entity_dirs(entities.iter().map(|e| e.name.as_str()));
+    let obj_bytes = entities
+        .iter()
+        .map(|e| e.mesh.triangles().count() as u64)
+        .sum::<u64>() * OBJ_BYTES_PER_TRIANGLE;
+    let mtl_bytes = entities.len() as u64 * MTL_BYTES_PER_ENTITY;
+    let output = |path: String, description: String, bytes: u64| Output {
+        path,
+        description,
+        bytes,
+        bytes_per_surfel: 0,
+    };
+    let mut outputs = Vec::new();
+
+    for (idx, effect) in effects.iter().enumerate() {
+        let effect_name = format!("{}#{}", effect.kind(), idx);
+        let effect_values = iteration_values.clone().effect(effect.kind(), idx);
+
+        match effect {
+            &EffectSpec::Density {
+                width,
+                height,
+                ref tex_pattern,
+                ref obj_pattern,
+                ref mtl_pattern,
+                adaptive_resolution,
+                ..
+            } => for substance in substances {
+                let values = effect_values
+                    .clone()
+                    .size(width as u32, height as u32)
+                    .substance(substance);
+
+                for (ent_idx, entity) in entities.iter().enumerate() {
+                    let (width, height) = density_size(width, height, adaptive_resolution, entity);
+                    outputs.push(output(
+                        values
+                            .clone()
+                            .size(width as u32, height as u32)
+                            .entity(ent_idx, &entity.name)
+                            .entity_dir(&entity_dirs[ent_idx])
+                            .material(entity.material.name())
+                            .substitute(tex_pattern),
+                        format!(
+                            "{} texture of entity {} for {}",
+                            effect_name, entity.name, substance
+                        ),
+                        texture_bytes((width as u32, height as u32)),
+                    ));
+                }
+
+                let scene = [(obj_pattern, "OBJ", obj_bytes), (mtl_pattern, "MTL", mtl_bytes)];
+                for &(pattern, kind, bytes) in scene.iter() {
+                    if let Some(pattern) = pattern.as_ref() {
+                        outputs.push(output(
+                            values.substitute(pattern),
+                            format!("{} {} for {}", effect_name, kind, substance),
+                            bytes,
+                        ));
+                    }
+                }
+            },
+            &EffectSpec::Export {
+                ref obj_pattern,
+                ref mtl_pattern,
+            } => {
+                // Scene exports substitute "all" for {substance}
+                let values = effect_values.clone().substance("all");
+
+                let scene = [(obj_pattern, "OBJ", obj_bytes), (mtl_pattern, "MTL", mtl_bytes)];
+                for &(pattern, kind, bytes) in scene.iter() {
+                    if let Some(pattern) = pattern.as_ref() {
+                        outputs.push(output(
+                            values.substitute(pattern),
+                            format!("{} {}", effect_name, kind),
+                            bytes,
+                        ));
+                    }
+                }
+            }
+            &EffectSpec::Layer {
+                ref substance,
+                ref exr_pattern,
+                ..
+            } if substances.contains(substance) => {
+                let affected_entities = entities
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, e)| effect.affects_entity(&e.name, e.material.name()));
+
+                for (ent_idx, entity) in affected_entities {
+                    let values = effect_values
+                        .clone()
+                        .entity(ent_idx, &entity.name)
+                        .entity_dir(&entity_dirs[ent_idx])
+                        .material(entity.material.name())
+                        .substance(substance);
+
+                    let maps = layer_maps(effect, &entity.material);
+                    for &(channel, blend, map) in maps.iter() {
+                        let size = blend_size(blend, map, texture_sizes);
+                        outputs.push(output(
+                            sized(&values, size).substitute(&blend.tex_pattern),
+                            format!("{} {} of entity {}", effect_name, channel, entity.name),
+                            size.map_or(0, texture_bytes),
+                        ));
+                    }
+
+                    if let Some(exr_pattern) = exr_pattern.as_ref() {
+                        // All maps share the size of the first, with floats
+                        // for each channel and another channel for the guide
+                        let size = maps
+                            .first()
+                            .and_then(|&(_, blend, map)| blend_size(blend, map, texture_sizes));
+                        let channels = maps
+                            .iter()
+                            .map(|&(channel, _, _)| match channel {
+                                "albedo" | "normal" => 4,
+                                _ => 1,
+                            })
+                            .sum::<u64>() + 1;
+                        outputs.push(output(
+                            sized(&values, size).substitute(exr_pattern),
+                            format!("{} EXR of entity {}", effect_name, entity.name),
+                            size.map_or(0, |(w, h)| w as u64 * h as u64 * channels * 4),
+                        ));
+                    }
+                }
+            }
+            &EffectSpec::DumpSurfels { ref obj_pattern } => {
+                outputs.push(Output {
+                    bytes_per_surfel: OBJ_BYTES_PER_SURFEL,
+                    ..output(
+                        effect_values.substitute(obj_pattern),
+                        format!("{} surfel OBJ", effect_name),
+                        0,
+                    )
+                });
+            }
+            &EffectSpec::Wear {
+                width,
+                height,
+                ref tex_pattern,
+                ..
+            } => for (ent_idx, entity) in entities.iter().enumerate() {
+                outputs.push(output(
+                    effect_values
+                        .clone()
+                        .entity(ent_idx, &entity.name)
+                        .entity_dir(&entity_dirs[ent_idx])
+                        .material(entity.material.name())
+                        .size(width as u32, height as u32)
+                        .substitute(tex_pattern),
+                    format!("{} texture of entity {}", effect_name, entity.name),
+                    texture_bytes((width as u32, height as u32)),
+                ));
+            },
+            // Substance is excluded from the run
+            &EffectSpec::Layer { .. } => (),
+        }
+    }
+
+    outputs
+}
+
+/// Reports any paths that more than one of the given outputs would be
+/// written to, since later writes would silently replace earlier ones.
+///
+/// An output that lacks `{iteration}` being overwritten in each iteration is
+/// not considered a collision.
+pub fn check_output_collisions(outputs: &[Output]) -> Result<(), Error> {
+    let mut writers: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for output in outputs {
+        match writers.entry(&output.path) {
+            Entry::Occupied(mut entry) => entry.get_mut().push(&output.description),
+            Entry::Vacant(entry) => {
+                entry.insert(vec![&output.description]);
+            }
+        }
+    }
+
+    let collisions: Vec<String> = writers
+        .into_iter()
+        .filter(|&(_, ref outputs)| outputs.len() > 1)
+        .map(|(path, outputs)| format!("{}: {}", path, outputs.join(", ")))
+        .collect();
+
+    if collisions.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::OutputCollisions {
+            count: collisions.len(),
+            list: collisions.join("\n"),
+        })
+    }
+}
+
+/// Estimates how much the given outputs will write over all iterations that
+/// run effects and compares it against the free space on the volumes they
+/// are written to, so that a long run does not fail when the disk runs full
+/// near its end.
+///
+/// Outputs with `{iteration}` in their path are written again for each
+/// iteration, others are only counted once since they are overwritten.
+pub fn check_disk_space(
+    outputs: &[Output],
+    surfel_count: usize,
+    effect_iterations: u64,
+    check: DiskSpaceCheck,
+) -> Result<(), Error> {
+    if check == DiskSpaceCheck::Off {
+        return Ok(());
+    }
+
+    let working_dir = env::current_dir()?;
+    // Directories on the same volume share its free space
+    let mut volumes: BTreeMap<u64, (PathBuf, Volume, u64)> = BTreeMap::new();
+    for (dir, bytes) in needed_space(outputs, surfel_count, effect_iterations, &working_dir) {
+        match volume(&dir) {
+            Ok(volume) => volumes.entry(volume.device).or_insert((dir, volume, 0)).2 += bytes,
+            Err(err) => warn!(
+                "Could not determine free space for outputs in {}: {}",
+                dir.display(),
+                err
+            ),
+        }
+    }
+
+    let mut shortages = Vec::new();
+    for &(ref dir, volume, bytes) in volumes.values() {
+        let estimate = format!(
+            "{}: up to {} of outputs, {} available",
+            dir.display(),
+            format_bytes(bytes),
+            format_bytes(volume.available)
+        );
+        if bytes > volume.available {
+            shortages.push(estimate);
+        } else {
+            info!("Disk space: {}", estimate);
+        }
+    }
+
+    match check {
+        _ if shortages.is_empty() => Ok(()),
+        DiskSpaceCheck::Warn => {
+            for shortage in shortages {
+                warn!("Outputs may not fit into free space, {}", shortage);
+            }
+            Ok(())
+        }
+        _ => Err(Error::InsufficientDiskSpace {
+            count: shortages.len(),
+            list: shortages.join("\n"),
+        }),
+    }
+}
+
+/// Estimated bytes written to each existing directory that the outputs are
+/// written to or created in, with relative outputs in the given working
+/// directory.
+fn needed_space(
+    outputs: &[Output],
+    surfel_count: usize,
+    effect_iterations: u64,
+    working_dir: &Path,
+) -> BTreeMap<PathBuf, u64> {
+    let mut needed = BTreeMap::new();
+    for output in outputs {
+        let path = working_dir.join(&output.path);
+        if let Some(dir) = existing_dir(&path) {
+            let writes = if has_placeholder(&output.path, "iteration") {
+                effect_iterations
+            } else {
+                1
+            };
+            let bytes = output.bytes + output.bytes_per_surfel * surfel_count as u64;
+            *needed.entry(dir.to_path_buf()).or_insert(0) += writes * bytes;
+        }
+    }
+    needed
+}
+
+/// Blends of the given layer effect with their channel names, each with the
+/// map of the given material that it is blended over.
+fn layer_maps<'a>(
+    effect: &'a EffectSpec,
+    material: &'a Material,
+) -> Vec<(&'static str, &'a Blend, Option<&'a PathBuf>)> {
+    match effect {
+        &EffectSpec::Layer {
+            ref normal,
+            ref displacement,
+            ref albedo,
+            ref metallicity,
+            ref roughness,
+            ..
+        } => vec![
+            ("normal", normal, material.normal_map()),
+            ("displacement", displacement, material.displacement_map()),
+            ("albedo", albedo, material.diffuse_color_map()),
+            ("metallicity", metallicity, material.metallic_map()),
+            ("roughness", roughness, material.roughness_map()),
+        ].into_iter()
+            .filter_map(|(channel, blend, map)| blend.as_ref().map(|b| (channel, b, map)))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Size of the texture that a blend writes, like when running: the explicit
+/// size, the size of the original map or the size of the largest stop.
+fn blend_size(
+    blend: &Blend,
+    original_map: Option<&PathBuf>,
+    texture_sizes: &TextureSizes,
+) -> Option<(u32, u32)> {
+    match (blend.width, blend.height) {
+        (Some(w), Some(h)) => Some((w as u32, h as u32)),
+        (Some(size), None) | (None, Some(size)) => Some((size as u32, size as u32)),
+        (None, None) => match original_map {
+            Some(map) => texture_sizes.get(map).cloned(),
+            None => blend
+                .stops
+                .iter()
+                .filter_map(|s| s.sample.as_ref())
+                .filter_map(|sample| texture_sizes.get(sample).cloned())
+                .max(),
+        },
+    }
+}
+
+/// The given values with the given texture size, if known.
+fn sized<'a>(values: &PatternValues<'a>, size: Option<(u32, u32)>) -> PatternValues<'a> {
+    match size {
+        Some((width, height)) => values.clone().size(width, height),
+        None => values.clone(),
+    }
+}
+
+fn texture_bytes((width, height): (u32, u32)) -> u64 {
+    width as u64 * height as u64 * BYTES_PER_TEXEL
+}
+
+/// Formats bytes with a binary unit, e.g. `1.5 GiB`.
+fn format_bytes(bytes: u64) -> String {
+    let units = ["bytes", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < units.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} bytes", bytes)
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_yaml;
+
+    #[test]
+    fn report_all_missing_samples() {
+        let effects: Vec<EffectSpec> = serde_yaml::from_str(
+            "- layer:
+                materials: []
+                substance: rust
+                albedo:
+                  tex_pattern: albedo.png
+                  stops:
+                    - sample: tests/examples/white_pixel.png
+                      cenith: 0.0
+                    - sample: tests/examples/nonexistent_pixel.png
+                      cenith: 0.5
+                roughness:
+                  tex_pattern: roughness.png
+                  stops:
+                    - sample: tests/examples/nonexistent_roughness.png
+                      cenith: 0.5",
+        ).unwrap();
+
+        match check_textures(&effects, &[]) {
+            Err(Error::UnloadableTextures { count, list }) => {
+                assert_eq!(2, count);
+                assert!(list.contains("nonexistent_pixel.png"));
+                assert!(list.contains("nonexistent_roughness.png"));
+                assert!(!list.contains("white_pixel.png"));
+            }
+            _ => panic!("Expected both missing samples to be reported"),
+        }
+    }
+
+    #[test]
+    fn report_colliding_density_outputs() {
+        let effects: Vec<EffectSpec> = serde_yaml::from_str(
+            "
+            - density:
+                width: 8
+                height: 8
+                tex_pattern: out/{iteration}-{id}-{substance}.png
+                obj_pattern: out/{iteration}.obj
+                mtl_pattern: out/{iteration}-{substance}.mtl
+            - export:
+                obj_pattern: out/{iteration}-final.obj",
+        ).unwrap();
+        let substances = vec![String::from("rust"), String::from("dust")];
+
+        let outputs = planned_outputs(&effects, &[], &substances, &TextureSizes::new());
+        match check_output_collisions(&outputs) {
+            Err(Error::OutputCollisions { count, list }) => {
+                assert_eq!(1, count);
+                assert!(list.contains("out/{iteration}.obj: density#0 OBJ for rust, density#0 OBJ for dust"));
+            }
+            _ => panic!("Expected the density OBJ to collide for both substances"),
+        }
+    }
+
+    #[test]
+    fn distinct_outputs_do_not_collide() {
+        let effects: Vec<EffectSpec> = serde_yaml::from_str(
+            "
+            - density:
+                width: 8
+                height: 8
+                tex_pattern: out/{iteration}-{id}-{substance}.png
+                obj_pattern: out/{iteration}-{substance}.obj
+            - export:
+                obj_pattern: out/{iteration}-{substance}.obj
+            - dump_surfels:
+                obj_pattern: out/surfels.obj",
+        ).unwrap();
+        let substances = vec![String::from("rust"), String::from("dust")];
+
+        let outputs = planned_outputs(&effects, &[], &substances, &TextureSizes::new());
+        assert!(check_output_collisions(&outputs).is_ok());
+    }
+
+    #[test]
+    fn estimate_disk_space() {
+        let effects: Vec<EffectSpec> = serde_yaml::from_str(
+            "
+            - dump_surfels:
+                obj_pattern: out/{iteration}/surfels.obj
+            - export:
+                obj_pattern: out/final.obj",
+        ).unwrap();
+        let outputs = planned_outputs(&effects, &[], &[], &TextureSizes::new());
+
+        let dir = env::temp_dir();
+        let needed = needed_space(&outputs, 1000, 3, &dir.join("aitios-preflight-nonexistent"));
+        assert_eq!(
+            Some(&(3 * 1000 * OBJ_BYTES_PER_SURFEL)),
+            needed.get(&dir),
+            "Expected the surfel dumps of all iterations and an empty scene"
+        );
+
+        let huge = vec![Output {
+            path: dir.join("huge.png").to_str().unwrap().to_string(),
+            description: String::from("huge"),
+            bytes: u64::max_value() / 2,
+            bytes_per_surfel: 0,
+        }];
+        match check_disk_space(&huge, 0, 1, DiskSpaceCheck::Fail) {
+            Err(Error::InsufficientDiskSpace { count, .. }) => assert_eq!(1, count),
+            _ => panic!("Expected the huge output to exceed the free space"),
+        }
+        assert!(check_disk_space(&huge, 0, 1, DiskSpaceCheck::Warn).is_ok());
+
+        assert_eq!("512 bytes", format_bytes(512));
+        assert_eq!("1.5 GiB", format_bytes(3 << 29));
+    }
+}
@@ -0,0 +1,224 @@
+use geom::{Vec2, Vec3, Vertex};
+use spec::EmitterShape;
+use std::f32::consts::PI;
+
+/// Radius of the spheres that point and cone emitters emit from, small enough
+/// to look like a point in scenes of usual size.
+const POINT_RADIUS: f32 = 0.001;
+/// Subdivisions around the direction of generated emitters.
+const SEGMENTS: usize = 32;
+/// Subdivisions from the direction to the rim of spherical emitters.
+const RINGS: usize = 16;
+
+/// Triangles of the emission geometry of the given shape, three vertices each,
+/// with normals pointing into the emission direction, or `None` if the shape
+/// has no surface area.
+///
+/// Tons leave the generated geometry along the normals, so it has to be used
+/// for emission that is not diffuse.
+pub fn emitter_vertices(shape: &EmitterShape) -> Option<Vec<Vertex>> {
+    match shape {
+        &EmitterShape::Point { position } => {
+            // A full sphere, i.e. a cap around any axis that extends to the opposite pole
+            Some(cap(vec3(position), Vec3::new(0.0, 1.0, 0.0), PI))
+        }
+        &EmitterShape::Directional {
+            position,
+            direction,
+            radius,
+        } => {
+            let direction = normalized(vec3(direction))?;
+            if radius > 0.0 {
+                Some(disk(vec3(position), direction, radius))
+            } else {
+                None
+            }
+        }
+        &EmitterShape::Cone {
+            position,
+            direction,
+            angle,
+        } => {
+            let direction = normalized(vec3(direction))?;
+            if angle > 0.0 && angle <= 180.0 {
+                Some(cap(vec3(position), direction, angle.to_radians()))
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// Disk facing the given axis, wound counter-clockwise around it.
+fn disk(center: Vec3, axis: Vec3, radius: f32) -> Vec<Vertex> {
+    let (tangent, bitangent) = basis(axis);
+    let rim = |segment: usize| {
+        let phi = 2.0 * PI * segment as f32 / SEGMENTS as f32;
+        let offset = tangent * phi.cos() + bitangent * phi.sin();
+        Vertex {
+            position: center + offset * radius,
+            normal: axis,
+            texcoords: Vec2::new(0.5 + 0.5 * phi.cos(), 0.5 + 0.5 * phi.sin()),
+        }
+    };
+    let middle = Vertex {
+        position: center,
+        normal: axis,
+        texcoords: Vec2::new(0.5, 0.5),
+    };
+
+    (0..SEGMENTS)
+        .flat_map(|segment| vec![middle, rim(segment), rim(segment + 1)])
+        .collect()
+}
+
+/// Part of a sphere around `center` with the radius `POINT_RADIUS`, covering
+/// the directions at most `angle` radians off the axis, with normals pointing
+/// outwards.
+fn cap(center: Vec3, axis: Vec3, angle: f32) -> Vec<Vertex> {
+    let (tangent, bitangent) = basis(axis);
+    let vertex = |ring: usize, segment: usize| {
+        let theta = angle * ring as f32 / RINGS as f32;
+        let phi = 2.0 * PI * segment as f32 / SEGMENTS as f32;
+        let normal =
+            (tangent * phi.cos() + bitangent * phi.sin()) * theta.sin() + axis * theta.cos();
+        Vertex {
+            position: center + normal * POINT_RADIUS,
+            normal,
+            texcoords: Vec2::new(segment as f32 / SEGMENTS as f32, ring as f32 / RINGS as f32),
+        }
+    };
+
+    let mut vertices = Vec::with_capacity(RINGS * SEGMENTS * 6);
+    for ring in 0..RINGS {
+        for segment in 0..SEGMENTS {
+            let (top, bottom) = (ring, ring + 1);
+            let (left, right) = (segment, segment + 1);
+
+            // Quads touching a pole collapse into a single triangle
+            if ring != 0 {
+                vertices.extend(vec![
+                    vertex(top, left),
+                    vertex(bottom, right),
+                    vertex(top, right),
+                ]);
+            }
+            if bottom != RINGS || angle < PI {
+                vertices.extend(vec![
+                    vertex(top, left),
+                    vertex(bottom, left),
+                    vertex(bottom, right),
+                ]);
+            }
+        }
+    }
+
+    vertices
+}
+
+/// Two unit vectors that are orthogonal to each other and the given unit
+/// vector, with the first crossed with the second yielding the given vector.
+pub fn basis(axis: Vec3) -> (Vec3, Vec3) {
+    // Any vector that is not parallel to the axis will do
+    let helper = if axis.x.abs() < 0.9 {
+        Vec3::new(1.0, 0.0, 0.0)
+    } else {
+        Vec3::new(0.0, 1.0, 0.0)
+    };
+    let tangent = normalized(helper.cross(axis)).unwrap();
+    (tangent, axis.cross(tangent))
+}
+
+pub fn normalized(vec: Vec3) -> Option<Vec3> {
+    let length = dot(vec, vec).sqrt();
+    if length > 0.0 && length.is_finite() {
+        Some(vec / length)
+    } else {
+        None
+    }
+}
+
+fn dot(a: Vec3, b: Vec3) -> f32 {
+    a.x * b.x + a.y * b.y + a.z * b.z
+}
+
+pub fn vec3(arr: [f32; 3]) -> Vec3 {
+    Vec3::new(arr[0], arr[1], arr[2])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn face_normal(triangle: &[Vertex]) -> Vec3 {
+        let normal = (triangle[1].position - triangle[0].position)
+            .cross(triangle[2].position - triangle[0].position);
+        normalized(normal).expect("Generated triangle is degenerate")
+    }
+
+    #[test]
+    fn triangles_face_emission_direction() {
+        let down = [0.0, -1.0, 0.0];
+        let shapes = vec![
+            EmitterShape::Point {
+                position: [1.0, 2.0, 3.0],
+            },
+            EmitterShape::Directional {
+                position: [0.0, 10.0, 0.0],
+                direction: down,
+                radius: 5.0,
+            },
+            EmitterShape::Cone {
+                position: [0.0, 2.0, 0.0],
+                direction: down,
+                angle: 30.0,
+            },
+        ];
+
+        for shape in shapes {
+            let vertices = emitter_vertices(&shape).unwrap();
+            assert_eq!(0, vertices.len() % 3);
+
+            for triangle in vertices.chunks(3) {
+                let average = triangle[0].normal + triangle[1].normal + triangle[2].normal;
+                assert!(
+                    dot(face_normal(triangle), average) > 0.0,
+                    "Triangle of {:?} faces away from emission direction",
+                    shape
+                );
+            }
+        }
+
+        let cone = emitter_vertices(&EmitterShape::Cone {
+            position: [0.0, 0.0, 0.0],
+            direction: down,
+            angle: 30.0,
+        })
+        .unwrap();
+        let widest = cone
+            .iter()
+            .map(|v| dot(v.normal, Vec3::new(0.0, -1.0, 0.0)))
+            .fold(1.0, f32::min);
+        assert!((widest - 30.0f32.to_radians().cos()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn degenerate_shapes() {
+        assert_eq!(
+            None,
+            emitter_vertices(&EmitterShape::Cone {
+                position: [0.0, 0.0, 0.0],
+                direction: [0.0, 0.0, 0.0],
+                angle: 30.0,
+            })
+        );
+        assert_eq!(
+            None,
+            emitter_vertices(&EmitterShape::Directional {
+                position: [0.0, 0.0, 0.0],
+                direction: [0.0, -1.0, 0.0],
+                radius: 0.0,
+            })
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use builder::parse::parse_spec_value;
+use builder::Error;
+use failure;
+use serde_yaml::Value;
+use sim::TonSource;
+use spec::TonSourceSpec;
+use std::path::Path;
+
+/// Builds ton sources from source spec files with a custom `type`, registered
+/// with `SimulationBuilder::source_factory`.
+///
+/// Custom sources cannot introduce new substances, they can only use those
+/// mentioned in surfel specs and built-in ton source specs.
+pub trait TonSourceFactory {
+    /// Builds a source from the YAML document of the spec file at `spec_path`,
+    /// including its `type`. Concentrations and absorption rates passed to the
+    /// source must be ordered like `substance_names`.
+    fn build(
+        &self,
+        spec: &Value,
+        spec_path: &Path,
+        substance_names: &[String],
+    ) -> Result<TonSource, failure::Error>;
+}
+
+impl<F> TonSourceFactory for F
+where
+    F: Fn(&Value, &Path, &[String]) -> Result<TonSource, failure::Error>,
+{
+    fn build(
+        &self,
+        spec: &Value,
+        spec_path: &Path,
+        substance_names: &[String],
+    ) -> Result<TonSource, failure::Error> {
+        self(spec, spec_path, substance_names)
+    }
+}
+
+/// A loaded ton source spec, either for the built-in mesh-shaped sources or
+/// for a source type provided by a factory.
+#[derive(Debug)]
+pub enum SourceSpec {
+    Mesh(TonSourceSpec),
+    Custom { kind: String, spec: Value },
+}
+
+impl SourceSpec {
+    /// Parses a built-in spec, unless the document has a `type` other than `mesh`.
+    pub fn from_document(mut document: Value, strict: bool) -> Result<SourceSpec, Error> {
+        let kind = match document.as_mapping_mut() {
+            Some(mapping) => mapping.remove(&Value::String(String::from("type"))),
+            None => None,
+        };
+
+        match kind {
+            None => Ok(SourceSpec::Mesh(parse_spec_value(document, strict)?)),
+            Some(Value::String(ref kind)) if kind == "mesh" => {
+                Ok(SourceSpec::Mesh(parse_spec_value(document, strict)?))
+            }
+            Some(Value::String(kind)) => {
+                // Put the type back so the factory sees the complete document
+                if let Some(mapping) = document.as_mapping_mut() {
+                    mapping.insert(
+                        Value::String(String::from("type")),
+                        Value::String(kind.clone()),
+                    );
+                }
+                Ok(SourceSpec::Custom {
+                    kind,
+                    spec: document,
+                })
+            }
+            Some(other) => Err(Error::InvalidSourceType(format!("{:?}", other))),
+        }
+    }
+
+    /// Gets the built-in spec, if this is not a custom source.
+    pub fn mesh(&self) -> Option<&TonSourceSpec> {
+        match self {
+            &SourceSpec::Mesh(ref spec) => Some(spec),
+            &SourceSpec::Custom { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_yaml;
+
+    #[test]
+    fn custom_type() {
+        let document = serde_yaml::from_str("type: sphere\nradius: 2.0").unwrap();
+
+        match SourceSpec::from_document(document, true).unwrap() {
+            SourceSpec::Custom { kind, spec } => {
+                assert_eq!("sphere", kind);
+                assert_eq!(Some("sphere"), spec["type"].as_str());
+                assert_eq!(Some(2.0), spec["radius"].as_f64());
+            }
+            _ => panic!("Expected custom source spec"),
+        }
+    }
+
+    #[test]
+    fn builtin_mesh_type() {
+        let document = serde_yaml::from_str(
+            "
+            type: mesh
+            name: Rain
+            description: Rain dropping from the sky
+            mesh: sky.obj
+            emission_count: 10
+            p_straight: 0.0
+            p_parabolic: 0.3
+            p_flow: 0.7
+            initial:
+              rust: 0.0
+            absorb:
+              rust: 0.2
+            interaction_radius: 0.1
+            parabola_height: 0.07
+            flow_distance: 0.17",
+        )
+        .unwrap();
+
+        let spec = SourceSpec::from_document(document, true).unwrap();
+        assert_eq!("Rain", spec.mesh().unwrap().name);
+    }
+}
@@ -0,0 +1,95 @@
+use builder::shape::{basis, normalized};
+use geom::{TupleTriangle, Vertex};
+
+/// Copies of each emission triangle with their normals tilted into different
+/// directions of an emission cone.
+const SPREAD_DIRECTIONS: usize = 16;
+/// Angle in radians between the azimuths of consecutive directions, which
+/// spreads them evenly around the cone.
+const GOLDEN_ANGLE: f32 = 2.399_963;
+
+/// Triangles of emission geometry, three vertices each, repeated with
+/// normals tilted up to `degrees` off the original normals.
+///
+/// Non-diffuse sources emit tons around the normals, so each copy emits into
+/// another direction of the cone, covering it evenly by solid angle. Since
+/// tons are emitted proportionally to area, the emission of the geometry
+/// stays the same in total. Degrees of zero keep the normals, 90 degrees
+/// spread over the hemisphere around them.
+pub fn spread_vertices<I>(triangles: I, degrees: f32) -> Vec<Vertex>
+where
+    I: IntoIterator<Item = TupleTriangle<Vertex>>,
+{
+    let max_cos = degrees.to_radians().cos();
+    let tilts: Vec<(f32, f32)> = (0..SPREAD_DIRECTIONS)
+        .map(|idx| {
+            // Uniform in the cosine of the polar angle is uniform in solid angle
+            let progress = (idx as f32 + 0.5) / SPREAD_DIRECTIONS as f32;
+            let theta = (1.0 - progress * (1.0 - max_cos)).acos();
+            (theta, GOLDEN_ANGLE * idx as f32)
+        })
+        .collect();
+
+    let mut spread = Vec::new();
+    for TupleTriangle(a, b, c) in triangles {
+        // Same tilt for all vertices, so interpolated normals tilt alike
+        let axis = match normalized(a.normal + b.normal + c.normal) {
+            Some(axis) => axis,
+            None => {
+                spread.extend(vec![a, b, c]);
+                continue;
+            }
+        };
+        let (tangent, bitangent) = basis(axis);
+
+        for &(theta, phi) in tilts.iter() {
+            let tilt = (tangent * phi.cos() + bitangent * phi.sin()) * theta.sin();
+            spread.extend(vec![a, b, c].into_iter().map(|vertex| Vertex {
+                normal: normalized(vertex.normal * theta.cos() + tilt).unwrap_or(vertex.normal),
+                ..vertex
+            }));
+        }
+    }
+
+    spread
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geom::{Vec2, Vec3};
+
+    fn angle_to_up(vertex: &Vertex) -> f32 {
+        vertex.normal.y.min(1.0).acos().to_degrees()
+    }
+
+    #[test]
+    fn tilt_within_cone() {
+        let vertex = |x: f32, z: f32| Vertex {
+            position: Vec3::new(x, 0.0, z),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            texcoords: Vec2::new(x, z),
+        };
+        let triangle = TupleTriangle(vertex(0.0, 0.0), vertex(0.0, 1.0), vertex(1.0, 0.0));
+
+        let spread = spread_vertices(vec![triangle], 30.0);
+        assert_eq!(3 * SPREAD_DIRECTIONS, spread.len());
+        let angles: Vec<f32> = spread.iter().map(angle_to_up).collect();
+        assert!(angles.iter().all(|&a| a <= 30.0 + 1e-3), "{:?}", angles);
+        assert!(angles.iter().any(|&a| a > 20.0), "{:?}", angles);
+
+        // Tilted into all directions, not only to one side
+        let sideways = spread.iter().fold(Vec3::new(0.0, 0.0, 0.0), |sum, v| {
+            sum + Vec3::new(v.normal.x, 0.0, v.normal.z)
+        });
+        let average = sideways / spread.len() as f32;
+        assert!(
+            average.x.abs() < 0.05 && average.z.abs() < 0.05,
+            "{:?}",
+            average
+        );
+
+        let straight = spread_vertices(vec![triangle], 0.0);
+        assert!(straight.iter().all(|v| angle_to_up(v) < 1e-3));
+    }
+}
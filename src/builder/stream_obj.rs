@@ -0,0 +1,340 @@
+use builder::err::Error;
+use geom::{Vec2, Vec3, Vertex};
+use scene::{Entity, Material, MaterialBuilder};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::rc::Rc;
+
+/// Name of entities before the first `o` or `g` statement.
+const DEFAULT_NAME: &'static str = "default";
+
+/// Entities read from an OBJ file, together with the names of all materials
+/// that faces were assigned, including those of skipped entities.
+pub struct StreamedScene {
+    pub entities: Vec<Entity>,
+    pub material_names: BTreeSet<String>,
+}
+
+/// Reads the OBJ file at the given path line by line, without ever holding
+/// the whole file in memory.
+///
+/// Faces between `o`, `g` and `usemtl` statements form an entity, named after
+/// the current object or group, with the material of the same name from the
+/// `mtllib` files. Polygons are split into triangle fans.
+///
+/// Faces with a material that `keep` rejects are skipped while reading, so
+/// they never take up memory. Without `texcoords`, texture coordinates are
+/// left at zero and materials get no maps.
+pub fn stream_obj<F>(path: &Path, texcoords: bool, keep: F) -> Result<StreamedScene, Error>
+where
+    F: Fn(&str) -> bool,
+{
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let reader = BufReader::new(File::open(path)?);
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut materials = HashMap::new();
+    let mut material_names = BTreeSet::new();
+    let mut entities = Vec::new();
+
+    let mut name = String::from(DEFAULT_NAME);
+    let mut material = String::new();
+    let mut vertices: Vec<Vertex> = Vec::new();
+    let mut material_used = false;
+
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let syntax = |reason: &str| Error::ObjSyntax {
+            path: path.to_path_buf(),
+            line: line_idx + 1,
+            reason: reason.to_string(),
+        };
+
+        let mut words = line.split_whitespace();
+        let keyword = match words.next() {
+            Some(keyword) if !keyword.starts_with('#') => keyword,
+            _ => continue,
+        };
+        let rest = line.trim()[keyword.len()..].trim();
+
+        match keyword {
+            "v" => positions.push(parse_vec3(words).ok_or_else(|| syntax("expected x y z"))?),
+            "vn" => normals.push(parse_vec3(words).ok_or_else(|| syntax("expected x y z"))?),
+            // Coordinates are parsed even when dropped to report syntax errors
+            "vt" => {
+                let mut coords = words.map(str::parse::<f32>);
+                let u = match coords.next() {
+                    Some(Ok(u)) => u,
+                    _ => return Err(syntax("expected u [v]")),
+                };
+                let v = match coords.next() {
+                    Some(Ok(v)) => v,
+                    None => 0.0,
+                    Some(Err(_)) => return Err(syntax("expected u [v]")),
+                };
+                if texcoords {
+                    uvs.push(Vec2::new(u, v));
+                }
+            }
+            "o" | "g" | "usemtl" => {
+                finish_entity(
+                    &mut entities,
+                    &name,
+                    &material,
+                    &mut materials,
+                    &mut vertices,
+                );
+                material_used = false;
+                match keyword {
+                    "usemtl" => material = rest.to_string(),
+                    _ if rest.is_empty() => name = String::from(DEFAULT_NAME),
+                    _ => name = rest.to_string(),
+                }
+            }
+            "mtllib" => {
+                for mtl in words {
+                    materials.extend(load_mtl(&dir.join(mtl), texcoords)?);
+                }
+            }
+            "f" => {
+                if !material_used {
+                    material_names.insert(material.clone());
+                    material_used = true;
+                }
+                if !keep(&material) {
+                    continue;
+                }
+
+                let corners = words
+                    .map(|corner| {
+                        parse_corner(corner, &positions, &normals, &uvs, texcoords)
+                            .ok_or_else(|| syntax(&format!("invalid face vertex {}", corner)))
+                    })
+                    .collect::<Result<Vec<Vertex>, Error>>()?;
+                if corners.len() < 3 {
+                    return Err(syntax("faces need at least three vertices"));
+                }
+                for idx in 1..corners.len() - 1 {
+                    vertices.extend_from_slice(&[corners[0], corners[idx], corners[idx + 1]]);
+                }
+            }
+            // Smoothing groups, lines, points and free-form geometry are ignored
+            _ => (),
+        }
+    }
+
+    finish_entity(
+        &mut entities,
+        &name,
+        &material,
+        &mut materials,
+        &mut vertices,
+    );
+
+    Ok(StreamedScene {
+        entities,
+        material_names,
+    })
+}
+
+/// Turns the faces read since the last entity into an entity, if there are
+/// any. Materials missing from the MTL files get a material with only the
+/// name.
+fn finish_entity(
+    entities: &mut Vec<Entity>,
+    name: &str,
+    material: &str,
+    materials: &mut HashMap<String, Rc<Material>>,
+    vertices: &mut Vec<Vertex>,
+) {
+    if vertices.is_empty() {
+        return;
+    }
+
+    let material = materials
+        .entry(material.to_string())
+        .or_insert_with(|| Rc::new(MaterialBuilder::new().name(material).build()))
+        .clone();
+    entities.push(Entity {
+        name: name.to_string(),
+        material,
+        mesh: Rc::new(vertices.drain(..).collect()),
+    });
+}
+
+/// Materials of the MTL file at the given path, with texture maps relative
+/// to the MTL file unless maps are dropped.
+fn load_mtl(path: &Path, maps: bool) -> Result<HashMap<String, Rc<Material>>, Error> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let reader = BufReader::new(File::open(path)?);
+
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MaterialBuilder)> = None;
+
+    for line in reader.lines() {
+        let line = line?;
+        let mut words = line.split_whitespace();
+        let keyword = match words.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+
+        if keyword == "newmtl" {
+            if let Some((name, builder)) = current.take() {
+                materials.insert(name, Rc::new(builder.build()));
+            }
+            let name = line.trim()[keyword.len()..].trim().to_string();
+            current = Some((name.clone(), MaterialBuilder::new().name(name)));
+            continue;
+        }
+
+        // Map options like -bm come first, the file name is last
+        let map = match words.last() {
+            Some(map) if maps => dir.join(map),
+            _ => continue,
+        };
+        current = current.map(|(name, builder)| {
+            let builder = match keyword {
+                "map_Kd" => builder.diffuse_color_map(map),
+                "norm" | "map_Bump" | "map_bump" | "bump" => builder.normal_map(map),
+                "disp" => builder.displacement_map(map),
+                "map_Pm" => builder.metallic_map(map),
+                "map_Pr" => builder.roughness_map(map),
+                _ => builder,
+            };
+            (name, builder)
+        });
+    }
+
+    if let Some((name, builder)) = current {
+        materials.insert(name, Rc::new(builder.build()));
+    }
+
+    Ok(materials)
+}
+
+fn parse_vec3<'a, I: Iterator<Item = &'a str>>(words: I) -> Option<Vec3> {
+    let coords: Vec<f32> = words.take(3).filter_map(|w| w.parse().ok()).collect();
+    if coords.len() == 3 {
+        Some(Vec3::new(coords[0], coords[1], coords[2]))
+    } else {
+        None
+    }
+}
+
+/// Vertex of a face from its `v`, `v/vt`, `v//vn` or `v/vt/vn` indexes, which
+/// are one-based or negative to count back from the last vertex read.
+fn parse_corner(
+    corner: &str,
+    positions: &[Vec3],
+    normals: &[Vec3],
+    uvs: &[Vec2],
+    texcoords: bool,
+) -> Option<Vertex> {
+    let mut indexes = corner.split('/');
+    let position = lookup(indexes.next(), positions)?;
+    let uv = match indexes.next() {
+        Some(idx) if texcoords && !idx.is_empty() => lookup(Some(idx), uvs)?,
+        _ => Vec2::new(0.0, 0.0),
+    };
+    let normal = match indexes.next() {
+        Some(idx) if !idx.is_empty() => lookup(Some(idx), normals)?,
+        // Zero normals mark missing normals, like in other loaded scenes
+        _ => Vec3::new(0.0, 0.0, 0.0),
+    };
+
+    Some(Vertex {
+        position,
+        normal,
+        texcoords: uv,
+    })
+}
+
+fn lookup<T: Copy>(idx: Option<&str>, values: &[T]) -> Option<T> {
+    let idx: isize = idx?.parse().ok()?;
+    let idx = if idx > 0 {
+        idx - 1
+    } else {
+        values.len() as isize + idx
+    };
+    if idx >= 0 && (idx as usize) < values.len() {
+        Some(values[idx as usize])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, remove_dir_all};
+    use std::io::Write;
+
+    #[test]
+    fn stream_entities() {
+        let dir = temp_dir().join("aitios-stream-obj-test");
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        let obj = dir.join("scene.obj");
+
+        File::create(dir.join("scene.mtl"))
+            .unwrap()
+            .write_all(b"newmtl stone\nmap_Kd -bm 1.0 stone.png\nnewmtl glass\n")
+            .unwrap();
+        File::create(&obj)
+            .unwrap()
+            .write_all(
+                b"mtllib scene.mtl
+# A quad of stone and a triangle of glass
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vn 0 0 1
+o wall
+usemtl stone
+f 1/1/1 2/2/1 3/3/1 4/3/1
+o window
+usemtl glass
+f -4//-1 -3//-1 -2//-1
+",
+            )
+            .unwrap();
+
+        let scene = stream_obj(&obj, true, |m| m == "stone").unwrap();
+        assert_eq!(1, scene.entities.len());
+        assert_eq!("wall", scene.entities[0].name);
+        let names: Vec<&str> = scene.material_names.iter().map(|n| n.as_str()).collect();
+        assert_eq!(vec!["glass", "stone"], names);
+
+        let broken = dir.join("broken.obj");
+        File::create(&broken)
+            .unwrap()
+            .write_all(b"v 0 0 0\nf 1 2 3\n")
+            .unwrap();
+        match stream_obj(&broken, false, |_| true) {
+            Err(Error::ObjSyntax { line, .. }) => assert_eq!(2, line),
+            _ => panic!("Expected syntax error for out of range indexes"),
+        }
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn lookup_indexes() {
+        let values = [1, 2, 3];
+        assert_eq!(Some(1), lookup(Some("1"), &values));
+        assert_eq!(Some(3), lookup(Some("-1"), &values));
+        assert_eq!(None, lookup(Some("0"), &values));
+        assert_eq!(None, lookup(Some("4"), &values));
+        assert_eq!(None, lookup(Some("-4"), &values));
+    }
+}
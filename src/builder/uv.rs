@@ -0,0 +1,273 @@
+use geom::{TupleTriangle, Vec2, Vec3, Vertex};
+use runner::{overlapping_islands, separated_islands, uv_islands};
+use scene::{Entity, Mesh};
+use spec::Unwrap;
+use std::rc::Rc;
+
+/// Resolution that overlaps are searched in when separating islands, fine
+/// enough to catch overlaps of a few texels in common texture sizes.
+const SEPARATION_SIZE: usize = 1024;
+
+/// Fraction of a cell that generated texture coordinates keep free on each
+/// side, so that island bleed does not reach into neighboring islands.
+const UNWRAP_MARGIN: f32 = 0.1;
+
+/// Texture space areas below this are considered zero.
+const DEGENERATE_TEXCOORD_AREA: f32 = 1e-12;
+
+/// Entities with texture coordinates generated with the given technique if
+/// they have no usable ones, that is, if no triangle has an area in texture
+/// space. Without a technique, such entities are only warned about.
+pub fn unwrapped_entities(entities: Vec<Entity>, unwrap: Option<Unwrap>) -> Vec<Entity> {
+    entities
+        .into_iter()
+        .map(|entity| {
+            let triangles: Vec<TupleTriangle<Vertex>> = entity.mesh.triangles().collect();
+            if has_usable_texcoords(&triangles) {
+                return entity;
+            }
+
+            let unwrap = match unwrap {
+                Some(unwrap) => unwrap,
+                None => {
+                    warn!(
+                        "Entity {entity} with material {material} has no usable texture coordinates, textures synthesized for it will not make sense. Set auto_unwrap to per_triangle or box to generate some.",
+                        entity = entity.name,
+                        material = entity.material.name(),
+                    );
+                    return entity;
+                }
+            };
+
+            warn!(
+                "Entity {entity} with material {material} has no usable texture coordinates, generating them with {unwrap:?} unwrap. Maps of the original material will not line up with synthesized textures.",
+                entity = entity.name,
+                material = entity.material.name(),
+                unwrap = unwrap,
+            );
+
+            let vertices = match unwrap {
+                Unwrap::PerTriangle => per_triangle_unwrap(&triangles),
+                Unwrap::Box => box_unwrap(&triangles),
+            };
+
+            Entity {
+                name: entity.name,
+                material: entity.material,
+                mesh: Rc::new(vertices.into_iter().collect()),
+            }
+        })
+        .collect()
+}
+
+/// Splits entities with overlapping UV islands into entities whose islands do
+/// not overlap, so that each gets textures of its own.
+///
+/// The first part keeps the name of the entity, further parts get the 1-based
+/// index of the part appended, e.g. `statue-uv2`. Parts share the material of
+/// the split entity.
+pub fn separated_entities(entities: Vec<Entity>) -> Vec<Entity> {
+    entities
+        .into_iter()
+        .flat_map(|entity| {
+            let triangles: Vec<TupleTriangle<Vertex>> = entity.mesh.triangles().collect();
+            let islands = uv_islands(&triangles);
+            let (overlapping, _) =
+                overlapping_islands(&triangles, &islands, SEPARATION_SIZE, SEPARATION_SIZE);
+            if overlapping.is_empty() {
+                return vec![entity];
+            }
+
+            let groups = separated_islands(islands.len(), &overlapping);
+            let group_count = groups.iter().max().map_or(0, |&max| max + 1);
+            info!(
+                "Separating overlapping UV islands of entity {entity} into {parts} entities.",
+                entity = entity.name,
+                parts = group_count,
+            );
+
+            (0..group_count)
+                .map(|group| {
+                    let vertices = islands
+                        .iter()
+                        .zip(groups.iter())
+                        .filter(|&(_, &g)| g == group)
+                        .flat_map(|(island, _)| island.iter())
+                        .flat_map(|&idx| {
+                            let TupleTriangle(a, b, c) = triangles[idx].clone();
+                            vec![a, b, c]
+                        });
+
+                    Entity {
+                        name: if group == 0 {
+                            entity.name.clone()
+                        } else {
+                            format!("{}-uv{}", entity.name, group + 1)
+                        },
+                        material: Rc::clone(&entity.material),
+                        mesh: Rc::new(vertices.collect()),
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn has_usable_texcoords(triangles: &[TupleTriangle<Vertex>]) -> bool {
+    triangles.is_empty()
+        || triangles.iter().any(|&TupleTriangle(ref a, ref b, ref c)| {
+            let (ta, tb, tc) = (a.texcoords, b.texcoords, c.texcoords);
+            let area = (tb.x - ta.x) * (tc.y - ta.y) - (tb.y - ta.y) * (tc.x - ta.x);
+            area.abs() > DEGENERATE_TEXCOORD_AREA
+        })
+}
+
+/// Vertices of the given triangles with each triangle mapped onto one half of
+/// a cell in a square grid, two triangles per cell.
+fn per_triangle_unwrap(triangles: &[TupleTriangle<Vertex>]) -> Vec<Vertex> {
+    let cells = (triangles.len() + 1) / 2;
+    let side = (cells as f32).sqrt().ceil().max(1.0) as usize;
+    let m = UNWRAP_MARGIN;
+    let lower = [(m, m), (1.0 - 2.0 * m, m), (m, 1.0 - 2.0 * m)];
+    let upper = [(1.0 - m, 1.0 - m), (2.0 * m, 1.0 - m), (1.0 - m, 2.0 * m)];
+
+    triangles
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, triangle)| {
+            let cell = idx / 2;
+            let (col, row) = ((cell % side) as f32, (cell / side) as f32);
+            let corners = if idx % 2 == 0 { lower } else { upper };
+            let TupleTriangle(a, b, c) = triangle.clone();
+
+            vec![a, b, c]
+                .into_iter()
+                .zip(corners.iter())
+                .map(move |(vertex, &(u, v))| Vertex {
+                    texcoords: Vec2::new((col + u) / side as f32, (row + v) / side as f32),
+                    ..vertex
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Vertices of the given triangles projected along the axis closest to their
+/// normal, with positive and negative x, y and z each in a cell of a three by
+/// two grid, scaled uniformly to fit the largest extent of the triangles.
+fn box_unwrap(triangles: &[TupleTriangle<Vertex>]) -> Vec<Vertex> {
+    let positions = triangles
+        .iter()
+        .flat_map(|&TupleTriangle(ref a, ref b, ref c)| vec![a.position, b.position, c.position]);
+    let (mut min, mut max) = ([::std::f32::MAX; 3], [::std::f32::MIN; 3]);
+    for position in positions {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(component(position, axis));
+            max[axis] = max[axis].max(component(position, axis));
+        }
+    }
+    let extent = (0..3).map(|axis| max[axis] - min[axis]).fold(0.0, f32::max);
+    let extent = if extent > 0.0 { extent } else { 1.0 };
+    let m = UNWRAP_MARGIN;
+
+    triangles
+        .iter()
+        .flat_map(|triangle| {
+            let TupleTriangle(a, b, c) = triangle.clone();
+            let e1 = b.position - a.position;
+            let e2 = c.position - a.position;
+            let normal = Vec3::new(
+                e1.y * e2.z - e1.z * e2.y,
+                e1.z * e2.x - e1.x * e2.z,
+                e1.x * e2.y - e1.y * e2.x,
+            );
+            let axis = (0..3)
+                .max_by(|&i, &j| {
+                    let (i, j) = (component(normal, i).abs(), component(normal, j).abs());
+                    i.partial_cmp(&j).unwrap_or(::std::cmp::Ordering::Equal)
+                })
+                .unwrap();
+            let negative = component(normal, axis) < 0.0;
+            let cell = axis * 2 + negative as usize;
+            let (col, row) = ((cell % 3) as f32, (cell / 3) as f32);
+            let (u_axis, v_axis) = ((axis + 1) % 3, (axis + 2) % 3);
+
+            vec![a, b, c]
+                .into_iter()
+                .map(move |vertex| {
+                    let local = |axis: usize| {
+                        (component(vertex.position, axis) - min[axis]) / extent * (1.0 - 2.0 * m)
+                            + m
+                    };
+                    // Mirrored on the negative side, so the winding stays the same
+                    let u = if negative {
+                        1.0 - local(u_axis)
+                    } else {
+                        local(u_axis)
+                    };
+                    Vertex {
+                        texcoords: Vec2::new((col + u) / 3.0, (row + local(v_axis)) / 2.0),
+                        ..vertex
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+fn component(vec: Vec3, axis: usize) -> f32 {
+    match axis {
+        0 => vec.x,
+        1 => vec.y,
+        _ => vec.z,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn vertex(x: f32, y: f32, z: f32) -> Vertex {
+        Vertex {
+            position: Vec3::new(x, y, z),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            texcoords: Vec2::new(0.0, 0.0),
+        }
+    }
+
+    #[test]
+    fn unwrap_without_overlaps() {
+        let front = (0..5).map(|i| {
+            let x = i as f32;
+            TupleTriangle(
+                vertex(x, 0.0, 0.0),
+                vertex(x + 1.0, 0.0, 0.0),
+                vertex(x, 1.0, 0.0),
+            )
+        });
+        // Same triangles, facing the other way
+        let back = front
+            .clone()
+            .map(|TupleTriangle(a, b, c)| TupleTriangle(a, c, b));
+        let triangles: Vec<_> = front.chain(back).collect();
+        assert!(!has_usable_texcoords(&triangles));
+
+        for unwrap in [per_triangle_unwrap, box_unwrap].iter() {
+            let vertices = unwrap(&triangles);
+            assert!(vertices.iter().all(|v| v.texcoords.x >= 0.0
+                && v.texcoords.x <= 1.0
+                && v.texcoords.y >= 0.0
+                && v.texcoords.y <= 1.0));
+
+            let unwrapped: Vec<_> = vertices
+                .chunks(3)
+                .map(|t| TupleTriangle(t[0].clone(), t[1].clone(), t[2].clone()))
+                .collect();
+            assert!(has_usable_texcoords(&unwrapped));
+
+            let islands = uv_islands(&unwrapped);
+            let (overlapping, _) = overlapping_islands(&unwrapped, &islands, 64, 64);
+            assert_eq!(Vec::<(usize, usize)>::new(), overlapping);
+        }
+    }
+}
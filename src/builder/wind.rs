@@ -0,0 +1,76 @@
+use builder::shape::{normalized, vec3};
+use geom::{Vec3, Vertex};
+use spec::Wind;
+
+/// Vertices of emission geometry with normals tilted into the wind.
+///
+/// Tons leave emission geometry around the normals, so tilting them biases
+/// straight and parabolic trajectories alike into the wind direction.
+pub fn blown_vertices<I>(vertices: I, wind: &Wind) -> Vec<Vertex>
+where
+    I: IntoIterator<Item = Vertex>,
+{
+    let blow = wind_vector(wind);
+    vertices
+        .into_iter()
+        .map(|vertex| Vertex {
+            // Normals facing exactly against the wind are left alone
+            normal: normalized(vertex.normal + blow).unwrap_or(vertex.normal),
+            ..vertex
+        })
+        .collect()
+}
+
+/// Flow direction of a source with the given static flow direction in the
+/// wind. Sources without a flow direction flow along the wind.
+pub fn blown_flow_direction(flow_direction: Option<[f32; 3]>, wind: &Wind) -> Option<Vec3> {
+    let blow = wind_vector(wind);
+    match flow_direction.map(vec3) {
+        Some(direction) => match normalized(direction) {
+            Some(unit) => Some(normalized(unit + blow).unwrap_or(direction)),
+            None => Some(direction),
+        },
+        None => normalized(blow),
+    }
+}
+
+/// Wind direction scaled to the strength of the wind.
+fn wind_vector(wind: &Wind) -> Vec3 {
+    normalized(vec3(wind.direction)).map_or(Vec3::new(0.0, 0.0, 0.0), |d| d * wind.strength)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geom::Vec2;
+
+    #[test]
+    fn tilt_into_wind() {
+        let wind = Wind {
+            direction: [2.0, 0.0, 0.0],
+            strength: 1.0,
+        };
+        let up = Vertex {
+            position: Vec3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            texcoords: Vec2::new(0.0, 0.0),
+        };
+
+        let blown = blown_vertices(vec![up], &wind)[0].normal;
+        let diagonal = 0.5f32.sqrt();
+        assert!((blown.x - diagonal).abs() < 1e-6 && (blown.y - diagonal).abs() < 1e-6);
+
+        let flow = blown_flow_direction(None, &wind).unwrap();
+        assert_eq!(Vec3::new(1.0, 0.0, 0.0), flow);
+
+        let calm = Wind {
+            strength: 0.0,
+            ..wind
+        };
+        assert_eq!(None, blown_flow_direction(None, &calm));
+        assert_eq!(
+            Some(Vec3::new(0.0, -1.0, 0.0)),
+            blown_flow_direction(Some([0.0, -1.0, 0.0]), &calm)
+        );
+    }
+}
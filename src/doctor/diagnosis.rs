@@ -0,0 +1,359 @@
+use asset::obj;
+use failure::{Error, ResultExt};
+use geom::{TupleTriangle, Vec3, Vertex};
+use scene::Mesh;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Areas below this are considered zero, both in world and texture space.
+const DEGENERATE_AREA: f32 = 1e-12;
+
+/// Problems found in the entities of an OBJ file.
+pub struct Diagnosis {
+    scene: PathBuf,
+    entities: Vec<EntityDiagnosis>,
+}
+
+struct EntityDiagnosis {
+    name: String,
+    material: String,
+    triangle_count: usize,
+    issues: Vec<Issue>,
+}
+
+/// A kind of problem and how many triangles or edges of an entity have it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IssueKind {
+    /// Triangles with zero length vertex normals.
+    MissingNormals,
+    /// All texture coordinates of the entity are the same, usually because
+    /// the OBJ has none.
+    MissingTexcoords,
+    /// Triangles with an area in world space, but none in texture space.
+    DegenerateTexcoords,
+    /// Triangles whose winding makes them face away from their vertex normals.
+    FlippedWinding,
+    /// Triangles without area in world space.
+    DegenerateTriangles,
+    /// Edges shared by more than two triangles.
+    NonManifoldEdges,
+}
+
+/// All kinds of issues, in the order they are reported.
+const ISSUE_KINDS: [IssueKind; 6] = [
+    IssueKind::MissingNormals,
+    IssueKind::MissingTexcoords,
+    IssueKind::DegenerateTexcoords,
+    IssueKind::FlippedWinding,
+    IssueKind::DegenerateTriangles,
+    IssueKind::NonManifoldEdges,
+];
+
+/// What an issue breaks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Impact {
+    Sampling,
+    Synthesis,
+    /// Works, but may give unexpected results.
+    Warning,
+}
+
+impl IssueKind {
+    pub fn impact(&self) -> Impact {
+        match *self {
+            IssueKind::MissingNormals | IssueKind::FlippedWinding => Impact::Sampling,
+            IssueKind::MissingTexcoords | IssueKind::DegenerateTexcoords => Impact::Synthesis,
+            IssueKind::DegenerateTriangles | IssueKind::NonManifoldEdges => Impact::Warning,
+        }
+    }
+
+    fn describe(&self, count: usize, triangle_count: usize) -> String {
+        match *self {
+            IssueKind::MissingNormals => format!(
+                "{} of {} triangles have no vertex normals, surfels would have no orientation",
+                count, triangle_count
+            ),
+            IssueKind::MissingTexcoords => String::from(
+                "no texture coordinates, no texels can be mapped to surfels",
+            ),
+            IssueKind::DegenerateTexcoords => format!(
+                "{} of {} triangles have no area in texture space and get no texels",
+                count, triangle_count
+            ),
+            IssueKind::FlippedWinding => format!(
+                "{} of {} triangles face away from their vertex normals, surfels would face into the mesh",
+                count, triangle_count
+            ),
+            IssueKind::DegenerateTriangles => format!(
+                "{} of {} triangles have no area and get no surfels",
+                count, triangle_count
+            ),
+            IssueKind::NonManifoldEdges => format!(
+                "{} edges are shared by more than two triangles, flow across them is unreliable",
+                count
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Impact {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Impact::Sampling => write!(f, "breaks surfel sampling"),
+            Impact::Synthesis => write!(f, "breaks texture synthesis"),
+            Impact::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Loads the OBJ at the given path and checks each of its entities.
+pub fn diagnose<P: AsRef<Path>>(scene: P) -> Result<Diagnosis, Error> {
+    let scene = scene.as_ref();
+    let entities =
+        obj::load(scene).with_context(|_| format!("Could not load scene {}.", scene.display()))?;
+
+    Ok(Diagnosis {
+        scene: scene.to_path_buf(),
+        entities: entities
+            .iter()
+            .map(|entity| EntityDiagnosis {
+                name: entity.name.clone(),
+                material: entity.material.name().to_string(),
+                triangle_count: entity.mesh.triangles().count(),
+                issues: diagnose_triangles(entity.mesh.triangles()),
+            })
+            .collect(),
+    })
+}
+
+/// Issues of the mesh with the given triangles, in the order of `IssueKind`.
+pub fn diagnose_triangles<I>(triangles: I) -> Vec<Issue>
+where
+    I: IntoIterator<Item = TupleTriangle<Vertex>>,
+{
+    let mut counts = [0; 6];
+
+    let mut first_texcoords = None;
+    let mut texcoords_vary = false;
+    let mut degenerate_texcoords = 0;
+    let mut edges = HashMap::new();
+
+    for TupleTriangle(a, b, c) in triangles {
+        let vertices = [&a, &b, &c];
+
+        if vertices.iter().any(|v| length_sqr(v.normal) == 0.0) {
+            counts[IssueKind::MissingNormals as usize] += 1;
+        }
+
+        let face_normal = cross(b.position - a.position, c.position - a.position);
+        let area = length_sqr(face_normal);
+        if area <= DEGENERATE_AREA {
+            counts[IssueKind::DegenerateTriangles as usize] += 1;
+        } else {
+            let vertex_normal = a.normal + b.normal + c.normal;
+            if dot(face_normal, vertex_normal) < 0.0 {
+                counts[IssueKind::FlippedWinding as usize] += 1;
+            }
+
+            let (ta, tb, tc) = (a.texcoords, b.texcoords, c.texcoords);
+            let texcoord_area = (tb.x - ta.x) * (tc.y - ta.y) - (tb.y - ta.y) * (tc.x - ta.x);
+            if texcoord_area.abs() <= DEGENERATE_AREA {
+                degenerate_texcoords += 1;
+            }
+        }
+
+        for vertex in vertices.iter() {
+            let texcoords = (vertex.texcoords.x, vertex.texcoords.y);
+            match first_texcoords {
+                None => first_texcoords = Some(texcoords),
+                Some(first) => texcoords_vary = texcoords_vary || first != texcoords,
+            }
+        }
+
+        for &(from, to) in [(&a, &b), (&b, &c), (&c, &a)].iter() {
+            let (from, to) = (position_key(from), position_key(to));
+            let edge = if from < to { (from, to) } else { (to, from) };
+            *edges.entry(edge).or_insert(0) += 1;
+        }
+    }
+
+    if first_texcoords.is_some() && !texcoords_vary {
+        counts[IssueKind::MissingTexcoords as usize] += 1;
+    } else {
+        // Only worth reporting if the triangles have texture coordinates at all
+        counts[IssueKind::DegenerateTexcoords as usize] = degenerate_texcoords;
+    }
+
+    counts[IssueKind::NonManifoldEdges as usize] =
+        edges.values().filter(|&&shared| shared > 2).count();
+
+    ISSUE_KINDS
+        .iter()
+        .filter(|&&kind| counts[kind as usize] > 0)
+        .map(|&kind| Issue {
+            kind,
+            count: counts[kind as usize],
+        })
+        .collect()
+}
+
+impl Diagnosis {
+    /// Whether any entity has an issue with the given impact.
+    pub fn has(&self, impact: Impact) -> bool {
+        self.entities
+            .iter()
+            .flat_map(|e| e.issues.iter())
+            .any(|i| i.kind.impact() == impact)
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}\n", self.scene.display())?;
+
+        for entity in self.entities.iter() {
+            write!(
+                f,
+                "  {} ({}), {} triangles: ",
+                entity.name, entity.material, entity.triangle_count
+            )?;
+
+            if entity.issues.is_empty() {
+                write!(f, "ok\n")?;
+            } else {
+                write!(f, "{} issues\n", entity.issues.len())?;
+                for issue in entity.issues.iter() {
+                    write!(
+                        f,
+                        "    {}: {}\n",
+                        issue.kind.impact(),
+                        issue.kind.describe(issue.count, entity.triangle_count)
+                    )?;
+                }
+            }
+        }
+
+        for &impact in [Impact::Sampling, Impact::Synthesis].iter() {
+            let affected = self
+                .entities
+                .iter()
+                .filter(|e| e.issues.iter().any(|i| i.kind.impact() == impact))
+                .count();
+            write!(
+                f,
+                "{} of {} entities: {}\n",
+                affected,
+                self.entities.len(),
+                impact
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Position as bits, so that vertices of neighboring triangles at exactly
+/// the same position share edges.
+fn position_key(vertex: &Vertex) -> [u32; 3] {
+    [
+        vertex.position.x.to_bits(),
+        vertex.position.y.to_bits(),
+        vertex.position.z.to_bits(),
+    ]
+}
+
+fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    Vec3::new(
+        a.y * b.z - a.z * b.y,
+        a.z * b.x - a.x * b.z,
+        a.x * b.y - a.y * b.x,
+    )
+}
+
+fn dot(a: Vec3, b: Vec3) -> f32 {
+    a.x * b.x + a.y * b.y + a.z * b.z
+}
+
+fn length_sqr(a: Vec3) -> f32 {
+    dot(a, a)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geom::Vec2;
+
+    fn vertex(x: f32, y: f32, normal_z: f32, u: f32, v: f32) -> Vertex {
+        Vertex {
+            position: Vec3::new(x, y, 0.0),
+            normal: Vec3::new(0.0, 0.0, normal_z),
+            texcoords: Vec2::new(u, v),
+        }
+    }
+
+    #[test]
+    fn healthy_quad() {
+        let quad = vec![
+            TupleTriangle(
+                vertex(0.0, 0.0, 1.0, 0.0, 0.0),
+                vertex(1.0, 0.0, 1.0, 1.0, 0.0),
+                vertex(1.0, 1.0, 1.0, 1.0, 1.0),
+            ),
+            TupleTriangle(
+                vertex(0.0, 0.0, 1.0, 0.0, 0.0),
+                vertex(1.0, 1.0, 1.0, 1.0, 1.0),
+                vertex(0.0, 1.0, 1.0, 0.0, 1.0),
+            ),
+        ];
+        assert_eq!(Vec::<Issue>::new(), diagnose_triangles(quad));
+    }
+
+    #[test]
+    fn broken_triangles() {
+        let triangles = vec![
+            // Facing away from its normals and without texture coordinates
+            TupleTriangle(
+                vertex(0.0, 0.0, -1.0, 0.0, 0.0),
+                vertex(1.0, 0.0, -1.0, 0.0, 0.0),
+                vertex(1.0, 1.0, -1.0, 0.0, 0.0),
+            ),
+            // Without normals
+            TupleTriangle(
+                vertex(0.0, 0.0, 0.0, 0.0, 0.0),
+                vertex(1.0, 1.0, 0.0, 0.0, 0.0),
+                vertex(0.0, 1.0, 0.0, 0.0, 0.0),
+            ),
+            // Third and fourth triangle on the same diagonal edge
+            TupleTriangle(
+                vertex(0.0, 0.0, 1.0, 0.0, 0.0),
+                vertex(2.0, 0.0, 1.0, 0.0, 0.0),
+                vertex(1.0, 1.0, 1.0, 0.0, 0.0),
+            ),
+            TupleTriangle(
+                vertex(0.0, 0.0, 1.0, 0.0, 0.0),
+                vertex(3.0, 0.0, 1.0, 0.0, 0.0),
+                vertex(1.0, 1.0, 1.0, 0.0, 0.0),
+            ),
+        ];
+
+        let issues = diagnose_triangles(triangles);
+        let kinds: Vec<IssueKind> = issues.iter().map(|i| i.kind).collect();
+        assert_eq!(
+            vec![
+                IssueKind::MissingNormals,
+                IssueKind::MissingTexcoords,
+                IssueKind::FlippedWinding,
+                IssueKind::NonManifoldEdges,
+            ],
+            kinds
+        );
+        assert!(issues.iter().all(|i| i.count == 1));
+    }
+}
@@ -0,0 +1,6 @@
+//! Checks meshes for problems that break surfel sampling or texture
+//! synthesis, before spending time on a simulation.
+
+mod diagnosis;
+
+pub use self::diagnosis::{diagnose, diagnose_triangles, Diagnosis, Impact, Issue, IssueKind};
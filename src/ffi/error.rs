@@ -0,0 +1,30 @@
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::ptr;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+/// Stores the message of an error for retrieval with `aitios_last_error`.
+pub fn set_last_error<S: Into<String>>(message: S) {
+    // Interior null bytes would truncate the message, replace them
+    let message = message.into().replace('\0', " ");
+    let message = CString::new(message).expect("Null bytes have been replaced");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+pub fn clear_last_error() {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+}
+
+/// Gets the message of the last error on this thread, or null if the last
+/// call succeeded. The string is valid until the next call on this thread.
+#[no_mangle]
+pub extern "C" fn aitios_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match *last.borrow() {
+        Some(ref message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
@@ -0,0 +1,15 @@
+//! C API for embedding aitios into other applications, e.g. plugins for
+//! DCC tools, enabled with the `ffi` feature. See `include/aitios.h`.
+//!
+//! Functions that can fail return null or a negative number and store an
+//! error message that can be obtained with `aitios_last_error`.
+
+mod error;
+mod simulation;
+
+pub use self::error::aitios_last_error;
+pub use self::simulation::{
+    aitios_simulation_free, aitios_simulation_iteration, aitios_simulation_last_iteration,
+    aitios_simulation_new, aitios_simulation_output_count, aitios_simulation_output_path,
+    aitios_simulation_run, aitios_simulation_step, AitiosSimulation,
+};
@@ -0,0 +1,191 @@
+use builder::SimulationBuilder;
+use failure::Error;
+use ffi::error::{clear_last_error, set_last_error};
+use runner::{Observer, SimulationRunner};
+use std::cell::{Cell, RefCell};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+use std::rc::Rc;
+
+/// Opaque handle to a simulation for C code.
+pub struct AitiosSimulation {
+    runner: SimulationRunner,
+    progress: Rc<Progress>,
+}
+
+/// Progress and output paths, recorded by observing the runner.
+struct Progress {
+    iteration: Cell<u32>,
+    last_iteration: Cell<u32>,
+    outputs: RefCell<Vec<CString>>,
+}
+
+struct ProgressObserver(Rc<Progress>);
+
+impl Observer for ProgressObserver {
+    fn iteration_started(&self, iteration: u32, last_iteration: u32) {
+        self.0.iteration.set(iteration);
+        self.0.last_iteration.set(last_iteration);
+    }
+
+    fn file_written(&self, path: &Path) {
+        if let Ok(path) = CString::new(path.to_string_lossy().into_owned()) {
+            self.0.outputs.borrow_mut().push(path);
+        }
+    }
+}
+
+/// Calls the given function, turning errors and panics into the given
+/// fallback value and storing the error message.
+fn guarded<T, F>(fallback: T, f: F) -> T
+where
+    F: FnOnce() -> Result<T, Error>,
+{
+    clear_last_error();
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(err)) => {
+            let message = err
+                .iter_chain()
+                .map(|cause| cause.to_string())
+                .collect::<Vec<_>>()
+                .join("\ncause: ");
+            set_last_error(message);
+            fallback
+        }
+        Err(_) => {
+            set_last_error("aitios panicked, see log output for details.");
+            fallback
+        }
+    }
+}
+
+/// Builds a simulation from a null-terminated YAML spec. Relative paths are
+/// resolved against the working directory.
+///
+/// Returns null on failure. Free the simulation with `aitios_simulation_free`.
+#[no_mangle]
+pub unsafe extern "C" fn aitios_simulation_new(spec_yaml: *const c_char) -> *mut AitiosSimulation {
+    guarded(ptr::null_mut(), || {
+        if spec_yaml.is_null() {
+            bail!("Spec YAML is null.");
+        }
+        let spec_yaml = CStr::from_ptr(spec_yaml).to_str()?;
+
+        let mut runner = SimulationBuilder::new()
+            .append_spec_fragment_str(spec_yaml)?
+            .build()?;
+
+        let progress = Rc::new(Progress {
+            iteration: Cell::new(0),
+            last_iteration: Cell::new(0),
+            outputs: RefCell::new(Vec::new()),
+        });
+        runner.add_observer(Box::new(ProgressObserver(Rc::clone(&progress))));
+
+        Ok(Box::into_raw(Box::new(AitiosSimulation {
+            runner,
+            progress,
+        })))
+    })
+}
+
+/// Performs the next iteration. Returns 1 if an iteration was performed,
+/// 0 if all iterations are done and -1 on failure.
+#[no_mangle]
+pub unsafe extern "C" fn aitios_simulation_step(simulation: *mut AitiosSimulation) -> c_int {
+    guarded(-1, || match simulation.as_mut() {
+        Some(simulation) => Ok(if simulation.runner.step()?.is_some() {
+            1
+        } else {
+            0
+        }),
+        None => bail!("Simulation is null."),
+    })
+}
+
+/// Performs all remaining iterations. Returns 0 on success and -1 on failure.
+#[no_mangle]
+pub unsafe extern "C" fn aitios_simulation_run(simulation: *mut AitiosSimulation) -> c_int {
+    guarded(-1, || match simulation.as_mut() {
+        Some(simulation) => simulation.runner.run().map(|_| 0),
+        None => bail!("Simulation is null."),
+    })
+}
+
+/// Gets the number of the iteration that was performed last, or is being performed.
+#[no_mangle]
+pub unsafe extern "C" fn aitios_simulation_iteration(simulation: *const AitiosSimulation) -> u32 {
+    simulation
+        .as_ref()
+        .map(|s| s.progress.iteration.get())
+        .unwrap_or(0)
+}
+
+/// Gets the number of the last iteration, known after the first step.
+#[no_mangle]
+pub unsafe extern "C" fn aitios_simulation_last_iteration(
+    simulation: *const AitiosSimulation,
+) -> u32 {
+    simulation
+        .as_ref()
+        .map(|s| s.progress.last_iteration.get())
+        .unwrap_or(0)
+}
+
+/// Gets the number of files written so far.
+#[no_mangle]
+pub unsafe extern "C" fn aitios_simulation_output_count(
+    simulation: *const AitiosSimulation,
+) -> usize {
+    simulation
+        .as_ref()
+        .map(|s| s.progress.outputs.borrow().len())
+        .unwrap_or(0)
+}
+
+/// Gets the path of the written file with the given index in writing order,
+/// or null if out of range. The string is valid until the simulation is freed.
+#[no_mangle]
+pub unsafe extern "C" fn aitios_simulation_output_path(
+    simulation: *const AitiosSimulation,
+    index: usize,
+) -> *const c_char {
+    simulation
+        .as_ref()
+        .and_then(|s| s.progress.outputs.borrow().get(index).map(|p| p.as_ptr()))
+        .unwrap_or(ptr::null())
+}
+
+/// Frees a simulation created with `aitios_simulation_new`. Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn aitios_simulation_free(simulation: *mut AitiosSimulation) {
+    if !simulation.is_null() {
+        drop(Box::from_raw(simulation));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ffi::aitios_last_error;
+
+    #[test]
+    fn invalid_spec_sets_error() {
+        let spec = CString::new("iterations: [").unwrap();
+
+        unsafe {
+            assert!(aitios_simulation_new(spec.as_ptr()).is_null());
+
+            let error = aitios_last_error();
+            assert!(!error.is_null());
+            assert!(CStr::from_ptr(error)
+                .to_str()
+                .unwrap()
+                .starts_with("Simulation spec failed to parse."));
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use files::create_file_recursively;
+use std::fs::{remove_file, rename, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Writes the file at the given path with the given function, first into a
+/// `.part` file next to it that only replaces the path once writing
+/// succeeded, so interrupted or failed writes never leave a partially written
+/// file at the path.
+pub fn write_atomically<P, F, E>(path: P, write: F) -> Result<(), E>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut File) -> Result<(), E>,
+    E: From<io::Error>,
+{
+    let path = path.as_ref();
+    let partial = partial_path(path);
+
+    let written = create_file_recursively(&partial)
+        .map_err(E::from)
+        .and_then(|mut file| {
+            write(&mut file)?;
+            // Renamed files must not lose their contents on power loss
+            file.sync_all()?;
+            Ok(())
+        })
+        .and_then(|_| rename(&partial, path).map_err(E::from));
+
+    if written.is_err() {
+        // Only exists if writing failed after creating it
+        let _ = remove_file(&partial);
+    }
+    written
+}
+
+/// Path next to the given path that it is written to before renaming it.
+pub fn partial_path(path: &Path) -> PathBuf {
+    let mut partial = path.as_os_str().to_os_string();
+    partial.push(".part");
+    PathBuf::from(partial)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, read_to_string, remove_dir_all};
+    use std::io::Write;
+
+    #[test]
+    fn failed_writes_keep_previous_file() {
+        let dir = temp_dir().join("aitios-atomic-write-test");
+        let path = dir.join("out.txt");
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+
+        write_atomically(&path, |file| file.write_all(b"weathered")).unwrap();
+        assert_eq!("weathered", read_to_string(&path).unwrap());
+
+        let failed: io::Result<()> = write_atomically(&path, |file| {
+            file.write_all(b"half")?;
+            Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"))
+        });
+        assert!(failed.is_err());
+        assert_eq!("weathered", read_to_string(&path).unwrap());
+        assert!(!partial_path(&path).exists());
+
+        remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,157 @@
+use files::{write_atomically, ResolveError};
+use reqwest::{self, Url};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Downloads the given URL into the download cache, unless it has been
+/// downloaded before, and returns the canonicalized path of the local copy.
+pub fn fetch(cache: &Path, url: &str) -> Result<PathBuf, ResolveError> {
+    let url = Url::parse(url).map_err(|_| ResolveError::InvalidUrl {
+        url: url.to_string(),
+    })?;
+    fetch_url(cache, &url)
+}
+
+/// Fetches a search path relative to a base directory inside the download
+/// cache from the server the base was downloaded from, e.g. a mesh referenced
+/// by a downloaded ton source spec.
+///
+/// Returns `None` if the base is not inside the cache.
+pub fn fetch_relative(
+    cache: &Path,
+    base: &Path,
+    search_path: &Path,
+) -> Option<Result<PathBuf, ResolveError>> {
+    let base_url = origin_url(cache, base)?;
+    let search_path = search_path.to_str()?;
+    Some(
+        base_url
+            .join(search_path)
+            .map_err(|_| ResolveError::InvalidUrl {
+                url: format!("{}{}", base_url, search_path),
+            })
+            .and_then(|url| fetch_url(cache, &url)),
+    )
+}
+
+fn fetch_url(cache: &Path, url: &Url) -> Result<PathBuf, ResolveError> {
+    let local = cache_path(cache, url).ok_or_else(|| ResolveError::InvalidUrl {
+        url: url.to_string(),
+    })?;
+
+    if !local.exists() {
+        download(url, &local).map_err(|cause| ResolveError::Download {
+            url: url.to_string(),
+            cause,
+        })?;
+    }
+
+    local
+        .canonicalize()
+        .map_err(|cause| ResolveError::Download {
+            url: url.to_string(),
+            cause,
+        })
+}
+
+fn download(url: &Url, dest: &Path) -> io::Result<()> {
+    let mut response = reqwest::get(url.clone())
+        .and_then(|r| r.error_for_status())
+        .map_err(to_io)?;
+
+    // Write next to the destination first, so interrupted downloads are not
+    // mistaken for cached files on the next run
+    write_atomically(dest, |file| response.copy_to(file).map(|_| ()).map_err(to_io))
+}
+
+fn to_io(err: reqwest::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err)
+}
+
+/// Local path of a downloaded URL, mirroring scheme, host and path, e.g.
+/// `https://example.com:8080/rain/sky.obj` is downloaded to
+/// `<cache>/https/example.com_8080/rain/sky.obj`.
+///
+/// Returns `None` for URLs with other schemes than `http` and `https`, without
+/// a host or that point to a directory.
+fn cache_path(cache: &Path, url: &Url) -> Option<PathBuf> {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return None;
+    }
+
+    let mut path = cache.join(url.scheme());
+    path.push(match url.port() {
+        Some(port) => format!("{}_{}", url.host_str()?, port),
+        None => url.host_str()?.to_string(),
+    });
+
+    for segment in url.path_segments()? {
+        // Parsing already removed . and .., so empty segments are the only
+        // way to point at a directory
+        if segment.is_empty() {
+            return None;
+        }
+        path.push(segment);
+    }
+
+    Some(path)
+}
+
+/// Inverse of `cache_path` for directories, with a trailing slash so relative
+/// URLs can be joined to it.
+fn origin_url(cache: &Path, dir: &Path) -> Option<Url> {
+    let mut components = dir.strip_prefix(cache).ok()?.iter();
+    let scheme = components.next()?.to_str()?;
+    let host = components.next()?.to_str()?;
+
+    // Ports were separated with an underscore, since colons are not allowed
+    // in file names on all platforms
+    let host = match host.rfind('_') {
+        Some(idx) if host[idx + 1..].parse::<u16>().is_ok() => {
+            format!("{}:{}", &host[..idx], &host[idx + 1..])
+        }
+        _ => host.to_string(),
+    };
+
+    let mut url = format!("{}://{}/", scheme, host);
+    for segment in components {
+        url.push_str(segment.to_str()?);
+        url.push('/');
+    }
+
+    Url::parse(&url).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cache_path_round_trip() {
+        let cache = Path::new("/tmp/aitios-downloads");
+        let url = Url::parse("https://example.com:8080/assets/rain/rain.yml").unwrap();
+
+        let local = cache_path(cache, &url).unwrap();
+        assert_eq!(
+            Path::new("/tmp/aitios-downloads/https/example.com_8080/assets/rain/rain.yml"),
+            local
+        );
+
+        let origin = origin_url(cache, local.parent().unwrap()).unwrap();
+        assert_eq!("https://example.com:8080/assets/rain/", origin.as_str());
+        assert_eq!(url, origin.join("rain.yml").unwrap());
+    }
+
+    #[test]
+    fn reject_unmappable_urls() {
+        let cache = Path::new("/tmp/aitios-downloads");
+        for url in &[
+            "https://example.com/",
+            "https://example.com/dir/",
+            "ftp://example.com/a",
+        ] {
+            let url = Url::parse(url).unwrap();
+            assert!(cache_path(cache, &url).is_none(), "{} accepted", url);
+        }
+    }
+}
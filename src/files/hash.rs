@@ -0,0 +1,101 @@
+#[cfg(feature = "native")]
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// Content hashes of input files by their paths.
+pub type InputHashes = BTreeMap<PathBuf, String>;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Hex digest of the contents of the file at the given path, to tell whether
+/// an input changed since it was recorded. Uses 64-bit FNV-1a, which detects
+/// accidental changes but not deliberate collisions.
+pub fn content_hash(path: &Path) -> io::Result<String> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut buf = [0; 64 * 1024];
+    let mut hash = FNV_OFFSET_BASIS;
+
+    loop {
+        let len = reader.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        for &byte in buf[..len].iter() {
+            hash = (hash ^ byte as u64).wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    Ok(format!("{:016x}", hash))
+}
+
+/// Hex SHA-256 digest of the contents of the file at the given path, for
+/// inputs pinned in the spec.
+#[cfg(feature = "native")]
+pub fn sha256(path: &Path) -> io::Result<String> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut buf = [0; 64 * 1024];
+    let mut hasher = Sha256::new();
+
+    loop {
+        let len = reader.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        hasher.input(&buf[..len]);
+    }
+
+    Ok(hasher
+        .result()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// Paths of inputs with contents that differ from the recorded hashes, sorted.
+/// Inputs that only one of them has are not compared, since they stem from a
+/// changed spec rather than changed files.
+pub fn changed_inputs<'a>(recorded: &InputHashes, current: &'a InputHashes) -> Vec<&'a Path> {
+    current
+        .iter()
+        .filter(|&(path, hash)| recorded.get(path).map_or(false, |before| before != hash))
+        .map(|(path, _)| path.as_path())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs::{remove_file, write};
+
+    #[test]
+    fn hash_and_compare() {
+        let path = temp_dir().join("aitios-content-hash-test.obj");
+        write(&path, b"").unwrap();
+        assert_eq!("cbf29ce484222325", content_hash(&path).unwrap());
+        write(&path, b"a").unwrap();
+        assert_eq!("af63dc4c8601ec8c", content_hash(&path).unwrap());
+        #[cfg(feature = "native")]
+        assert_eq!(
+            "ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb",
+            sha256(&path).unwrap()
+        );
+        remove_file(&path).unwrap();
+
+        let hashes = |entries: &[(&str, &str)]| -> InputHashes {
+            entries
+                .iter()
+                .map(|&(path, hash)| (PathBuf::from(path), String::from(hash)))
+                .collect()
+        };
+        let recorded = hashes(&[("scene.obj", "1"), ("iron.yml", "2"), ("rust.png", "3")]);
+        let current = hashes(&[("scene.obj", "1"), ("iron.yml", "4"), ("moss.png", "5")]);
+
+        assert!(changed_inputs(&recorded, &recorded).is_empty());
+        assert_eq!(vec![Path::new("iron.yml")], changed_inputs(&recorded, &current));
+    }
+}
@@ -1,7 +1,23 @@
+mod atomic;
+#[cfg(feature = "http")]
+mod download;
+mod hash;
+mod output;
+mod pattern;
 mod recursive;
 mod resolv;
 mod timestamp;
+#[cfg(feature = "native")]
+mod volume;
 
+pub use self::atomic::write_atomically;
+pub use self::hash::{changed_inputs, content_hash, InputHashes};
+#[cfg(feature = "native")]
+pub use self::hash::sha256;
+pub use self::output::OutputResolver;
+pub use self::pattern::{entity_dirs, has_placeholder, placeholders, unformatted, PatternValues};
 pub use self::recursive::create_file_recursively;
 pub use self::resolv::{ResolveError, Resolver};
 pub use self::timestamp::fs_timestamp;
+#[cfg(feature = "native")]
+pub use self::volume::{existing_dir, volume, Volume};
@@ -0,0 +1,95 @@
+use std::env::current_dir;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Makes paths of outputs absolute relative to a write root, like `Resolver`
+/// does for inputs.
+///
+/// In contrast to inputs, outputs do not need to exist. They are normalized
+/// instead, so that different spellings of the same file, e.g. with `..` or
+/// through symlinks, turn into the same path and outputs that would overwrite
+/// each other can be detected by comparing paths.
+#[derive(Clone, Debug)]
+pub struct OutputResolver {
+    root: PathBuf,
+}
+
+impl OutputResolver {
+    /// Resolves outputs relative to the given root directory, which is in
+    /// turn relative to the current working directory if relative.
+    ///
+    /// The root does not need to exist.
+    pub fn new<P: AsRef<Path>>(root: P) -> io::Result<Self> {
+        Ok(OutputResolver {
+            root: normalize(&current_dir()?.join(root)),
+        })
+    }
+
+    /// Resolves outputs relative to the current working directory.
+    pub fn working_dir() -> io::Result<Self> {
+        Self::new("")
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Makes the given output path or pattern absolute and normalizes it.
+    /// Absolute paths are only normalized.
+    pub fn resolve<P: AsRef<Path>>(&self, output: P) -> PathBuf {
+        normalize(&self.root.join(output))
+    }
+}
+
+/// Removes `.`, applies `..` and replaces symlinks with their targets, as
+/// far as the path exists.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            // Applied after resolving symlinks, like the file system would
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => {
+                normalized.push(component.as_os_str());
+                if let Ok(canonical) = normalized.canonicalize() {
+                    normalized = canonical;
+                }
+            }
+        }
+    }
+
+    normalized
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn same_output_same_path() {
+        let outputs = OutputResolver::new("output_resolver_test").unwrap();
+        let root = current_dir()
+            .unwrap()
+            .canonicalize()
+            .unwrap()
+            .join("output_resolver_test");
+
+        assert_eq!(root, outputs.root());
+        assert_eq!(
+            root.join("{datetime}/albedo.png"),
+            outputs.resolve("{datetime}/albedo.png")
+        );
+        assert_eq!(
+            outputs.resolve("{datetime}/albedo.png"),
+            outputs.resolve("./{datetime}/textures/../albedo.png")
+        );
+        assert_eq!(
+            PathBuf::from("/nonexistent/out.png"),
+            outputs.resolve("/nonexistent/out.png")
+        );
+    }
+}
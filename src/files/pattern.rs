@@ -0,0 +1,331 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::DateTime;
+
+/// Values for the placeholders in output file patterns.
+///
+/// Placeholders without a value are left in the pattern unchanged,
+/// e.g. `{entity}` in scene-wide OBJ patterns.
+#[derive(Debug, Clone, Default)]
+pub struct PatternValues<'a> {
+    pub datetime: &'a str,
+    pub iteration: Option<u32>,
+    pub id: Option<usize>,
+    pub entity: Option<&'a str>,
+    /// Directory name of the entity, see `entity_dirs`.
+    pub entity_dir: Option<&'a str>,
+    pub material: Option<&'a str>,
+    pub substance: Option<&'a str>,
+    /// Kind and index of the effect in the spec.
+    pub effect: Option<(&'a str, usize)>,
+    /// Width and height of the written texture.
+    pub size: Option<(u32, u32)>,
+}
+
+impl<'a> PatternValues<'a> {
+    pub fn new(datetime: &'a str) -> Self {
+        PatternValues {
+            datetime,
+            ..Default::default()
+        }
+    }
+
+    pub fn iteration(mut self, iteration: u32) -> Self {
+        self.iteration = Some(iteration);
+        self
+    }
+
+    /// Sets both the index of the entity and its name.
+    pub fn entity(mut self, id: usize, name: &'a str) -> Self {
+        self.id = Some(id);
+        self.entity = Some(name);
+        self
+    }
+
+    /// Sets the directory name of the entity, see `entity_dirs`.
+    pub fn entity_dir(mut self, dir: &'a str) -> Self {
+        self.entity_dir = Some(dir);
+        self
+    }
+
+    /// Sets the name of the material of the entity.
+    pub fn material(mut self, material: &'a str) -> Self {
+        self.material = Some(material);
+        self
+    }
+
+    pub fn substance(mut self, substance: &'a str) -> Self {
+        self.substance = Some(substance);
+        self
+    }
+
+    /// Sets the resolution of the texture that is written.
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.size = Some((width, height));
+        self
+    }
+
+    /// Sets the effect by its kind and index in the spec, substituted like
+    /// `layer-1`.
+    pub fn effect(mut self, kind: &'a str, idx: usize) -> Self {
+        self.effect = Some((kind, idx));
+        self
+    }
+
+    /// Replaces all placeholders in the given pattern that have a value.
+    ///
+    /// Placeholders can have a format after a colon, a zero-padded width for
+    /// numbers like `{iteration:04}` or a `strftime` format for the date like
+    /// `{datetime:%Y%m%d}`. Placeholders with a format that their value does
+    /// not support are left unchanged, like placeholders without a value.
+    pub fn substitute(&self, pattern: &str) -> String {
+        let mut path = String::with_capacity(pattern.len());
+        let mut rest = pattern;
+
+        for placeholder in placeholders(pattern) {
+            let start = rest.find(placeholder).unwrap();
+            path.push_str(&rest[..start]);
+            match self.value(placeholder) {
+                Some(value) => path.push_str(&value),
+                None => path.push_str(placeholder),
+            }
+            rest = &rest[start + placeholder.len()..];
+        }
+
+        path.push_str(rest);
+        path
+    }
+
+    fn value(&self, placeholder: &str) -> Option<String> {
+        let (name, format) = split(placeholder);
+        match (name, format) {
+            ("iteration", _) => self.iteration.and_then(|i| format_number(i as usize, format)),
+            ("id", _) => self.id.and_then(|id| format_number(id, format)),
+            ("width", _) => self.size.and_then(|(w, _)| format_number(w as usize, format)),
+            ("height", _) => self.size.and_then(|(_, h)| format_number(h as usize, format)),
+            ("entity", None) => self.entity.map(String::from),
+            ("entity_dir", None) => self.entity_dir.map(String::from),
+            ("material", None) => self.material.map(String::from),
+            ("substance", None) => self.substance.map(String::from),
+            ("effect", None) => self.effect.map(|(kind, idx)| format!("{}-{}", kind, idx)),
+            ("datetime", None) => Some(String::from(self.datetime)),
+            ("datetime", Some(format)) => format_datetime(self.datetime, format),
+            _ => None,
+        }
+    }
+}
+
+/// Names for a directory of each of the entities with the given names, in the
+/// same order. Characters that separate or are not allowed in paths are
+/// replaced with `_`, and names that several entities share are suffixed with
+/// the index of the entity, e.g. `Crate-3`.
+pub fn entity_dirs<'a, I>(names: I) -> Vec<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let sanitized: Vec<String> = names.into_iter().map(sanitized_dir).collect();
+    let suffixed = |dirs: &[String]| -> Vec<String> {
+        dirs.iter()
+            .enumerate()
+            .map(|(idx, dir)| {
+                if dirs.iter().filter(|&other| other == dir).count() > 1 {
+                    format!("{}-{}", dir, idx)
+                } else {
+                    dir.clone()
+                }
+            })
+            .collect()
+    };
+
+    // Suffixing twice also separates suffixed names from other names that
+    // happen to end in the same suffix
+    let once = suffixed(&sanitized);
+    suffixed(&once)
+}
+
+fn sanitized_dir(name: &str) -> String {
+    let dir: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    match dir.as_str() {
+        "" => String::from("_"),
+        "." | ".." => dir.replace('.', "_"),
+        _ => dir,
+    }
+}
+
+/// Finds all placeholders in the given pattern, that is, substrings enclosed
+/// in curly braces, including the braces and the format if any.
+pub fn placeholders(pattern: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut rest = pattern;
+
+    while let Some(start) = rest.find('{') {
+        match rest[start..].find('}') {
+            Some(len) => {
+                tokens.push(&rest[start..start + len + 1]);
+                rest = &rest[start + len + 1..];
+            }
+            None => break,
+        }
+    }
+
+    tokens
+}
+
+/// Whether the given pattern contains the placeholder with the given name,
+/// with or without a format.
+pub fn has_placeholder(pattern: &str, name: &str) -> bool {
+    placeholders(pattern)
+        .into_iter()
+        .any(|placeholder| split(placeholder).0 == name)
+}
+
+/// The given placeholder without its format, e.g. `{iteration}` for
+/// `{iteration:04}`, and whether the format is one that the placeholder
+/// supports. Placeholders without a format are always well-formed.
+pub fn unformatted(placeholder: &str) -> (String, bool) {
+    let (name, format) = split(placeholder);
+    let well_formed = match (name, format) {
+        (_, None) => true,
+        ("iteration", Some(format))
+        | ("id", Some(format))
+        | ("width", Some(format))
+        | ("height", Some(format)) => {
+            format_number(0, Some(format)).is_some()
+        }
+        ("datetime", Some(format)) => is_strftime(format),
+        _ => false,
+    };
+    (format!("{{{}}}", name), well_formed)
+}
+
+/// Name and format of a placeholder with its braces.
+fn split(placeholder: &str) -> (&str, Option<&str>) {
+    let inner = &placeholder[1..placeholder.len() - 1];
+    match inner.find(':') {
+        Some(colon) => (&inner[..colon], Some(&inner[colon + 1..])),
+        None => (inner, None),
+    }
+}
+
+/// Formats a number with a zero-padded width like `04`, like `{:04}` does.
+fn format_number(number: usize, format: Option<&str>) -> Option<String> {
+    match format {
+        None => Some(number.to_string()),
+        Some(format) if format.len() > 1 && format.starts_with('0') => format[1..]
+            .parse::<usize>()
+            .ok()
+            .map(|width| format!("{:0width$}", number, width = width)),
+        Some(_) => None,
+    }
+}
+
+/// Formats a filename-safe timestamp made by `fs_timestamp` again, keeping
+/// the result filename-safe.
+fn format_datetime(datetime: &str, format: &str) -> Option<String> {
+    if !is_strftime(format) {
+        return None;
+    }
+    DateTime::parse_from_rfc3339(&datetime.replace('_', ":"))
+        .ok()
+        .map(|datetime| datetime.format(format).to_string().replace(':', "_"))
+}
+
+fn is_strftime(format: &str) -> bool {
+    !format.is_empty() && !StrftimeItems::new(format).any(|item| item == Item::Error)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn substitute_set_placeholders_only() {
+        let values = PatternValues::new("2018-07-17T18_06_53")
+            .iteration(3)
+            .substance("rust");
+
+        assert_eq!(
+            "2018-07-17T18_06_53/iteration-3/{id}-{entity}-rust.png",
+            values.substitute("{datetime}/iteration-{iteration}/{id}-{entity}-{substance}.png")
+        );
+
+        let values = values.entity(4, "Buddha");
+        assert_eq!(
+            "2018-07-17T18_06_53/iteration-3/4-Buddha-rust.png",
+            values.substitute("{datetime}/iteration-{iteration}/{id}-{entity}-{substance}.png")
+        );
+    }
+
+    #[test]
+    fn substitute_formatted_placeholders() {
+        let values = PatternValues::new("2018-07-17T18_06_53+02_00")
+            .iteration(3)
+            .entity(12, "Buddha");
+
+        assert_eq!(
+            "20180717/0003-012-Buddha-{entity:04}",
+            values.substitute("{datetime:%Y%m%d}/{iteration:04}-{id:03}-{entity}-{entity:04}")
+        );
+        assert_eq!("18_06", values.substitute("{datetime:%H:%M}"));
+        assert_eq!("{iteration:4}", values.substitute("{iteration:4}"));
+        assert_eq!("{datetime:%Q}", values.substitute("{datetime:%Q}"));
+
+        assert_eq!((String::from("{iteration}"), true), unformatted("{iteration:04}"));
+        assert_eq!((String::from("{substance}"), false), unformatted("{substance:04}"));
+        assert!(has_placeholder("out/{iteration:02}.png", "iteration"));
+        assert!(!has_placeholder("out/{id}.png", "iteration"));
+    }
+
+    #[test]
+    fn substitute_material_and_effect() {
+        let values = PatternValues::new("2018-07-17T18_06_53")
+            .entity(4, "Buddha")
+            .material("bronze")
+            .effect("layer", 2);
+
+        assert_eq!(
+            "Buddha-bronze-layer-2.png",
+            values.substitute("{entity}-{material}-{effect}.png")
+        );
+        assert_eq!(
+            "{material}-density-0.png",
+            PatternValues::new("")
+                .effect("density", 0)
+                .substitute("{material}-{effect}.png")
+        );
+    }
+
+    #[test]
+    fn safe_and_distinct_entity_dirs() {
+        let names = ["Statue", "Crate", "Crate", "a/../b", "..", "Crate-2", "Crate"];
+        assert_eq!(
+            vec!["Statue", "Crate-1", "Crate-2-2", "a_.._b", "__", "Crate-2-5", "Crate-6"],
+            entity_dirs(names.iter().cloned())
+        );
+
+        let values = PatternValues::new("").entity(2, "a/b").entity_dir("a_b");
+        assert_eq!("a_b/a/b.png", values.substitute("{entity_dir}/{entity}.png"));
+    }
+
+    #[test]
+    fn substitute_resolution() {
+        let values = PatternValues::new("").size(512, 256);
+
+        assert_eq!(
+            "albedo_512x256.png",
+            values.substitute("albedo_{width}x{height}.png")
+        );
+        assert_eq!("0512", values.substitute("{width:04}"));
+        assert_eq!(
+            "albedo_{width}.png",
+            PatternValues::new("").substitute("albedo_{width}.png")
+        );
+    }
+}
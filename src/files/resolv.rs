@@ -1,3 +1,9 @@
+#[cfg(feature = "http")]
+use files::download;
+use glob::{glob, Pattern, PatternError};
+use std::collections::BTreeMap;
+#[cfg(feature = "http")]
+use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
 
@@ -19,26 +25,94 @@ pub enum ResolveError {
         cause: io::Error,
     },
     #[fail(
-        display = "Resolving search path {:?} failed. It was neither absolute and existing nor found in any of the base paths {:?}.",
+        display = "Resolving search path {:?} failed. It was neither absolute and existing nor found in any of the base paths {:?}. Tried, in order: {:?}",
         search_path,
-        bases
+        bases,
+        tried
     )]
     NotFound {
         search_path: PathBuf,
         bases: Vec<PathBuf>,
+        /// Candidate paths that were checked for existence, in order.
+        tried: Vec<PathBuf>,
+    },
+    #[fail(display = "Search pattern {:?} is not a valid glob pattern.", pattern)]
+    InvalidPattern {
+        pattern: PathBuf,
+        #[cause]
+        cause: PatternError,
+    },
+    #[fail(
+        display = "Search pattern {:?} did not match any files. It was neither absolute and matching nor matching in any of the base paths {:?}.",
+        pattern,
+        bases
+    )]
+    NoMatches { pattern: PathBuf, bases: Vec<PathBuf> },
+    #[fail(
+        display = "Search path {} is a URL, but no download cache has been configured to fetch it into. Downloading requires aitios to be built with the http feature.",
+        url
+    )]
+    DownloadsDisabled { url: String },
+    #[fail(
+        display = "URL {} cannot be downloaded. Only http and https URLs of files are supported.",
+        url
+    )]
+    InvalidUrl { url: String },
+    #[fail(display = "Downloading {} into the download cache failed.", url)]
+    Download {
+        url: String,
+        #[cause]
+        cause: io::Error,
+    },
+    #[fail(display = "Download cache {:?} could not be created.", cache_path)]
+    InaccessibleDownloadCache {
+        cache_path: PathBuf,
+        #[cause]
+        cause: io::Error,
     },
 }
 
 /// Resolves existing relative and absolute filenames for using a list
 /// of base paths that the filenames for lookup can be relative to.
+///
+/// With the `http` feature and a download cache, `http://` and `https://`
+/// URLs can be resolved too.
 #[derive(Clone)]
 pub struct Resolver {
     bases: Vec<PathBuf>,
+    #[cfg(feature = "http")]
+    download_cache: Option<PathBuf>,
 }
 
 impl Resolver {
     pub fn new() -> Self {
-        Self { bases: Vec::new() }
+        Self {
+            bases: Vec::new(),
+            #[cfg(feature = "http")]
+            download_cache: None,
+        }
+    }
+
+    /// Sets the directory that URLs are downloaded into when resolving them,
+    /// creating it if necessary.
+    ///
+    /// Files are stored with paths that mirror scheme, host and path of the URL
+    /// and are not downloaded again if already present in the cache. Paths that
+    /// are relative to a base inside the cache are fetched from the server the
+    /// base was downloaded from if they have not been downloaded yet, so specs
+    /// on a server can reference their neighbours with relative paths.
+    #[cfg(feature = "http")]
+    pub fn set_download_cache<P: AsRef<Path>>(&mut self, cache: P) -> Result<(), ResolveError> {
+        let cache = cache.as_ref();
+        let canonical = fs::create_dir_all(cache)
+            .and_then(|_| cache.canonicalize())
+            .map_err(|cause| ResolveError::InaccessibleDownloadCache {
+                cache_path: cache.to_path_buf(),
+                cause,
+            })?;
+
+        self.download_cache = Some(canonical);
+        Ok(())
     }
 
     /// Adds a base directory for later calls to resolve.
@@ -147,55 +221,218 @@ impl Resolver {
     /// }
     /// ```
     pub fn resolve<P: AsRef<Path>>(&self, search_path_param: P) -> Result<PathBuf, ResolveError> {
-        let mut search_path = search_path_param.as_ref();
+        let search_path = search_path_param.as_ref();
 
         if search_path.as_os_str().is_empty() {
             return Err(ResolveError::EmptySearchPath);
         }
 
-        // If search path is already absolute, first try to canonicalize it and
-        // returning it without looking for it in base directories.
+        if let Some(url) = url(search_path) {
+            return self.fetch(url);
+        }
+
+        let candidates = self.candidates(search_path);
+        for candidate in candidates.iter() {
+            if let Ok(resolved) = candidate.canonicalize() {
+                // No further existence check required, canonicalize does this
+                return Ok(resolved);
+            }
+        }
+
+        #[cfg(feature = "http")]
+        {
+            if let Some(ref cache) = self.download_cache {
+                let fetched = self
+                    .bases
+                    .iter()
+                    .filter_map(|base| {
+                        download::fetch_relative(cache, base, pseudo_relative(search_path))
+                    })
+                    .filter_map(Result::ok)
+                    .next();
+
+                if let Some(fetched) = fetched {
+                    return Ok(fetched);
+                }
+            }
+        }
+
+        debug!("Resolving {:?} failed, tried in order:", search_path);
+        for candidate in candidates.iter() {
+            debug!("    {}", candidate.display());
+        }
+
+        Err(ResolveError::NotFound {
+            search_path: search_path.to_path_buf(),
+            bases: self.bases.clone(),
+            tried: candidates,
+        })
+    }
+
+    /// Gets the paths that `resolve` checks for existence for the given
+    /// search path, in order, together with their canonicalized form if they
+    /// exist. The first existing one is the result of `resolve`.
+    ///
+    /// Useful to find out why a path was not found or resolved to an
+    /// unexpected file.
+    pub fn trace<P: AsRef<Path>>(&self, search_path: P) -> Vec<(PathBuf, Option<PathBuf>)> {
+        self.candidates(search_path.as_ref())
+            .into_iter()
+            .map(|candidate| {
+                let resolved = candidate.canonicalize().ok();
+                (candidate, resolved)
+            }).collect()
+    }
+
+    fn candidates(&self, search_path: &Path) -> Vec<PathBuf> {
+        let mut candidates = Vec::with_capacity(self.bases.len() + 1);
+
+        if search_path.as_os_str().is_empty() {
+            return candidates;
+        }
+
+        // If search path is already absolute, it is tried first as it is,
+        // without looking for it in base directories.
         //
         // REVIEW is there some potential for accidents where a pseudo-root also is
         // an absolute file?
         if search_path.is_absolute() {
-            match search_path.canonicalize() {
-                Ok(canonicalized) => return Ok(canonicalized),
-                // If canonicalization of the path failed, e.g. because an
-                // intermediate directory did not exist or the final file or
-                // directory did not exist, we try to reinterpret the path
-                // as relative to one of the bases.
-                //
-                // This allows to use the bases as a sort of "pseudo-root".
-                Err(_) => {
-                    // Drop the prefix component like / on unix or C:\ on Windows
-                    // Result is always Ok, otherwise canonicalization would have succeeded with
-                    // a root path
-                    search_path = search_path
-                        .strip_prefix(
-                            search_path.iter().next().unwrap(), // unwrap safe since is_empty() returned false
-                        )
-                        .unwrap();
-                }
-            }
+            candidates.push(search_path.to_path_buf());
         }
 
         // Otherwise, interpret any path as relative, even if it was a non-existing absolute path.
-        for mut resolve_attempt in self.bases.iter().cloned() {
-            resolve_attempt.push(search_path);
-            if let Ok(resolve_attempt) = resolve_attempt.canonicalize() {
-                // No further existence check required, canonicalize does this
-                return Ok(resolve_attempt);
+        for base in self.bases.iter() {
+            candidates.push(base.join(pseudo_relative(search_path)));
+        }
+
+        candidates
+    }
+
+    /// Like `resolve`, but the search path may be a glob pattern like
+    /// `samples/rust_*.png` that matches multiple files.
+    ///
+    /// Matches from all base paths are combined. If the same relative path
+    /// matches in multiple base paths, the one from the first base path is
+    /// used, like with `resolve`. The matches are sorted by their path relative
+    /// to the base path or root they were found in.
+    ///
+    /// Search paths without any of the special characters `*`, `?` and `[`
+    /// and URLs are resolved with `resolve`, returning a single path.
+    ///
+    /// If nothing matches, returns a `NoMatches` error.
+    pub fn resolve_glob<P: AsRef<Path>>(
+        &self,
+        pattern_param: P,
+    ) -> Result<Vec<PathBuf>, ResolveError> {
+        let mut pattern = pattern_param.as_ref();
+
+        let is_pattern = |p: &str| p.contains(|c| c == '*' || c == '?' || c == '[');
+        let pattern_str = match pattern.to_str() {
+            Some(p) if url(pattern).is_none() && is_pattern(p) => p,
+            _ => return self.resolve(pattern).map(|p| vec![p]),
+        };
+
+        Pattern::new(pattern_str).map_err(|cause| ResolveError::InvalidPattern {
+            pattern: pattern.to_path_buf(),
+            cause,
+        })?;
+
+        // Absolute patterns first match as they are, then in the bases like pseudo-absolute paths
+        if pattern.is_absolute() {
+            let matches = glob_in(Path::new(""), pattern_str);
+            if !matches.is_empty() {
+                return Ok(matches.into_iter().map(|(_, path)| path).collect());
             }
+
+            pattern = pseudo_relative(pattern);
         }
 
-        Err(ResolveError::NotFound {
-            search_path: search_path_param.as_ref().to_path_buf(),
-            bases: self.bases.clone(),
+        // Sorted by relative path, earlier bases take precedence for the same relative path
+        let mut matches = BTreeMap::new();
+        let pattern_str = pattern.to_str().unwrap();
+        for base in self.bases.iter() {
+            for (relative, path) in glob_in(base, pattern_str) {
+                matches.entry(relative).or_insert(path);
+            }
+        }
+
+        if matches.is_empty() {
+            Err(ResolveError::NoMatches {
+                pattern: pattern_param.as_ref().to_path_buf(),
+                bases: self.bases.clone(),
+            })
+        } else {
+            Ok(matches.into_iter().map(|(_, path)| path).collect())
+        }
+    }
+
+    #[cfg(feature = "http")]
+    fn fetch(&self, url: &str) -> Result<PathBuf, ResolveError> {
+        match self.download_cache {
+            Some(ref cache) => download::fetch(cache, url),
+            None => Err(ResolveError::DownloadsDisabled {
+                url: url.to_string(),
+            }),
+        }
+    }
+
+    #[cfg(not(feature = "http"))]
+    fn fetch(&self, url: &str) -> Result<PathBuf, ResolveError> {
+        Err(ResolveError::DownloadsDisabled {
+            url: url.to_string(),
         })
     }
 }
 
+/// Makes absolute search paths relative by dropping the root.
+///
+/// If an absolute path does not exist, e.g. because an intermediate
+/// directory did not exist or the final file or directory did not
+/// exist, we try to reinterpret the path as relative to one of
+/// the bases. This allows to use the bases as a sort of "pseudo-root".
+fn pseudo_relative(search_path: &Path) -> &Path {
+    if search_path.is_absolute() {
+        // Drop the prefix component like / on unix or C:\ on Windows
+        search_path
+            .strip_prefix(
+                search_path.iter().next().unwrap(), // unwrap safe since absolute paths have a root
+            ).unwrap()
+    } else {
+        search_path
+    }
+}
+
+/// Canonicalized paths of existing files matching the pattern relative to
+/// the given base, together with the matched path relative to the base.
+fn glob_in(base: &Path, pattern: &str) -> Vec<(PathBuf, PathBuf)> {
+    // Special characters in the base must not be interpreted as a pattern
+    let full_pattern = match base.to_str() {
+        Some(base) if !base.is_empty() => format!("{}/{}", Pattern::escape(base), pattern),
+        Some(_) => pattern.to_string(),
+        None => return Vec::new(),
+    };
+
+    let paths = match glob(&full_pattern) {
+        Ok(paths) => paths,
+        Err(_) => return Vec::new(),
+    };
+
+    paths
+        .filter_map(Result::ok)
+        .filter(|path| path.is_file())
+        .filter_map(|path| {
+            let relative = path.strip_prefix(base).unwrap_or(&path).to_path_buf();
+            path.canonicalize().ok().map(|path| (relative, path))
+        }).collect()
+}
+
+/// Returns the search path as a string if it is an `http://` or `https://` URL.
+fn url(search_path: &Path) -> Option<&str> {
+    search_path
+        .to_str()
+        .filter(|p| p.starts_with("http://") || p.starts_with("https://"))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -459,6 +696,75 @@ mod test {
         );
     }
 
+    #[test]
+    fn glob_across_bases() {
+        let directory = "resolver_test_glob_inner";
+        let files = [
+            "resolver_test_glob_b.txt",
+            "resolver_test_glob_inner/resolver_test_glob_a.txt",
+            "resolver_test_glob_inner/resolver_test_glob_b.txt",
+        ];
+
+        create_dir(directory).unwrap();
+        for file in files.iter() {
+            File::create(file).unwrap();
+        }
+
+        let mut resolver = Resolver::new();
+        resolver.add_base(".").unwrap();
+        resolver.add_base(directory).unwrap();
+
+        let matches = resolver.resolve_glob("resolver_test_glob_?.txt").unwrap();
+        assert_eq!(2, matches.len());
+        assert!(matches[0].ends_with(files[1]), "Expected a from inner base");
+        assert!(
+            matches[1].ends_with(files[0]) && !matches[1].ends_with(files[2]),
+            "Expected b from first base"
+        );
+
+        assert!(resolver.resolve_glob("resolver_test_glob_*.png").is_err());
+
+        for file in files.iter() {
+            remove_file(file).unwrap();
+        }
+        remove_dir(directory).unwrap();
+    }
+
+    #[test]
+    fn trace_pseudo_absolute() {
+        let mut resolver = Resolver::new();
+        resolver.add_base(".").unwrap();
+        let cwd = current_dir().unwrap().canonicalize().unwrap();
+
+        let trace = resolver.trace("/holodriodl.txt");
+        assert_eq!(
+            vec![
+                (PathBuf::from("/holodriodl.txt"), None),
+                (cwd.join("holodriodl.txt"), None),
+            ],
+            trace
+        );
+
+        match resolver.resolve("/holodriodl.txt") {
+            Err(ResolveError::NotFound { tried, .. }) => assert_eq!(
+                trace.into_iter().map(|(c, _)| c).collect::<Vec<_>>(),
+                tried
+            ),
+            other => panic!("Expected not found error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn url_without_download_cache() {
+        let resolver = Resolver::new();
+        match resolver.resolve("https://example.com/rain.yml") {
+            Err(ResolveError::DownloadsDisabled { url }) => {
+                assert_eq!("https://example.com/rain.yml", url)
+            }
+            other => panic!("Expected downloads to be disabled, got {:?}", other),
+        }
+    }
+
     #[test]
     fn deduplicate() {
         let mut resolver = Resolver::new();
@@ -0,0 +1,50 @@
+use std::io;
+use std::path::Path;
+
+/// File system volume of a directory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Volume {
+    /// Device of the volume, the same for all directories on it.
+    pub device: u64,
+    /// Bytes that unprivileged users can still write to the volume.
+    pub available: u64,
+}
+
+/// Nearest existing directory that the given path is in or would be created
+/// in, or `None` if not even the root of the path exists.
+pub fn existing_dir(path: &Path) -> Option<&Path> {
+    path.ancestors().skip(1).find(|dir| dir.is_dir())
+}
+
+/// Volume of the given existing directory.
+#[cfg(unix)]
+pub fn volume(dir: &Path) -> io::Result<Volume> {
+    use libc;
+    use std::ffi::CString;
+    use std::mem;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::MetadataExt;
+
+    let device = dir.metadata()?.dev();
+    let dir = CString::new(dir.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+    let mut stat: libc::statvfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statvfs(dir.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(Volume {
+        device,
+        available: stat.f_bavail as u64 * stat.f_frsize as u64,
+    })
+}
+
+/// Volume of the given existing directory.
+#[cfg(not(unix))]
+pub fn volume(_dir: &Path) -> io::Result<Volume> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "Free space of volumes can only be determined on unix",
+    ))
+}
@@ -0,0 +1,285 @@
+use failure::{Error, ResultExt};
+use std::fmt;
+use std::fs::{copy, create_dir_all, read_dir};
+use std::path::{Path, PathBuf};
+use tex::{self, RgbaImage};
+
+/// Extensions of the outputs that are compared, other outputs like OBJ
+/// files or surfel snapshots are ignored.
+const IMAGE_EXTENSIONS: &'static [&'static str] = &["png", "jpg", "jpeg"];
+
+/// Outcomes of comparing all images of a run against the references.
+#[derive(Debug)]
+pub struct GoldenReport {
+    pub tolerance: f32,
+    pub diffs: Vec<ImageDiff>,
+}
+
+/// Outcome for one image, with its path relative to the output and reference
+/// directories.
+#[derive(Debug)]
+pub struct ImageDiff {
+    pub path: PathBuf,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    /// Mean and maximum CIE76 color difference of the pixels, where a
+    /// difference of about 2.3 is just noticeable.
+    Compared { mean: f32, max: f32 },
+    /// Output and reference differ in width or height.
+    SizeMismatch {
+        output: (u32, u32),
+        reference: (u32, u32),
+    },
+    /// The run wrote an image that has no reference.
+    MissingReference,
+    /// The reference has no image written by the run.
+    MissingOutput,
+}
+
+impl GoldenReport {
+    /// Whether all images have a reference and none differs by more than the
+    /// tolerance on average.
+    pub fn passed(&self) -> bool {
+        self.diffs.iter().all(|d| self.passes(d.outcome))
+    }
+
+    fn passes(&self, outcome: Outcome) -> bool {
+        match outcome {
+            Outcome::Compared { mean, .. } => mean <= self.tolerance,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for GoldenReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for diff in self.diffs.iter() {
+            let status = if self.passes(diff.outcome) {
+                "ok"
+            } else {
+                "FAILED"
+            };
+            write!(f, "{:<7}{}: ", status, diff.path.display())?;
+            match diff.outcome {
+                Outcome::Compared { mean, max } => {
+                    writeln!(f, "mean ΔE {:.3}, max ΔE {:.3}", mean, max)?
+                }
+                Outcome::SizeMismatch { output, reference } => writeln!(
+                    f,
+                    "{}x{} but the reference is {}x{}",
+                    output.0, output.1, reference.0, reference.1
+                )?,
+                Outcome::MissingReference => writeln!(f, "no reference image")?,
+                Outcome::MissingOutput => writeln!(f, "not written by the run")?,
+            }
+        }
+
+        let failed = self
+            .diffs
+            .iter()
+            .filter(|d| !self.passes(d.outcome))
+            .count();
+        write!(
+            f,
+            "{} of {} images within mean ΔE {}",
+            self.diffs.len() - failed,
+            self.diffs.len(),
+            self.tolerance
+        )
+    }
+}
+
+/// Compares the images in the output directory against the images at the
+/// same relative paths in the reference directory.
+pub fn compare_images(
+    outputs: &Path,
+    references: &Path,
+    tolerance: f32,
+) -> Result<GoldenReport, Error> {
+    let output_images = images_in(outputs)?;
+    let reference_images = images_in(references)?;
+
+    let mut diffs = Vec::new();
+    for path in output_images.iter() {
+        let outcome = if reference_images.contains(path) {
+            let output = open(&outputs.join(path))?;
+            let reference = open(&references.join(path))?;
+            compare(&output, &reference)
+        } else {
+            Outcome::MissingReference
+        };
+        diffs.push(ImageDiff {
+            path: path.clone(),
+            outcome,
+        });
+    }
+    for path in reference_images.iter() {
+        if !output_images.contains(path) {
+            diffs.push(ImageDiff {
+                path: path.clone(),
+                outcome: Outcome::MissingOutput,
+            });
+        }
+    }
+    diffs.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(GoldenReport { tolerance, diffs })
+}
+
+/// Copies the images in the output directory into the reference directory,
+/// returning their relative paths.
+pub fn update_references(outputs: &Path, references: &Path) -> Result<Vec<PathBuf>, Error> {
+    let images = images_in(outputs)?;
+    for path in images.iter() {
+        let reference = references.join(path);
+        if let Some(dir) = reference.parent() {
+            create_dir_all(dir)?;
+        }
+        copy(outputs.join(path), &reference)
+            .with_context(|_| format!("Could not write reference {}.", reference.display()))?;
+    }
+    Ok(images)
+}
+
+fn compare(output: &RgbaImage, reference: &RgbaImage) -> Outcome {
+    if output.dimensions() != reference.dimensions() {
+        return Outcome::SizeMismatch {
+            output: output.dimensions(),
+            reference: reference.dimensions(),
+        };
+    }
+
+    let (sum, max) = output
+        .pixels()
+        .zip(reference.pixels())
+        .map(|(o, r)| delta_e(o.data, r.data))
+        .fold((0.0, 0.0f32), |(sum, max), delta| {
+            (sum + delta as f64, max.max(delta))
+        });
+    let count = output.width() as f64 * output.height() as f64;
+    let mean = if count > 0.0 {
+        (sum / count) as f32
+    } else {
+        0.0
+    };
+
+    Outcome::Compared { mean, max }
+}
+
+fn open(path: &Path) -> Result<RgbaImage, Error> {
+    let image =
+        tex::open(path).with_context(|_| format!("Could not load image {}.", path.display()))?;
+    Ok(image.to_rgba())
+}
+
+/// Paths of the images in the given directory and its subdirectories,
+/// relative to it and sorted. A missing directory has no images.
+fn images_in(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut images = Vec::new();
+    if dir.is_dir() {
+        collect_images(dir, Path::new(""), &mut images)?;
+    }
+    images.sort();
+    Ok(images)
+}
+
+fn collect_images(root: &Path, relative: &Path, images: &mut Vec<PathBuf>) -> Result<(), Error> {
+    let dir = root.join(relative);
+    for entry in read_dir(&dir).with_context(|_| format!("Could not list {}.", dir.display()))? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            collect_images(root, &path, images)?;
+        } else if is_image(&path) {
+            images.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| IMAGE_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// CIE76 difference of two sRGB colors, ignoring alpha.
+fn delta_e(a: [u8; 4], b: [u8; 4]) -> f32 {
+    let (a, b) = (lab(a), lab(b));
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+/// CIELAB coordinates of an sRGB color under a D65 white point.
+fn lab(color: [u8; 4]) -> [f32; 3] {
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let (r, g, b) = (linear(color[0]), linear(color[1]), linear(color[2]));
+
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f32| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tex::Rgba;
+
+    #[test]
+    fn delta_e_of_gray_levels() {
+        let gray = |level| {
+            RgbaImage::from_pixel(
+                4,
+                2,
+                Rgba {
+                    data: [level, level, level, 255],
+                },
+            )
+        };
+
+        assert_eq!(
+            Outcome::Compared {
+                mean: 0.0,
+                max: 0.0
+            },
+            compare(&gray(128), &gray(128))
+        );
+        match compare(&gray(128), &gray(130)) {
+            Outcome::Compared { mean, max } => {
+                assert!(mean > 0.5 && mean < 1.0, "mean ΔE was {}", mean);
+                assert_eq!(mean, max);
+            }
+            outcome => panic!("Expected comparison, got {:?}", outcome),
+        }
+        // White is L=100 and black L=0
+        assert!((delta_e([255; 4], [0, 0, 0, 255]) - 100.0).abs() < 0.1);
+        assert_eq!(
+            Outcome::SizeMismatch {
+                output: (4, 2),
+                reference: (2, 2)
+            },
+            compare(&gray(0), &RgbaImage::new(2, 2))
+        );
+    }
+}
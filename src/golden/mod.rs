@@ -0,0 +1,6 @@
+//! Compares textures written by a run against stored reference images, to
+//! check that refactoring a spec or upgrading crates did not change results.
+
+mod comparison;
+
+pub use self::comparison::{compare_images, update_references, GoldenReport, ImageDiff, Outcome};
@@ -1,28 +1,60 @@
+#[cfg(feature = "native")]
 extern crate aitios_asset as asset;
+#[cfg(feature = "native")]
 extern crate aitios_geom as geom;
+#[cfg(feature = "native")]
 extern crate aitios_scene as scene;
+#[cfg(feature = "native")]
 extern crate aitios_sim as sim;
+#[cfg(feature = "native")]
 extern crate aitios_surf as surf;
+#[cfg(feature = "native")]
 extern crate aitios_tex as tex;
+#[cfg(feature = "native")]
 #[macro_use]
 extern crate clap;
-#[macro_use]
+#[cfg_attr(feature = "native", macro_use)]
 extern crate failure;
 #[macro_use]
 extern crate failure_derive;
+#[cfg(all(feature = "native", unix))]
+extern crate libc;
 extern crate chrono;
+extern crate glob;
 #[macro_use]
 extern crate serde_derive;
+#[cfg(feature = "native")]
 extern crate rayon;
+#[cfg(feature = "http")]
+extern crate reqwest;
 extern crate serde;
+extern crate serde_ignored;
+extern crate serde_json;
 extern crate serde_yaml;
+#[cfg(feature = "native")]
+extern crate sha2;
 #[macro_use]
 extern crate log;
+#[cfg(feature = "native")]
 extern crate simplelog;
+#[cfg(feature = "native")]
+extern crate zip;
 
+#[cfg(feature = "native")]
 pub mod app;
+#[cfg(feature = "native")]
 mod bencher;
 pub mod builder;
+#[cfg(feature = "native")]
+pub mod doctor;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+// Without the simulation, only path resolution is used
+#[cfg_attr(not(feature = "native"), allow(dead_code, unused_imports))]
 mod files;
+#[cfg(feature = "native")]
+pub mod golden;
+pub mod profiler;
+#[cfg(feature = "native")]
 pub mod runner;
 pub mod spec;
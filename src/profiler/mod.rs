@@ -0,0 +1,9 @@
+//! Records nested timing spans of the simulation phases and persists
+//! them in the chrome tracing format, which can be viewed in
+//! `chrome://tracing` or compatible tools.
+
+mod profiler;
+mod span;
+
+pub use self::profiler::Profiler;
+pub use self::span::Span;
@@ -0,0 +1,121 @@
+use profiler::Span;
+use serde_json;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Collects spans of simulation phases in memory until written
+/// out with `write_chrome_trace`.
+pub struct Profiler {
+    origin: Instant,
+    events: RefCell<Vec<TraceEvent>>,
+}
+
+/// Complete event in the chrome tracing format, with timestamp
+/// and duration in microseconds.
+#[derive(Serialize)]
+struct TraceEvent {
+    name: String,
+    cat: &'static str,
+    ph: &'static str,
+    ts: f64,
+    dur: f64,
+    pid: u32,
+    tid: u32,
+}
+
+#[derive(Serialize)]
+struct Trace<'a> {
+    #[serde(rename = "traceEvents")]
+    trace_events: &'a [TraceEvent],
+    #[serde(rename = "displayTimeUnit")]
+    display_time_unit: &'static str,
+}
+
+impl Profiler {
+    /// Creates a new profiler with timestamps relative to now.
+    pub fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+            events: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Starts a span with the given name and category, e.g. `"tracing"`,
+    /// which is recorded when the returned span is dropped.
+    pub fn span<'a, S: Into<String>>(&'a self, name: S, category: &'static str) -> Span<'a> {
+        Span::new(self, name.into(), category)
+    }
+
+    /// Number of spans recorded so far.
+    pub fn span_count(&self) -> usize {
+        self.events.borrow().len()
+    }
+
+    pub fn record(&self, name: String, category: &'static str, start: Instant, end: Instant) {
+        let event = TraceEvent {
+            name,
+            cat: category,
+            ph: "X",
+            ts: micros(start.duration_since(self.origin)),
+            dur: micros(end.duration_since(start)),
+            pid: 1,
+            tid: 1,
+        };
+
+        self.events.borrow_mut().push(event);
+    }
+
+    /// Writes all spans recorded so far into the given sink as a JSON
+    /// object in the chrome tracing format.
+    pub fn write_chrome_trace<W: Write>(&self, sink: W) -> io::Result<()> {
+        let events = self.events.borrow();
+        let trace = Trace {
+            trace_events: &events,
+            display_time_unit: "ms",
+        };
+
+        serde_json::to_writer_pretty(sink, &trace).map_err(io::Error::from)
+    }
+}
+
+fn micros(duration: Duration) -> f64 {
+    (duration.as_secs() as f64) * 1_000_000.0 + (duration.subsec_nanos() as f64) / 1_000.0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::Value;
+    use std::thread::sleep;
+
+    #[test]
+    fn nested_spans() {
+        let profiler = Profiler::new();
+
+        {
+            let _outer = profiler.span("outer", "test");
+            {
+                let _inner = profiler.span("inner", "test");
+                sleep(Duration::from_millis(10));
+            }
+        }
+
+        assert_eq!(2, profiler.span_count());
+
+        let mut json = Vec::new();
+        profiler
+            .write_chrome_trace(&mut json)
+            .expect("Could not serialize trace");
+
+        let trace: Value = serde_json::from_slice(&json).expect("Trace is not valid JSON");
+        let events = trace["traceEvents"].as_array().unwrap();
+
+        // Inner span ends first and is recorded first
+        assert_eq!("inner", events[0]["name"]);
+        assert_eq!("outer", events[1]["name"]);
+        assert_eq!("X", events[1]["ph"]);
+        assert!(events[0]["dur"].as_f64().unwrap() >= 10_000.0);
+        assert!(events[1]["dur"].as_f64().unwrap() >= events[0]["dur"].as_f64().unwrap());
+    }
+}
@@ -0,0 +1,31 @@
+use profiler::Profiler;
+use std::time::Instant;
+
+/// A span running in a profiler.
+///
+/// It is recorded in the profiler when dropped.
+pub struct Span<'a> {
+    profiler: &'a Profiler,
+    name: String,
+    category: &'static str,
+    start_time: Instant,
+}
+
+impl<'a> Span<'a> {
+    pub fn new(profiler: &'a Profiler, name: String, category: &'static str) -> Self {
+        Self {
+            profiler,
+            name,
+            category,
+            start_time: Instant::now(),
+        }
+    }
+}
+
+impl<'a> Drop for Span<'a> {
+    fn drop(&mut self) {
+        let name = ::std::mem::replace(&mut self.name, String::new());
+        self.profiler
+            .record(name, self.category, self.start_time, Instant::now());
+    }
+}
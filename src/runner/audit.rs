@@ -0,0 +1,168 @@
+use sim::SurfelRule;
+
+/// Changes in the total of a substance over all surfels while tracing that
+/// the spec explains, through sources that carry or pick up the substance
+/// and rules that create, transfer or remove it.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Budget {
+    pub may_grow: bool,
+    pub may_shrink: bool,
+}
+
+impl Budget {
+    /// A substance that may change in either direction, e.g. if a custom
+    /// source carries it.
+    pub fn unbounded() -> Self {
+        Budget {
+            may_grow: true,
+            may_shrink: true,
+        }
+    }
+}
+
+/// Marks the substances that the given rule may create or remove.
+pub fn explain_rule(budgets: &mut [Budget], rule: &SurfelRule) {
+    match rule {
+        &SurfelRule::Transfer {
+            source_substance_idx,
+            target_substance_idx,
+            factor,
+        } => {
+            if factor > 0.0 {
+                budgets[source_substance_idx].may_shrink = true;
+                budgets[target_substance_idx].may_grow = true;
+            } else if factor < 0.0 {
+                budgets[source_substance_idx].may_grow = true;
+                budgets[target_substance_idx].may_shrink = true;
+            }
+        }
+        &SurfelRule::Deteriorate {
+            substance_idx,
+            factor,
+        } => {
+            budgets[substance_idx].may_grow |= factor > 0.0;
+            budgets[substance_idx].may_shrink |= factor < 0.0;
+        }
+        &SurfelRule::Deposit {
+            substance_idx,
+            amount,
+        } => {
+            budgets[substance_idx].may_grow |= amount > 0.0;
+            budgets[substance_idx].may_shrink |= amount < 0.0;
+        }
+    }
+}
+
+/// Compares the totals of substances before and after stages of iterations
+/// for `--audit`, to find configurations that create or destroy mass
+/// without meaning to, e.g. with some transport modes.
+///
+/// Rules apply while tracing, so their changes are audited together with
+/// substance transport.
+#[derive(Debug, Clone)]
+pub struct Audit {
+    names: Vec<String>,
+    budgets: Vec<Budget>,
+}
+
+impl Audit {
+    pub fn new(names: Vec<String>, budgets: Vec<Budget>) -> Self {
+        Audit { names, budgets }
+    }
+
+    /// Describes how the total of each substance changed in the given stage,
+    /// e.g. `rust +0.52, humidity -12.1`, or `None` if none changed.
+    pub fn changes(&self, before: &[f32], after: &[f32]) -> Option<String> {
+        let changes: Vec<String> = self
+            .names
+            .iter()
+            .zip(before.iter().zip(after.iter()))
+            .filter(|&(_, (before, after))| changed(*before, *after))
+            .map(|(name, (before, after))| format!("{} {:+}", name, after - before))
+            .collect();
+
+        if changes.is_empty() {
+            None
+        } else {
+            Some(changes.join(", "))
+        }
+    }
+
+    /// Describes changes while tracing that neither the sources nor the rules
+    /// of the spec explain, one for each substance.
+    pub fn unexplained(&self, before: &[f32], after: &[f32]) -> Vec<String> {
+        self.names
+            .iter()
+            .zip(self.budgets.iter())
+            .zip(before.iter().zip(after.iter()))
+            .filter_map(|((name, budget), (&before, &after))| {
+                if !changed(before, after) {
+                    None
+                } else if after > before && !budget.may_grow {
+                    Some(format!(
+                        "{} grew by {} although no source carries it and no rule creates it",
+                        name,
+                        after - before
+                    ))
+                } else if after < before && !budget.may_shrink {
+                    Some(format!(
+                        "{} shrank by {} although no source picks it up and no rule removes it",
+                        name,
+                        before - after
+                    ))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// Whether a total changed by more than the rounding error of summing up
+/// the concentrations of many surfels.
+fn changed(before: f32, after: f32) -> bool {
+    (after - before).abs() > 1e-4 * before.abs().max(1.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unexplained_changes() {
+        let mut budgets = vec![Budget::default(); 3];
+        // Humidity turns into rust
+        explain_rule(
+            &mut budgets,
+            &SurfelRule::Transfer {
+                source_substance_idx: 0,
+                target_substance_idx: 1,
+                factor: 0.5,
+            },
+        );
+        let audit = Audit::new(
+            vec![
+                String::from("humidity"),
+                String::from("rust"),
+                String::from("moss"),
+            ],
+            budgets,
+        );
+
+        let before = [10.0, 1.0, 2.0];
+        assert!(audit.unexplained(&before, &[9.0, 2.0, 2.0]).is_empty());
+        assert!(audit.unexplained(&before, &[10.0, 1.0, 2.00001]).is_empty());
+
+        let unexplained = audit.unexplained(&before, &[11.0, 0.5, 3.0]);
+        assert_eq!(3, unexplained.len());
+        assert!(unexplained[0].starts_with("humidity grew"));
+        assert!(unexplained[1].starts_with("rust shrank"));
+        assert!(unexplained[2].starts_with("moss grew"));
+
+        assert_eq!(
+            Some(String::from("humidity -1, rust +1")),
+            audit.changes(&before, &[9.0, 2.0, 2.0])
+        );
+        assert_eq!(None, audit.changes(&before, &before));
+    }
+}
@@ -0,0 +1,146 @@
+use tex::{Rgba, RgbaImage};
+
+/// Blends sRGB encoded maps in linear light, since interpolating gamma
+/// encoded values darkens the midtones between light and dark samples.
+pub struct LinearLight {
+    /// Linear value of each 8-bit sRGB level.
+    levels: [f32; 256],
+}
+
+impl LinearLight {
+    pub fn new() -> Self {
+        let mut levels = [0.0; 256];
+        for (level, linear) in levels.iter_mut().enumerate() {
+            *linear = srgb_to_linear(level as f32 / 255.0);
+        }
+        LinearLight { levels }
+    }
+
+    /// Interpolates the samples of the stops around the concentration in the
+    /// red channel of the guide for each texel, like a guided blend does for
+    /// linear maps. Stops must be sorted by cenith and samples of other sizes
+    /// than the guide are sampled at the nearest texel.
+    pub fn guided_blend(&self, stops: &[(f32, RgbaImage)], guide: &RgbaImage) -> RgbaImage {
+        let (width, height) = guide.dimensions();
+        RgbaImage::from_fn(width, height, |x, y| {
+            let concentration = guide.get_pixel(x, y).data[0] as f32 / 255.0;
+            let sample = |idx: usize| {
+                let sample: &RgbaImage = &stops[idx].1;
+                let (sample_width, sample_height) = sample.dimensions();
+                *sample.get_pixel(x * sample_width / width, y * sample_height / height)
+            };
+
+            match stops.iter().position(|s| s.0 > concentration) {
+                Some(0) => sample(0),
+                Some(upper) => {
+                    let (lower_cenith, upper_cenith) = (stops[upper - 1].0, stops[upper].0);
+                    let t = (concentration - lower_cenith) / (upper_cenith - lower_cenith);
+                    self.mix(sample(upper - 1), sample(upper), t)
+                }
+                None => sample(stops.len() - 1),
+            }
+        })
+    }
+
+    /// Composites `top` over `bottom` in linear light, with alpha as coverage.
+    pub fn over(&self, bottom: Rgba<u8>, top: Rgba<u8>) -> Rgba<u8> {
+        let top_alpha = top.data[3] as f32 / 255.0;
+        let bottom_alpha = bottom.data[3] as f32 / 255.0 * (1.0 - top_alpha);
+        let alpha = top_alpha + bottom_alpha;
+        if alpha == 0.0 {
+            return Rgba { data: [0; 4] };
+        }
+
+        let mut data = [0; 4];
+        for channel in 0..3 {
+            let linear = (self.decode(top.data[channel]) * top_alpha
+                + self.decode(bottom.data[channel]) * bottom_alpha)
+                / alpha;
+            data[channel] = encode(linear);
+        }
+        data[3] = (alpha * 255.0).round() as u8;
+        Rgba { data }
+    }
+
+    /// Average of the given texels with colors averaged in linear light.
+    pub fn average(&self, texels: &[Rgba<u8>]) -> Rgba<u8> {
+        let count = texels.len() as f32;
+        let mut data = [0; 4];
+        for channel in 0..3 {
+            let linear: f32 = texels.iter().map(|t| self.decode(t.data[channel])).sum();
+            data[channel] = encode(linear / count);
+        }
+        let alpha: f32 = texels.iter().map(|t| t.data[3] as f32).sum();
+        data[3] = (alpha / count).round() as u8;
+        Rgba { data }
+    }
+
+    /// Interpolates colors in linear light and alpha as it is.
+    fn mix(&self, from: Rgba<u8>, to: Rgba<u8>, t: f32) -> Rgba<u8> {
+        let mut data = [0; 4];
+        for channel in 0..3 {
+            let linear =
+                self.decode(from.data[channel]) * (1.0 - t) + self.decode(to.data[channel]) * t;
+            data[channel] = encode(linear);
+        }
+        data[3] = (from.data[3] as f32 * (1.0 - t) + to.data[3] as f32 * t).round() as u8;
+        Rgba { data }
+    }
+
+    fn decode(&self, level: u8) -> f32 {
+        self.levels[level as usize]
+    }
+}
+
+/// Converts an sRGB encoded value between 0 and 1 to linear light.
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Nearest 8-bit sRGB level of a linear value between 0 and 1.
+fn encode(linear: f32) -> u8 {
+    let linear = linear.max(0.0).min(1.0);
+    let srgb = if linear <= 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn blends_in_linear_light() {
+        let gray = |level| Rgba {
+            data: [level, level, level, 255],
+        };
+        let light = LinearLight::new();
+
+        for level in 0..256 {
+            assert_eq!(level as u8, encode(light.decode(level as u8)));
+        }
+
+        // Half way between black and white is brighter than 128 in sRGB
+        let stops = vec![
+            (0.0, RgbaImage::from_pixel(2, 2, gray(0))),
+            (1.0, RgbaImage::from_pixel(1, 1, gray(255))),
+        ];
+        let guide = RgbaImage::from_fn(2, 1, |x, _| gray(if x == 0 { 0 } else { 128 }));
+        let blended = light.guided_blend(&stops, &guide);
+        assert_eq!(gray(0), *blended.get_pixel(0, 0));
+        assert_eq!(gray(188), *blended.get_pixel(1, 0));
+
+        let half_white = Rgba {
+            data: [255, 255, 255, 128],
+        };
+        assert_eq!(gray(188), light.over(gray(0), half_white));
+        assert_eq!(gray(0), light.over(gray(0), Rgba { data: [0; 4] }));
+    }
+}
@@ -0,0 +1,16 @@
+/// Command to a running simulation, handled between iterations. Send them
+/// through the channel passed to `SimulationRunner::set_commands`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Wait after the current iteration until resumed or aborted.
+    Pause,
+    Resume,
+    /// Run the effects right away on the current surfels, regardless of the
+    /// effect schedule.
+    RunEffects,
+    /// Dump the surfels to an OBJ file with the given path pattern.
+    DumpSurfels(String),
+    /// Run the effects a last time, so exports reflect the current surfels,
+    /// then stop without performing the remaining iterations.
+    Abort,
+}
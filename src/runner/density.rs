@@ -0,0 +1,291 @@
+use geom::{TupleTriangle, Vertex};
+use rayon::prelude::*;
+use scene::{Entity, Mesh};
+use sim::SurfelData;
+use spec::AdaptiveResolution;
+use std::f32;
+use surf;
+use tex::{Rgba, RgbaImage, SubstanceFilter};
+
+type Surface = surf::Surface<surf::Surfel<Vertex, SurfelData>>;
+
+/// Rows of texels that one parallel task accumulates.
+const CHUNK_ROWS: usize = 16;
+
+/// Surfels closer to a texel than this are weighted like surfels at this
+/// distance when filtering smoothly.
+const MIN_DISTANCE: f32 = 1e-6;
+
+/// Bakes concentrations of surfels into textures, with the surfels of each
+/// texel listed by a surfel table.
+///
+/// Densities are first accumulated into a flat buffer with one density per
+/// texel, in parallel chunks of rows, and only turned into colors once all
+/// texels are known.
+pub struct DensityMap {
+    width: usize,
+    height: usize,
+    island_bleed: usize,
+    min: f32,
+    max: f32,
+    undefined_color: Rgba<u8>,
+    min_color: Rgba<u8>,
+    max_color: Rgba<u8>,
+    filter: SubstanceFilter,
+}
+
+impl DensityMap {
+    /// Makes a map that colors texels without surfels with the undefined
+    /// color, unless they are within the given amount of texels of texels
+    /// with surfels, and interpolates the other colors between the given
+    /// colors for concentrations from `min` to `max`.
+    pub fn new(
+        width: usize,
+        height: usize,
+        island_bleed: usize,
+        min: f32,
+        max: f32,
+        undefined_color: Rgba<u8>,
+        min_color: Rgba<u8>,
+        max_color: Rgba<u8>,
+        filter: SubstanceFilter,
+    ) -> Self {
+        DensityMap {
+            width,
+            height,
+            island_bleed,
+            min,
+            max,
+            undefined_color,
+            min_color,
+            max_color,
+            filter,
+        }
+    }
+
+    /// Bakes the given concentrations, one for each surfel that the given
+    /// surfel table indexes, into a texture.
+    pub fn bake(&self, concentrations: &[f32], table: &[Vec<(f32, usize)>]) -> RgbaImage {
+        let mut densities = self.densities(concentrations, table);
+        bleed(&mut densities, self.width, self.height, self.island_bleed);
+        self.colorize(&densities)
+    }
+
+    /// Density of each texel, NaN for texels without surfels.
+    fn densities(&self, concentrations: &[f32], table: &[Vec<(f32, usize)>]) -> Vec<f32> {
+        let chunk_len = self.width.max(1) * CHUNK_ROWS;
+        let mut densities = vec![f32::NAN; self.width * self.height];
+
+        densities
+            .par_chunks_mut(chunk_len)
+            .zip(table.par_chunks(chunk_len))
+            .for_each(|(densities, texels)| {
+                for (density, surfels) in densities.iter_mut().zip(texels) {
+                    *density = texel_density(surfels, concentrations, self.filter);
+                }
+            });
+
+        densities
+    }
+
+    fn colorize(&self, densities: &[f32]) -> RgbaImage {
+        let chunk_len = self.width.max(1) * CHUNK_ROWS;
+        let mut pixels = vec![0; densities.len() * 4];
+
+        pixels
+            .par_chunks_mut(chunk_len * 4)
+            .zip(densities.par_chunks(chunk_len))
+            .for_each(|(pixels, densities)| {
+                for (pixel, &density) in pixels.chunks_mut(4).zip(densities) {
+                    pixel.copy_from_slice(&self.color(density).data);
+                }
+            });
+
+        // Can unwrap since there are four channels for every texel
+        RgbaImage::from_raw(self.width as u32, self.height as u32, pixels).unwrap()
+    }
+
+    fn color(&self, density: f32) -> Rgba<u8> {
+        if density.is_nan() {
+            return self.undefined_color;
+        }
+
+        let range = self.max - self.min;
+        let t = if range > 0.0 {
+            ((density - self.min) / range).max(0.0).min(1.0)
+        } else if density < self.min {
+            0.0
+        } else {
+            1.0
+        };
+
+        let (from, to) = (self.min_color.data, self.max_color.data);
+        let mut color = [0; 4];
+        for channel in 0..4 {
+            let (from, to) = (from[channel] as f32, to[channel] as f32);
+            color[channel] = (from + (to - from) * t).round() as u8;
+        }
+        Rgba { data: color }
+    }
+}
+
+/// Concentrations of the substance with the given index, one for each surfel,
+/// so that baking does not need to look up surfel data for each texel.
+pub fn concentrations(surface: &Surface, substance_idx: usize) -> Vec<f32> {
+    surface
+        .samples
+        .iter()
+        .map(|s| s.data().substances[substance_idx])
+        .collect()
+}
+
+/// Size of the density textures of the given entity, scaled with its surface
+/// area if the effect has an adaptive resolution.
+pub fn density_size(
+    width: usize,
+    height: usize,
+    adaptive_resolution: Option<AdaptiveResolution>,
+    entity: &Entity,
+) -> (usize, usize) {
+    match adaptive_resolution {
+        Some(adaptive) => {
+            let (area, uv_area) = surface_areas(entity.mesh.triangles());
+            adaptive.size(width, height, area, uv_area)
+        }
+        None => (width, height),
+    }
+}
+
+/// Total area of the given triangles in world space and in texture space.
+pub fn surface_areas<I>(triangles: I) -> (f32, f32)
+where
+    I: IntoIterator<Item = TupleTriangle<Vertex>>,
+{
+    triangles
+        .into_iter()
+        .fold((0.0, 0.0), |(area, uv_area), TupleTriangle(a, b, c)| {
+            let (e1, e2) = (b.position - a.position, c.position - a.position);
+            let cross_x = e1.y * e2.z - e1.z * e2.y;
+            let cross_y = e1.z * e2.x - e1.x * e2.z;
+            let cross_z = e1.x * e2.y - e1.y * e2.x;
+            let (t1, t2) = (b.texcoords - a.texcoords, c.texcoords - a.texcoords);
+
+            (
+                area + 0.5 * (cross_x * cross_x + cross_y * cross_y + cross_z * cross_z).sqrt(),
+                uv_area + 0.5 * (t1.x * t2.y - t1.y * t2.x).abs(),
+            )
+        })
+}
+
+/// Mean concentration of the given surfels, weighted by their inverse
+/// distance to the texel when filtering smoothly, NaN if there are none.
+fn texel_density(surfels: &[(f32, usize)], concentrations: &[f32], filter: SubstanceFilter) -> f32 {
+    let (sum, weight_sum) =
+        surfels
+            .iter()
+            .fold((0.0, 0.0), |(sum, weight_sum), &(distance, surfel_idx)| {
+                let weight = match filter {
+                    SubstanceFilter::Flat => 1.0,
+                    SubstanceFilter::Smooth => 1.0 / distance.max(MIN_DISTANCE),
+                };
+                (
+                    sum + weight * concentrations[surfel_idx],
+                    weight_sum + weight,
+                )
+            });
+
+    if weight_sum > 0.0 {
+        sum / weight_sum
+    } else {
+        f32::NAN
+    }
+}
+
+/// Fills texels without surfels that border texels with surfels with the
+/// mean density of their defined neighbors, growing UV islands by one texel
+/// for each pass so that bilinear filtering at island borders does not pick
+/// up undefined texels.
+fn bleed(densities: &mut Vec<f32>, width: usize, height: usize, passes: usize) {
+    for _ in 0..passes {
+        let previous = densities.clone();
+        let mut grown = false;
+
+        for y in 0..height {
+            for x in 0..width {
+                if !previous[y * width + x].is_nan() {
+                    continue;
+                }
+
+                let (mut sum, mut count) = (0.0, 0);
+                for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                        let neighbor = previous[ny * width + nx];
+                        if !neighbor.is_nan() {
+                            sum += neighbor;
+                            count += 1;
+                        }
+                    }
+                }
+
+                if count > 0 {
+                    densities[y * width + x] = sum / count as f32;
+                    grown = true;
+                }
+            }
+        }
+
+        if !grown {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bake_flat_buffer() {
+        let concentrations = [0.0, 1.0, 0.5];
+        // Three texels in a row, the last one without surfels
+        let table = vec![vec![(1.0, 0), (3.0, 1)], vec![(1.0, 2)], vec![]];
+        let white = Rgba {
+            data: [255, 255, 255, 255],
+        };
+        let black = Rgba {
+            data: [0, 0, 0, 255],
+        };
+
+        let flat = DensityMap::new(
+            3,
+            1,
+            0,
+            0.0,
+            1.0,
+            white,
+            white,
+            black,
+            SubstanceFilter::Flat,
+        );
+        let densities = flat.densities(&concentrations, &table);
+        assert_eq!(&[0.5, 0.5], &densities[..2]);
+        assert!(densities[2].is_nan());
+
+        let smooth = DensityMap {
+            filter: SubstanceFilter::Smooth,
+            ..flat
+        };
+        assert_eq!(0.25, smooth.densities(&concentrations, &table)[0]);
+
+        let baked = DensityMap {
+            island_bleed: 1,
+            ..smooth
+        }
+        .bake(&concentrations, &table);
+        // 0.25 is a quarter of the way from white to black
+        assert_eq!([191, 191, 191, 255], baked.get_pixel(0, 0).data);
+        assert_eq!([128, 128, 128, 255], baked.get_pixel(1, 0).data);
+        // Bled from the middle texel only, the first one is not a neighbor
+        assert_eq!([128, 128, 128, 255], baked.get_pixel(2, 0).data);
+    }
+}
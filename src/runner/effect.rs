@@ -0,0 +1,79 @@
+use failure::{Error, ResultExt};
+use files::PatternValues;
+use geom::Vertex;
+use runner::{OutputSink, Surfels};
+use scene::Entity;
+use sim::SurfelData;
+use std::io::Write;
+use std::path::Path;
+use surf::{Surface, Surfel};
+
+/// Effect implemented outside of this crate, registered with
+/// `SimulationRunner::add_effect` and performed after the effects from the spec
+/// whenever they are scheduled.
+pub trait Effect {
+    /// Name for logs, profiling and error messages.
+    fn name(&self) -> &str;
+
+    /// Performs the effect on the copy of the scene that the effects from the
+    /// spec operated on in this iteration, e.g. to replace materials.
+    fn perform(&self, context: &EffectContext, entities: &mut Vec<Entity>) -> Result<(), Error>;
+}
+
+/// Simulation state and output helpers available to custom effects.
+pub struct EffectContext<'a> {
+    pub surface: &'a Surface<Surfel<Vertex, SurfelData>>,
+    pub substance_names: &'a [String],
+    pub iteration: u32,
+    datetime: &'a str,
+    sink: &'a OutputSink,
+}
+
+impl<'a> EffectContext<'a> {
+    pub fn new(
+        surface: &'a Surface<Surfel<Vertex, SurfelData>>,
+        substance_names: &'a [String],
+        iteration: u32,
+        datetime: &'a str,
+        sink: &'a OutputSink,
+    ) -> Self {
+        EffectContext {
+            surface,
+            substance_names,
+            iteration,
+            datetime,
+            sink,
+        }
+    }
+
+    /// Iterates the surfels with their concentrations by substance name.
+    pub fn surfels(&self) -> Surfels<'a> {
+        Surfels::new(&self.surface.samples, self.substance_names)
+    }
+
+    /// Replaces the placeholders in an output pattern like the built-in effects do.
+    /// `{entity}`, `{id}` and `{substance}` are only replaced if given.
+    pub fn output_path(
+        &self,
+        pattern: &str,
+        entity: Option<(usize, &str)>,
+        substance: Option<&str>,
+    ) -> String {
+        let mut values = PatternValues::new(self.datetime).iteration(self.iteration);
+        if let Some((id, name)) = entity {
+            values = values.entity(id, name);
+        }
+        if let Some(substance) = substance {
+            values = values.substance(substance);
+        }
+        values.substitute(pattern)
+    }
+
+    /// Creates an output in the output sink of the runner, by default a file.
+    pub fn create_output(&self, path: &str) -> Result<Box<Write>, Error> {
+        Ok(self
+            .sink
+            .create(Path::new(path))
+            .with_context(|_| format!("Could not create output file {}.", path))?)
+    }
+}
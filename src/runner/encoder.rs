@@ -0,0 +1,226 @@
+use failure::Error;
+use runner::ktx2;
+use runner::mipmap::mip_levels;
+use runner::sink::suffixed_path;
+use spec::{ColorSpace, Compression};
+use std::sync::mpsc::{channel, sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use tex::{self, RgbaImage};
+
+/// Threads encoding textures while the runner synthesizes the next ones.
+pub const ENCODING_THREADS: usize = 2;
+
+/// Textures that can wait for a free encoding thread before submitting more
+/// blocks, so that synthesis cannot run ahead and pile up decoded textures.
+pub const ENCODING_QUEUE: usize = 2;
+
+/// How a texture is encoded, as a KTX2 container if the target ends in
+/// `.ktx2` and as PNG otherwise.
+#[derive(Debug, Clone, Copy)]
+pub struct Encoding {
+    /// Whether the smaller mip levels are encoded too, into the KTX2
+    /// container or each into a PNG next to the texture, e.g.
+    /// `albedo-mip1.png`.
+    pub mipmaps: bool,
+    /// Encoding of the texture, for filtering mip levels and for the format
+    /// of KTX2 containers.
+    pub color_space: ColorSpace,
+    /// Block compression of KTX2 containers, PNGs are never block compressed.
+    pub compression: Compression,
+}
+
+struct Job {
+    target: String,
+    texture: RgbaImage,
+    encoding: Encoding,
+}
+
+/// A texture encoded as KTX2 or PNG along with its mip levels, if any, or the
+/// error encoding it.
+pub struct Encoded {
+    pub target: String,
+    /// Paths and encoded bytes, starting with the texture itself at the target.
+    pub files: Result<Vec<(String, Vec<u8>)>, Error>,
+}
+
+/// Encodes textures as PNG or KTX2 on a small pool of worker threads, so that
+/// encoding overlaps with synthesis.
+///
+/// Encoded textures are handed back to the thread that submitted them for
+/// writing, since output sinks are not shared across threads.
+pub struct Encoder {
+    jobs: Option<SyncSender<Job>>,
+    encoded: Receiver<Encoded>,
+    workers: Vec<JoinHandle<()>>,
+    /// Submitted textures that have not been handed back yet.
+    pending: usize,
+}
+
+impl Encoder {
+    pub fn new(threads: usize, queue: usize) -> Self {
+        let (jobs, queued) = sync_channel::<Job>(queue);
+        let (finished, encoded) = channel();
+        let queued = Arc::new(Mutex::new(queued));
+
+        let workers = (0..threads.max(1))
+            .map(|_| {
+                let queued = queued.clone();
+                let finished = finished.clone();
+                thread::spawn(move || loop {
+                    // Lock is released before encoding so the others can take jobs
+                    let job = match queued.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    };
+                    let files = encode(&job.target, job.texture, job.encoding);
+                    if finished
+                        .send(Encoded {
+                            target: job.target,
+                            files,
+                        })
+                        .is_err()
+                    {
+                        break;
+                    }
+                })
+            })
+            .collect();
+
+        Encoder {
+            jobs: Some(jobs),
+            encoded,
+            workers,
+            pending: 0,
+        }
+    }
+
+    /// Queues the given texture for encoding, blocking while the queue is
+    /// full.
+    pub fn submit(&mut self, target: &str, texture: RgbaImage, encoding: Encoding) {
+        let job = Job {
+            target: String::from(target),
+            texture,
+            encoding,
+        };
+        // Can unwrap since the workers only stop when the encoder is dropped
+        self.jobs.as_ref().unwrap().send(job).unwrap();
+        self.pending += 1;
+    }
+
+    /// Textures that have been encoded since the last call, without waiting
+    /// for the others.
+    pub fn finished(&mut self) -> Vec<Encoded> {
+        let finished: Vec<Encoded> = self.encoded.try_iter().collect();
+        self.pending -= finished.len();
+        finished
+    }
+
+    /// Waits for all submitted textures to be encoded and returns the ones
+    /// that have not been handed back yet.
+    pub fn wait(&mut self) -> Vec<Encoded> {
+        let finished: Vec<Encoded> = self.encoded.iter().take(self.pending).collect();
+        self.pending = 0;
+        finished
+    }
+}
+
+impl Drop for Encoder {
+    fn drop(&mut self) {
+        // Closing the queue lets the workers finish their jobs and return
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn encode(
+    target: &str,
+    texture: RgbaImage,
+    encoding: Encoding,
+) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    let levels = if encoding.mipmaps {
+        mip_levels(&texture, encoding.color_space)
+    } else {
+        Vec::new()
+    };
+
+    if ktx2::is_ktx2(target) {
+        let mut all_levels = vec![texture];
+        all_levels.extend(levels);
+        let ktx = ktx2::encode(&all_levels, encoding.color_space, encoding.compression);
+        return Ok(vec![(String::from(target), ktx)]);
+    }
+
+    let mut files = vec![(String::from(target), png(texture)?)];
+    for (idx, level) in levels.into_iter().enumerate() {
+        let path = suffixed_path(target, &format!("mip{}", idx + 1));
+        files.push((path, png(level)?));
+    }
+    Ok(files)
+}
+
+fn png(texture: RgbaImage) -> Result<Vec<u8>, Error> {
+    let mut png = Vec::new();
+    tex::ImageRgba8(texture).write_to(&mut png, tex::PNG)?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tex::{load_from_memory, GenericImage, Rgba};
+
+    const PLAIN: Encoding = Encoding {
+        mipmaps: false,
+        color_space: ColorSpace::Linear,
+        compression: Compression::None,
+    };
+
+    #[test]
+    fn encodes_all_submitted() {
+        let mut encoder = Encoder::new(2, 1);
+        for size in 1..6 {
+            let texture = RgbaImage::from_pixel(
+                size,
+                size,
+                Rgba {
+                    data: [size as u8; 4],
+                },
+            );
+            encoder.submit(&format!("{}.png", size), texture, PLAIN);
+        }
+
+        let mut encoded = encoder.finished();
+        encoded.extend(encoder.wait());
+        assert!(encoder.wait().is_empty());
+
+        let mut sizes: Vec<u32> = encoded
+            .into_iter()
+            .map(|e| {
+                let image = load_from_memory(&e.files.unwrap()[0].1).unwrap();
+                assert_eq!(format!("{}.png", image.width()), e.target);
+                image.width()
+            })
+            .collect();
+        sizes.sort();
+        assert_eq!(vec![1, 2, 3, 4, 5], sizes);
+    }
+
+    #[test]
+    fn encodes_mip_levels() {
+        let mipmaps = Encoding {
+            mipmaps: true,
+            ..PLAIN
+        };
+        let files = encode("out/albedo.png", RgbaImage::new(4, 2), mipmaps).unwrap();
+
+        let paths: Vec<&str> = files.iter().map(|f| f.0.as_str()).collect();
+        assert_eq!(
+            vec!["out/albedo.png", "out/albedo-mip1.png", "out/albedo-mip2.png"],
+            paths
+        );
+        assert_eq!(1, load_from_memory(&files[2].1).unwrap().width());
+    }
+}
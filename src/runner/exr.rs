@@ -0,0 +1,189 @@
+use runner::color_space::srgb_to_linear;
+use spec::ColorSpace;
+use tex::{DynamicImage, FilterType, RgbaImage};
+
+/// Pixel type of 32-bit float channels.
+const FLOAT: i32 = 2;
+
+/// Channels of the synthesized maps of one entity, bundled into a single
+/// uncompressed multi-layer OpenEXR image.
+///
+/// Each map becomes a layer named after it, e.g. `albedo.R`, so compositing
+/// tools can show them side by side from one file. Maps of other sizes than
+/// the first are resized to match.
+#[derive(Debug, Default)]
+pub struct MultiLayerExr {
+    size: Option<(u32, u32)>,
+    /// Channel names with their values in scanline order.
+    channels: Vec<(String, Vec<f32>)>,
+}
+
+impl MultiLayerExr {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a synthesized map of a layer effect as a layer named after its
+    /// channel, with RGBA for albedo and normal maps and only luminance for
+    /// the grayscale ones. Colors of sRGB maps are converted to linear values.
+    pub fn add_map(&mut self, channel: &str, map: &RgbaImage, color_space: ColorSpace) {
+        match channel {
+            "albedo" | "normal" => self.add_color(channel, map, color_space == ColorSpace::Srgb),
+            _ => self.add_scalar(channel, map),
+        }
+    }
+
+    /// Adds the RGBA channels of the given map as a layer. With `linearize`,
+    /// the color channels are converted from sRGB to linear values, which
+    /// EXR viewers expect for colors.
+    pub fn add_color(&mut self, layer: &str, map: &RgbaImage, linearize: bool) {
+        let map = self.fitted(map);
+        for (channel, name) in ["R", "G", "B", "A"].iter().enumerate() {
+            let values = map
+                .pixels()
+                .map(|p| {
+                    let value = p.data[channel] as f32 / 255.0;
+                    if linearize && channel < 3 {
+                        srgb_to_linear(value)
+                    } else {
+                        value
+                    }
+                })
+                .collect();
+            self.channels.push((format!("{}.{}", layer, name), values));
+        }
+    }
+
+    /// Adds the red channel of the given grayscale map as the single
+    /// luminance channel of a layer.
+    pub fn add_scalar(&mut self, layer: &str, map: &RgbaImage) {
+        let map = self.fitted(map);
+        let values = map.pixels().map(|p| p.data[0] as f32 / 255.0).collect();
+        self.channels.push((format!("{}.Y", layer), values));
+    }
+
+    pub fn has_layer(&self, layer: &str) -> bool {
+        let prefix = format!("{}.", layer);
+        self.channels.iter().any(|&(ref name, _)| name.starts_with(&prefix))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+    /// Encodes the layers as an OpenEXR file with one uncompressed scanline
+    /// per block.
+    pub fn encode(&self) -> Vec<u8> {
+        let (width, height) = self.size.unwrap_or((0, 0));
+        // Readers expect channels in alphabetical order
+        let mut channels: Vec<&(String, Vec<f32>)> = self.channels.iter().collect();
+        channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut exr = vec![0x76, 0x2f, 0x31, 0x01];
+        // Version 2, single part scanline image
+        push_i32(&mut exr, 2);
+
+        let mut chlist = Vec::new();
+        for &&(ref name, _) in channels.iter() {
+            chlist.extend_from_slice(name.as_bytes());
+            chlist.push(0);
+            push_i32(&mut chlist, FLOAT);
+            // Not perceptually linear and three reserved bytes
+            chlist.extend_from_slice(&[0, 0, 0, 0]);
+            // Sampled at every pixel in x and y
+            push_i32(&mut chlist, 1);
+            push_i32(&mut chlist, 1);
+        }
+        chlist.push(0);
+
+        let mut window = Vec::new();
+        for &coord in [0, 0, width as i32 - 1, height as i32 - 1].iter() {
+            push_i32(&mut window, coord);
+        }
+
+        attribute(&mut exr, "channels", "chlist", &chlist);
+        attribute(&mut exr, "compression", "compression", &[0]);
+        attribute(&mut exr, "dataWindow", "box2i", &window);
+        attribute(&mut exr, "displayWindow", "box2i", &window);
+        attribute(&mut exr, "lineOrder", "lineOrder", &[0]);
+        attribute(&mut exr, "pixelAspectRatio", "float", &1.0f32.to_bits().to_le_bytes());
+        attribute(&mut exr, "screenWindowCenter", "v2f", &[0; 8]);
+        attribute(&mut exr, "screenWindowWidth", "float", &1.0f32.to_bits().to_le_bytes());
+        exr.push(0);
+
+        let line_bytes = width as usize * channels.len() * 4;
+        let table_end = exr.len() + height as usize * 8;
+        for y in 0..height as usize {
+            let offset = table_end + y * (8 + line_bytes);
+            exr.extend_from_slice(&(offset as u64).to_le_bytes());
+        }
+
+        for y in 0..height as usize {
+            push_i32(&mut exr, y as i32);
+            push_i32(&mut exr, line_bytes as i32);
+            for &&(_, ref values) in channels.iter() {
+                let line = &values[y * width as usize..(y + 1) * width as usize];
+                for value in line {
+                    exr.extend_from_slice(&value.to_bits().to_le_bytes());
+                }
+            }
+        }
+
+        exr
+    }
+
+    /// The given map, resized to the size of the first map if necessary.
+    fn fitted(&mut self, map: &RgbaImage) -> RgbaImage {
+        let (width, height) = *self.size.get_or_insert(map.dimensions());
+        if map.dimensions() == (width, height) {
+            map.clone()
+        } else {
+            DynamicImage::ImageRgba8(map.clone())
+                .resize_exact(width, height, FilterType::Triangle)
+                .to_rgba()
+        }
+    }
+}
+
+fn attribute(exr: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    exr.extend_from_slice(name.as_bytes());
+    exr.push(0);
+    exr.extend_from_slice(kind.as_bytes());
+    exr.push(0);
+    push_i32(exr, value.len() as i32);
+    exr.extend_from_slice(value);
+}
+
+fn push_i32(bytes: &mut Vec<u8>, value: i32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tex::Rgba;
+
+    #[test]
+    fn encode_sorted_layers() {
+        let white = RgbaImage::from_pixel(2, 2, Rgba { data: [255; 4] });
+        let mut exr = MultiLayerExr::new();
+        exr.add_scalar("roughness", &white);
+        exr.add_color("albedo", &RgbaImage::from_pixel(4, 4, Rgba { data: [0; 4] }), true);
+        let encoded = exr.encode();
+
+        assert_eq!(&[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0], &encoded[..8]);
+        let header = String::from_utf8_lossy(&encoded);
+        let albedo = header.find("albedo.A").unwrap();
+        assert!(albedo < header.find("albedo.R").unwrap());
+        assert!(header.find("albedo.R").unwrap() < header.find("roughness.Y").unwrap());
+
+        // Five channels of two float scanlines, with the scanline headers
+        // and offsets
+        let pixels = 5 * 2 * 2 * 4;
+        let lines = 2 * (8 + 8);
+        let header_end = encoded.len() - pixels - lines;
+        assert_eq!(0, encoded[header_end - 1]);
+        // The last value is roughness, which was white
+        assert_eq!(&1.0f32.to_bits().to_le_bytes(), &encoded[encoded.len() - 4..]);
+    }
+}
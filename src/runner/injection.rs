@@ -0,0 +1,68 @@
+use geom::Vertex;
+use scene::Entity;
+use sim::SurfelData;
+use spec::InjectionSpec;
+use surf;
+
+type Surface = surf::Surface<surf::Surfel<Vertex, SurfelData>>;
+
+/// An injection from a schedule in the spec with its substance looked up.
+pub struct Injection {
+    pub spec: InjectionSpec,
+    pub substance_idx: usize,
+}
+
+/// Substances added to surfels before tracing in scheduled iterations.
+pub struct Injections {
+    injections: Vec<Injection>,
+}
+
+impl Injections {
+    pub fn new(injections: Vec<Injection>) -> Self {
+        Injections { injections }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.injections.is_empty()
+    }
+
+    /// Whether any substance is injected before tracing the given iteration.
+    pub fn is_scheduled(&self, iteration: u32) -> bool {
+        self.injections
+            .iter()
+            .any(|i| i.spec.iteration == iteration)
+    }
+
+    /// Adds the substances injected in the given iteration to the surfels of
+    /// entities with an affected material, keeping concentrations from
+    /// dropping below zero.
+    pub fn apply(&self, iteration: u32, entities: &[Entity], surface: &mut Surface) {
+        for injection in self
+            .injections
+            .iter()
+            .filter(|i| i.spec.iteration == iteration)
+        {
+            info!(
+                "Injecting {} {} into the surfels of {}.",
+                injection.spec.amount,
+                injection.spec.substance,
+                match injection.spec.material {
+                    Some(ref material) if material != "_" => material.as_str(),
+                    _ => "all materials",
+                }
+            );
+
+            let affected: Vec<bool> = entities
+                .iter()
+                .map(|e| injection.spec.affects_material(e.material.name()))
+                .collect();
+            for surfel in surface.samples.iter_mut() {
+                let data = surfel.data_mut();
+                if affected[data.entity_idx] {
+                    let concentration = &mut data.substances[injection.substance_idx];
+                    *concentration = (*concentration + injection.spec.amount).max(0.0);
+                }
+            }
+        }
+    }
+}
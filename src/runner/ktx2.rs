@@ -0,0 +1,484 @@
+use failure::Error;
+use spec::{ColorSpace, Compression};
+use std::fs::read;
+use std::path::Path;
+use tex::{self, DynamicImage, Rgba, RgbaImage};
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+
+/// Bytes of the header and the section index before the level index.
+const HEADER_BYTES: usize = 80;
+
+/// Bytes of a level index entry, offset, length and uncompressed length.
+const LEVEL_INDEX_BYTES: usize = 24;
+
+/// Data format descriptor color models.
+const MODEL_RGBSDA: u32 = 1;
+const MODEL_BC1A: u32 = 128;
+const MODEL_BC3: u32 = 130;
+
+/// Marks the alpha sample of sRGB formats as linear.
+const SAMPLE_LINEAR: u32 = 0x10;
+
+/// Vulkan formats that textures are written in, with the block compression
+/// and color space they stand for.
+const FORMATS: &'static [(u32, Compression, ColorSpace)] = &[
+    (37, Compression::None, ColorSpace::Linear),
+    (43, Compression::None, ColorSpace::Srgb),
+    (131, Compression::Bc1, ColorSpace::Linear),
+    (132, Compression::Bc1, ColorSpace::Srgb),
+    (137, Compression::Bc3, ColorSpace::Linear),
+    (138, Compression::Bc3, ColorSpace::Srgb),
+];
+
+/// Whether textures written to the given path are KTX2 containers.
+pub fn is_ktx2(path: &str) -> bool {
+    path.to_lowercase().ends_with(".ktx2")
+}
+
+/// Decodes the texture at the given path, with the largest level of KTX2
+/// containers written by `encode` and any other format through the image
+/// crate, so that later effects can read synthesized textures either way.
+pub fn open_texture<P: AsRef<Path>>(path: P) -> Result<DynamicImage, Error> {
+    let path = path.as_ref();
+    if is_ktx2(&path.to_string_lossy()) {
+        Ok(DynamicImage::ImageRgba8(decode(&read(path)?)?))
+    } else {
+        Ok(tex::open(path)?)
+    }
+}
+
+/// Encodes the given mip levels, largest first, as a KTX2 container for
+/// graphics APIs to upload without further processing, optionally with
+/// block compression.
+pub fn encode(levels: &[RgbaImage], color_space: ColorSpace, compression: Compression) -> Vec<u8> {
+    let vk_format = FORMATS
+        .iter()
+        .find(|&&(_, c, s)| c == compression && s == color_space)
+        .map(|&(format, _, _)| format)
+        .unwrap();
+    let (width, height) = levels[0].dimensions();
+    let level_data: Vec<Vec<u8>> = levels
+        .iter()
+        .map(|level| match compression {
+            Compression::None => level.clone().into_raw(),
+            Compression::Bc1 => compress_blocks(level, 8, push_color_block),
+            Compression::Bc3 => compress_blocks(level, 16, bc3_block),
+        })
+        .collect();
+    let dfd = data_format_descriptor(color_space, compression);
+
+    let mut ktx = IDENTIFIER.to_vec();
+    push_u32(&mut ktx, vk_format);
+    push_u32(&mut ktx, 1); // type size
+    push_u32(&mut ktx, width);
+    push_u32(&mut ktx, height);
+    push_u32(&mut ktx, 0); // depth
+    push_u32(&mut ktx, 0); // layers
+    push_u32(&mut ktx, 1); // faces
+    push_u32(&mut ktx, levels.len() as u32);
+    push_u32(&mut ktx, 0); // no supercompression
+
+    let dfd_offset = HEADER_BYTES + LEVEL_INDEX_BYTES * levels.len();
+    push_u32(&mut ktx, dfd_offset as u32);
+    push_u32(&mut ktx, dfd.len() as u32);
+    // No key/value data and no supercompression global data
+    push_u32(&mut ktx, 0);
+    push_u32(&mut ktx, 0);
+    push_u64(&mut ktx, 0);
+    push_u64(&mut ktx, 0);
+
+    // Smallest levels come first in the file, each aligned to whole blocks
+    let alignment = match compression {
+        Compression::None => 4,
+        Compression::Bc1 => 8,
+        Compression::Bc3 => 16,
+    };
+    let mut offsets = vec![0; levels.len()];
+    let mut offset = dfd_offset + dfd.len();
+    for (idx, data) in level_data.iter().enumerate().rev() {
+        offset += (alignment - offset % alignment) % alignment;
+        offsets[idx] = offset;
+        offset += data.len();
+    }
+    for (data, &offset) in level_data.iter().zip(offsets.iter()) {
+        push_u64(&mut ktx, offset as u64);
+        push_u64(&mut ktx, data.len() as u64);
+        push_u64(&mut ktx, data.len() as u64);
+    }
+
+    ktx.extend_from_slice(&dfd);
+    for (data, &offset) in level_data.iter().zip(offsets.iter()).rev() {
+        ktx.resize(offset, 0);
+        ktx.extend_from_slice(data);
+    }
+
+    ktx
+}
+
+/// Decodes the largest level of a KTX2 container in one of the formats that
+/// `encode` writes.
+pub fn decode(ktx: &[u8]) -> Result<RgbaImage, Error> {
+    if ktx.len() < HEADER_BYTES + LEVEL_INDEX_BYTES || ktx[0..12] != IDENTIFIER {
+        bail!("it is not a KTX2 container");
+    }
+
+    let vk_format = read_u32(ktx, 12);
+    let compression = match FORMATS.iter().find(|f| f.0 == vk_format) {
+        Some(&(_, compression, _)) => compression,
+        None => bail!("format {} is not supported", vk_format),
+    };
+    if read_u32(ktx, 44) != 0 {
+        bail!("supercompressed containers are not supported");
+    }
+    let (width, height) = (read_u32(ktx, 20), read_u32(ktx, 24));
+    let offset = read_u64(ktx, HEADER_BYTES) as usize;
+    let length = read_u64(ktx, HEADER_BYTES + 8) as usize;
+    let block_bytes = match compression {
+        Compression::None => 4,
+        Compression::Bc1 => 8,
+        Compression::Bc3 => 16,
+    };
+    let expected = match compression {
+        Compression::None => width as usize * height as usize * 4,
+        _ => (width as usize + 3) / 4 * ((height as usize + 3) / 4) * block_bytes,
+    };
+    if length != expected || offset + length > ktx.len() {
+        bail!("the largest level is truncated");
+    }
+    let data = &ktx[offset..offset + length];
+
+    let texture = match compression {
+        Compression::None => RgbaImage::from_raw(width, height, data.to_vec()).unwrap(),
+        _ => {
+            let blocks_per_row = (width as usize + 3) / 4;
+            RgbaImage::from_fn(width, height, |x, y| {
+                let (x, y) = (x as usize, y as usize);
+                let block = (y / 4 * blocks_per_row + x / 4) * block_bytes;
+                let texel = y % 4 * 4 + x % 4;
+                match compression {
+                    Compression::Bc3 => {
+                        let mut color = decode_color(&data[block + 8..block + 16], texel, false);
+                        color.data[3] = decode_alpha(&data[block..block + 8], texel);
+                        color
+                    }
+                    _ => decode_color(&data[block..block + 8], texel, true),
+                }
+            })
+        }
+    };
+
+    Ok(texture)
+}
+
+fn data_format_descriptor(color_space: ColorSpace, compression: Compression) -> Vec<u8> {
+    let transfer = match color_space {
+        ColorSpace::Srgb => 2,
+        ColorSpace::Linear => 1,
+    };
+    let alpha_qualifier = match color_space {
+        ColorSpace::Srgb => SAMPLE_LINEAR,
+        ColorSpace::Linear => 0,
+    };
+    // Channel, bit offset, bit length and upper value of each sample
+    let (model, block_dimensions, plane_bytes, samples) = match compression {
+        Compression::None => (
+            MODEL_RGBSDA,
+            0,
+            4,
+            vec![
+                (0, 0, 8, 255),
+                (1, 8, 8, 255),
+                (2, 16, 8, 255),
+                (15 | alpha_qualifier, 24, 8, 255),
+            ],
+        ),
+        Compression::Bc1 => (MODEL_BC1A, 0x0303, 8, vec![(0, 0, 64, u32::max_value())]),
+        Compression::Bc3 => (
+            MODEL_BC3,
+            0x0303,
+            16,
+            vec![
+                (15 | alpha_qualifier, 0, 64, u32::max_value()),
+                (0, 64, 64, u32::max_value()),
+            ],
+        ),
+    };
+
+    let block_bytes = 24 + 16 * samples.len() as u32;
+    let mut dfd = Vec::new();
+    push_u32(&mut dfd, 4 + block_bytes);
+    push_u32(&mut dfd, 0); // Khronos vendor, basic descriptor
+    push_u32(&mut dfd, 2 | block_bytes << 16); // version 1.3
+    push_u32(&mut dfd, model | 1 << 8 | transfer << 16); // BT.709 primaries, straight alpha
+    push_u32(&mut dfd, block_dimensions);
+    push_u32(&mut dfd, plane_bytes);
+    push_u32(&mut dfd, 0);
+    for (channel, bit_offset, bit_length, upper) in samples {
+        push_u32(
+            &mut dfd,
+            bit_offset | (bit_length - 1) << 16 | channel << 24,
+        );
+        push_u32(&mut dfd, 0); // sample position
+        push_u32(&mut dfd, 0); // lower
+        push_u32(&mut dfd, upper);
+    }
+    dfd
+}
+
+/// Compresses each 4x4 block of the texture with the given function, with
+/// texels beyond the edges repeating the last row or column.
+fn compress_blocks<F>(texture: &RgbaImage, block_bytes: usize, compress: F) -> Vec<u8>
+where
+    F: Fn(&[Rgba<u8>; 16], &mut Vec<u8>),
+{
+    let (width, height) = texture.dimensions();
+    let mut data =
+        Vec::with_capacity(((width as usize + 3) / 4) * ((height as usize + 3) / 4) * block_bytes);
+    for block_y in 0..(height + 3) / 4 {
+        for block_x in 0..(width + 3) / 4 {
+            let mut texels = [Rgba { data: [0; 4] }; 16];
+            for (idx, texel) in texels.iter_mut().enumerate() {
+                let x = (block_x * 4 + idx as u32 % 4).min(width - 1);
+                let y = (block_y * 4 + idx as u32 / 4).min(height - 1);
+                *texel = *texture.get_pixel(x, y);
+            }
+            compress(&texels, &mut data);
+        }
+    }
+    data
+}
+
+fn bc3_block(texels: &[Rgba<u8>; 16], data: &mut Vec<u8>) {
+    let max = texels.iter().map(|t| t.data[3]).max().unwrap();
+    let min = texels.iter().map(|t| t.data[3]).min().unwrap();
+    data.push(max);
+    data.push(min);
+
+    let palette = alpha_palette(max, min);
+    let mut indices = 0u64;
+    if max != min {
+        for (idx, texel) in texels.iter().enumerate() {
+            indices |= (nearest(&palette, |a| (*a as i32 - texel.data[3] as i32).pow(2)) as u64)
+                << (3 * idx);
+        }
+    }
+    data.extend_from_slice(&indices.to_le_bytes()[0..6]);
+
+    push_color_block(texels, data);
+}
+
+/// Endpoints along the principal axis of the colors of the block and the
+/// nearest of the four interpolated colors for each texel.
+fn push_color_block(texels: &[Rgba<u8>; 16], data: &mut Vec<u8>) {
+    let colors: Vec<[f32; 3]> = texels
+        .iter()
+        .map(|t| [t.data[0] as f32, t.data[1] as f32, t.data[2] as f32])
+        .collect();
+    let mut mean = [0.0; 3];
+    for color in colors.iter() {
+        for c in 0..3 {
+            mean[c] += color[c] / 16.0;
+        }
+    }
+    let mut covariance = [[0.0f32; 3]; 3];
+    for color in colors.iter() {
+        for i in 0..3 {
+            for j in 0..3 {
+                covariance[i][j] += (color[i] - mean[i]) * (color[j] - mean[j]);
+            }
+        }
+    }
+    // Power iteration converges on the principal axis
+    let mut axis = [1.0f32, 1.0, 1.0];
+    for _ in 0..8 {
+        let row = |i: usize| {
+            covariance[i][0] * axis[0] + covariance[i][1] * axis[1] + covariance[i][2] * axis[2]
+        };
+        let next = [row(0), row(1), row(2)];
+        let length = (next[0] * next[0] + next[1] * next[1] + next[2] * next[2]).sqrt();
+        if length < 1e-6 {
+            break;
+        }
+        axis = [next[0] / length, next[1] / length, next[2] / length];
+    }
+
+    let project = |color: &[f32; 3]| {
+        (color[0] - mean[0]) * axis[0]
+            + (color[1] - mean[1]) * axis[1]
+            + (color[2] - mean[2]) * axis[2]
+    };
+    let brightest = colors
+        .iter()
+        .max_by(|a, b| project(a).partial_cmp(&project(b)).unwrap())
+        .unwrap();
+    let darkest = colors
+        .iter()
+        .min_by(|a, b| project(a).partial_cmp(&project(b)).unwrap())
+        .unwrap();
+    let (mut color0, mut color1) = (to_565(brightest), to_565(darkest));
+    if color0 < color1 {
+        ::std::mem::swap(&mut color0, &mut color1);
+    }
+
+    data.extend_from_slice(&color0.to_le_bytes());
+    data.extend_from_slice(&color1.to_le_bytes());
+    let mut indices = 0u32;
+    if color0 != color1 {
+        let palette = color_palette(color0, color1, false);
+        for (idx, color) in colors.iter().enumerate() {
+            let index = nearest(&palette, |p| {
+                (0..3)
+                    .map(|c| (p.data[c] as f32 - color[c]).powi(2))
+                    .sum::<f32>()
+            });
+            indices |= (index as u32) << (2 * idx);
+        }
+    }
+    data.extend_from_slice(&indices.to_le_bytes());
+}
+
+fn decode_color(block: &[u8], texel: usize, bc1: bool) -> Rgba<u8> {
+    let color0 = u16::from(block[0]) | u16::from(block[1]) << 8;
+    let color1 = u16::from(block[2]) | u16::from(block[3]) << 8;
+    let indices = read_u32(block, 4);
+    let palette = color_palette(color0, color1, bc1);
+    palette[(indices >> (2 * texel) & 3) as usize]
+}
+
+fn decode_alpha(block: &[u8], texel: usize) -> u8 {
+    let mut indices = [0u8; 8];
+    indices[0..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(indices);
+    alpha_palette(block[0], block[1])[(indices >> (3 * texel) & 7) as usize]
+}
+
+/// The endpoints and the colors in between, with three colors and black if
+/// the first endpoint is not greater in BC1.
+fn color_palette(color0: u16, color1: u16, bc1: bool) -> [Rgba<u8>; 4] {
+    let (c0, c1) = (from_565(color0), from_565(color1));
+    let mix = |a: u32, b: u32, parts: u32| {
+        let mut data = [0, 0, 0, 255];
+        for c in 0..3 {
+            data[c] = ((a * c0.data[c] as u32 + b * c1.data[c] as u32) / parts) as u8;
+        }
+        Rgba { data }
+    };
+    if color0 > color1 || !bc1 {
+        [c0, c1, mix(2, 1, 3), mix(1, 2, 3)]
+    } else {
+        [
+            c0,
+            c1,
+            mix(1, 1, 2),
+            Rgba {
+                data: [0, 0, 0, 255],
+            },
+        ]
+    }
+}
+
+/// The endpoints and six alphas in between, or four and the extremes if the
+/// first endpoint is not greater.
+fn alpha_palette(alpha0: u8, alpha1: u8) -> [u8; 8] {
+    let (a0, a1) = (alpha0 as u32, alpha1 as u32);
+    let mut palette = [alpha0, alpha1, 0, 0, 0, 0, 0, 255];
+    if alpha0 > alpha1 {
+        for i in 1..7 {
+            palette[i + 1] = (((7 - i as u32) * a0 + i as u32 * a1) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (((5 - i as u32) * a0 + i as u32 * a1) / 5) as u8;
+        }
+    }
+    palette
+}
+
+fn nearest<T, F: Fn(&T) -> D, D: PartialOrd>(palette: &[T], distance: F) -> usize {
+    (0..palette.len())
+        .min_by(|&a, &b| {
+            distance(&palette[a])
+                .partial_cmp(&distance(&palette[b]))
+                .unwrap()
+        })
+        .unwrap()
+}
+
+fn to_565(color: &[f32; 3]) -> u16 {
+    let quantize = |value: f32, max: f32| (value / 255.0 * max).round() as u16;
+    quantize(color[0], 31.0) << 11 | quantize(color[1], 63.0) << 5 | quantize(color[2], 31.0)
+}
+
+fn from_565(color: u16) -> Rgba<u8> {
+    let (r, g, b) = (color >> 11 & 31, color >> 5 & 63, color & 31);
+    Rgba {
+        data: [
+            (r << 3 | r >> 2) as u8,
+            (g << 2 | g >> 4) as u8,
+            (b << 3 | b >> 2) as u8,
+            255,
+        ],
+    }
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u64(bytes: &mut Vec<u8>, value: u64) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut value = [0; 4];
+    value.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(value)
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut value = [0; 8];
+    value.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let gradient = RgbaImage::from_fn(6, 5, |x, y| Rgba {
+            data: [x as u8 * 40, 100, x as u8 * 20, 255 - y as u8 * 30],
+        });
+        let levels = [gradient.clone(), RgbaImage::new(3, 2), RgbaImage::new(1, 1)];
+
+        let plain = encode(&levels, ColorSpace::Srgb, Compression::None);
+        assert_eq!(IDENTIFIER, plain[0..12]);
+        assert_eq!(43, read_u32(&plain, 12));
+        assert_eq!(3, read_u32(&plain, 40));
+        assert_eq!(gradient.clone().into_raw(), decode(&plain).unwrap().into_raw());
+
+        for &compression in [Compression::Bc1, Compression::Bc3].iter() {
+            let compressed = encode(&levels, ColorSpace::Linear, compression);
+            let decoded = decode(&compressed).unwrap();
+            assert_eq!(gradient.dimensions(), decoded.dimensions());
+            for (original, decoded) in gradient.pixels().zip(decoded.pixels()) {
+                let alpha = if compression == Compression::Bc1 {
+                    255
+                } else {
+                    original.data[3]
+                };
+                for c in 0..3 {
+                    let error = (original.data[c] as i32 - decoded.data[c] as i32).abs();
+                    assert!(error <= 24, "{:?} decoded as {:?}", original, decoded);
+                }
+                assert!((alpha as i32 - decoded.data[3] as i32).abs() <= 10);
+            }
+        }
+
+        assert!(decode(b"not a container").is_err());
+    }
+}
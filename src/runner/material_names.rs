@@ -0,0 +1,107 @@
+use scene::{Entity, Material, MaterialBuilder};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// Copies of the given entities for export where each distinct material has a
+/// name of its own, since MTL files with several materials of the same name
+/// break some importers. Equal materials with the same name are merged into
+/// one, conflicting ones get a numbered suffix in order of first use, e.g.
+/// `rust-2`.
+pub fn uniquely_named(entities: &[&Entity]) -> Vec<Entity> {
+    let names = unique_names(
+        entities
+            .iter()
+            .map(|e| (e.material.name(), &e.material))
+            .collect::<Vec<_>>()
+            .as_slice(),
+        |a, b| Rc::ptr_eq(a, b) || a == b,
+    );
+
+    // Each distinct material has a name of its own, so all entities with an
+    // equal material can share the first one, or its renamed copy
+    let mut distinct: HashMap<String, Rc<Material>> = HashMap::new();
+    entities
+        .iter()
+        .zip(names.into_iter())
+        .map(|(entity, name)| {
+            let material = distinct
+                .entry(name.clone())
+                .or_insert_with(|| {
+                    if name == entity.material.name() {
+                        entity.material.clone()
+                    } else {
+                        Rc::new(
+                            MaterialBuilder::from(&*entity.material)
+                                .name(name.as_str())
+                                .build(),
+                        )
+                    }
+                })
+                .clone();
+            Entity {
+                material,
+                ..(*entity).clone()
+            }
+        })
+        .collect()
+}
+
+/// Name in the export for each of the given materials with their names, in
+/// the same order. The first material with a name keeps it, as do later ones
+/// that are equal to it.
+fn unique_names<M, F>(materials: &[(&str, M)], equal: F) -> Vec<String>
+where
+    F: Fn(&M, &M) -> bool,
+{
+    // Original name, material and name in the export of each distinct material
+    let mut distinct: Vec<(&str, &M, String)> = Vec::new();
+    let mut taken = HashSet::new();
+
+    materials
+        .iter()
+        .map(|&(name, ref material)| {
+            let merged = distinct
+                .iter()
+                .find(|&&(original, other, _)| original == name && equal(other, material))
+                .map(|&(_, _, ref exported)| exported.clone());
+            if let Some(exported) = merged {
+                return exported;
+            }
+
+            let exported = if taken.contains(name) {
+                (2..)
+                    .map(|suffix| format!("{}-{}", name, suffix))
+                    .find(|candidate| !taken.contains(candidate.as_str()))
+                    .unwrap()
+            } else {
+                name.to_string()
+            };
+            taken.insert(exported.clone());
+            distinct.push((name, material, exported.clone()));
+            exported
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn merges_equal_and_renames_conflicting() {
+        let materials = [
+            ("rust", 1),
+            ("paint", 2),
+            ("rust", 1),
+            ("rust", 3),
+            ("rust-2", 4),
+            ("rust", 3),
+            ("rust", 5),
+        ];
+
+        assert_eq!(
+            vec!["rust", "paint", "rust", "rust-2", "rust-2-2", "rust-2", "rust-3"],
+            unique_names(&materials, |a, b| a == b)
+        );
+    }
+}
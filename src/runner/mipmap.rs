@@ -0,0 +1,74 @@
+use runner::color_space::LinearLight;
+use spec::ColorSpace;
+use tex::{Rgba, RgbaImage};
+
+/// Halves the given texture until it is 1x1, returning the smaller levels
+/// from level 1 on. Each texel averages the 2x2 texels above it, in linear
+/// light for sRGB textures. Odd sides round down and repeat the last texel.
+pub fn mip_levels(texture: &RgbaImage, color_space: ColorSpace) -> Vec<RgbaImage> {
+    let linear_light = match color_space {
+        ColorSpace::Srgb => Some(LinearLight::new()),
+        ColorSpace::Linear => None,
+    };
+
+    let mut levels: Vec<RgbaImage> = Vec::new();
+    loop {
+        let next = {
+            let previous = levels.last().unwrap_or(texture);
+            let (width, height) = previous.dimensions();
+            if width == 1 && height == 1 {
+                break;
+            }
+
+            RgbaImage::from_fn((width / 2).max(1), (height / 2).max(1), |x, y| {
+                let texel =
+                    |x: u32, y: u32| *previous.get_pixel(x.min(width - 1), y.min(height - 1));
+                let texels = [
+                    texel(2 * x, 2 * y),
+                    texel(2 * x + 1, 2 * y),
+                    texel(2 * x, 2 * y + 1),
+                    texel(2 * x + 1, 2 * y + 1),
+                ];
+                match linear_light {
+                    Some(ref linear_light) => linear_light.average(&texels),
+                    None => average(&texels),
+                }
+            })
+        };
+        levels.push(next);
+    }
+
+    levels
+}
+
+fn average(texels: &[Rgba<u8>]) -> Rgba<u8> {
+    let mut data = [0; 4];
+    for channel in 0..4 {
+        let sum: u32 = texels.iter().map(|t| t.data[channel] as u32).sum();
+        data[channel] = ((sum as f32) / texels.len() as f32).round() as u8;
+    }
+    Rgba { data }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn halves_down_to_one_texel() {
+        let checker = RgbaImage::from_fn(5, 2, |x, _| Rgba {
+            data: if x % 2 == 0 { [0, 0, 0, 255] } else { [255; 4] },
+        });
+
+        let linear = mip_levels(&checker, ColorSpace::Linear);
+        let sizes: Vec<(u32, u32)> = linear.iter().map(|l| l.dimensions()).collect();
+        assert_eq!(vec![(2, 1), (1, 1)], sizes);
+        assert_eq!([128, 128, 128, 255], linear[0].get_pixel(0, 0).data);
+
+        // Half black and half white is brighter than 128 in sRGB
+        let srgb = mip_levels(&checker, ColorSpace::Srgb);
+        assert_eq!([188, 188, 188, 255], srgb[0].get_pixel(0, 0).data);
+
+        assert!(mip_levels(&RgbaImage::new(1, 1), ColorSpace::Linear).is_empty());
+    }
+}
@@ -1,4 +1,48 @@
+mod audit;
+mod color_space;
+mod control;
+mod density;
+mod effect;
+mod encoder;
+mod exr;
+mod injection;
+mod ktx2;
+mod material_names;
+mod mipmap;
+mod notify;
+mod observer;
+mod report;
+mod retry;
+mod rules;
 mod runner;
+mod schedule;
+mod sink;
+mod snapshot;
+mod streaks;
 mod surfel_table_cache;
+mod surfels;
+mod swap;
+mod texture_cache;
+mod uv_overlap;
+mod wear;
 
+pub use self::audit::{explain_rule, Budget};
+pub use self::control::Command;
+pub use self::density::{density_size, surface_areas};
+pub use self::effect::{Effect, EffectContext};
+pub use self::injection::{Injection, Injections};
+#[cfg(feature = "http")]
+pub use self::notify::HttpNotifier;
+pub use self::notify::ProgressEvent;
+pub use self::observer::Observer;
+pub use self::report::IterationReport;
+pub use self::rules::{EntityRules, StochasticRules};
 pub use self::runner::SimulationRunner;
+pub use self::schedule::{sim_config, SourceGroup, SourceSchedule};
+pub use self::sink::{FileSystemSink, OutputSink};
+pub use self::snapshot::SurfelSnapshot;
+pub use self::streaks::streaked;
+pub use self::surfels::{SurfelView, Surfels};
+pub use self::swap::{MaterialSwap, MaterialSwaps};
+pub use self::uv_overlap::{overlapping_islands, separated_islands, uv_islands};
+pub use self::wear::wear_mask;
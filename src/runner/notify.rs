@@ -0,0 +1,141 @@
+use failure::Error;
+#[cfg(feature = "http")]
+use reqwest::{Client, Url};
+#[cfg(feature = "http")]
+use runner::Observer;
+#[cfg(feature = "http")]
+use std::time::Duration;
+
+/// Time to wait for a response before giving up on a notification.
+#[cfg(feature = "http")]
+const TIMEOUT_SECS: u64 = 10;
+
+/// Progress of a run, sent as JSON with the type of event in `event`, e.g.
+/// `{"event":"iteration_finished","name":"rust","iteration":3,"last_iteration":30}`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event")]
+pub enum ProgressEvent<'a> {
+    #[serde(rename = "run_started")]
+    RunStarted { name: &'a str, last_iteration: u32 },
+    #[serde(rename = "iteration_finished")]
+    IterationFinished {
+        name: &'a str,
+        iteration: u32,
+        last_iteration: u32,
+    },
+    #[serde(rename = "run_complete")]
+    RunComplete { name: &'a str },
+    /// The error and its causes, one per line.
+    #[serde(rename = "run_failed")]
+    RunFailed { name: &'a str, error: String },
+}
+
+impl<'a> ProgressEvent<'a> {
+    pub fn run_finished(name: &'a str, error: Option<&Error>) -> Self {
+        match error {
+            None => ProgressEvent::RunComplete { name },
+            Some(error) => ProgressEvent::RunFailed {
+                name,
+                error: error
+                    .iter_chain()
+                    .map(|cause| cause.to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            },
+        }
+    }
+}
+
+/// Observer that POSTs the progress of a run to a URL.
+///
+/// Failed notifications are logged, but never fail the run.
+#[cfg(feature = "http")]
+pub struct HttpNotifier {
+    url: Url,
+    /// Name of the simulation spec, sent with every event.
+    name: String,
+    client: Client,
+}
+
+#[cfg(feature = "http")]
+impl HttpNotifier {
+    /// Makes a notifier for the simulation with the given name, or returns
+    /// `None` if the URL is not a valid HTTP or HTTPS URL.
+    pub fn new(url: &str, name: &str) -> Option<Self> {
+        let url = Url::parse(url).ok()?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return None;
+        }
+
+        let client = Client::builder()
+            .timeout(Duration::from_secs(TIMEOUT_SECS))
+            .build()
+            .ok()?;
+
+        Some(HttpNotifier {
+            url,
+            name: name.to_string(),
+            client,
+        })
+    }
+
+    fn send(&self, event: &ProgressEvent) {
+        let sent = self
+            .client
+            .post(self.url.clone())
+            .json(event)
+            .send()
+            .and_then(|response| response.error_for_status());
+
+        if let Err(err) = sent {
+            warn!("Could not notify {} of {:?}: {}", self.url, event, err);
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+impl Observer for HttpNotifier {
+    fn run_started(&self, last_iteration: u32) {
+        self.send(&ProgressEvent::RunStarted {
+            name: &self.name,
+            last_iteration,
+        });
+    }
+
+    fn iteration_finished(&self, iteration: u32, last_iteration: u32) {
+        self.send(&ProgressEvent::IterationFinished {
+            name: &self.name,
+            iteration,
+            last_iteration,
+        });
+    }
+
+    fn run_finished(&self, error: Option<&Error>) {
+        self.send(&ProgressEvent::run_finished(&self.name, error));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn events_as_json() {
+        let started = ProgressEvent::RunStarted {
+            name: "rust",
+            last_iteration: 30,
+        };
+        assert_eq!(
+            r#"{"event":"run_started","name":"rust","last_iteration":30}"#,
+            serde_json::to_string(&started).unwrap()
+        );
+
+        let error = format_err!("Could not load texture.").context("Iteration 3 failed.");
+        let failed = ProgressEvent::run_finished("rust", Some(&error.into()));
+        assert_eq!(
+            r#"{"event":"run_failed","name":"rust","error":"Iteration 3 failed.\nCould not load texture."}"#,
+            serde_json::to_string(&failed).unwrap()
+        );
+    }
+}
@@ -0,0 +1,34 @@
+use failure::Error;
+use std::path::Path;
+
+/// Gets notified about the progress of a `SimulationRunner`, e.g. to update
+/// a progress bar. Register with `SimulationRunner::add_observer`.
+///
+/// All methods do nothing by default. They take `&self` since they are called
+/// while the runner is busy, so use cells or channels to record state.
+pub trait Observer {
+    /// Called when `SimulationRunner::run` starts, with the last iteration
+    /// that will be performed.
+    fn run_started(&self, _last_iteration: u32) {}
+
+    /// Called when an iteration starts, with the last iteration that will
+    /// be performed. Iteration 0 only runs effects.
+    fn iteration_started(&self, _iteration: u32, _last_iteration: u32) {}
+
+    /// Called after tons have been traced and substances transported.
+    fn tracing_finished(&self, _iteration: u32) {}
+
+    /// Called after an effect has been performed, with type and index in the
+    /// effect list like `layer#1`, or the name of a custom effect.
+    fn effect_finished(&self, _iteration: u32, _effect: &str) {}
+
+    /// Called after a texture, OBJ or MTL file has been written by an effect.
+    fn file_written(&self, _path: &Path) {}
+
+    /// Called after an iteration has been performed, including its effects.
+    fn iteration_finished(&self, _iteration: u32, _last_iteration: u32) {}
+
+    /// Called when `SimulationRunner::run` returns, with the error if the run
+    /// failed.
+    fn run_finished(&self, _error: Option<&Error>) {}
+}
@@ -0,0 +1,85 @@
+use std::fmt;
+
+/// Summary of one iteration performed with `SimulationRunner::step`.
+#[derive(Debug, Clone)]
+pub struct IterationReport {
+    /// Number of the iteration, 0 being the unweathered reference iteration
+    /// that only runs effects.
+    pub iteration: u32,
+    /// Amount of tons emitted while tracing, zero for iteration 0.
+    pub tons_emitted: usize,
+    /// Tons emitted by each built-in source by source name, empty for
+    /// iteration 0 and for custom sources.
+    pub source_emissions: Vec<(String, usize)>,
+    /// Change of the summed concentration over all surfels, by substance name.
+    pub substance_deltas: Vec<(String, f32)>,
+    /// Effects that were performed as type and index in the effect list, e.g.
+    /// `layer#1`. Empty if the effect interval skipped this iteration.
+    pub effects: Vec<String>,
+}
+
+impl fmt::Display for IterationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Iteration {}: {} tons emitted",
+            self.iteration, self.tons_emitted
+        )?;
+
+        if !self.source_emissions.is_empty() {
+            let sources: Vec<_> = self
+                .source_emissions
+                .iter()
+                .map(|&(ref source, emitted)| format!("{} {}", source, emitted))
+                .collect();
+            write!(f, " ({})", sources.join(", "))?;
+        }
+
+        for &(ref substance, delta) in self.substance_deltas.iter() {
+            write!(f, ", {} {:+}", substance, delta)?;
+        }
+
+        if self.effects.is_empty() {
+            write!(f, ", no effects")
+        } else {
+            write!(f, ", effects: {}", self.effects.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let report = IterationReport {
+            iteration: 3,
+            tons_emitted: 100,
+            source_emissions: Vec::new(),
+            substance_deltas: vec![(String::from("rust"), 0.5), (String::from("water"), -2.0)],
+            effects: vec![String::from("density#0"), String::from("layer#1")],
+        };
+
+        assert_eq!(
+            "Iteration 3: 100 tons emitted, rust +0.5, water -2, effects: density#0, layer#1",
+            format!("{}", report)
+        );
+    }
+
+    #[test]
+    fn display_source_emissions() {
+        let report = IterationReport {
+            iteration: 1,
+            tons_emitted: 100,
+            source_emissions: vec![(String::from("Rain"), 80), (String::from("Dust"), 20)],
+            substance_deltas: vec![(String::from("water"), 1.5)],
+            effects: Vec::new(),
+        };
+
+        assert_eq!(
+            "Iteration 1: 100 tons emitted (Rain 80, Dust 20), water +1.5, no effects",
+            format!("{}", report)
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use spec::OutputRetry;
+use std::fmt::Display;
+use std::thread::sleep;
+use std::time::Duration;
+
+/// Calls the given function until it succeeds or the attempts of the given
+/// policy are used up, waiting longer after each failed attempt. Returns the
+/// error of the last attempt if all of them failed.
+pub fn retried<T, E, F>(policy: &OutputRetry, output: &str, mut write: F) -> Result<T, E>
+where
+    E: Display,
+    F: FnMut() -> Result<T, E>,
+{
+    let mut backoff = policy.backoff;
+    let mut attempt = 1;
+    loop {
+        match write() {
+            Ok(written) => return Ok(written),
+            Err(err) if attempt < policy.attempts => {
+                warn!(
+                    "Attempt {} of {} to write {} failed, retrying in {}s: {}",
+                    attempt, policy.attempts, output, backoff, err
+                );
+                sleep(Duration::from_millis((backoff * 1000.0) as u64));
+                backoff *= 2.0;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn retry_until_success() {
+        let failures = Cell::new(0);
+        let flaky = || {
+            failures.set(failures.get() + 1);
+            if failures.get() < 3 {
+                Err("stale file handle")
+            } else {
+                Ok(failures.get())
+            }
+        };
+
+        let patient = OutputRetry {
+            attempts: 3,
+            backoff: 0.0,
+        };
+        assert_eq!(Ok(3), retried(&patient, "out.png", &flaky));
+
+        failures.set(0);
+        let impatient = OutputRetry {
+            attempts: 2,
+            ..patient
+        };
+        assert_eq!(
+            Err("stale file handle"),
+            retried(&impatient, "out.png", &flaky)
+        );
+        assert_eq!(2, failures.get());
+    }
+}
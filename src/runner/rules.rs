@@ -0,0 +1,121 @@
+use geom::Vertex;
+use sim::{SurfelData, SurfelRule};
+use surf;
+
+type Surface = surf::Surface<surf::Surfel<Vertex, SurfelData>>;
+
+/// Rules of the surfels of an entity, split by whether they apply in every
+/// iteration.
+#[derive(Clone, Default)]
+pub struct EntityRules {
+    pub always: Vec<SurfelRule>,
+    /// Rules that apply to each surfel in an iteration with the given
+    /// probability.
+    pub sometimes: Vec<(SurfelRule, f32)>,
+}
+
+impl EntityRules {
+    pub fn is_stochastic(&self) -> bool {
+        !self.sometimes.is_empty()
+    }
+}
+
+/// Decides which rules with a probability apply to which surfels.
+///
+/// The rules of a surfel are fixed while tracing, so the decision is made in
+/// advance for each iteration by replacing the rules of all surfels.
+/// Decisions only depend on the seed, iteration, surfel and rule, so the same
+/// spec always weathers the same way.
+pub struct StochasticRules {
+    seed: u64,
+    by_entity: Vec<EntityRules>,
+    /// Rules with a probability from the simulation spec, for all surfels.
+    global: Vec<(SurfelRule, f32)>,
+}
+
+impl StochasticRules {
+    pub fn new(seed: u64, by_entity: Vec<EntityRules>, global: Vec<(SurfelRule, f32)>) -> Self {
+        StochasticRules {
+            seed,
+            by_entity,
+            global,
+        }
+    }
+
+    /// Replaces the rules of an entity, e.g. after a material swap.
+    pub fn replace(&mut self, entity_idx: usize, rules: EntityRules) {
+        self.by_entity[entity_idx] = rules;
+    }
+
+    /// Sets the rules of each surfel to the rules that apply to it in the
+    /// given iteration.
+    pub fn draw(&self, iteration: u32, surface: &mut Surface) {
+        for (surfel_idx, surfel) in surface.samples.iter_mut().enumerate() {
+            let data = surfel.data_mut();
+            let entity_rules = &self.by_entity[data.entity_idx];
+
+            let drawn = entity_rules
+                .sometimes
+                .iter()
+                .chain(self.global.iter())
+                .enumerate()
+                .filter(|&(rule_idx, &(_, probability))| {
+                    unit_random(&[
+                        self.seed,
+                        iteration as u64,
+                        surfel_idx as u64,
+                        rule_idx as u64,
+                    ]) < probability
+                })
+                .map(|(_, &(ref rule, _))| rule.clone());
+
+            data.rules = entity_rules.always.iter().cloned().chain(drawn).collect();
+        }
+    }
+}
+
+/// Number in `[0, 1)` that is uniformly distributed over the possible keys.
+pub fn unit_random(keys: &[u64]) -> f32 {
+    let hash = keys.iter().fold(0, |hash, &key| mix(hash ^ mix(key)));
+    // Upper 24 bits, which an f32 holds exactly
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Finalizer of splitmix64, scrambling the bits of the input.
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_random_is_uniform() {
+        let samples = 10_000;
+        let mut below_quarter = 0;
+        for surfel_idx in 0..samples {
+            let random = unit_random(&[0, 1, surfel_idx, 0]);
+            assert!(random >= 0.0 && random < 1.0);
+            if random < 0.25 {
+                below_quarter += 1;
+            }
+        }
+
+        let fraction = below_quarter as f32 / samples as f32;
+        assert!((fraction - 0.25).abs() < 0.02, "{} below 0.25", fraction);
+    }
+
+    #[test]
+    fn unit_random_depends_on_all_keys() {
+        let base = unit_random(&[0, 1, 2, 3]);
+        assert_eq!(base, unit_random(&[0, 1, 2, 3]));
+        assert_ne!(base, unit_random(&[1, 1, 2, 3]));
+        assert_ne!(base, unit_random(&[0, 2, 2, 3]));
+        assert_ne!(base, unit_random(&[0, 1, 3, 3]));
+        assert_ne!(base, unit_random(&[0, 1, 2, 4]));
+    }
+}
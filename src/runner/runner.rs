@@ -1,19 +1,50 @@
 use asset::obj;
-use bencher::Bencher;
-use files::create_file_recursively;
+use bencher::{as_secs, csv_field, write_summaries, Bencher, Benchmark, Layout, Summary};
+use failure::{Error, ResultExt};
+use files::{
+    create_file_recursively, entity_dirs, write_atomically, InputHashes, PatternValues,
+};
 use geom::Vertex;
+use profiler::{Profiler, Span};
+use runner::audit::Audit;
+use runner::color_space::LinearLight;
+use runner::density::{concentrations, density_size, DensityMap};
+use runner::encoder::{Encoded, Encoder, Encoding, ENCODING_QUEUE, ENCODING_THREADS};
+use runner::ktx2::open_texture;
+use runner::material_names::uniquely_named;
+use runner::exr::MultiLayerExr;
+use runner::retry::retried;
 use runner::surfel_table_cache::SurfelTableCache;
-use scene::{Entity, MaterialBuilder};
+use runner::texture_cache::{TextureCache, TEXTURE_CACHE_BYTES};
+use runner::sink::{staged_path, staging_dir, versioned_path};
+use runner::{
+    streaked, wear_mask, Budget, Command, Effect, EffectContext, FileSystemSink, Injections,
+    IterationReport, MaterialSwaps, Observer, OutputSink, SourceSchedule, StochasticRules,
+    SurfelSnapshot, Surfels,
+};
+use scene::{Entity, Material, MaterialBuilder};
 use sim::Simulation;
 use sim::SurfelData;
-use spec::{BenchSpec, Blend, EffectSpec, SimulationSpec, SurfelLookup};
+use spec::{
+    AdaptiveResolution, BenchSpec, Blend, ColorSpace, Compression, EffectSpec, EntityFilter,
+    NormalConvention, Overwrite, SimulationSpec, SurfelLookup, WearMask,
+};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fmt;
-use std::path::PathBuf;
+use std::env::temp_dir;
+use std::fs::{create_dir_all, remove_dir_all, rename, File};
+use std::io::{self, Read, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::mpsc::Receiver;
+use std::time::Instant;
 use surf;
 use tex::{
-    self, combine_normals, open, BlendType, Density, DynamicImage, FilterType, GenericImage,
-    GuidedBlend, Pixel, Rgba, Stop, SubstanceFilter,
+    combine_normals, BlendType, DynamicImage, FilterType, GenericImage, GuidedBlend, Pixel,
+    Rgba, RgbaImage, Stop, SubstanceFilter,
 };
 
 type Surface = surf::Surface<surf::Surfel<Vertex, SurfelData>>;
@@ -22,13 +53,58 @@ pub struct SimulationRunner {
     spec: SimulationSpec,
     sim: Simulation,
     iteration: u32,
+    /// Iteration that the next call to step will perform.
+    next_iteration: u32,
     unique_substance_names: Vec<String>,
     entities: Vec<Entity>,
+    /// Directory names of the entities for `{entity_dir}`.
+    entity_dirs: Vec<String>,
     surfel_tables: SurfelTableCache,
-    iteration_benchmark: Option<Bencher>,
-    tracing_benchmark: Option<Bencher>,
-    synthesis_benchmark: Option<Bencher>,
+    iteration_benchmark: Bencher,
+    tracing_benchmark: Bencher,
+    synthesis_benchmark: Bencher,
     datetime: String,
+    profiler: Option<Rc<Profiler>>,
+    custom_effects: Vec<Box<Effect>>,
+    observers: Vec<Box<Observer>>,
+    sink: Box<OutputSink>,
+    source_schedule: Option<SourceSchedule>,
+    /// Tons that built-in sources emit per iteration by source name, each
+    /// entry applying from its iteration until the next entry.
+    source_emissions: Vec<(u32, Vec<(String, usize)>)>,
+    /// Receives a CSV line with emitted tons and substance deltas after each
+    /// iteration, if set.
+    iteration_stats: Option<Box<Write>>,
+    material_swaps: Option<MaterialSwaps>,
+    stochastic_rules: Option<StochasticRules>,
+    injections: Option<Injections>,
+    /// Surface for the next iteration if surfels have been changed at the end
+    /// of the last one, by material swaps, by drawing stochastic rules or by
+    /// injecting substances.
+    next_surface: Option<Surface>,
+    /// Whether effects skip outputs that exist already.
+    resume_effects: bool,
+    /// Whether changes of substance totals are logged and checked against
+    /// the budgets after each stage of an iteration.
+    audit: bool,
+    /// Changes of substance totals while tracing that the spec explains, by
+    /// substance index.
+    substance_budgets: Vec<Budget>,
+    /// Hashes of the input files, recorded in surfel snapshots.
+    input_hashes: InputHashes,
+    overwrite: Overwrite,
+    /// Paths that outputs written during the run have been written to, by the
+    /// path of the output, so that later writes go to the same path.
+    output_targets: RefCell<HashMap<String, String>>,
+    /// Encodes textures in the background, written when effects finish.
+    encoder: RefCell<Encoder>,
+    /// Decoded original maps and blend stop samples.
+    textures: RefCell<TextureCache>,
+    /// Commands received between iterations, if controlled remotely.
+    commands: Option<Receiver<Command>>,
+    /// Substances that density and layer effects write outputs for, all of
+    /// them if `None`.
+    synthesized_substances: Option<Vec<String>>,
 }
 
 impl SimulationRunner {
@@ -39,52 +115,565 @@ impl SimulationRunner {
         entities: Vec<Entity>,
         // Datetime to replace in file patterns
         datetime: &str,
-    ) -> Self {
-        let surfel_tables = build_surfel_tables(&spec.effects, &entities, sim.surface());
+        profiler: Option<Rc<Profiler>>,
+    ) -> Result<Self, Error> {
+        // Sizes of layer outputs are looked up in the original maps and samples
+        let mut textures = TextureCache::new(TEXTURE_CACHE_BYTES);
+        let surfel_tables = {
+            let _tables_span = profiler.as_ref().map(|p| p.span("surfel tables", "setup"));
+            build_surfel_tables(&spec.effects, &entities, sim.surface(), &mut textures)?
+        };
 
         let (iteration_benchmark, tracing_benchmark, synthesis_benchmark) =
-            build_benchmarks(&spec.benchmark, datetime);
+            build_benchmarks(&spec.benchmark, datetime)?;
+        let overwrite = spec.overwrite.unwrap_or_default();
+        let entity_dirs = entity_dirs(entities.iter().map(|e| e.name.as_str()));
+        let substance_budgets = vec![Budget::unbounded(); unique_substance_names.len()];
 
-        Self {
+        Ok(Self {
             spec,
             sim,
             iteration: 0,
+            next_iteration: 0,
             unique_substance_names,
             entities,
+            entity_dirs,
             surfel_tables,
             iteration_benchmark,
             tracing_benchmark,
             synthesis_benchmark,
             datetime: String::from(datetime),
-        }
+            profiler,
+            custom_effects: Vec::new(),
+            observers: Vec::new(),
+            sink: Box::new(FileSystemSink),
+            source_schedule: None,
+            source_emissions: Vec::new(),
+            iteration_stats: None,
+            material_swaps: None,
+            stochastic_rules: None,
+            injections: None,
+            next_surface: None,
+            resume_effects: false,
+            audit: false,
+            substance_budgets,
+            input_hashes: InputHashes::new(),
+            overwrite,
+            output_targets: RefCell::new(HashMap::new()),
+            encoder: RefCell::new(Encoder::new(ENCODING_THREADS, ENCODING_QUEUE)),
+            textures: RefCell::new(textures),
+            commands: None,
+            synthesized_substances: None,
+        })
     }
 
     pub fn spec(&self) -> &SimulationSpec {
         &self.spec
     }
 
-    pub fn run(&mut self) {
-        // Iteration 0 only performs effects, no tracing is performed.
-        // Useful as a reference for iteration 1.
-        self.iteration = 0;
-        self.perform_effects();
+    /// Registers an effect to perform after the effects from the spec, in
+    /// registration order.
+    pub fn add_effect(&mut self, effect: Box<Effect>) {
+        self.custom_effects.push(effect);
+    }
+
+    /// Registers an observer to notify about progress.
+    pub fn add_observer(&mut self, observer: Box<Observer>) {
+        self.observers.push(observer);
+    }
+
+    /// Redirects textures, OBJ and MTL files written by effects into the
+    /// given sink instead of the file system.
+    pub fn set_output_sink(&mut self, sink: Box<OutputSink>) {
+        self.sink = sink;
+    }
+
+    /// Makes effects skip textures, OBJ and MTL files that exist already in the
+    /// local file system, e.g. to continue an interrupted run without writing
+    /// its outputs again. Textures that cannot be read, e.g. because writing
+    /// them was interrupted, are written again.
+    ///
+    /// Outputs are only skipped if their path is the same as in the previous
+    /// run, so patterns should not contain `{datetime}`.
+    pub fn set_resume_effects(&mut self, resume_effects: bool) {
+        self.resume_effects = resume_effects;
+    }
+
+    /// Logs how the total of each substance over all surfels changes in each
+    /// stage of an iteration, and warns if tracing creates or destroys a
+    /// substance that no source or rule explains.
+    pub fn set_audit(&mut self, audit: bool) {
+        self.audit = audit;
+    }
+
+    /// Sets which substances the sources and rules may create or remove
+    /// while tracing, unbounded for all substances by default.
+    pub fn set_substance_budgets(&mut self, budgets: Vec<Budget>) {
+        self.substance_budgets = budgets;
+    }
+
+    /// Sets the hashes of the scenes, surfel specs and samples that the run
+    /// uses, which surfel snapshots record to detect changed inputs when
+    /// they are restored.
+    pub fn set_input_hashes(&mut self, input_hashes: InputHashes) {
+        self.input_hashes = input_hashes;
+    }
+
+    pub fn input_hashes(&self) -> &InputHashes {
+        &self.input_hashes
+    }
+
+    /// Sets what happens to outputs that exist in the local file system
+    /// before the run, overriding `overwrite` in the spec.
+    pub fn set_overwrite(&mut self, overwrite: Overwrite) {
+        self.overwrite = overwrite;
+    }
+
+    /// Handles commands from the given channel after each iteration of `run`,
+    /// e.g. to pause or abort a long run.
+    pub fn set_commands(&mut self, commands: Receiver<Command>) {
+        self.commands = Some(commands);
+    }
+
+    /// Restricts density and layer effects to the substances with the given
+    /// names, skipping their outputs for all other substances.
+    pub fn set_synthesized_substances(&mut self, substances: Vec<String>) {
+        self.synthesized_substances = Some(substances);
+    }
+
+    /// Replaces the sources of the simulation in later iterations.
+    pub fn set_source_schedule(&mut self, schedule: SourceSchedule) {
+        self.source_schedule = Some(schedule);
+    }
+
+    /// Sets the tons that built-in sources emit per iteration by source name,
+    /// each entry applying from its iteration until the next entry.
+    pub fn set_source_emissions(&mut self, emissions: Vec<(u32, Vec<(String, usize)>)>) {
+        self.source_emissions = emissions;
+    }
+
+    /// Writes a CSV line with the tons emitted by each source and the
+    /// substance deltas of each iteration into the given writer, after a
+    /// header line.
+    pub fn set_iteration_stats(&mut self, stats: Box<Write>) {
+        self.iteration_stats = Some(stats);
+    }
+
+    /// Swaps surfel specs and materials of entities in later iterations.
+    ///
+    /// Swapping sets up the simulation again with the sources of the next
+    /// iteration, so the source schedule needs sources for every iteration.
+    pub fn set_material_swaps(&mut self, swaps: MaterialSwaps) {
+        self.material_swaps = Some(swaps);
+    }
+
+    /// Draws the rules that apply to each surfel before each later iteration.
+    ///
+    /// Like material swaps, this sets up the simulation again with the
+    /// sources of the next iteration, so sources are needed for every
+    /// iteration.
+    pub fn set_stochastic_rules(&mut self, rules: StochasticRules) {
+        self.stochastic_rules = Some(rules);
+    }
+
+    /// Adds substances to surfels before tracing the iterations scheduled
+    /// in the given injections, except for the first iteration, which is
+    /// expected to be injected when setting up the simulation.
+    ///
+    /// Like material swaps, this sets up the simulation again with the
+    /// sources of the next iteration, so sources are needed for every
+    /// iteration.
+    pub fn set_injections(&mut self, injections: Injections) {
+        self.injections = Some(injections);
+    }
+
+    /// Names of all substances in the simulation.
+    pub fn substance_names(&self) -> &[String] {
+        &self.unique_substance_names
+    }
+
+    /// Iterates over the surfels of the simulated scene in their current state,
+    /// with position, normal and substance concentrations.
+    pub fn surfels<'a>(&'a self) -> Surfels<'a> {
+        Surfels::new(&self.sim.surface().samples, &self.unique_substance_names)
+    }
+
+    /// Runs all remaining iterations of the simulation and the configured effects.
+    ///
+    /// Returns an error if an effect fails, e.g. because a texture could not be
+    /// loaded or an output file could not be written.
+    pub fn run(&mut self) -> Result<(), Error> {
+        let last_iteration = self.iterations();
+        self.notify(|o| o.run_started(last_iteration));
+
+        let result = self.run_iterations();
+        self.notify(|o| o.run_finished(result.as_ref().err()));
+        result
+    }
+
+    /// Only runs the configured effects once, as the given iteration, on the
+    /// surfels as they are, e.g. after restoring them from a snapshot.
+    pub fn run_effects(&mut self, iteration: u32) -> Result<(), Error> {
+        let last_iteration = self.iterations();
+        self.iteration = iteration;
+        // Nothing is left to trace afterwards
+        self.next_iteration = last_iteration + 1;
+        self.notify(|o| o.run_started(last_iteration));
+        self.notify(|o| o.iteration_started(iteration, last_iteration));
+
+        let result = self
+            .perform_effects()
+            .with_context(|_| format!("Effects failed for iteration {}.", iteration))
+            .map_err(Error::from);
+        if result.is_ok() {
+            self.notify(|o| o.iteration_finished(iteration, last_iteration));
+        }
+        self.notify(|o| o.run_finished(result.as_ref().err()));
+        result
+    }
+
+    fn run_iterations(&mut self) -> Result<(), Error> {
+        self.write_stats_header()?;
+        while let Some(report) = self.step()? {
+            info!("{}", report);
+            self.write_stats(&report)?;
+            if self.handle_commands()? {
+                warn!(
+                    "Aborted after iteration {} of {}.",
+                    self.iteration,
+                    self.iterations()
+                );
+                break;
+            }
+        }
+        self.summarize_benchmarks()
+    }
+
+    fn write_stats_header(&mut self) -> Result<(), Error> {
+        let header = iter::once(String::from("iteration"))
+            .chain(iter::once(String::from("tons_emitted")))
+            .chain(self.stats_source_names())
+            .chain(self.unique_substance_names.iter().cloned())
+            .map(|field| csv_field(&field))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        match self.iteration_stats {
+            Some(ref mut stats) => writeln!(stats, "{}", header)
+                .and_then(|_| stats.flush())
+                .context("Failed to write iteration stats.")
+                .map_err(Error::from),
+            None => Ok(()),
+        }
+    }
+
+    /// Names of all built-in sources in order of their first appearance in
+    /// the source emissions, one column each in the iteration stats.
+    fn stats_source_names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for &(_, ref emissions) in self.source_emissions.iter() {
+            for &(ref name, _) in emissions.iter() {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+        names
+    }
+
+    /// Writes the emitted tons and substance deltas of the given report as a
+    /// CSV line, sources that did not emit in the iteration count as zero.
+    fn write_stats(&mut self, report: &IterationReport) -> Result<(), Error> {
+        let mut line = format!("{},{}", report.iteration, report.tons_emitted);
+        for name in self.stats_source_names() {
+            let emitted = report
+                .source_emissions
+                .iter()
+                .find(|&&(ref source, _)| *source == name)
+                .map(|&(_, emitted)| emitted)
+                .unwrap_or(0);
+            line.push_str(&format!(",{}", emitted));
+        }
+        for &(_, delta) in report.substance_deltas.iter() {
+            line.push_str(&format!(",{}", delta));
+        }
+
+        match self.iteration_stats {
+            Some(ref mut stats) => writeln!(stats, "{}", line)
+                .and_then(|_| stats.flush())
+                .context("Failed to write iteration stats.")
+                .map_err(Error::from),
+            None => Ok(()),
+        }
+    }
+
+    /// Handles the commands received since the last call, waiting for more
+    /// while paused, and returns whether to abort.
+    ///
+    /// Effect passes and surfel dumps that fail only log the error, since
+    /// they are not part of the run.
+    fn handle_commands(&self) -> Result<bool, Error> {
+        let commands = match self.commands {
+            Some(ref commands) => commands,
+            None => return Ok(false),
+        };
+
+        let mut paused = false;
+        loop {
+            let command = if paused {
+                commands.recv().ok()
+            } else {
+                commands.try_recv().ok()
+            };
+
+            match command {
+                // Closed channel also resumes, there is no one left to do it
+                None => return Ok(false),
+                Some(Command::Pause) => {
+                    info!("Paused after iteration {}.", self.iteration);
+                    paused = true;
+                }
+                Some(Command::Resume) => if paused {
+                    info!("Resumed.");
+                    paused = false;
+                },
+                Some(Command::RunEffects) => {
+                    info!("Running effects on request...");
+                    if let Err(err) = self.perform_effects() {
+                        warn!("Requested effects failed: {}", err);
+                    }
+                }
+                Some(Command::DumpSurfels(pattern)) => {
+                    if let Err(err) = self.export_surfels(&pattern, &self.pattern_values()) {
+                        warn!("Requested surfel dump failed: {}", err);
+                    }
+                }
+                Some(Command::Abort) => {
+                    info!("Aborting on request, running effects a last time...");
+                    self.perform_effects()?;
+                    return Ok(true);
+                }
+            }
+        }
+    }
+
+    /// Logs mean, median and 95th percentile of iteration, tracing and
+    /// synthesis durations and writes them to the summary file of the spec, if
+    /// any.
+    fn summarize_benchmarks(&self) -> Result<(), Error> {
+        let summaries: Vec<Summary> = [
+            &self.iteration_benchmark,
+            &self.tracing_benchmark,
+            &self.synthesis_benchmark,
+        ].iter()
+            .flat_map(|b| b.summarize())
+            .collect();
+
+        info!("Timings in seconds:");
+        for summary in summaries.iter() {
+            info!(
+                "{}: mean {:.3}, median {:.3}, p95 {:.3} over {} runs",
+                summary.name(),
+                summary.mean,
+                summary.median,
+                summary.p95,
+                summary.count
+            );
+        }
+
+        if let Some(ref benchmark) = self.spec.benchmark {
+            if let Some(ref summary_path) = benchmark.summary {
+                let summary_path =
+                    PatternValues::new(&self.datetime).substitute(&summary_path.to_string_lossy());
+                write_atomically(&summary_path, |f| {
+                    write_summaries(f, Layout::for_spec(benchmark), &summaries)
+                }).with_context(|_| {
+                    format!("Could not write benchmark summary to {}.", summary_path)
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Performs the next iteration and reports what happened, or returns `None`
+    /// if all iterations have already been performed.
+    ///
+    /// The first step performs iteration 0, which only runs the effects on the
+    /// unweathered scene. Each following step traces and then runs the effects
+    /// if scheduled by `effects_at` or the effect interval.
+    pub fn step(&mut self) -> Result<Option<IterationReport>, Error> {
+        if self.next_iteration > self.iterations() {
+            return Ok(None);
+        }
+
+        self.iteration = self.next_iteration;
+        self.next_iteration += 1;
+
+        let last_iteration = self.iterations();
+        self.notify(|o| o.iteration_started(self.iteration, last_iteration));
+
+        let totals_before = self.substance_totals();
 
-        for _ in 0..self.iterations() {
+        let (tons_emitted, effects_performed) = if self.iteration == 0 {
+            // Iteration 0 only performs effects, no tracing is performed.
+            // Useful as a reference for iteration 1.
+            self.perform_effects()
+                .context("Effects failed for unweathered reference iteration 0.")?;
+            (0, true)
+        } else {
             // Iteration 1 is the first iteration with actual gammaton simulation before effects.
-            self.iteration += 1;
-            self.perform_iteration();
+            self.perform_iteration()
+                .with_context(|_| format!("Iteration {} failed.", self.iteration))?
+        };
+
+        let substance_deltas = self
+            .unique_substance_names
+            .iter()
+            .cloned()
+            .zip(
+                self.substance_totals()
+                    .into_iter()
+                    .zip(totals_before)
+                    .map(|(after, before)| after - before),
+            )
+            .collect();
+
+        let effects = if effects_performed {
+            self.spec
+                .effects
+                .iter()
+                .enumerate()
+                .map(|(idx, effect)| format!("{}#{}", effect.kind(), idx))
+                .chain(self.custom_effects.iter().map(|e| String::from(e.name())))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        self.notify(|o| o.iteration_finished(self.iteration, last_iteration));
+
+        Ok(Some(IterationReport {
+            iteration: self.iteration,
+            tons_emitted,
+            source_emissions: self.current_source_emissions(),
+            substance_deltas,
+            effects,
+        }))
+    }
+
+    /// Concentrations of the substance that map to the lowest and highest
+    /// values in density maps and guides, zero to one unless the spec
+    /// defines a range for the substance.
+    fn substance_bounds(&self, substance_idx: usize) -> (f32, f32) {
+        let name = &self.unique_substance_names[substance_idx];
+        match self.spec.substances.get(name) {
+            Some(range) => range.bounds(
+                self.sim
+                    .surface()
+                    .samples
+                    .iter()
+                    .map(|s| s.data().substances[substance_idx]),
+            ),
+            None => (0.0, 1.0),
         }
     }
 
+    /// Tons that each built-in source emits in the current iteration.
+    fn current_source_emissions(&self) -> Vec<(String, usize)> {
+        if self.iteration == 0 {
+            return Vec::new();
+        }
+
+        self.source_emissions
+            .iter()
+            .take_while(|&&(from, _)| from <= self.iteration)
+            .last()
+            .map(|&(_, ref emissions)| emissions.clone())
+            .unwrap_or_else(Vec::new)
+    }
+
+    /// Sums up the concentrations of each substance over all surfels.
+    fn substance_totals(&self) -> Vec<f32> {
+        surface_totals(self.sim.surface(), self.unique_substance_names.len())
+    }
+
+    /// Logs how the substance totals changed in the given stage of the
+    /// current iteration and, if requested, warns about changes that the
+    /// budgets do not explain.
+    fn audit_stage(&self, stage: &str, before: &[f32], after: &[f32], check: bool) {
+        let audit = Audit::new(
+            self.unique_substance_names.clone(),
+            self.substance_budgets.clone(),
+        );
+
+        match audit.changes(before, after) {
+            Some(changes) => info!(
+                "Audit of iteration {}, {}: {}",
+                self.iteration, stage, changes
+            ),
+            None => info!(
+                "Audit of iteration {}, {}: no substance changed",
+                self.iteration, stage
+            ),
+        }
+
+        if check {
+            for unexplained in audit.unexplained(before, after) {
+                warn!(
+                    "Audit of iteration {}, {}: {}, check the rules and transport.",
+                    self.iteration, stage, unexplained
+                );
+            }
+        }
+    }
+
+    /// Measures a part of synthesis, but only if the synthesis benchmark is
+    /// labeled, so that the rows can be told apart from whole syntheses.
+    fn synthesis_detail_bench<'a>(&'a self) -> Option<Benchmark<'a>> {
+        if self.synthesis_benchmark.is_labeled() {
+            Some(self.synthesis_benchmark.bench().iteration(self.iteration))
+        } else {
+            None
+        }
+    }
+
+    /// Starts a profiling span if a profiler has been configured.
+    fn profile<'a, S: Into<String>>(&'a self, name: S, category: &'static str) -> Option<Span<'a>> {
+        self.profiler.as_ref().map(|p| p.span(name, category))
+    }
+
+    fn notify<F: Fn(&Observer)>(&self, notification: F) {
+        for observer in self.observers.iter() {
+            notification(&**observer);
+        }
+    }
+
+    /// Placeholder values shared by all outputs of the current iteration.
+    fn pattern_values<'a>(&'a self) -> PatternValues<'a> {
+        PatternValues::new(&self.datetime).iteration(self.iteration)
+    }
+
     fn iterations(&self) -> u32 {
         // Default to 1 iteration
         self.spec.iterations.unwrap_or(1)
     }
 
-    fn perform_iteration(&mut self) {
+    /// Traces and performs the effects if scheduled, returning the amount of
+    /// traced tons and whether effects were performed.
+    fn perform_iteration(&mut self) -> Result<(usize, bool), Error> {
         // Write timings of complete iterations to CSV benchmarks if required
         // by simulation spec.
-        let _iteration_bench = self.iteration_benchmark.as_ref().map(|b| b.bench());
+        let _iteration_bench = self
+            .iteration_benchmark
+            .bench()
+            .iteration(self.iteration)
+            .phase("iteration");
+        // Clone the profiler handle so the spans do not borrow self while tracing
+        let profiler = self.profiler.clone();
+        let _iteration_span = profiler
+            .as_ref()
+            .map(|p| p.span(format!("iteration {}", self.iteration), "iteration"));
 
         info!(
             "Iteration {} of {} started...",
@@ -92,46 +681,184 @@ impl SimulationRunner {
             self.iterations()
         );
 
+        let reseeded = match (self.source_schedule.as_mut(), self.next_surface.take()) {
+            (Some(schedule), Some(surface)) => schedule.restart(self.iteration, surface),
+            (Some(schedule), None) => schedule.reseed(self.iteration, &self.sim),
+            (None, _) => None,
+        };
+        if let Some(sim) = reseeded {
+            info!("Switching to the sources of iteration {}.", self.iteration);
+            self.sim = sim;
+        }
+
         // Perform tracing and substance transport every iteration.
-        {
-            let _tracing_and_transport_bench = self.tracing_benchmark.as_ref().map(|b| b.bench());
+        let tons = {
+            let tracing_and_transport_bench = self
+                .tracing_benchmark
+                .bench()
+                .iteration(self.iteration)
+                .phase("tracing");
+            let _tracing_span = profiler.as_ref().map(|p| p.span("tracing", "tracing"));
 
             info!("Tracing...");
-            self.sim.run();
-        }
+            let totals_before = if self.audit {
+                Some(self.substance_totals())
+            } else {
+                None
+            };
+            let tracing_start = Instant::now();
+            let mut tons = 0;
+            loop {
+                tons += self.sim.emission_count();
+                self.sim.run();
+
+                // Sources with other transports or bursts continue on the traced surface
+                let next_group = match self.source_schedule {
+                    Some(ref mut schedule) => schedule.next_group(&self.sim),
+                    None => None,
+                };
+                match next_group {
+                    Some(sim) => self.sim = sim,
+                    None => break,
+                }
+            }
+            let _tracing_and_transport_bench = tracing_and_transport_bench.processed(tons as u64);
+
+            let secs = as_secs(tracing_start.elapsed());
+            info!(
+                "Traced {} tons in {:.3}s, {:.0} tons per second.",
+                tons,
+                secs,
+                tons as f64 / secs
+            );
+
+            if let Some(totals_before) = totals_before {
+                let totals_after = self.substance_totals();
+                self.audit_stage(
+                    "transport and rules",
+                    &totals_before,
+                    &totals_after,
+                    true,
+                );
+            }
 
-        let effects_scheduled = match self.spec.effect_interval {
-            // Interval is defined, 1-based iteration index must be divisible.
-            Some(interval) if (self.iteration % interval) == 0 => true,
-            // Either no interval defined or defined and not divisible, skip effects,
-            // except for the last iteration.
-            _ => self.iteration == self.iterations(),
+            tons
         };
 
+        self.notify(|o| o.tracing_finished(self.iteration));
+
+        let effects_scheduled = self.spec.runs_effects(self.iteration);
         if effects_scheduled {
             // NOTE surfel table cache invalidation necessary if geometry was changed
             info!("Texture synthesis...");
-            self.perform_effects();
+            self.perform_effects()?;
+        }
+
+        // Swapped surfels, drawn rules and injected substances take effect in
+        // the next iteration, if any
+        if self.iteration < self.iterations() {
+            if let Some(swaps) = self.material_swaps.as_mut() {
+                self.next_surface = swaps.apply(
+                    &mut self.entities,
+                    self.sim.surface(),
+                    self.stochastic_rules.as_mut(),
+                );
+            }
+            if let Some(ref rules) = self.stochastic_rules {
+                let mut surface = self
+                    .next_surface
+                    .take()
+                    .unwrap_or_else(|| self.sim.surface().clone());
+                rules.draw(self.iteration + 1, &mut surface);
+                self.next_surface = Some(surface);
+            }
+            match self.injections {
+                Some(ref injections) if injections.is_scheduled(self.iteration + 1) => {
+                    let mut surface = self
+                        .next_surface
+                        .take()
+                        .unwrap_or_else(|| self.sim.surface().clone());
+                    injections.apply(self.iteration + 1, &self.entities, &mut surface);
+                    self.next_surface = Some(surface);
+                }
+                _ => (),
+            }
+            // Swaps and injections change concentrations on purpose, so they
+            // are only logged
+            if let (true, Some(surface)) = (self.audit, self.next_surface.as_ref()) {
+                let totals_after = surface_totals(surface, self.unique_substance_names.len());
+                self.audit_stage(
+                    "swaps and injections",
+                    &self.substance_totals(),
+                    &totals_after,
+                    false,
+                );
+            }
         }
+
+        Ok((tons, effects_scheduled))
     }
 
-    fn perform_effects(&self) {
+    fn perform_effects(&self) -> Result<(), Error> {
         // NOTE this will run for iteration 0, so there will be one benchmark more for
         //      synthesis when compared to tracing
-        let _synthesis_bench = self.synthesis_benchmark.as_ref().map(|b| b.bench());
+        let _synthesis_bench = self
+            .synthesis_benchmark
+            .bench()
+            .iteration(self.iteration)
+            .phase("synthesis");
+        let _synthesis_span = self.profile("effects", "synthesis");
 
         // Make a fresh copy of the scene to run the effects on for each effect run.
         // With this technique, effects can accumulate throughout one iteration,
         // but each iteration will apply its effects on top of the base material.
         let mut entities = self.entities.clone();
 
-        for effect in &self.spec.effects {
-            self.perform_effect(effect, &mut entities);
+        for (idx, effect) in self.spec.effects.iter().enumerate() {
+            let effect_name = format!("{}#{}", effect.kind(), idx);
+            let _effect_span = self.profile(effect_name.as_str(), "effect");
+            let _effect_bench = self
+                .synthesis_detail_bench()
+                .map(|b| b.phase("effect").effect(effect_name.as_str()));
+            let values = self.pattern_values().effect(effect.kind(), idx);
+            self.perform_effect(effect, &effect_name, &values, &mut entities)
+                .and_then(|_| self.flush_textures())
+                .with_context(|_| format!("Effect {} failed.", effect_name))?;
+            self.notify(|o| o.effect_finished(self.iteration, &effect_name));
+        }
+
+        if !self.custom_effects.is_empty() {
+            let context = EffectContext::new(
+                self.sim.surface(),
+                &self.unique_substance_names,
+                self.iteration,
+                &self.datetime,
+                &*self.sink,
+            );
+
+            for effect in self.custom_effects.iter() {
+                let _effect_span = self.profile(effect.name(), "effect");
+                let _effect_bench = self
+                    .synthesis_detail_bench()
+                    .map(|b| b.phase("effect").effect(effect.name()));
+                effect
+                    .perform(&context, &mut entities)
+                    .with_context(|_| format!("Effect {} failed.", effect.name()))?;
+                self.notify(|o| o.effect_finished(self.iteration, effect.name()));
+            }
         }
+
+        Ok(())
     }
 
     // Applies the given effect.
-    fn perform_effect(&self, effect: &EffectSpec, entities: &mut Vec<Entity>) {
+    fn perform_effect(
+        &self,
+        effect: &EffectSpec,
+        effect_name: &str,
+        values: &PatternValues,
+        entities: &mut Vec<Entity>,
+    ) -> Result<(), Error> {
         match effect {
             &EffectSpec::Density {
                 width,
@@ -141,46 +868,86 @@ impl SimulationRunner {
                 ref tex_pattern,
                 ref obj_pattern,
                 ref mtl_pattern,
-                ..
+                adaptive_resolution,
             } => self.perform_density(
                 width,
                 height,
+                adaptive_resolution,
                 island_bleed,
                 surfel_lookup,
                 tex_pattern,
                 obj_pattern,
                 mtl_pattern,
+                values,
             ),
-            &EffectSpec::DumpSurfels { ref obj_pattern } => self.export_surfels(obj_pattern),
+            &EffectSpec::DumpSurfels { ref obj_pattern } => {
+                self.export_surfels(obj_pattern, values)
+            }
             &EffectSpec::Layer {
                 ref materials,
+                entities: ref entity_filter,
                 ref substance,
                 surfel_lookup,
                 island_bleed,
+                ref wear,
                 ref normal,
                 ref displacement,
                 ref albedo,
                 ref metallicity,
                 ref roughness,
+                ref exr_pattern,
             } => self.perform_layer(
+                effect_name,
+                values,
                 entities,
                 materials,
+                entity_filter,
                 substance,
                 surfel_lookup,
                 island_bleed,
+                wear,
                 normal,
                 displacement,
                 albedo,
                 metallicity,
                 roughness,
+                exr_pattern,
             ),
             &EffectSpec::Export {
                 ref obj_pattern,
                 ref mtl_pattern,
-            } => self.export_scene(entities.iter(), obj_pattern, mtl_pattern, "all"), // When {substance} is used, write "all"
+            } => self.export_scene(
+                entities.iter(),
+                obj_pattern,
+                mtl_pattern,
+                // When {substance} is used, write "all"
+                &values.clone().substance("all"),
+            ),
+            &EffectSpec::Wear {
+                width,
+                height,
+                ref mask,
+                surfel_lookup,
+                island_bleed,
+                ref tex_pattern,
+            } => self.perform_wear(
+                width,
+                height,
+                mask,
+                surfel_lookup,
+                island_bleed,
+                tex_pattern,
+                values,
+            ),
         }
     }
 
+    fn is_synthesized(&self, substance: &str) -> bool {
+        self.synthesized_substances
+            .as_ref()
+            .map_or(true, |substances| substances.iter().any(|s| s == substance))
+    }
+
     fn filtering(&self) -> SubstanceFilter {
         match self.spec.flat_filtering {
             Some(true) => SubstanceFilter::Flat,
@@ -191,35 +958,54 @@ impl SimulationRunner {
     /// For each substance, create a density map for each entity, then serialize a scene with
     /// textures applied. Does not influence other effects and leaves the original scene unchanged.
     /// Useful for debugging.
+    ///
+    /// With an adaptive resolution, each entity gets a texture size of its own and `{width}` and
+    /// `{height}` in the texture pattern refer to it, while the scene uses the specified size.
     fn perform_density(
         &self,
         width: usize,
         height: usize,
+        adaptive_resolution: Option<AdaptiveResolution>,
         island_bleed: usize,
         surfel_lookup: SurfelLookup,
         tex_pattern: &String,
         obj_pattern: &Option<String>,
         mtl_pattern: &Option<String>,
-    ) {
-        for (substance_idx, substance_name) in self.unique_substance_names.iter().enumerate() {
-            let density = Density::new(
-                substance_idx,
-                width,  // tex_width
-                height, // tex_height
-                island_bleed,
-                0.0, // min_density
-                1.0, // max_density
-                Rgba {
-                    data: [255, 255, 255, 255],
-                }, // undefined_color
-                Rgba {
-                    data: [255, 255, 255, 255],
-                }, // min color
-                Rgba {
-                    data: [0, 0, 0, 255],
-                }, // max color
-                self.filtering(),
-            );
+        values: &PatternValues,
+    ) -> Result<(), Error> {
+        let sizes: Vec<(usize, usize)> = self
+            .entities
+            .iter()
+            .map(|ent| density_size(width, height, adaptive_resolution, ent))
+            .collect();
+        let values = values.clone().size(width as u32, height as u32);
+        let substances = self
+            .unique_substance_names
+            .iter()
+            .enumerate()
+            .filter(|&(_, name)| self.is_synthesized(name));
+        for (substance_idx, substance_name) in substances {
+            let (min_density, max_density) = self.substance_bounds(substance_idx);
+            let concentrations = concentrations(self.sim.surface(), substance_idx);
+            let density = |width, height| {
+                DensityMap::new(
+                    width,  // tex_width
+                    height, // tex_height
+                    island_bleed,
+                    min_density,
+                    max_density,
+                    Rgba {
+                        data: [255, 255, 255, 255],
+                    }, // undefined_color
+                    Rgba {
+                        data: [255, 255, 255, 255],
+                    }, // min color
+                    Rgba {
+                        data: [0, 0, 0, 255],
+                    }, // max color
+                    self.filtering(),
+                )
+            };
 
             // Make lazy copy of original scene with each material replaced
             // by a new one with diffuse color set to substance density
@@ -228,33 +1014,43 @@ impl SimulationRunner {
                 .iter()
                 .enumerate()
                 .map(|(ent_idx, ent)| {
-                    let surfel_table = self.surfel_tables.lookup(
-                        ent_idx,
-                        width,
-                        height,
-                        surfel_lookup,
-                        island_bleed,
-                    );
-
-                    let density_tex = density.collect_with_table(self.sim.surface(), surfel_table);
-
-                    let tex_filename = tex_pattern
-                        .replace("{iteration}", &format!("{}", self.iteration))
-                        .replace("{id}", &format!("{}", ent_idx))
-                        .replace("{entity}", &ent.name)
-                        .replace("{substance}", substance_name)
-                        .replace("{datetime}", &self.datetime);
-
-                    let mut fout = create_file_recursively(&tex_filename)
-                        .expect("Could not create image file for density effect.");
-
-                    tex::ImageRgba8(density_tex)
-                        .write_to(&mut fout, tex::PNG)
-                        .expect("Density texture could not be persisted");
+                    let (width, height) = sizes[ent_idx];
+                    let tex_filename = values
+                        .clone()
+                        .size(width as u32, height as u32)
+                        .entity(ent_idx, &ent.name)
+                        .entity_dir(&self.entity_dirs[ent_idx])
+                        .material(ent.material.name())
+                        .substance(substance_name)
+                        .substitute(tex_pattern);
+
+                    let tex_filename = if self.is_texture_resumed(&tex_filename) {
+                        tex_filename
+                    } else {
+                        let surfel_table = self.surfel_tables.lookup(
+                            ent_idx,
+                            width,
+                            height,
+                            surfel_lookup,
+                            island_bleed,
+                        );
+
+                        let density_tex =
+                            density(width, height).bake(&concentrations, surfel_table);
+
+                        self.write_texture(
+                            &tex_filename,
+                            density_tex,
+                            ColorSpace::Linear,
+                            Compression::None,
+                        ).with_context(|_| {
+                            format!("Density texture {} could not be persisted.", tex_filename)
+                        })?
+                    };
 
                     // Reference old entity name and mesh, but replace
                     // material in a fresh entity
-                    Entity {
+                    Ok(Entity {
                         material: Rc::new(
                             MaterialBuilder::new()
                                 .name(format!(
@@ -265,123 +1061,209 @@ impl SimulationRunner {
                                 .build(),
                         ),
                         ..ent.clone()
-                    }
+                    })
                 })
-                .collect::<Vec<_>>();
+                .collect::<Result<Vec<_>, Error>>()?;
 
             self.export_scene(
                 density_scene.iter(),
                 obj_pattern,
                 mtl_pattern,
-                &substance_name,
-            );
+                &values.clone().substance(substance_name),
+            )?;
         }
+
+        Ok(())
     }
 
     fn perform_layer(
         &self,
+        effect_name: &str,
+        values: &PatternValues,
         entities: &mut Vec<Entity>,
         materials: &Vec<String>,
+        entity_filter: &Option<EntityFilter>,
         substance: &String,
         surfel_lookup: SurfelLookup,
         island_bleed: usize,
+        wear: &Option<WearMask>,
         // REVIEW should normal and displacement be usable together? maybe the normal map should be derived from the displacement map to ensure consistency
         normal: &Option<Blend>,
         displacement: &Option<Blend>,
         albedo: &Option<Blend>,
         metallicity: &Option<Blend>,
         roughness: &Option<Blend>,
-    ) {
+        exr_pattern: &Option<String>,
+    ) -> Result<(), Error> {
+        if !self.is_synthesized(substance) {
+            debug!("Skipping {} for excluded substance {}.", effect_name, substance);
+            return Ok(());
+        }
+
         let substance_idx = self
             .unique_substance_names
             .iter()
             .position(|s| s == substance)
-            .expect(&format!("Blend substance does not exist: {}", substance));
+            .ok_or_else(|| format_err!("Blend substance does not exist: {}", substance))?;
 
-        entities
+        let worn_surface = wear.map(|mask| self.worn_surface(substance_idx, &mask));
+        let surface = worn_surface.as_ref().unwrap_or(self.sim.surface());
+
+        for (idx, entity) in entities
             .iter_mut()
             .enumerate()
-            .filter(|(_, e)| is_entity_applicable_for_materials(e, materials))
-            .for_each(|(idx, entity)| {
-                let mut mat = MaterialBuilder::from(&*entity.material);
-
-                if let Some(normal) = normal {
-                    let new_tex_path = self.perform_blend(
-                        entity,
-                        entity.material.normal_map(),
-                        normal,
-                        substance_idx,
-                        idx,
-                        surfel_lookup,
-                        island_bleed,
-                        BlendType::Normal,
-                    );
-                    mat = mat.normal_map(new_tex_path);
+            .filter(|(_, e)| is_entity_applicable(e, materials, entity_filter))
+        {
+            let _entity_bench = self
+                .synthesis_detail_bench()
+                .map(|b| b.phase("entity").effect(effect_name).entity(idx, &entity.name));
+            let original = entity.material.clone();
+            let mut mat = MaterialBuilder::from(&*original);
+            let entity_values = values
+                .clone()
+                .entity(idx, &entity.name)
+                .entity_dir(&self.entity_dirs[idx])
+                .material(original.name())
+                .substance(substance);
+            let exr_filename = match exr_pattern {
+                // Maps in the EXR are resized to the first one
+                Some(exr_pattern) => {
+                    let first_map = [
+                        (normal, original.normal_map()),
+                        (displacement, original.displacement_map()),
+                        (albedo, original.diffuse_color_map()),
+                        (metallicity, original.metallic_map()),
+                        (roughness, original.roughness_map()),
+                    ].iter()
+                        .filter_map(|&(blend, map)| blend.as_ref().map(|b| (b, map)))
+                        .next();
+                    let values = match first_map {
+                        Some((blend, map)) => {
+                            let (width, height) = self.output_size(blend, map)?;
+                            entity_values.clone().size(width, height)
+                        }
+                        None => entity_values.clone(),
+                    };
+                    Some(values.substitute(exr_pattern))
                 }
-
-                if let Some(displacement) = displacement {
-                    let new_tex_path = self.perform_blend(
-                        entity,
-                        entity.material.displacement_map(),
-                        displacement,
-                        substance_idx,
-                        idx,
-                        surfel_lookup,
-                        island_bleed,
-                        BlendType::Linear,
-                    );
-                    mat = mat.displacement_map(new_tex_path);
+                None => None,
+            };
+            let mut exr = match exr_filename {
+                Some(ref exr_filename) if !self.is_resumed(exr_filename) => {
+                    Some(MultiLayerExr::new())
                 }
+                _ => None,
+            };
 
-                if let Some(albedo) = albedo {
-                    let new_tex_path = self.perform_blend(
-                        entity,
-                        entity.material.diffuse_color_map(),
-                        albedo,
-                        substance_idx,
-                        idx,
-                        surfel_lookup,
-                        island_bleed,
-                        BlendType::Linear,
-                    );
-                    mat = mat.diffuse_color_map(new_tex_path);
-                }
+            if let Some(normal) = normal {
+                let new_tex_path = self.perform_blend(
+                    surface,
+                    entity,
+                    entity.material.normal_map(),
+                    normal,
+                    substance_idx,
+                    idx,
+                    surfel_lookup,
+                    island_bleed,
+                    &entity_values,
+                    BlendType::Normal,
+                    "normal",
+                    exr.as_mut(),
+                )?;
+                mat = mat.normal_map(new_tex_path);
+            }
 
-                if let Some(metallicity) = metallicity {
-                    let new_tex_path = self.perform_blend(
-                        entity,
-                        entity.material.metallic_map(),
-                        metallicity,
-                        substance_idx,
-                        idx,
-                        surfel_lookup,
-                        island_bleed,
-                        BlendType::Linear,
-                    );
-                    mat = mat.metallic_map(new_tex_path);
-                }
+            if let Some(displacement) = displacement {
+                let new_tex_path = self.perform_blend(
+                    surface,
+                    entity,
+                    entity.material.displacement_map(),
+                    displacement,
+                    substance_idx,
+                    idx,
+                    surfel_lookup,
+                    island_bleed,
+                    &entity_values,
+                    BlendType::Linear,
+                    "displacement",
+                    exr.as_mut(),
+                )?;
+                mat = mat.displacement_map(new_tex_path);
+            }
 
-                // REVIEW since mtl supports glossiness, maybe invert the roughness with a MTL filter
-                if let Some(roughness) = roughness {
-                    let new_tex_path = self.perform_blend(
-                        entity,
-                        entity.material.roughness_map(),
-                        roughness,
-                        substance_idx,
-                        idx,
-                        surfel_lookup,
-                        island_bleed,
-                        BlendType::Linear,
-                    );
-                    mat = mat.roughness_map(new_tex_path);
+            if let Some(albedo) = albedo {
+                let new_tex_path = self.perform_blend(
+                    surface,
+                    entity,
+                    entity.material.diffuse_color_map(),
+                    albedo,
+                    substance_idx,
+                    idx,
+                    surfel_lookup,
+                    island_bleed,
+                    &entity_values,
+                    BlendType::Linear,
+                    "albedo",
+                    exr.as_mut(),
+                )?;
+                mat = mat.diffuse_color_map(new_tex_path);
+            }
+
+            if let Some(metallicity) = metallicity {
+                let new_tex_path = self.perform_blend(
+                    surface,
+                    entity,
+                    entity.material.metallic_map(),
+                    metallicity,
+                    substance_idx,
+                    idx,
+                    surfel_lookup,
+                    island_bleed,
+                    &entity_values,
+                    BlendType::Linear,
+                    "metallicity",
+                    exr.as_mut(),
+                )?;
+                mat = mat.metallic_map(new_tex_path);
+            }
+
+            // REVIEW since mtl supports glossiness, maybe invert the roughness with a MTL filter
+            if let Some(roughness) = roughness {
+                let new_tex_path = self.perform_blend(
+                    surface,
+                    entity,
+                    entity.material.roughness_map(),
+                    roughness,
+                    substance_idx,
+                    idx,
+                    surfel_lookup,
+                    island_bleed,
+                    &entity_values,
+                    BlendType::Linear,
+                    "roughness",
+                    exr.as_mut(),
+                )?;
+                mat = mat.roughness_map(new_tex_path);
+            }
+
+            if let (Some(exr), Some(exr_filename)) = (exr, exr_filename) {
+                if !exr.is_empty() {
+                    self.write_output(&exr_filename, &exr.encode())
+                        .with_context(|_| format!("EXR {} could not be persisted.", exr_filename))?;
                 }
+            }
 
-                entity.material = Rc::new(mat.build());
-            });
+            entity.material = Rc::new(mat.build());
+            // Done with this entity, so its maps need not stay decoded
+            self.demote_textures(&original);
+        }
+
+        Ok(())
     }
 
     fn perform_blend(
         &self,
+        surface: &Surface,
         entity: &Entity,
         original_map: Option<&PathBuf>,
         blend: &Blend,
@@ -389,9 +1271,24 @@ impl SimulationRunner {
         entity_idx: usize,
         surfel_lookup: SurfelLookup,
         island_bleed: usize,
+        values: &PatternValues,
         blend_type: BlendType,
-    ) -> PathBuf {
-        let (width, height) = blend_output_size(blend, original_map);
+        channel: &str,
+        exr: Option<&mut MultiLayerExr>,
+    ) -> Result<PathBuf, Error> {
+        let (width, height) = self.output_size(blend, original_map)?;
+        let tex_filename = values.clone().size(width, height).substitute(&blend.tex_pattern);
+
+        if self.is_texture_resumed(&tex_filename) {
+            if let (Some(exr), Some(local)) = (exr, self.existing_output(&tex_filename)) {
+                exr.add_map(
+                    channel,
+                    &open_texture(local)?.to_rgba(),
+                    blend.color_space_of(channel),
+                );
+            }
+            return Ok(PathBuf::from(tex_filename));
+        }
 
         let table = self.surfel_tables.lookup(
             entity_idx,
@@ -401,13 +1298,13 @@ impl SimulationRunner {
             island_bleed,
         );
 
-        let guide = Density::new(
-            substance_idx,
+        let (min_density, max_density) = self.substance_bounds(substance_idx);
+        let guide = DensityMap::new(
             width as usize,  // tex_width
             height as usize, // tex_height
             island_bleed,
-            0.0, // min_density
-            1.0, // max_density
+            min_density,
+            max_density,
             Rgba {
                 data: [0, 0, 0, 255],
             }, // undefined_color
@@ -418,17 +1315,47 @@ impl SimulationRunner {
                 data: [255, 255, 255, 255],
             }, // max color
             self.filtering(),
-        ).collect_with_table(self.sim.surface(), table);
+        ).bake(&concentrations(surface, substance_idx), table);
+        let guide = match blend.streaks {
+            Some(ref streaks) => streaked(&guide, entity, streaks),
+            None => guide,
+        };
+
+        // Bundle the guide of the first synthesized map
+        let mut exr = exr;
+        if let Some(ref mut exr) = exr {
+            if !exr.has_layer("guide") {
+                exr.add_scalar("guide", &guide);
+            }
+        }
 
-        let guided_blend = Self::make_guided_blend(blend, blend_type, original_map);
-        let mut blend_result_tex = guided_blend.perform(&guide);
+        // Gamma encoded colors are blended in linear light, normals never are
+        let color_space = blend.color_space_of(channel);
+        let linear_light = match (blend_type, color_space) {
+            (BlendType::Linear, ColorSpace::Srgb) => Some(LinearLight::new()),
+            _ => None,
+        };
+        let mut blend_result_tex = match linear_light {
+            Some(ref linear_light) => {
+                let mut stops = self
+                    .blend_samples(blend, blend_type, original_map)?
+                    .into_iter()
+                    .map(|(cenith, sample)| (cenith, sample.to_rgba()))
+                    .collect::<Vec<_>>();
+                stops.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+                linear_light.guided_blend(&stops, &guide)
+            }
+            None => self
+                .make_guided_blend(blend, blend_type, original_map)?
+                .perform(&guide),
+        };
 
         // If original map is specified, blend the synthesized
         // weathering signs over the original map.
         // If no original texture, keep the output map with transparency
         // without blending over.
         if let Some(original_map) = original_map {
-            let mut original_map = open(original_map).unwrap();
+            let mut original_map = self.open_original(original_map, blend_type)?;
 
             if blend_result_tex.dimensions() != original_map.dimensions() {
                 let (width, height) = blend_result_tex.dimensions();
@@ -460,62 +1387,212 @@ impl SimulationRunner {
                         if blend.influence != 1.0 {
                             top.apply_with_alpha(|c| c, |a| (((a as f32) * blend.influence) as u8));
                         }
-                        bottom.blend(top);
+                        match linear_light {
+                            Some(ref linear_light) => bottom = linear_light.over(bottom, *top),
+                            None => bottom.blend(top),
+                        }
                         *top = bottom;
                     }), // TODO maybe displacement needs some special treatment so the baseline is at 0.5
                         //      displacement and normals should maybe also be mutually exclusive
             }
         }
 
-        let tex_filename = blend
-            .tex_pattern
-            .replace("{iteration}", &format!("{}", self.iteration))
-            .replace("{id}", &format!("{}", entity_idx))
-            .replace("{entity}", &entity.name)
-            .replace("{substance}", &self.unique_substance_names[substance_idx])
-            .replace("{datetime}", &self.datetime);
+        // Normals are blended in OpenGL convention and written in the one of the scene
+        if let (BlendType::Normal, NormalConvention::DirectX) =
+            (blend_type, self.spec.normal_convention.unwrap_or_default())
+        {
+            flip_green(&mut blend_result_tex);
+        }
+
+        if let Some(exr) = exr {
+            exr.add_map(channel, &blend_result_tex, color_space);
+        }
+
+        let tex_filename = self
+            .write_texture(
+                &tex_filename,
+                blend_result_tex,
+                color_space,
+                blend.compression.unwrap_or_default(),
+            )
+            .with_context(|_| format!("Blended texture {} could not be persisted.", tex_filename))?;
+
+        Ok(PathBuf::from(tex_filename))
+    }
 
-        let mut tex_file = create_file_recursively(&tex_filename)
-            .expect("Could not create texture file for blending effect");
+    /// Copy of the simulated surface with the concentration of the given
+    /// substance faded towards its lower bound where the surface is not worn.
+    fn worn_surface(&self, substance_idx: usize, mask: &WearMask) -> Surface {
+        let (min_density, _) = self.substance_bounds(substance_idx);
+        let mut surface = self.sim.surface().clone();
+        let wear = wear_mask(&surface.samples, mask);
 
-        tex::ImageRgba8(blend_result_tex)
-            .write_to(&mut tex_file, tex::PNG)
-            .expect("Density texture could not be persisted");
+        for (surfel, wear) in surface.samples.iter_mut().zip(wear) {
+            let concentration = &mut surfel.data_mut().substances[substance_idx];
+            *concentration = min_density + (*concentration - min_density) * wear;
+        }
 
-        PathBuf::from(tex_filename)
+        surface
+    }
+
+    /// Bakes a wear mask for each entity into a texture, white where worn.
+    fn perform_wear(
+        &self,
+        width: usize,
+        height: usize,
+        mask: &WearMask,
+        surfel_lookup: SurfelLookup,
+        island_bleed: usize,
+        tex_pattern: &String,
+        values: &PatternValues,
+    ) -> Result<(), Error> {
+        let values = values.clone().size(width as u32, height as u32);
+        // Baked like concentrations of a substance, one for each surfel
+        let wear = wear_mask(&self.sim.surface().samples, mask);
+
+        let density = DensityMap::new(
+            width,
+            height,
+            island_bleed,
+            0.0,
+            1.0,
+            Rgba {
+                data: [0, 0, 0, 255],
+            }, // undefined_color
+            Rgba {
+                data: [0, 0, 0, 255],
+            }, // min color
+            Rgba {
+                data: [255, 255, 255, 255],
+            }, // max color
+            self.filtering(),
+        );
+
+        for (ent_idx, ent) in self.entities.iter().enumerate() {
+            let tex_filename = values
+                .clone()
+                .entity(ent_idx, &ent.name)
+                .entity_dir(&self.entity_dirs[ent_idx])
+                .material(ent.material.name())
+                .substitute(tex_pattern);
+            if self.is_texture_resumed(&tex_filename) {
+                continue;
+            }
+
+            let table = self.surfel_tables.lookup(
+                ent_idx,
+                width,
+                height,
+                surfel_lookup,
+                island_bleed,
+            );
+            let wear_tex = density.bake(&wear, table);
+
+            self.write_texture(&tex_filename, wear_tex, ColorSpace::Linear, Compression::None)
+                .with_context(|_| {
+                    format!("Wear texture {} could not be persisted.", tex_filename)
+                })?;
+        }
+
+        Ok(())
     }
 
     fn make_guided_blend(
+        &self,
         blend: &Blend,
         blend_type: BlendType,
         original_map: Option<&PathBuf>,
-    ) -> GuidedBlend<DynamicImage> {
+    ) -> Result<GuidedBlend<DynamicImage>, Error> {
+        let stops = self
+            .blend_samples(blend, blend_type, original_map)?
+            .into_iter()
+            .map(|(cenith, sample)| Stop::new(cenith, sample));
+        Ok(GuidedBlend::with_type(stops, blend_type))
+    }
+
+    /// Ceniths and decoded samples of the stops of a blend, starting with the
+    /// original map at cenith 0 unless a stop is defined there.
+    fn blend_samples(
+        &self,
+        blend: &Blend,
+        blend_type: BlendType,
+        original_map: Option<&PathBuf>,
+    ) -> Result<Vec<(f32, DynamicImage)>, Error> {
         let mut stops = Vec::with_capacity(blend.stops.len() + 1);
 
         // Add implicit 0.0 stop with original texture, if present
         match original_map {
             Some(original_map) => if !blend.stops.iter().any(|s| s.cenith == 0.0) {
-                stops.push(Stop::new(0.0, tex::open(original_map).unwrap()));
+                stops.push((0.0, self.open_original(original_map, blend_type)?));
             },
             None => if blend.stops.is_empty() {
-                panic!("Failed to do a blend effect because no stops are defined and no original map is defined either")
+                bail!("Failed to do a blend effect because no stops are defined and no original map is defined either")
             },
         }
 
         // Then add the configured stops
         for stop in &blend.stops {
-            stops.push(
-                Stop::new(
-                    stop.cenith,
-                    tex::open(
-                        stop.sample.as_ref().or(original_map)
-                            .expect("Defined a blend stop without texture, but applicable material does not define base texture")
-                    ).expect("Blend stop texture could not be loaded")
-                )
-            )
+            let sample = match (stop.sample.as_ref(), original_map) {
+                (Some(sample_path), _) => self.open_texture(sample_path).with_context(|_| {
+                    format!("Blend stop texture {:?} could not be loaded.", sample_path)
+                })?,
+                (None, Some(original_map)) => self.open_original(original_map, blend_type)?,
+                (None, None) => bail!("Defined a blend stop without texture, but applicable material does not define base texture"),
+            };
+
+            stops.push((stop.cenith, sample))
         }
 
-        GuidedBlend::with_type(stops.into_iter(), blend_type)
+        Ok(stops)
+    }
+
+    /// Decodes an original map of an entity, with the normals of normal maps
+    /// in OpenGL convention like the blend samples.
+    fn open_original(&self, path: &Path, blend_type: BlendType) -> Result<DynamicImage, Error> {
+        let original = self
+            .open_texture(path)
+            .with_context(|_| format!("Original map {:?} could not be loaded.", path))?;
+
+        match (blend_type, self.spec.normal_convention.unwrap_or_default()) {
+            (BlendType::Normal, NormalConvention::DirectX) => {
+                let mut original = original.to_rgba();
+                flip_green(&mut original);
+                Ok(DynamicImage::ImageRgba8(original))
+            }
+            _ => Ok(original),
+        }
+    }
+
+    /// Size of the maps that the given blend synthesizes over the given
+    /// original map, looked up in the texture cache.
+    fn output_size(
+        &self,
+        blend: &Blend,
+        original_map: Option<&PathBuf>,
+    ) -> Result<(u32, u32), Error> {
+        blend_output_size(blend, original_map, &mut self.textures.borrow_mut())
+    }
+
+    /// Decodes the texture at the given path, or copies it from the texture
+    /// cache if it was decoded before.
+    fn open_texture(&self, path: &Path) -> Result<DynamicImage, Error> {
+        self.textures.borrow_mut().open(path)
+    }
+
+    /// Lets the texture cache drop the decoded maps of the given material
+    /// once other textures are decoded.
+    fn demote_textures(&self, material: &Material) {
+        let maps = [
+            material.normal_map(),
+            material.displacement_map(),
+            material.diffuse_color_map(),
+            material.metallic_map(),
+            material.roughness_map(),
+        ];
+        let mut textures = self.textures.borrow_mut();
+        for map in maps.iter().filter_map(|m| *m) {
+            textures.demote(map);
+        }
     }
 
     fn export_scene<'a, E>(
@@ -523,54 +1600,295 @@ impl SimulationRunner {
         entities: E,
         obj_pattern: &Option<String>,
         mtl_pattern: &Option<String>,
-        substance: &str,
-    ) where
+        values: &PatternValues,
+    ) -> Result<(), Error>
+    where
         E: IntoIterator<Item = &'a Entity>,
     {
-        let datetime = &self.datetime;
+        // Either OBJ or MTL can be left out, e.g. to only export geometry for debugging
+        // or to only write materials referencing previously exported geometry.
+        let obj_filename = obj_pattern.as_ref().map(|p| values.substitute(p));
+        let mtl_filename = mtl_pattern.as_ref().map(|p| values.substitute(p));
 
-        // TODO handle deduplication of material names,
-        // e.g. group by name and then make every multiply used name unique if values differ
+        if obj_filename.is_none() && mtl_filename.is_none() {
+            return Ok(());
+        }
 
-        match (obj_pattern, mtl_pattern) {
-            (&Some(ref obj_pattern), &Some(ref mtl_pattern)) => {
-                let obj_filename = obj_pattern.replace("{iteration}", &format!("{}", self.iteration))
-                    .replace("{substance}", substance)
-                    .replace("{datetime}", datetime);
+        if obj_filename
+            .iter()
+            .chain(mtl_filename.iter())
+            .all(|f| self.is_resumed(f))
+        {
+            return Ok(());
+        }
+
+        let obj_filename = match obj_filename {
+            Some(obj_filename) => Some(self.output_target(&obj_filename)?),
+            None => None,
+        };
+        let mtl_filename = match mtl_filename {
+            Some(mtl_filename) => Some(self.output_target(&mtl_filename)?),
+            None => None,
+        };
 
-                let mtl_filename = mtl_pattern.replace("{iteration}", &format!("{}", self.iteration))
-                    .replace("{substance}", substance)
-                    .replace("{datetime}", datetime);
+        // OBJ and MTL can only be saved to local files, so they are written to a
+        // staging directory first. It is next to the outputs if the sink writes
+        // to the file system, so they can be moved into place once complete.
+        let local_dir = obj_filename
+            .iter()
+            .chain(mtl_filename.iter())
+            .filter_map(|f| self.sink.local_path(Path::new(f)))
+            .filter_map(|local| local.parent().map(Path::to_path_buf))
+            .next();
+        let staging_dir = staging_dir(&local_dir.unwrap_or_else(temp_dir));
+
+        let staged = |filename: &String| staged_path(&staging_dir, Path::new(filename));
+        let obj_local = obj_filename.as_ref().map(&staged);
+        let mtl_local = mtl_filename.as_ref().map(&staged);
+
+        if let Some(ref obj_filename) = obj_filename {
+            info!("Persisting scene: {}", obj_filename);
+        }
+        if let Some(ref mtl_filename) = mtl_filename {
+            info!("Persisting materials: {}", mtl_filename);
+        }
 
-                info!("Persisting scene: {}", obj_filename);
+        let entities = uniquely_named(&entities.into_iter().collect::<Vec<_>>());
+        let outputs = format!("{:?} and {:?}", obj_filename, mtl_filename);
+        self.retried(&outputs, || -> Result<(), Error> {
+            for local in obj_local.iter().chain(mtl_local.iter()) {
+                create_file_recursively(local).with_context(|_| {
+                    format!("Failed to create {:?} when persisting effect results.", local)
+                })?;
+            }
 
-                create_file_recursively(&obj_filename)
-                    .expect("Failed to create OBJ file when persisting effect results.");
-                create_file_recursively(&mtl_filename)
-                    .expect("Failed to create MTL file when persisting effect results.");
+            obj::save(
+                entities.iter(),
+                obj_local.as_ref(),
+                mtl_local.as_ref(),
+            ).with_context(|_| {
+                format!(
+                    "Failed to save OBJ {:?} and MTL {:?}.",
+                    obj_local, mtl_local
+                )
+            })?;
+            Ok(())
+        })?;
 
-                obj::save(entities, Some(obj_filename), Some(mtl_filename))
-                    .expect("Failed to save OBJ/MTL.");
-            },
-            (&None, &None) => (),
-            _ => unimplemented!("Individual OBJ/MTL output without its counterpart unsupported by now. Export counterpart too to make it work.")
+        let staged = obj_filename
+            .iter()
+            .zip(obj_local.iter())
+            .chain(mtl_filename.iter().zip(mtl_local.iter()));
+        for (filename, staged_file) in staged {
+            self.move_staged(filename, staged_file)?;
+        }
+
+        if let Err(err) = remove_dir_all(&staging_dir) {
+            warn!("Failed to remove staging directory {:?}: {}", staging_dir, err);
         }
+
+        Ok(())
     }
 
-    fn export_surfels(&self, surfel_obj_pattern: &str) {
-        let datetime = &self.datetime;
+    /// Moves the given staged file to the output with the given path, or
+    /// copies it into the sink if it cannot be moved there, e.g. because the
+    /// sink does not write to the file system.
+    fn move_staged(&self, filename: &str, staged_file: &Path) -> Result<(), Error> {
+        if let Some(local) = self.sink.local_path(Path::new(filename)) {
+            let moved = local
+                .parent()
+                .map_or(Ok(()), create_dir_all)
+                .and_then(|_| rename(staged_file, &local));
+            if moved.is_ok() {
+                self.notify(|o| o.file_written(Path::new(filename)));
+                return Ok(());
+            }
+        }
 
-        let surfel_obj_path = surfel_obj_pattern
-            .replace("{iteration}", &format!("{}", self.iteration))
-            .replace("{datetime}", datetime);
+        let mut staged_bytes = Vec::new();
+        File::open(staged_file)
+            .and_then(|mut f| f.read_to_end(&mut staged_bytes))
+            .with_context(|_| format!("Failed to read staged {:?}.", staged_file))?;
+        self.write_output(filename, &staged_bytes)
+            .with_context(|_| format!("Failed to copy {} into output sink.", filename))?;
+        Ok(())
+    }
 
-        let mut obj_file = create_file_recursively(surfel_obj_path)
-            .expect("Failed to create OBJ file to save surfels into.");
+    fn export_surfels(
+        &self,
+        surfel_obj_pattern: &str,
+        values: &PatternValues,
+    ) -> Result<(), Error> {
+        let surfel_obj_path = values.substitute(surfel_obj_pattern);
+        if self.is_resumed(&surfel_obj_path) {
+            return Ok(());
+        }
+
+        if surfel_obj_path.ends_with(".bin") {
+            let mut snapshot = Vec::new();
+            SurfelSnapshot::new(
+                self.iteration,
+                self.sim.surface(),
+                &self.unique_substance_names,
+                &self.input_hashes,
+            ).write(&mut snapshot)?;
+            self.write_output(&surfel_obj_path, &snapshot)
+                .with_context(|_| format!("Failed to save surfel snapshot {}.", surfel_obj_path))?;
+            return Ok(());
+        }
 
+        let mut obj = Vec::new();
         self.sim
             .surface()
-            .dump(&mut obj_file)
-            .expect("Failed to save surfels to OBJ file");
+            .dump(&mut obj)
+            .with_context(|_| format!("Failed to dump surfels for OBJ file {}.", surfel_obj_path))?;
+        self.write_output(&surfel_obj_path, &obj)
+            .with_context(|_| format!("Failed to save surfels to OBJ file {}.", surfel_obj_path))?;
+
+        Ok(())
+    }
+
+    /// Queues the given texture for encoding as PNG, or as KTX2 with the given
+    /// compression if the path ends in `.ktx2`, and writing to the output
+    /// with the given path, returning the path it will be written to. Mip
+    /// levels are filtered according to the color space, if the spec asks
+    /// for them.
+    ///
+    /// Textures that finished encoding in the meantime are written, the rest
+    /// when flushing at the end of the effect.
+    fn write_texture(
+        &self,
+        filename: &str,
+        texture: RgbaImage,
+        color_space: ColorSpace,
+        compression: Compression,
+    ) -> Result<String, Error> {
+        let target = self.output_target(filename)?;
+        let encoding = Encoding {
+            mipmaps: self.spec.mipmaps.unwrap_or(false),
+            color_space,
+            compression,
+        };
+        let finished = {
+            let mut encoder = self.encoder.borrow_mut();
+            encoder.submit(&target, texture, encoding);
+            encoder.finished()
+        };
+        self.write_encoded(finished)?;
+        Ok(target)
+    }
+
+    /// Waits for all queued textures to be encoded and writes them, so later
+    /// effects can open them.
+    fn flush_textures(&self) -> Result<(), Error> {
+        let finished = self.encoder.borrow_mut().wait();
+        self.write_encoded(finished)
+    }
+
+    fn write_encoded(&self, encoded: Vec<Encoded>) -> Result<(), Error> {
+        for Encoded { target, files } in encoded {
+            let files = files.with_context(|_| format!("Could not encode {}.", target))?;
+            for (path, png) in files {
+                self.write_output(&path, &png)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the given bytes to the output with the given path, retrying
+    /// failed attempts as configured in the spec, and returns the path it was
+    /// written to according to the overwrite policy.
+    fn write_output(&self, filename: &str, bytes: &[u8]) -> Result<String, Error> {
+        let target = self.output_target(filename)?;
+        self.retried(&target, || self.sink.write(Path::new(&target), bytes))
+            .with_context(|_| format!("Could not write {}.", target))?;
+        self.notify(|o| o.file_written(Path::new(&target)));
+        Ok(target)
+    }
+
+    /// Path that the output with the given path is written to according to
+    /// the overwrite policy, the same for all writes of the output in a run.
+    ///
+    /// Outputs only exist before the run if the sink has local paths.
+    fn output_target(&self, filename: &str) -> Result<String, Error> {
+        if let Some(target) = self.output_targets.borrow().get(filename) {
+            return Ok(target.clone());
+        }
+
+        let exists = |filename: &str| {
+            self.sink
+                .local_path(Path::new(filename))
+                .map_or(false, |local| local.exists())
+        };
+        let target = match self.overwrite {
+            Overwrite::Never if exists(filename) => bail!(
+                "{} exists already and the overwrite policy is never, move it or choose another policy.",
+                filename
+            ),
+            Overwrite::Never | Overwrite::Always => String::from(filename),
+            Overwrite::Version => (0..)
+                .map(|version| versioned_path(filename, version))
+                .find(|versioned| !exists(versioned))
+                .unwrap(),
+        };
+        if target != filename {
+            info!("{} exists already, writing {} instead", filename, target);
+        }
+
+        let mut targets = self.output_targets.borrow_mut();
+        targets.insert(String::from(filename), target.clone());
+        // Later writes to the chosen path, e.g. when copying staged files
+        targets.insert(target.clone(), target.clone());
+        Ok(target)
+    }
+
+    fn retried<T, E, F>(&self, output: &str, write: F) -> Result<T, E>
+    where
+        E: fmt::Display,
+        F: FnMut() -> Result<T, E>,
+    {
+        retried(&self.spec.output_retry.unwrap_or_default(), output, write)
+    }
+
+    /// Whether effects resume and the output with the given path exists
+    /// already, so it does not need to be written again.
+    fn is_resumed(&self, filename: &str) -> bool {
+        let resumed = self.existing_output(filename).is_some();
+        if resumed {
+            info!("Resuming, skipped existing {}", filename);
+        }
+        resumed
+    }
+
+    /// Like `is_resumed`, but textures also need to be readable, so that
+    /// textures that were only partially written are written again.
+    fn is_texture_resumed(&self, filename: &str) -> bool {
+        match self.existing_output(filename).map(|local| open_texture(local)) {
+            Some(Ok(_)) => {
+                info!("Resuming, skipped existing {}", filename);
+                true
+            }
+            Some(Err(err)) => {
+                warn!(
+                    "Resuming, but existing {} cannot be read and is written again: {}",
+                    filename, err
+                );
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Local path of the output with the given path if effects resume and
+    /// the output exists already.
+    fn existing_output(&self, filename: &str) -> Option<PathBuf> {
+        if !self.resume_effects {
+            return None;
+        }
+
+        self.sink
+            .local_path(Path::new(filename))
+            .and_then(|local| if local.is_file() { Some(local) } else { None })
     }
 }
 
@@ -582,37 +1900,76 @@ fn is_entity_applicable_for_materials(entity: &Entity, materials: &Vec<String>)
             .any(|m| m == "_" || m == entity.material.name())
 }
 
+// Without an entity filter, only the materials decide
+fn is_entity_applicable(
+    entity: &Entity,
+    materials: &Vec<String>,
+    entity_filter: &Option<EntityFilter>,
+) -> bool {
+    is_entity_applicable_for_materials(entity, materials)
+        && entity_filter.as_ref().map_or(true, |f| f.admits(&entity.name))
+}
+
+/// Turns normals upside down in texture space, converting between the
+/// OpenGL and DirectX conventions.
+fn flip_green(texture: &mut RgbaImage) {
+    for texel in texture.pixels_mut() {
+        texel.data[1] = 255 - texel.data[1];
+    }
+}
+
+/// Sums up the concentrations of each substance over all surfels.
+fn surface_totals(surface: &Surface, substance_count: usize) -> Vec<f32> {
+    let mut totals = vec![0.0; substance_count];
+
+    for surfel in surface.samples.iter() {
+        for (total, concentration) in totals.iter_mut().zip(surfel.data().substances.iter()) {
+            *total += concentration;
+        }
+    }
+
+    totals
+}
+
+/// Builds benchers for iterations, tracing and synthesis. Benchmarks without a
+/// file in the spec are only kept in memory for the summary.
 fn build_benchmarks(
     benchmark: &Option<BenchSpec>,
     creation_time: &str,
-) -> (Option<Bencher>, Option<Bencher>, Option<Bencher>) {
-    fn build_benchmark(target_file: &Option<PathBuf>, creation_time: &str) -> Option<Bencher> {
-        target_file
-            .as_ref()
-            .and_then(|csv| {
-                let csv = csv.to_str().unwrap().replace("{datetime}", creation_time);
-
-                Some(create_file_recursively(csv).expect("Failed to create benchmark file"))
-            })
-            .and_then(|csv| Some(Bencher::new(csv)))
+) -> Result<(Bencher, Bencher, Bencher), Error> {
+    fn build_benchmark(
+        target_file: &Option<PathBuf>,
+        creation_time: &str,
+        layout: Layout,
+    ) -> Result<Bencher, Error> {
+        match target_file {
+            Some(csv) => {
+                let csv = PatternValues::new(creation_time).substitute(csv.to_str().unwrap());
+                let file = create_file_recursively(&csv)
+                    .with_context(|_| format!("Failed to create benchmark file {}", csv))?;
+                Ok(Bencher::new(file, layout))
+            }
+            None => Ok(Bencher::new(io::sink(), Layout::Durations)),
+        }
     }
 
-    if let Some(ref benchmark) = benchmark {
-        let iteration_benchmark = build_benchmark(&benchmark.iterations, creation_time);
-        let tracing_benchmark = build_benchmark(&benchmark.tracing, creation_time);
-        let synthesis_benchmark = build_benchmark(&benchmark.synthesis, creation_time);
+    let no_files = BenchSpec::default();
+    let benchmark = benchmark.as_ref().unwrap_or(&no_files);
+    let layout = Layout::for_spec(benchmark);
 
-        (iteration_benchmark, tracing_benchmark, synthesis_benchmark)
-    } else {
-        (None, None, None)
-    }
+    Ok((
+        build_benchmark(&benchmark.iterations, creation_time, layout)?,
+        build_benchmark(&benchmark.tracing, creation_time, layout)?,
+        build_benchmark(&benchmark.synthesis, creation_time, layout)?,
+    ))
 }
 
 fn build_surfel_tables(
     effects: &Vec<EffectSpec>,
     entities: &Vec<Entity>,
     surface: &Surface,
-) -> SurfelTableCache {
+    textures: &mut TextureCache,
+) -> Result<SurfelTableCache, Error> {
     let mut surfel_tables = SurfelTableCache::new();
 
     info!(
@@ -627,108 +1984,79 @@ fn build_surfel_tables(
                 island_bleed,
                 surfel_lookup,
                 ref materials,
+                entities: ref entity_filter,
                 ref normal,
                 ref displacement,
                 ref albedo,
                 ref metallicity,
                 ref roughness,
                 ..
-            } => entities.iter()
-                .enumerate()
+            } => {
                 // Ignore entities with a material not affected by this synthesis
                 // Do not filter anything if no material name given
-                .filter(|(_, e)| is_entity_applicable_for_materials(e, materials))
-                // And cache
-                .for_each(|(idx, e)| {
+                let applicable = entities
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, e)| is_entity_applicable(e, materials, entity_filter));
+                for (idx, e) in applicable {
                     let material = &e.material;
-
-                    if let Some(normal) = normal {
-                        let (width, height) = blend_output_size(
-                            normal,
-                            material.normal_map()
-                        );
-
-                        surfel_tables.prepare(
-                            idx,
-                            width as usize,
-                            height as usize,
-                            surfel_lookup,
-                            island_bleed,
-                            entities,
-                            surface
-                        )
-                    }
-
-                    if let Some(displacement) = displacement {
-                        let (width, height) = blend_output_size(
-                            displacement,
-                            material.displacement_map()
-                        );
-
-                        surfel_tables.prepare(
-                            idx,
-                            width as usize,
-                            height as usize,
-                            surfel_lookup,
-                            island_bleed,
-                            entities,
-                            surface
-                        )
-                    }
-
-                    if let Some(albedo) = albedo {
-                        let (width, height) = blend_output_size(
-                            albedo,
-                            material.diffuse_color_map()
-                        );
-
-                        surfel_tables.prepare(
-                            idx,
-                            width as usize,
-                            height as usize,
-                            surfel_lookup,
-                            island_bleed,
-                            entities,
-                            surface
-                        )
-                    }
-
-                    if let Some(metallicity) = metallicity {
-                        let (width, height) = blend_output_size(
-                            metallicity,
-                            material.metallic_map()
-                        );
-
-                        surfel_tables.prepare(
-                            idx,
-                            width as usize,
-                            height as usize,
-                            surfel_lookup,
-                            island_bleed,
-                            entities,
-                            surface
-                        )
+                    let blends = [
+                        (normal, material.normal_map()),
+                        (displacement, material.displacement_map()),
+                        (albedo, material.diffuse_color_map()),
+                        (metallicity, material.metallic_map()),
+                        (roughness, material.roughness_map()),
+                    ];
+
+                    for &(blend, original_map) in blends.iter() {
+                        if let Some(blend) = blend.as_ref() {
+                            let (width, height) = blend_output_size(blend, original_map, textures)
+                                .with_context(|_| {
+                                    format!(
+                                        "Could not determine output size of layer effect for entity {}",
+                                        e.name
+                                    )
+                                })?;
+
+                            surfel_tables.prepare(
+                                idx,
+                                width as usize,
+                                height as usize,
+                                surfel_lookup,
+                                island_bleed,
+                                entities,
+                                surface,
+                            )
+                        }
                     }
 
-                    if let Some(roughness) = roughness {
-                        let (width, height) = blend_output_size(
-                            roughness,
-                            material.roughness_map()
-                        );
-
-                        surfel_tables.prepare(
-                            idx,
-                            width as usize,
-                            height as usize,
-                            surfel_lookup,
-                            island_bleed,
-                            entities,
-                            surface
-                        )
+                    // Decoded again when the entity is synthesized, unless
+                    // it is the last one
+                    for map in blends.iter().filter_map(|&(_, map)| map) {
+                        textures.demote(map);
                     }
-
-                }),
+                }
+            }
             &EffectSpec::Density {
+                width,
+                height,
+                island_bleed,
+                surfel_lookup,
+                adaptive_resolution,
+                ..
+            } => entities.iter().enumerate().for_each(|(idx, entity)| {
+                let (width, height) = density_size(width, height, adaptive_resolution, entity);
+                surfel_tables.prepare(
+                    idx,
+                    width,
+                    height,
+                    surfel_lookup,
+                    island_bleed,
+                    &entities,
+                    surface,
+                )
+            }),
+            &EffectSpec::Wear {
                 width,
                 height,
                 island_bleed,
@@ -751,26 +2079,38 @@ fn build_surfel_tables(
 
     info!("Surfel table pre-calculation complete.");
 
-    surfel_tables
+    Ok(surfel_tables)
 }
 
-fn blend_output_size(blend: &Blend, original_tex_path: Option<&PathBuf>) -> (u32, u32) {
+fn blend_output_size(
+    blend: &Blend,
+    original_tex_path: Option<&PathBuf>,
+    textures: &mut TextureCache,
+) -> Result<(u32, u32), Error> {
     match (blend.width, blend.height) {
-        (Some(w), Some(h)) => (w as u32, h as u32),
-        (Some(w), None) => (w as u32, w as u32),
-        (None, Some(h)) => (h as u32, h as u32),
-        (None, None) => original_tex_path
+        (Some(w), Some(h)) => Ok((w as u32, h as u32)),
+        (Some(w), None) => Ok((w as u32, w as u32)),
+        (None, Some(h)) => Ok((h as u32, h as u32)),
+        (None, None) => {
             // Let diffuse color texture map determine surfel table resolution
-            .map(|p| tex::open(p).expect(&format!("Texture of entity could not be loaded {:?}", p)))
+            if let Some(p) = original_tex_path {
+                let original = textures
+                    .open(p)
+                    .with_context(|_| format!("Texture of entity could not be loaded {:?}", p))?;
+                return Ok(original.dimensions());
+            }
+
             // If undefined, pick largest blending stop
-            .or_else(|| blend.stops
-                .iter()
-                .filter_map(|s| s.sample.as_ref())
-                .map(|p| tex::open(p).expect(&format!("Blend sample texture could not be loaded {:?}", p)))
-                .max_by_key(|i| i.dimensions())
-            )
-            .map(|i| i.dimensions())
-            .expect("Cannot determine surfel table size for layer effect in absence of preferred blend output size. Neither the material nor any blend stop define a loadable texture that could be used to derive a fallback size.")
+            let mut largest = None;
+            for p in blend.stops.iter().filter_map(|s| s.sample.as_ref()) {
+                let sample = textures
+                    .open(p)
+                    .with_context(|_| format!("Blend sample texture could not be loaded {:?}", p))?;
+                largest = largest.into_iter().chain(Some(sample.dimensions())).max();
+            }
+
+            largest.ok_or_else(|| format_err!("Cannot determine surfel table size for layer effect in absence of preferred blend output size. Neither the material nor any blend stop define a loadable texture that could be used to derive a fallback size."))
+        }
     }
 }
 
@@ -782,12 +2122,23 @@ impl fmt::Display for SimulationRunner {
             write!(
                 f,
                 "Scene:              {}\n",
-                scene.file_name().unwrap().to_str().unwrap()
+                scene.path().file_name().unwrap().to_str().unwrap()
+            )?;
+        }
+        for occluder in self.spec.occluders.iter() {
+            write!(
+                f,
+                "Occluder:           {}\n",
+                occluder.path().file_name().unwrap().to_str().unwrap()
             )?;
         }
         write!(f, "Iterations:         {}\n", self.iterations())?;
         write!(f, "Surfels:            {}\n", self.sim.surfel_count())?;
         write!(f, "Tons per iteration: {}\n", self.sim.emission_count())?;
-        write!(f, "Substances:         {:?}", self.unique_substance_names)
+        write!(f, "Substances:         {:?}", self.unique_substance_names)?;
+        if let Some(ref substances) = self.synthesized_substances {
+            write!(f, "\nSynthesized:        {:?}", substances)?;
+        }
+        Ok(())
     }
 }
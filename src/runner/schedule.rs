@@ -0,0 +1,192 @@
+use geom::{TupleTriangle, Vertex};
+use sim::{Config, Simulation, SurfelData, SurfelRule, TonSource, Transport};
+use spec;
+use std::collections::VecDeque;
+use std::mem;
+use surf;
+
+type Surface = surf::Surface<surf::Surfel<Vertex, SurfelData>>;
+
+/// Sources that share a transport, `None` for the default transport, and are
+/// traced in the same burst of an iteration.
+pub struct SourceGroup {
+    pub transport: Option<spec::Transport>,
+    /// Index of the tracing pass in the iteration, surfel rules apply after
+    /// the last group of each burst.
+    pub burst: u32,
+    pub sources: Vec<TonSource>,
+}
+
+/// Sources that replace the sources of the simulation in later iterations,
+/// e.g. when sources are only active in some of the iterations.
+///
+/// Sources cannot be changed on a running simulation, so a new simulation is
+/// set up from the same scene, rules and the current state of the surface.
+///
+/// A simulation only has one transport, so iterations with sources of
+/// different transports or bursts are traced with one simulation per group of
+/// sources, each continuing with the surface of the previous one.
+pub struct SourceSchedule {
+    /// Scene triangles that tons interact with.
+    triangles: Vec<TupleTriangle<Vertex>>,
+    rules: Vec<SurfelRule>,
+    transport: Option<spec::Transport>,
+    /// Sources by iteration, in order.
+    sources: VecDeque<IterationSources>,
+    /// Groups still to be traced in the current iteration, last one first.
+    pending: Vec<SourceGroup>,
+    /// Surfel rules, which only apply after tracing the last group of a burst.
+    stripped_rules: Option<Vec<Vec<SurfelRule>>>,
+}
+
+struct IterationSources {
+    iteration: u32,
+    /// Whether the sources differ from those of the previous iteration.
+    changed: bool,
+    groups: Vec<SourceGroup>,
+}
+
+impl SourceSchedule {
+    /// Schedules the given source groups, each with the iteration they are
+    /// built for and whether they differ from those of the previous iteration.
+    ///
+    /// Sources need to be given for every iteration that changes them, and
+    /// also for unchanged iterations that `restart` will be called in or that
+    /// have more than one group.
+    pub fn new(
+        triangles: Vec<TupleTriangle<Vertex>>,
+        rules: Vec<SurfelRule>,
+        transport: Option<spec::Transport>,
+        sources: Vec<(u32, bool, Vec<SourceGroup>)>,
+    ) -> Self {
+        SourceSchedule {
+            triangles,
+            rules,
+            transport,
+            sources: sources
+                .into_iter()
+                .map(|(iteration, changed, groups)| IterationSources {
+                    iteration,
+                    changed,
+                    groups,
+                })
+                .collect(),
+            pending: Vec::new(),
+            stripped_rules: None,
+        }
+    }
+
+    /// Sets up a simulation with the sources of the given iteration, continuing
+    /// where the given simulation left off, if the sources change in that
+    /// iteration or are traced in multiple groups.
+    pub fn reseed(&mut self, iteration: u32, sim: &Simulation) -> Option<Simulation> {
+        match self.take(iteration) {
+            Some(IterationSources {
+                changed, groups, ..
+            }) => {
+                if changed || groups.len() > 1 {
+                    Some(self.simulation(groups, sim.surface().clone()))
+                } else {
+                    None
+                }
+            }
+            None => None,
+        }
+    }
+
+    /// Sets up a simulation with the sources of the given iteration and the
+    /// given surface, e.g. after changing surfel properties, if sources have
+    /// been scheduled for the iteration.
+    pub fn restart(&mut self, iteration: u32, surface: Surface) -> Option<Simulation> {
+        self.take(iteration)
+            .map(|s| self.simulation(s.groups, surface))
+    }
+
+    /// Sets up a simulation for the next group of sources of the current
+    /// iteration, continuing where the given simulation left off, or returns
+    /// `None` if all groups have been traced.
+    pub fn next_group(&mut self, sim: &Simulation) -> Option<Simulation> {
+        let group = self.pending.pop()?;
+        Some(self.group_simulation(group, sim.surface().clone()))
+    }
+
+    /// Drops the sources of earlier iterations and takes the sources of the
+    /// given iteration, if any.
+    fn take(&mut self, iteration: u32) -> Option<IterationSources> {
+        while self
+            .sources
+            .front()
+            .map_or(false, |s| s.iteration < iteration)
+        {
+            self.sources.pop_front();
+        }
+
+        if self
+            .sources
+            .front()
+            .map_or(false, |s| s.iteration == iteration)
+        {
+            self.sources.pop_front()
+        } else {
+            None
+        }
+    }
+
+    /// Simulation for the first group, with the other groups pending.
+    fn simulation(&mut self, mut groups: Vec<SourceGroup>, surface: Surface) -> Simulation {
+        groups.reverse();
+        let first = groups.pop().unwrap_or_else(|| SourceGroup {
+            transport: self.transport,
+            burst: 0,
+            sources: Vec::new(),
+        });
+        self.pending = groups;
+        self.group_simulation(first, surface)
+    }
+
+    fn group_simulation(&mut self, group: SourceGroup, mut surface: Surface) -> Simulation {
+        // Rules apply once per burst, after tracing its last group
+        let last_of_burst = self
+            .pending
+            .last()
+            .map_or(true, |next| next.burst != group.burst);
+        let rules = if last_of_burst {
+            if let Some(stripped) = self.stripped_rules.take() {
+                for (surfel, rules) in surface.samples.iter_mut().zip(stripped) {
+                    surfel.data_mut().rules = rules;
+                }
+            }
+            self.rules.clone()
+        } else {
+            if self.stripped_rules.is_none() {
+                let stripped = surface
+                    .samples
+                    .iter_mut()
+                    .map(|s| mem::replace(&mut s.data_mut().rules, Vec::new()))
+                    .collect();
+                self.stripped_rules = Some(stripped);
+            }
+            Vec::new()
+        };
+
+        Simulation::new_with_config(
+            sim_config(group.transport),
+            group.sources,
+            self.triangles.iter().cloned(),
+            surface,
+            rules,
+        )
+    }
+}
+
+/// Simulation config for the transport in a simulation spec.
+pub fn sim_config(transport: Option<spec::Transport>) -> Config {
+    let transport = match transport {
+        Some(spec::Transport::Classic) => Transport::classic(),
+        Some(spec::Transport::Consistent) => Transport::consistent(),
+        Some(spec::Transport::Conserving) => Transport::conserving(),
+        Some(spec::Transport::Differential) | None => Transport::differential(),
+    };
+
+    Config { transport }
+}
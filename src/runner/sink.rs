@@ -0,0 +1,147 @@
+use files::{create_file_recursively, write_atomically};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static STAGING_DIR_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Destination of all files written by the runner, e.g. textures and OBJ
+/// exports. Defaults to `FileSystemSink`.
+pub trait OutputSink {
+    /// Opens a writer for the output with the given path, replacing anything
+    /// previously written to the same path.
+    fn create(&self, path: &Path) -> io::Result<Box<Write>>;
+
+    /// Writes the complete output with the given path at once, by default
+    /// with a writer from `create`.
+    fn write(&self, path: &Path, bytes: &[u8]) -> io::Result<()> {
+        let mut output = self.create(path)?;
+        output.write_all(bytes)?;
+        output.flush()
+    }
+
+    /// Returns the path on the local file system that the output with the given
+    /// path will end up at, if any.
+    ///
+    /// OBJ and MTL exports are written to this path directly. For sinks that
+    /// return `None`, the default, they are first written to a temporary
+    /// directory and then copied into the sink.
+    fn local_path(&self, _path: &Path) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Writes outputs to the file system, creating missing directories.
+///
+/// Complete outputs are written next to their path first and then renamed,
+/// so that interrupted runs leave no partially written outputs behind.
+pub struct FileSystemSink;
+
+impl OutputSink for FileSystemSink {
+    fn create(&self, path: &Path) -> io::Result<Box<Write>> {
+        Ok(Box::new(create_file_recursively(path)?))
+    }
+
+    fn write(&self, path: &Path, bytes: &[u8]) -> io::Result<()> {
+        write_atomically(path, |file| file.write_all(bytes))
+    }
+
+    fn local_path(&self, path: &Path) -> Option<PathBuf> {
+        Some(PathBuf::from(path))
+    }
+}
+
+/// Makes a path for a new, not yet created directory in the given directory
+/// to write outputs into before moving or copying them into a sink.
+pub fn staging_dir(parent: &Path) -> PathBuf {
+    parent.join(format!(
+        ".aitios-staging-{}-{}",
+        process::id(),
+        STAGING_DIR_COUNT.fetch_add(1, Ordering::SeqCst)
+    ))
+}
+
+/// Inserts the given version before the extension of the file name of the
+/// given path, e.g. `out/scene-2.obj`. Version 0 is the path itself.
+pub fn versioned_path(path: &str, version: u32) -> String {
+    if version == 0 {
+        String::from(path)
+    } else {
+        suffixed_path(path, &version.to_string())
+    }
+}
+
+/// Inserts a dash and the given suffix before the extension of the file name
+/// of the given path, e.g. `out/albedo-mip1.png`.
+pub fn suffixed_path(path: &str, suffix: &str) -> String {
+    let name_start = path.rfind(|c| c == '/' || c == '\\').map_or(0, |idx| idx + 1);
+    match path[name_start..].rfind('.') {
+        // Hidden files like .obj have no extension
+        Some(dot) if dot > 0 => {
+            let (stem, extension) = path.split_at(name_start + dot);
+            format!("{}-{}{}", stem, suffix, extension)
+        }
+        _ => format!("{}-{}", path, suffix),
+    }
+}
+
+/// Places an output path inside a staging directory, so that outputs
+/// with relative paths keep their relative location to each other.
+pub fn staged_path(staging_dir: &Path, path: &Path) -> PathBuf {
+    path.components()
+        .fold(PathBuf::from(staging_dir), |staged, component| match component {
+            Component::Normal(name) => staged.join(name),
+            _ => staged,
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs::{remove_dir_all, File};
+    use std::io::Read;
+
+    #[test]
+    fn file_system_sink_creates_directories() {
+        let dir = temp_dir().join("aitios-file-system-sink-test");
+        let path = dir.join("nested").join("out.txt");
+
+        FileSystemSink
+            .create(&path)
+            .unwrap()
+            .write_all(b"weathered")
+            .unwrap();
+
+        let mut written = String::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_string(&mut written)
+            .unwrap();
+        assert_eq!("weathered", written);
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn staged_paths_stay_inside_staging_dir() {
+        let dir = Path::new("/tmp/staging");
+
+        assert_eq!(
+            PathBuf::from("/tmp/staging/out/scene.obj"),
+            staged_path(dir, Path::new("/out/scene.obj"))
+        );
+        assert_eq!(
+            PathBuf::from("/tmp/staging/out/scene.mtl"),
+            staged_path(dir, Path::new("./out/scene.mtl"))
+        );
+    }
+
+    #[test]
+    fn versions_before_extension() {
+        assert_eq!("out/scene.obj", versioned_path("out/scene.obj", 0));
+        assert_eq!("out/scene-2.obj", versioned_path("out/scene.obj", 2));
+        assert_eq!("out.d/scene-1", versioned_path("out.d/scene", 1));
+        assert_eq!("out/.hidden-1", versioned_path("out/.hidden", 1));
+    }
+}
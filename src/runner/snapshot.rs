@@ -0,0 +1,251 @@
+use failure::Error;
+use files::InputHashes;
+use geom::{Position, Vertex};
+use sim::SurfelData;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use surf;
+
+type Surface = surf::Surface<surf::Surfel<Vertex, SurfelData>>;
+
+/// Leading bytes of surfel snapshots, followed by the format version.
+const MAGIC: &'static [u8; 8] = b"aitiossf";
+/// Version 2 adds the hashes of the inputs after the substance names.
+const VERSION: u32 = 2;
+
+/// Distance that surfels of the snapshot may be apart from the sampled ones,
+/// larger distances mean the snapshot was taken of another scene or sampling.
+const POSITION_TOLERANCE: f32 = 1e-4;
+
+/// Substance concentrations of all surfels after some iteration, so effects
+/// can be run on them again without tracing.
+///
+/// Surfels are stored in sampling order, with the index of their entity and
+/// their position to check that they are restored onto the same surfels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SurfelSnapshot {
+    pub iteration: u32,
+    pub substance_names: Vec<String>,
+    /// Hashes of the inputs of the run that took the snapshot, empty for
+    /// snapshots of version 1.
+    pub inputs: InputHashes,
+    surfels: Vec<SnapshotSurfel>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct SnapshotSurfel {
+    entity_idx: u32,
+    position: [f32; 3],
+    substances: Vec<f32>,
+}
+
+impl SurfelSnapshot {
+    /// Takes a snapshot of the given surface after the given iteration.
+    pub fn new(
+        iteration: u32,
+        surface: &Surface,
+        substance_names: &[String],
+        inputs: &InputHashes,
+    ) -> Self {
+        let surfels = surface
+            .samples
+            .iter()
+            .map(|surfel| {
+                let position = surfel.position();
+                SnapshotSurfel {
+                    entity_idx: surfel.data().entity_idx as u32,
+                    position: [position.x, position.y, position.z],
+                    substances: surfel.data().substances.clone(),
+                }
+            })
+            .collect();
+
+        SurfelSnapshot {
+            iteration,
+            substance_names: substance_names.to_vec(),
+            inputs: inputs.clone(),
+            surfels,
+        }
+    }
+
+    /// Writes the snapshot in a little endian binary format.
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        write_u32(out, VERSION)?;
+        write_u32(out, self.iteration)?;
+        write_u32(out, self.substance_names.len() as u32)?;
+        for name in self.substance_names.iter() {
+            write_str(out, name)?;
+        }
+        write_u32(out, self.inputs.len() as u32)?;
+        for (path, hash) in self.inputs.iter() {
+            write_str(out, &path.to_string_lossy())?;
+            write_str(out, hash)?;
+        }
+
+        write_u32(out, self.surfels.len() as u32)?;
+        for surfel in self.surfels.iter() {
+            write_u32(out, surfel.entity_idx)?;
+            for &coord in surfel.position.iter().chain(surfel.substances.iter()) {
+                out.write_all(&coord.to_bits().to_le_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads a snapshot written with `write`.
+    pub fn read<R: Read>(input: &mut R) -> Result<Self, Error> {
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!("it is not a surfel snapshot");
+        }
+        let version = read_u32(input)?;
+        if version != 1 && version != VERSION {
+            bail!("it has unsupported version {}", version);
+        }
+
+        let iteration = read_u32(input)?;
+        let substance_names = (0..read_u32(input)?)
+            .map(|_| read_string(input))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let inputs = if version == 1 {
+            InputHashes::new()
+        } else {
+            (0..read_u32(input)?)
+                .map(|_| Ok((PathBuf::from(read_string(input)?), read_string(input)?)))
+                .collect::<Result<InputHashes, Error>>()?
+        };
+
+        let substance_count = substance_names.len();
+        let surfels = (0..read_u32(input)?)
+            .map(|_| {
+                let entity_idx = read_u32(input)?;
+                let position = [read_f32(input)?, read_f32(input)?, read_f32(input)?];
+                let substances = (0..substance_count)
+                    .map(|_| read_f32(input))
+                    .collect::<io::Result<Vec<_>>>()?;
+                Ok(SnapshotSurfel {
+                    entity_idx,
+                    position,
+                    substances,
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(SurfelSnapshot {
+            iteration,
+            substance_names,
+            inputs,
+            surfels,
+        })
+    }
+
+    /// Sets the concentrations of the surfels of the given surface to the ones
+    /// in the snapshot, by substance name. Substances that the snapshot lacks
+    /// keep their concentration.
+    ///
+    /// Fails if the surfels differ from the ones in the snapshot, e.g. because
+    /// the scene or surfel sampling changed since the snapshot was taken.
+    pub fn restore(&self, surface: &mut Surface, substance_names: &[String]) -> Result<(), Error> {
+        if surface.samples.len() != self.surfels.len() {
+            bail!(
+                "it has {} surfels, but {} were sampled, use the same scenes and surfel sampling as when taking it",
+                self.surfels.len(),
+                surface.samples.len()
+            );
+        }
+
+        // Index of each substance of the surface in the snapshot
+        let indexes: Vec<Option<usize>> = substance_names
+            .iter()
+            .map(|name| self.substance_names.iter().position(|s| s == name))
+            .collect();
+
+        for (idx, (surfel, saved)) in surface
+            .samples
+            .iter_mut()
+            .zip(self.surfels.iter())
+            .enumerate()
+        {
+            let position = surfel.position();
+            let distance = ((position.x - saved.position[0]).powi(2)
+                + (position.y - saved.position[1]).powi(2)
+                + (position.z - saved.position[2]).powi(2))
+            .sqrt();
+            if surfel.data().entity_idx as u32 != saved.entity_idx
+                || !(distance <= POSITION_TOLERANCE)
+            {
+                bail!(
+                    "surfel {} is not where it was sampled, use the same scenes and surfel sampling as when taking it",
+                    idx
+                );
+            }
+
+            let data = surfel.data_mut();
+            for (concentration, saved_idx) in data.substances.iter_mut().zip(indexes.iter()) {
+                if let &Some(saved_idx) = saved_idx {
+                    *concentration = saved.substances[saved_idx];
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn write_u32<W: Write>(out: &mut W, value: u32) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+fn read_u32<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn write_str<W: Write>(out: &mut W, value: &str) -> io::Result<()> {
+    write_u32(out, value.len() as u32)?;
+    out.write_all(value.as_bytes())
+}
+
+fn read_string<R: Read>(input: &mut R) -> Result<String, Error> {
+    let mut bytes = vec![0; read_u32(input)? as usize];
+    input.read_exact(&mut bytes)?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+fn read_f32<R: Read>(input: &mut R) -> io::Result<f32> {
+    read_u32(input).map(f32::from_bits)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn write_and_read() {
+        let snapshot = SurfelSnapshot {
+            iteration: 12,
+            substance_names: vec![String::from("rust"), String::from("moss")],
+            inputs: vec![(PathBuf::from("/scenes/park.obj"), String::from("af63dc4c8601ec8c"))]
+                .into_iter()
+                .collect(),
+            surfels: vec![SnapshotSurfel {
+                entity_idx: 3,
+                position: [1.0, -2.0, 0.5],
+                substances: vec![0.25, 1.0],
+            }],
+        };
+
+        let mut bytes = Vec::new();
+        snapshot.write(&mut bytes).unwrap();
+        assert_eq!(b"aitiossf", &bytes[..8]);
+        assert_eq!(snapshot, SurfelSnapshot::read(&mut &bytes[..]).unwrap());
+
+        assert!(SurfelSnapshot::read(&mut &bytes[..bytes.len() - 1]).is_err());
+        bytes[0] = b'x';
+        assert!(SurfelSnapshot::read(&mut &bytes[..]).is_err());
+    }
+}
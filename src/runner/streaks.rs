@@ -0,0 +1,268 @@
+use geom::{TupleTriangle, Vec3, Vertex};
+use runner::rules::unit_random;
+use scene::{Entity, Mesh};
+use spec::Streaks;
+use tex::RgbaImage;
+
+/// Projected flow on triangles that are tilted less than about six degrees
+/// from facing against it is too short for weathering to run down.
+const MIN_PROJECTED_FLOW: f32 = 0.1;
+
+/// Guide of a blend for the given entity, smeared along the flow direction
+/// of the streaks projected into texture space.
+pub fn streaked(guide: &RgbaImage, entity: &Entity, streaks: &Streaks) -> RgbaImage {
+    let (width, height) = guide.dimensions();
+    let directions = texel_directions(
+        entity.mesh.triangles(),
+        width as usize,
+        height as usize,
+        vec3(streaks.direction),
+    );
+    smear(guide, &directions, streaks)
+}
+
+/// Unit direction of the flow in texel space for each texel in rows from the
+/// top, or `None` for texels outside of triangles and on triangles facing
+/// the flow.
+fn texel_directions<I>(
+    triangles: I,
+    width: usize,
+    height: usize,
+    flow: Vec3,
+) -> Vec<Option<(f32, f32)>>
+where
+    I: IntoIterator<Item = TupleTriangle<Vertex>>,
+{
+    let mut directions = vec![None; width * height];
+    // Texture coordinates have their origin at the bottom, images at the top
+    let texel = |v: &Vertex| {
+        (
+            v.texcoords.x * width as f32,
+            (1.0 - v.texcoords.y) * height as f32,
+        )
+    };
+
+    for TupleTriangle(a, b, c) in triangles {
+        let direction = match triangle_direction(&a, &b, &c, flow, &texel) {
+            Some(direction) => direction,
+            None => continue,
+        };
+
+        let (ta, tb, tc) = (texel(&a), texel(&b), texel(&c));
+        let area = edge(ta, tb, tc);
+        if area == 0.0 {
+            continue;
+        }
+
+        let min_x = ta.0.min(tb.0).min(tc.0).floor().max(0.0) as usize;
+        let max_x = ta.0.max(tb.0).max(tc.0).ceil().min(width as f32) as usize;
+        let min_y = ta.1.min(tb.1).min(tc.1).floor().max(0.0) as usize;
+        let max_y = ta.1.max(tb.1).max(tc.1).ceil().min(height as f32) as usize;
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let center = (x as f32 + 0.5, y as f32 + 0.5);
+                let inside = [
+                    edge(tb, tc, center),
+                    edge(tc, ta, center),
+                    edge(ta, tb, center),
+                ]
+                .iter()
+                .all(|&e| e * area >= 0.0);
+
+                if inside {
+                    directions[y * width + x] = Some(direction);
+                }
+            }
+        }
+    }
+
+    directions
+}
+
+/// Direction of the flow on the plane of the triangle, mapped into texel
+/// space through the texture coordinates of the triangle.
+fn triangle_direction<F>(
+    a: &Vertex,
+    b: &Vertex,
+    c: &Vertex,
+    flow: Vec3,
+    texel: F,
+) -> Option<(f32, f32)>
+where
+    F: Fn(&Vertex) -> (f32, f32),
+{
+    let e1 = b.position - a.position;
+    let e2 = c.position - a.position;
+
+    // Express the flow in the plane of the triangle in terms of its edges
+    let (g11, g12, g22) = (dot(e1, e1), dot(e1, e2), dot(e2, e2));
+    let determinant = g11 * g22 - g12 * g12;
+    if determinant <= 0.0 {
+        return None;
+    }
+    let (f1, f2) = (dot(flow, e1), dot(flow, e2));
+    let s = (g22 * f1 - g12 * f2) / determinant;
+    let t = (g11 * f2 - g12 * f1) / determinant;
+
+    let projected = e1 * s + e2 * t;
+    if dot(projected, projected) < MIN_PROJECTED_FLOW * MIN_PROJECTED_FLOW * dot(flow, flow) {
+        return None;
+    }
+
+    let (ta, tb, tc) = (texel(a), texel(b), texel(c));
+    let x = (tb.0 - ta.0) * s + (tc.0 - ta.0) * t;
+    let y = (tb.1 - ta.1) * s + (tc.1 - ta.1) * t;
+    let length = (x * x + y * y).sqrt();
+    if length > 0.0 && length.is_finite() {
+        Some((x / length, y / length))
+    } else {
+        None
+    }
+}
+
+/// Each texel takes the brightest of the texels upstream of it, faded by
+/// how far along their streak it lies. Streak lengths vary by the texel
+/// they start from, so drips from neighboring texels end unevenly.
+fn smear(guide: &RgbaImage, directions: &[Option<(f32, f32)>], streaks: &Streaks) -> RgbaImage {
+    let (width, height) = guide.dimensions();
+    let max_length = streaks.length * width.max(height) as f32;
+    let mut streaked = guide.clone();
+
+    for y in 0..height {
+        for x in 0..width {
+            let (dx, dy) = match directions[(y * width + x) as usize] {
+                Some(direction) => direction,
+                None => continue,
+            };
+
+            let mut smeared = guide.get_pixel(x, y).data;
+            for step in 1..(max_length.ceil() as u32 + 1) {
+                let source_x = (x as f32 + 0.5 - dx * step as f32).floor();
+                let source_y = (y as f32 + 0.5 - dy * step as f32).floor();
+                if source_x < 0.0
+                    || source_y < 0.0
+                    || source_x >= width as f32
+                    || source_y >= height as f32
+                {
+                    break;
+                }
+                let (source_x, source_y) = (source_x as u32, source_y as u32);
+
+                let jitter = unit_random(&[source_x as u64, source_y as u64]) * streaks.jitter;
+                let length = max_length * (1.0 - jitter);
+                if step as f32 >= length {
+                    continue;
+                }
+
+                let weight = (1.0 - step as f32 / length).powf(streaks.falloff);
+                let source = guide.get_pixel(source_x, source_y).data;
+                for channel in 0..3 {
+                    let faded = (source[channel] as f32 * weight) as u8;
+                    smeared[channel] = smeared[channel].max(faded);
+                }
+            }
+
+            streaked.get_pixel_mut(x, y).data = smeared;
+        }
+    }
+
+    streaked
+}
+
+/// Twice the signed area of the triangle of the given points.
+fn edge(a: (f32, f32), b: (f32, f32), c: (f32, f32)) -> f32 {
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+fn dot(a: Vec3, b: Vec3) -> f32 {
+    a.x * b.x + a.y * b.y + a.z * b.z
+}
+
+fn vec3(arr: [f32; 3]) -> Vec3 {
+    Vec3::new(arr[0], arr[1], arr[2])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geom::Vec2;
+    use tex::Rgba;
+
+    fn vertex(x: f32, y: f32, u: f32, v: f32) -> Vertex {
+        Vertex {
+            position: Vec3::new(x, y, 0.0),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            texcoords: Vec2::new(u, v),
+        }
+    }
+
+    #[test]
+    fn streaks_run_down_a_wall() {
+        // A wall facing z, mapped so that up is up in the texture
+        let wall = vec![
+            TupleTriangle(
+                vertex(0.0, 0.0, 0.0, 0.0),
+                vertex(1.0, 0.0, 1.0, 0.0),
+                vertex(1.0, 1.0, 1.0, 1.0),
+            ),
+            TupleTriangle(
+                vertex(0.0, 0.0, 0.0, 0.0),
+                vertex(1.0, 1.0, 1.0, 1.0),
+                vertex(0.0, 1.0, 0.0, 1.0),
+            ),
+        ];
+        let directions = texel_directions(wall, 8, 8, Vec3::new(0.0, -1.0, 0.0));
+        assert!(directions.iter().all(|&d| d == Some((0.0, 1.0))));
+
+        let mut guide = RgbaImage::from_pixel(
+            8,
+            8,
+            Rgba {
+                data: [0, 0, 0, 255],
+            },
+        );
+        guide.put_pixel(
+            3,
+            1,
+            Rgba {
+                data: [255, 255, 255, 255],
+            },
+        );
+        let streaks = Streaks {
+            length: 0.5,
+            jitter: 0.0,
+            falloff: 1.0,
+            direction: [0.0, -1.0, 0.0],
+        };
+        let streaked = smear(&guide, &directions, &streaks);
+
+        // Fading down from the source, nothing above or beside it
+        let column: Vec<u8> = (0..8).map(|y| streaked.get_pixel(3, y).data[0]).collect();
+        assert_eq!(vec![0, 255, 191, 127, 63, 0, 0, 0], column);
+        assert_eq!(0, streaked.get_pixel(2, 2).data[0]);
+    }
+
+    #[test]
+    fn no_streaks_on_floors() {
+        let floor = vec![TupleTriangle(
+            Vertex {
+                position: Vec3::new(0.0, 0.0, 0.0),
+                normal: Vec3::new(0.0, 1.0, 0.0),
+                texcoords: Vec2::new(0.0, 0.0),
+            },
+            Vertex {
+                position: Vec3::new(1.0, 0.0, 0.0),
+                normal: Vec3::new(0.0, 1.0, 0.0),
+                texcoords: Vec2::new(1.0, 0.0),
+            },
+            Vertex {
+                position: Vec3::new(0.0, 0.0, 1.0),
+                normal: Vec3::new(0.0, 1.0, 0.0),
+                texcoords: Vec2::new(0.0, 1.0),
+            },
+        )];
+        let directions = texel_directions(floor, 4, 4, Vec3::new(0.0, -1.0, 0.0));
+        assert!(directions.iter().all(|d| d.is_none()));
+    }
+}
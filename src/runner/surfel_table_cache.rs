@@ -1,5 +1,6 @@
 use geom::Vertex;
-use scene::Entity;
+use runner::uv_overlap::{overlapping_islands, uv_islands};
+use scene::{Entity, Mesh};
 use sim::SurfelData;
 use spec::SurfelLookup;
 use std::collections::HashMap;
@@ -40,6 +41,9 @@ impl SurfelTableCache {
     /// distance to the world space position represented by the texel and the usize
     /// is an index into the samples of the given surface.
     ///
+    /// Warns if UV islands of the entity overlap in a texture of the given size,
+    /// since the surfels of the overlapping islands then fight over the same texels.
+    ///
     /// # Panics
     /// This function currently panicks for surfel lookup policies different from
     /// `Nearest(usize)`, since this is not yet supported.
@@ -67,6 +71,7 @@ impl SurfelTableCache {
         };
 
         self.surfel_tables.entry(key).or_insert_with(|| {
+            warn_uv_overlaps(&entities[entity_idx], width, height);
             build_surfel_lookup_table(
                 &entities[entity_idx],
                 surface,
@@ -114,3 +119,27 @@ impl SurfelTableCache {
             .unwrap()
     }
 }
+
+fn warn_uv_overlaps(entity: &Entity, width: usize, height: usize) {
+    let triangles: Vec<_> = entity.mesh.triangles().collect();
+    let islands = uv_islands(&triangles);
+    let (overlapping, texels) = overlapping_islands(&triangles, &islands, width, height);
+
+    if !overlapping.is_empty() {
+        let mut overlapping_islands: Vec<usize> =
+            overlapping.iter().flat_map(|&(a, b)| vec![a, b]).collect();
+        overlapping_islands.sort();
+        overlapping_islands.dedup();
+
+        warn!(
+            "{islands} of {total} UV islands of entity {entity} with material {material} overlap in {texels} texels of {width}x{height} textures, surfels on them will fight over the same texels. Set uv_overlaps to separate to give them textures of their own.",
+            islands = overlapping_islands.len(),
+            total = islands.len(),
+            entity = entity.name,
+            material = entity.material.name(),
+            texels = texels,
+            width = width,
+            height = height,
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use geom::{Normal, Position, Vec3, Vertex};
+use sim::SurfelData;
+use std::slice;
+use surf::Surfel;
+
+/// Iterates over the surfels of a simulation, see `SimulationRunner::surfels`.
+pub struct Surfels<'a> {
+    surfels: slice::Iter<'a, Surfel<Vertex, SurfelData>>,
+    substance_names: &'a [String],
+}
+
+/// Read-only view of a single surfel and its substance concentrations.
+pub struct SurfelView<'a> {
+    surfel: &'a Surfel<Vertex, SurfelData>,
+    substance_names: &'a [String],
+}
+
+impl<'a> Surfels<'a> {
+    pub fn new(surfels: &'a [Surfel<Vertex, SurfelData>], substance_names: &'a [String]) -> Self {
+        Surfels {
+            surfels: surfels.iter(),
+            substance_names,
+        }
+    }
+}
+
+impl<'a> Iterator for Surfels<'a> {
+    type Item = SurfelView<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let substance_names = self.substance_names;
+        self.surfels.next().map(|surfel| SurfelView {
+            surfel,
+            substance_names,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.surfels.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for Surfels<'a> {}
+
+impl<'a> SurfelView<'a> {
+    pub fn position(&self) -> Vec3 {
+        self.surfel.position()
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        self.surfel.normal()
+    }
+
+    /// Index of the entity the surfel was sampled from.
+    pub fn entity_idx(&self) -> usize {
+        self.surfel.data().entity_idx
+    }
+
+    /// Concentration of the substance with the given name, or `None` if no
+    /// spec mentions a substance with that name.
+    pub fn concentration(&self, substance: &str) -> Option<f32> {
+        self.substance_names
+            .iter()
+            .position(|s| s == substance)
+            .map(|idx| self.surfel.data().substances[idx])
+    }
+
+    /// Pairs of substance names and concentrations for all substances.
+    pub fn concentrations(&self) -> Vec<(&'a str, f32)> {
+        let surfel = self.surfel;
+        self.substance_names
+            .iter()
+            .map(|s| s.as_str())
+            .zip(surfel.data().substances.iter().cloned())
+            .collect()
+    }
+}
@@ -0,0 +1,156 @@
+use geom::Vertex;
+use runner::{EntityRules, StochasticRules};
+use scene::{Entity, Material};
+use sim::SurfelData;
+use spec::MaterialSwapSpec;
+use std::collections::HashSet;
+use std::rc::Rc;
+use surf;
+
+type Surface = surf::Surface<surf::Surfel<Vertex, SurfelData>>;
+
+/// A material swap from the spec with its surfel spec and material loaded.
+pub struct MaterialSwap {
+    pub spec: MaterialSwapSpec,
+    pub substance_idx: usize,
+    /// Replacement surfel properties, entity and concentrations are ignored.
+    pub surfel: SurfelData,
+    /// Rules of the surfel spec, including those with a probability.
+    pub rules: EntityRules,
+    pub material: Option<Rc<Material>>,
+}
+
+/// Material swaps of a simulation and the entities that have been swapped.
+pub struct MaterialSwaps {
+    swaps: Vec<MaterialSwap>,
+    /// Indexes of swapped entities and the swap they went through.
+    performed: HashSet<(usize, usize)>,
+}
+
+impl MaterialSwaps {
+    pub fn new(swaps: Vec<MaterialSwap>) -> Self {
+        MaterialSwaps {
+            swaps,
+            performed: HashSet::new(),
+        }
+    }
+
+    /// Swaps entities with an average concentration above the threshold of a
+    /// swap that affects their material, replacing their material if the swap
+    /// specifies one. Rules with a probability of swapped entities are
+    /// replaced in the given stochastic rules.
+    ///
+    /// Returns a copy of the surface with the surfels of swapped entities
+    /// replaced, or `None` if no entity has been swapped.
+    pub fn apply(
+        &mut self,
+        entities: &mut [Entity],
+        surface: &Surface,
+        mut stochastic_rules: Option<&mut StochasticRules>,
+    ) -> Option<Surface> {
+        let mut swapped_surface: Option<Surface> = None;
+
+        for (swap_idx, swap) in self.swaps.iter().enumerate() {
+            let averages = average_concentrations(
+                surface.samples.iter().map(|s| s.data()),
+                swap.substance_idx,
+                entities.len(),
+            );
+
+            for (entity_idx, entity) in entities.iter_mut().enumerate() {
+                let exceeded = match averages[entity_idx] {
+                    Some(average) => average > swap.spec.threshold,
+                    None => false,
+                };
+                if !exceeded
+                    || !swap.spec.affects_material(entity.material.name())
+                    || !self.performed.insert((entity_idx, swap_idx))
+                {
+                    continue;
+                }
+
+                info!(
+                    "Average {} on {} exceeded {}, swapping to {}.",
+                    swap.spec.substance, entity.name, swap.spec.threshold, swap.spec.surfels
+                );
+
+                let swapped = swapped_surface.get_or_insert_with(|| surface.clone());
+                for surfel in swapped.samples.iter_mut() {
+                    let data = surfel.data_mut();
+                    if data.entity_idx == entity_idx {
+                        data.delta_straight = swap.surfel.delta_straight;
+                        data.delta_parabolic = swap.surfel.delta_parabolic;
+                        data.delta_flow = swap.surfel.delta_flow;
+                        data.deposition_rates = swap.surfel.deposition_rates.clone();
+                        data.rules = swap.surfel.rules.clone();
+                    }
+                }
+
+                if let Some(ref mut stochastic_rules) = stochastic_rules {
+                    stochastic_rules.replace(entity_idx, swap.rules.clone());
+                }
+                if let Some(ref material) = swap.material {
+                    entity.material = Rc::clone(material);
+                }
+            }
+        }
+
+        swapped_surface
+    }
+}
+
+/// Average concentration of a substance on each entity, or `None` for
+/// entities without surfels.
+fn average_concentrations<'a, I>(
+    surfels: I,
+    substance_idx: usize,
+    entity_count: usize,
+) -> Vec<Option<f32>>
+where
+    I: IntoIterator<Item = &'a SurfelData>,
+{
+    let mut sums = vec![(0.0, 0); entity_count];
+
+    for data in surfels {
+        let sum = &mut sums[data.entity_idx];
+        sum.0 += data.substances[substance_idx];
+        sum.1 += 1;
+    }
+
+    sums.into_iter()
+        .map(|(sum, count)| {
+            if count == 0 {
+                None
+            } else {
+                Some(sum / count as f32)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn surfel(entity_idx: usize, rust: f32) -> SurfelData {
+        SurfelData {
+            entity_idx,
+            delta_straight: 0.0,
+            delta_parabolic: 0.0,
+            delta_flow: 0.0,
+            substances: vec![0.0, rust],
+            deposition_rates: vec![0.0, 0.0],
+            rules: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn averages_by_entity() {
+        let surfels = vec![surfel(0, 0.2), surfel(2, 0.5), surfel(0, 0.4)];
+
+        let averages = average_concentrations(&surfels, 1, 3);
+        assert!((averages[0].unwrap() - 0.3).abs() < 1e-6);
+        assert_eq!(None, averages[1]);
+        assert_eq!(Some(0.5), averages[2]);
+    }
+}
@@ -0,0 +1,183 @@
+use failure::Error;
+use std::collections::HashMap;
+use std::fs::{canonicalize, metadata};
+use std::path::{Path, PathBuf};
+use runner::ktx2::open_texture;
+use std::time::SystemTime;
+use tex::{DynamicImage, GenericImage};
+
+/// Bytes of decoded textures kept for later effects and iterations.
+pub const TEXTURE_CACHE_BYTES: usize = 512 * 1024 * 1024;
+
+/// Decoded textures by canonical path, so that original maps and blend stop
+/// samples are only decoded once instead of every time a blend runs.
+///
+/// When the cached textures exceed the byte budget, the least recently used
+/// ones are dropped. Textures are decoded again if the file was modified
+/// since it was cached.
+///
+/// Textures only needed for one entity, like its original maps, can be
+/// demoted once the entity is done. Demoted textures are dropped as soon as
+/// any other texture is decoded, so that at most one entity's textures stay
+/// decoded besides the shared blend samples.
+pub struct TextureCache {
+    budget: usize,
+    bytes: usize,
+    /// Incremented on every lookup to order entries by last use.
+    clock: u64,
+    textures: HashMap<PathBuf, Entry>,
+}
+
+struct Entry {
+    texture: DynamicImage,
+    modified: Option<SystemTime>,
+    bytes: usize,
+    last_used: u64,
+    demoted: bool,
+}
+
+impl TextureCache {
+    pub fn new(budget: usize) -> Self {
+        TextureCache {
+            budget,
+            bytes: 0,
+            clock: 0,
+            textures: HashMap::new(),
+        }
+    }
+
+    /// Copy of the texture at the given path, decoded only if not cached.
+    pub fn open<P: AsRef<Path>>(&mut self, path: P) -> Result<DynamicImage, Error> {
+        let path = path.as_ref();
+        let key = canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let modified = metadata(&key).and_then(|m| m.modified()).ok();
+        self.clock += 1;
+
+        if let Some(entry) = self.textures.get_mut(&key) {
+            if entry.modified == modified {
+                entry.last_used = self.clock;
+                entry.demoted = false;
+                return Ok(entry.texture.clone());
+            }
+        }
+
+        let texture = open_texture(&key)?;
+        let (width, height) = texture.dimensions();
+        // Upper bound for eight bit textures
+        let bytes = width as usize * height as usize * 4;
+
+        if let Some(stale) = self.textures.remove(&key) {
+            self.bytes -= stale.bytes;
+        }
+        self.drop_demoted();
+        self.evict(bytes);
+        // Textures larger than the whole budget are not cached at all
+        if bytes <= self.budget {
+            self.bytes += bytes;
+            self.textures.insert(
+                key,
+                Entry {
+                    texture: texture.clone(),
+                    modified,
+                    bytes,
+                    last_used: self.clock,
+                    demoted: false,
+                },
+            );
+        }
+
+        Ok(texture)
+    }
+
+    /// Marks the texture at the given path as no longer needed, if cached.
+    /// It can still be reused until another texture is decoded, e.g. in the
+    /// next iteration of a scene with a single entity.
+    pub fn demote<P: AsRef<Path>>(&mut self, path: P) {
+        let path = path.as_ref();
+        let key = canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if let Some(entry) = self.textures.get_mut(&key) {
+            entry.demoted = true;
+        }
+    }
+
+    fn drop_demoted(&mut self) {
+        let bytes = &mut self.bytes;
+        self.textures.retain(|_, entry| {
+            if entry.demoted {
+                *bytes -= entry.bytes;
+            }
+            !entry.demoted
+        });
+    }
+
+    /// Drops least recently used textures until the given amount of bytes
+    /// fits into the budget.
+    fn evict(&mut self, bytes: usize) {
+        while self.bytes + bytes > self.budget {
+            let oldest = match self
+                .textures
+                .iter()
+                .min_by_key(|&(_, entry)| entry.last_used)
+                .map(|(path, _)| path.clone())
+            {
+                Some(oldest) => oldest,
+                None => return,
+            };
+            // Can unwrap since the key was just found
+            self.bytes -= self.textures.remove(&oldest).unwrap().bytes;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, remove_dir_all};
+    use tex::{Rgba, RgbaImage};
+
+    #[test]
+    fn least_recently_used_evicted() {
+        let dir = temp_dir().join("aitios-texture-cache-test");
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        let paths: Vec<PathBuf> = (0..3).map(|i| dir.join(format!("{}.png", i))).collect();
+        for path in paths.iter() {
+            RgbaImage::from_pixel(2, 2, Rgba { data: [255; 4] })
+                .save(path)
+                .unwrap();
+        }
+
+        // Room for two of the textures
+        let mut cache = TextureCache::new(2 * 2 * 4 * 2);
+        cache.open(&paths[0]).unwrap();
+        cache.open(&paths[1]).unwrap();
+        // Relative path with the same canonical path is a hit
+        cache
+            .open(dir.join("..").join(dir.file_name().unwrap()).join("0.png"))
+            .unwrap();
+        assert_eq!(2, cache.textures.len());
+
+        cache.open(&paths[2]).unwrap();
+        assert_eq!(2, cache.textures.len());
+        assert_eq!(32, cache.bytes);
+        let cached = |cache: &TextureCache, idx: usize| {
+            cache
+                .textures
+                .contains_key(&canonicalize(&paths[idx]).unwrap())
+        };
+        assert!(cached(&cache, 0) && !cached(&cache, 1) && cached(&cache, 2));
+
+        // Reused while nothing else is decoded, dropped once something is
+        cache.demote(&paths[0]);
+        cache.demote(&paths[2]);
+        cache.open(&paths[2]).unwrap();
+        assert!(cached(&cache, 0) && cached(&cache, 2));
+        cache.open(&paths[1]).unwrap();
+        assert!(!cached(&cache, 0) && cached(&cache, 1) && cached(&cache, 2));
+        assert_eq!(32, cache.bytes);
+
+        assert!(cache.open(dir.join("missing.png")).is_err());
+        remove_dir_all(&dir).unwrap();
+    }
+}
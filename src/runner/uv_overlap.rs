@@ -0,0 +1,223 @@
+use geom::{TupleTriangle, Vec2, Vertex};
+use std::collections::{BTreeSet, HashMap};
+
+/// Texel centers with a barycentric coordinate below this are not considered
+/// covered by a triangle, so islands that only touch do not overlap.
+const EDGE_TOLERANCE: f32 = 1e-4;
+
+/// Groups the given triangles into UV islands, which are sets of triangles
+/// connected by vertices with the same position and texture coordinates, so
+/// that copies of the same texture coordinates elsewhere in the mesh form
+/// islands of their own. Each island is a list of indexes
+/// into the triangles, in order, and the islands are ordered by their first
+/// triangle.
+pub fn uv_islands(triangles: &[TupleTriangle<Vertex>]) -> Vec<Vec<usize>> {
+    let mut parents: Vec<usize> = (0..triangles.len()).collect();
+    let mut first_with_vertex = HashMap::new();
+
+    for (idx, &TupleTriangle(ref a, ref b, ref c)) in triangles.iter().enumerate() {
+        for vertex in [a, b, c].iter() {
+            let key = [
+                vertex.position.x.to_bits(),
+                vertex.position.y.to_bits(),
+                vertex.position.z.to_bits(),
+                vertex.texcoords.x.to_bits(),
+                vertex.texcoords.y.to_bits(),
+            ];
+            let other = *first_with_vertex.entry(key).or_insert(idx);
+            let (root, other_root) = (root(&mut parents, idx), root(&mut parents, other));
+            // Lower index as root keeps islands ordered by first triangle
+            if root < other_root {
+                parents[other_root] = root;
+            } else {
+                parents[root] = other_root;
+            }
+        }
+    }
+
+    let mut islands: Vec<Vec<usize>> = Vec::new();
+    let mut island_by_root = HashMap::new();
+    for idx in 0..triangles.len() {
+        let root = root(&mut parents, idx);
+        let island_idx = *island_by_root.entry(root).or_insert(islands.len());
+        if island_idx == islands.len() {
+            islands.push(Vec::new());
+        }
+        islands[island_idx].push(idx);
+    }
+    islands
+}
+
+/// Pairs of islands that cover at least one common texel of a texture with
+/// the given size, each pair ordered and the pairs sorted, together with the
+/// amount of texels that are covered by more than one island.
+///
+/// Texture coordinates outside of the unit square are clamped, wrapping
+/// islands may overlap without being reported.
+pub fn overlapping_islands(
+    triangles: &[TupleTriangle<Vertex>],
+    islands: &[Vec<usize>],
+    width: usize,
+    height: usize,
+) -> (Vec<(usize, usize)>, usize) {
+    // First island covering each texel, more only for overlapping texels
+    let mut first_cover = vec![None; width * height];
+    let mut more_covers: HashMap<usize, Vec<usize>> = HashMap::new();
+
+    for (island_idx, island) in islands.iter().enumerate() {
+        for &triangle_idx in island.iter() {
+            let TupleTriangle(ref a, ref b, ref c) = triangles[triangle_idx];
+            let scale = |t: Vec2| (t.x * width as f32, t.y * height as f32);
+            let corners = [scale(a.texcoords), scale(b.texcoords), scale(c.texcoords)];
+
+            for texel in covered_texels(corners, width, height) {
+                match first_cover[texel] {
+                    None => first_cover[texel] = Some(island_idx),
+                    Some(first) if first == island_idx => (),
+                    Some(_) => {
+                        let covers = more_covers.entry(texel).or_insert_with(Vec::new);
+                        if !covers.contains(&island_idx) {
+                            covers.push(island_idx);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let mut pairs = BTreeSet::new();
+    for (texel, covers) in more_covers.iter() {
+        let all: Vec<usize> = first_cover[*texel]
+            .into_iter()
+            .chain(covers.iter().cloned())
+            .collect();
+        for (i, &island) in all.iter().enumerate() {
+            for &other in all[i + 1..].iter() {
+                pairs.insert((island.min(other), island.max(other)));
+            }
+        }
+    }
+
+    (pairs.into_iter().collect(), more_covers.len())
+}
+
+/// Assigns each of the given amount of islands to a group, so that no two
+/// overlapping islands share a group. Islands go into the first group that
+/// does not overlap them, so islands without overlaps stay in group 0.
+pub fn separated_islands(island_count: usize, overlapping: &[(usize, usize)]) -> Vec<usize> {
+    let mut groups: Vec<usize> = Vec::with_capacity(island_count);
+    for island in 0..island_count {
+        // Pairs are ordered, so the other island has been grouped already
+        let taken: BTreeSet<usize> = overlapping
+            .iter()
+            .filter(|&&(_, b)| b == island)
+            .map(|&(a, _)| groups[a])
+            .collect();
+        groups.push((0..).find(|g| !taken.contains(g)).unwrap());
+    }
+    groups
+}
+
+fn root(parents: &mut Vec<usize>, idx: usize) -> usize {
+    let mut root = idx;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    // Shorten the path for later lookups
+    let mut idx = idx;
+    while parents[idx] != root {
+        let next = parents[idx];
+        parents[idx] = root;
+        idx = next;
+    }
+    root
+}
+
+/// Indexes of the texels whose centers lie inside the triangle with the given
+/// corners in texel units.
+fn covered_texels(corners: [(f32, f32); 3], width: usize, height: usize) -> Vec<usize> {
+    let (a, b, c) = (corners[0], corners[1], corners[2]);
+    let area = edge(a, b, c);
+    if area == 0.0 {
+        return Vec::new();
+    }
+
+    let texel_range = |min: f32, max: f32, size: usize| {
+        let clamp = |v: f32| v.max(0.0).min(size as f32) as usize;
+        clamp(min.floor())..clamp(max.ceil())
+    };
+    let xs = texel_range(a.0.min(b.0).min(c.0), a.0.max(b.0).max(c.0), width);
+    let ys = texel_range(a.1.min(b.1).min(c.1), a.1.max(b.1).max(c.1), height);
+
+    let mut covered = Vec::new();
+    for y in ys {
+        for x in xs.clone() {
+            let center = (x as f32 + 0.5, y as f32 + 0.5);
+            // Barycentric coordinates, positive inside for either winding
+            let inside = [edge(b, c, center), edge(c, a, center), edge(a, b, center)]
+                .iter()
+                .all(|&e| e / area > EDGE_TOLERANCE);
+            if inside {
+                covered.push(y * width + x);
+            }
+        }
+    }
+    covered
+}
+
+/// Twice the signed area of the triangle `a`, `b`, `p`.
+fn edge(a: (f32, f32), b: (f32, f32), p: (f32, f32)) -> f32 {
+    (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geom::Vec3;
+
+    fn triangle(offset: f32, texcoords: [(f32, f32); 3]) -> TupleTriangle<Vertex> {
+        let vertex = |x: f32, (u, v): (f32, f32)| Vertex {
+            position: Vec3::new(x + offset, u, v),
+            normal: Vec3::new(1.0, 0.0, 0.0),
+            texcoords: Vec2::new(u, v),
+        };
+        TupleTriangle(
+            vertex(0.0, texcoords[0]),
+            vertex(0.0, texcoords[1]),
+            vertex(0.0, texcoords[2]),
+        )
+    }
+
+    #[test]
+    fn mirrored_islands_overlap() {
+        let lower = [(0.0, 0.0), (0.5, 0.0), (0.5, 0.5)];
+        let upper = [(0.0, 0.0), (0.5, 0.5), (0.0, 0.5)];
+        let triangles = vec![
+            // Quad in the lower left quarter
+            triangle(0.0, lower),
+            triangle(0.0, upper),
+            // Copy of the quad elsewhere with the same texture coordinates
+            triangle(5.0, lower),
+            triangle(5.0, upper),
+            // Island elsewhere in the upper right quarter, touching the quad
+            triangle(10.0, [(0.5, 0.5), (1.0, 0.5), (1.0, 1.0)]),
+        ];
+
+        let islands = uv_islands(&triangles);
+        assert_eq!(vec![vec![0, 1], vec![2, 3], vec![4]], islands);
+
+        let (overlapping, texels) = overlapping_islands(&triangles, &islands, 8, 8);
+        assert_eq!(vec![(0, 1)], overlapping);
+        // Texel centers on the diagonal of the quad are covered by neither half
+        assert_eq!(16 - 4, texels);
+
+        assert_eq!(
+            vec![0, 1, 0],
+            separated_islands(islands.len(), &overlapping)
+        );
+        assert_eq!(
+            vec![0, 1, 2],
+            separated_islands(3, &[(0, 1), (0, 2), (1, 2)])
+        );
+    }
+}
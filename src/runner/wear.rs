@@ -0,0 +1,156 @@
+use geom::{Normal, Position, Vec3};
+use spec::{WearKind, WearMask};
+use std::collections::HashMap;
+
+/// Scales the mean tangent plane offset of the neighbors, so that surfels
+/// right on a right-angled edge or in a right-angled corner get close to full
+/// wear.
+const WEAR_GAIN: f32 = 3.0;
+
+/// Wear of each of the given surfels in `[0, 1]`, from how far the surfels
+/// within the radius of the mask fall below the tangent plane of the surfel
+/// for edges, or rise above it for cavities. Surfels without neighbors on
+/// both sides of the tangent plane, e.g. on flat ground, get no wear.
+pub fn wear_mask<S: Position + Normal>(samples: &[S], mask: &WearMask) -> Vec<f32> {
+    let grid = Grid::new(samples, mask.radius);
+    let sign = match mask.kind {
+        WearKind::Edges => -1.0,
+        WearKind::Cavities => 1.0,
+    };
+
+    samples
+        .iter()
+        .enumerate()
+        .map(|(idx, sample)| {
+            let position = sample.position();
+            let normal = sample.normal();
+
+            let mut offset_sum = 0.0;
+            let mut neighbor_count = 0;
+            for neighbor_idx in grid.within(samples, position, mask.radius) {
+                if neighbor_idx == idx {
+                    continue;
+                }
+
+                let delta = samples[neighbor_idx].position() - position;
+                let distance = dot(delta, delta).sqrt();
+                if distance > 0.0 {
+                    offset_sum += dot(normal, delta) / distance;
+                    neighbor_count += 1;
+                }
+            }
+
+            if neighbor_count == 0 {
+                0.0
+            } else {
+                let offset = offset_sum / neighbor_count as f32;
+                (sign * offset * WEAR_GAIN).max(0.0).min(1.0)
+            }
+        })
+        .collect()
+}
+
+/// Indexes of samples by cubic cells with the edge length of the search
+/// radius, so only the cells around a position need to be searched.
+struct Grid {
+    cell_size: f32,
+    cells: HashMap<(i64, i64, i64), Vec<usize>>,
+}
+
+impl Grid {
+    fn new<S: Position>(samples: &[S], cell_size: f32) -> Self {
+        let mut grid = Grid {
+            cell_size,
+            cells: HashMap::new(),
+        };
+        for (idx, sample) in samples.iter().enumerate() {
+            let cell = grid.cell(sample.position());
+            grid.cells.entry(cell).or_insert_with(Vec::new).push(idx);
+        }
+        grid
+    }
+
+    /// Indexes of the samples within the radius around the position, which
+    /// must not exceed the cell size.
+    fn within<'a, S: Position>(
+        &'a self,
+        samples: &'a [S],
+        position: Vec3,
+        radius: f32,
+    ) -> impl Iterator<Item = usize> + 'a {
+        let (x, y, z) = self.cell(position);
+        let neighborhood = (-1..2).flat_map(move |dx| {
+            (-1..2).flat_map(move |dy| (-1..2).map(move |dz| (x + dx, y + dy, z + dz)))
+        });
+
+        neighborhood
+            .filter_map(move |cell| self.cells.get(&cell))
+            .flat_map(|indexes| indexes.iter().cloned())
+            .filter(move |&idx| {
+                let delta = samples[idx].position() - position;
+                dot(delta, delta) <= radius * radius
+            })
+    }
+
+    fn cell(&self, position: Vec3) -> (i64, i64, i64) {
+        (
+            (position.x / self.cell_size).floor() as i64,
+            (position.y / self.cell_size).floor() as i64,
+            (position.z / self.cell_size).floor() as i64,
+        )
+    }
+}
+
+fn dot(a: Vec3, b: Vec3) -> f32 {
+    a.x * b.x + a.y * b.y + a.z * b.z
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geom::{Vec2, Vertex};
+
+    /// Samples on the top and the side of a box with an edge along z at the
+    /// origin, with the top extending towards negative x and the side down.
+    fn box_edge() -> Vec<Vertex> {
+        let mut samples = Vec::new();
+        for i in 0..20 {
+            for k in -2..3 {
+                let along = i as f32 * 0.1;
+                let z = k as f32 * 0.1;
+                samples.push(Vertex {
+                    position: Vec3::new(-along, 0.0, z),
+                    normal: Vec3::new(0.0, 1.0, 0.0),
+                    texcoords: Vec2::new(0.0, 0.0),
+                });
+                samples.push(Vertex {
+                    position: Vec3::new(0.0, -along - 0.1, z),
+                    normal: Vec3::new(1.0, 0.0, 0.0),
+                    texcoords: Vec2::new(0.0, 0.0),
+                });
+            }
+        }
+        samples
+    }
+
+    #[test]
+    fn wear_on_edges_only() {
+        let samples = box_edge();
+        let edges = WearMask {
+            radius: 0.25,
+            kind: WearKind::Edges,
+        };
+        let cavities = WearMask {
+            kind: WearKind::Cavities,
+            ..edges
+        };
+
+        let edge_wear = wear_mask(&samples, &edges);
+        let cavity_wear = wear_mask(&samples, &cavities);
+
+        // First sample is right on the edge, the last ones far away from it
+        assert!(edge_wear[0] > 0.5, "Edge wear {} too low", edge_wear[0]);
+        assert_eq!(0.0, edge_wear[samples.len() - 1]);
+        assert!(cavity_wear.iter().all(|&w| w == 0.0));
+    }
+}
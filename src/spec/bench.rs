@@ -1,9 +1,36 @@
 use std::path::PathBuf;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct BenchSpec {
     pub iterations: Option<PathBuf>,
     pub tracing: Option<PathBuf>,
     pub synthesis: Option<PathBuf>,
     pub setup: Option<PathBuf>,
+    /// Mean, median and 95th percentile of iteration, tracing and synthesis
+    /// durations, written at the end of the run.
+    pub summary: Option<PathBuf>,
+    /// Writes a header and the iteration, phase, entity and effect next to
+    /// each duration, with extra rows for each effect and for each entity of
+    /// layer effects in the synthesis benchmark. Defaults to bare durations,
+    /// one per line.
+    pub labeled: Option<bool>,
+    /// Format of benchmark files, defaults to CSV.
+    pub format: Option<BenchFormat>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BenchFormat {
+    #[serde(rename = "csv")]
+    Csv,
+    /// One JSON object per line with the same fields as labeled CSV rows.
+    /// Always labeled.
+    #[serde(rename = "ndjson")]
+    Ndjson,
+}
+
+impl BenchSpec {
+    /// Checks whether benchmarks include iteration, phase, entity and effect.
+    pub fn is_labeled(&self) -> bool {
+        self.format == Some(BenchFormat::Ndjson) || self.labeled == Some(true)
+    }
 }
@@ -0,0 +1,20 @@
+/// What to do before running if the outputs of a run may not fit into the
+/// free space on the volumes they are written to.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DiskSpaceCheck {
+    /// Refuse to run.
+    #[serde(rename = "fail")]
+    Fail,
+    /// Only warn and run anyway, e.g. if space is freed up while running.
+    #[serde(rename = "warn")]
+    Warn,
+    /// Skip estimating the size of the outputs.
+    #[serde(rename = "off")]
+    Off,
+}
+
+impl Default for DiskSpaceCheck {
+    fn default() -> Self {
+        DiskSpaceCheck::Fail
+    }
+}
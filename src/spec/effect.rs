@@ -1,6 +1,7 @@
+use spec::EntityFilter;
 use std::path::PathBuf;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum EffectSpec {
     #[serde(rename = "density")]
     Density {
@@ -15,10 +16,16 @@ pub enum EffectSpec {
         tex_pattern: String,
         obj_pattern: Option<String>,
         mtl_pattern: Option<String>,
+        /// If specified, width and height only set the aspect ratio and the
+        /// size of each entity's textures follows its surface area instead.
+        adaptive_resolution: Option<AdaptiveResolution>,
     },
     /// Writes the scene with the effects before the declaration to the
     /// given paths. This should usually the last step, but exporting
     /// before can be useful for debugging.
+    ///
+    /// Either pattern can be left out to only write the OBJ geometry or
+    /// only the MTL materials.
     #[serde(rename = "export")]
     Export {
         obj_pattern: Option<String>,
@@ -42,24 +49,420 @@ pub enum EffectSpec {
     Layer {
         /// A list of material names where on each entity that uses it, a new material will be derived to replace it.
         materials: Vec<String>,
+        /// If specified, only entities whose names pass the filter are
+        /// affected, in addition to the material names.
+        entities: Option<EntityFilter>,
         /// The name of the substance that defines the texel concentration.
         substance: String,
         #[serde(default = "default_surfel_lookup")]
         surfel_lookup: SurfelLookup,
         #[serde(default = "default_bleed")]
         island_bleed: usize,
+        /// If specified, the concentration is multiplied with a wear mask
+        /// before blending, so weathering signs only show on edges or in
+        /// cavities.
+        wear: Option<WearMask>,
         // REVIEW should normal and displacement be usable together? maybe the normal map should be derived from the displacement map to ensure consistency
         normal: Option<Blend>,
         displacement: Option<Blend>,
         albedo: Option<Blend>,
         metallicity: Option<Blend>,
         roughness: Option<Blend>,
+        /// If specified, the synthesized maps of each entity are also bundled
+        /// into a multi-layer EXR at this path, together with the guide that
+        /// they were blended with, e.g. `albedo.R` or `guide.Y`.
+        ///
+        /// {entity} {iteration} {id} {substance}
+        exr_pattern: Option<String>,
     },
+    /// Writes the surfels as an OBJ point cloud, or as a surfel snapshot for
+    /// the `effects` subcommand if `obj_pattern` ends in `.bin`.
     #[serde(rename = "dump_surfels")]
     DumpSurfels { obj_pattern: String },
+    /// Bakes a mask of the edges or cavities of each entity into a texture,
+    /// white where the surfels within `radius` curve away from a surfel the
+    /// most. Edges chip and cavities collect grime first, which substance
+    /// concentrations alone do not express.
+    #[serde(rename = "wear")]
+    Wear {
+        width: usize,
+        height: usize,
+        #[serde(flatten)]
+        mask: WearMask,
+        #[serde(default = "default_surfel_lookup")]
+        surfel_lookup: SurfelLookup,
+        #[serde(default = "default_bleed")]
+        island_bleed: usize,
+        /// {entity} {iteration} {id}
+        tex_pattern: String,
+    },
+}
+
+impl EffectSpec {
+    /// Makes a density effect with default surfel lookup and island bleed.
+    pub fn density<S: Into<String>>(
+        width: usize,
+        height: usize,
+        tex_pattern: S,
+        obj_pattern: Option<String>,
+        mtl_pattern: Option<String>,
+    ) -> Self {
+        EffectSpec::Density {
+            width,
+            height,
+            surfel_lookup: default_surfel_lookup(),
+            island_bleed: default_bleed(),
+            tex_pattern: tex_pattern.into(),
+            obj_pattern,
+            mtl_pattern,
+            adaptive_resolution: None,
+        }
+    }
+
+    pub fn export(obj_pattern: Option<String>, mtl_pattern: Option<String>) -> Self {
+        EffectSpec::Export {
+            obj_pattern,
+            mtl_pattern,
+        }
+    }
+
+    /// Makes a layer effect without any maps, which can be added with
+    /// `normal`, `displacement`, `albedo`, `metallicity` and `roughness`.
+    pub fn layer<S: Into<String>>(materials: Vec<String>, substance: S) -> Self {
+        EffectSpec::Layer {
+            materials,
+            entities: None,
+            substance: substance.into(),
+            surfel_lookup: default_surfel_lookup(),
+            island_bleed: default_bleed(),
+            wear: None,
+            normal: None,
+            displacement: None,
+            albedo: None,
+            metallicity: None,
+            roughness: None,
+            exr_pattern: None,
+        }
+    }
+
+    pub fn dump_surfels<S: Into<String>>(obj_pattern: S) -> Self {
+        EffectSpec::DumpSurfels {
+            obj_pattern: obj_pattern.into(),
+        }
+    }
+
+    /// Makes a wear effect with default surfel lookup and island bleed.
+    pub fn wear<S: Into<String>>(
+        width: usize,
+        height: usize,
+        mask: WearMask,
+        tex_pattern: S,
+    ) -> Self {
+        EffectSpec::Wear {
+            width,
+            height,
+            mask,
+            surfel_lookup: default_surfel_lookup(),
+            island_bleed: default_bleed(),
+            tex_pattern: tex_pattern.into(),
+        }
+    }
+
+    /// Sets the wear mask of a layer effect.
+    ///
+    /// Panics if this is not a layer effect.
+    pub fn worn(mut self, mask: WearMask) -> Self {
+        match self {
+            EffectSpec::Layer { ref mut wear, .. } => *wear = Some(mask),
+            ref other => panic!("Tried to set wear mask on {} effect", other.kind()),
+        }
+        self
+    }
+
+    /// Restricts a layer effect to entities whose names pass the filter.
+    ///
+    /// Panics if this is not a layer effect.
+    pub fn entity_filter(mut self, filter: EntityFilter) -> Self {
+        match self {
+            EffectSpec::Layer {
+                ref mut entities, ..
+            } => *entities = Some(filter),
+            ref other => panic!("Tried to set entity filter on {} effect", other.kind()),
+        }
+        self
+    }
+
+    /// Scales the textures of a density effect with the surface area of each
+    /// entity.
+    ///
+    /// Panics if this is not a density effect.
+    pub fn adaptive(mut self, resolution: AdaptiveResolution) -> Self {
+        match self {
+            EffectSpec::Density {
+                ref mut adaptive_resolution,
+                ..
+            } => *adaptive_resolution = Some(resolution),
+            ref other => panic!("Tried to set adaptive resolution on {} effect", other.kind()),
+        }
+        self
+    }
+
+    /// Sets the normal map blend of a layer effect.
+    ///
+    /// Panics if this is not a layer effect, as do the other map setters.
+    pub fn normal(self, blend: Blend) -> Self {
+        self.layer_map("normal", blend)
+    }
+
+    pub fn displacement(self, blend: Blend) -> Self {
+        self.layer_map("displacement", blend)
+    }
+
+    pub fn albedo(self, blend: Blend) -> Self {
+        self.layer_map("albedo", blend)
+    }
+
+    pub fn metallicity(self, blend: Blend) -> Self {
+        self.layer_map("metallicity", blend)
+    }
+
+    pub fn roughness(self, blend: Blend) -> Self {
+        self.layer_map("roughness", blend)
+    }
+
+    /// Sets the path pattern of the multi-layer EXR of a layer effect.
+    ///
+    /// Panics if this is not a layer effect.
+    pub fn exr<S: Into<String>>(mut self, pattern: S) -> Self {
+        match self {
+            EffectSpec::Layer {
+                ref mut exr_pattern,
+                ..
+            } => *exr_pattern = Some(pattern.into()),
+            ref other => panic!("Tried to set EXR pattern on {} effect", other.kind()),
+        }
+        self
+    }
+
+    fn layer_map(mut self, map: &str, blend: Blend) -> Self {
+        match self {
+            EffectSpec::Layer {
+                ref mut normal,
+                ref mut displacement,
+                ref mut albedo,
+                ref mut metallicity,
+                ref mut roughness,
+                ..
+            } => {
+                let target = match map {
+                    "normal" => normal,
+                    "displacement" => displacement,
+                    "albedo" => albedo,
+                    "metallicity" => metallicity,
+                    "roughness" => roughness,
+                    _ => unreachable!(),
+                };
+                *target = Some(blend);
+            }
+            ref other => panic!("Tried to set {} map on {} effect", map, other.kind()),
+        }
+        self
+    }
+
+    /// Short lowercase name of the effect type as used in the YAML spec,
+    /// e.g. `"layer"`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            &EffectSpec::Density { .. } => "density",
+            &EffectSpec::Export { .. } => "export",
+            &EffectSpec::Layer { .. } => "layer",
+            &EffectSpec::DumpSurfels { .. } => "dump_surfels",
+            &EffectSpec::Wear { .. } => "wear",
+        }
+    }
+
+    /// Checks whether entities with the given material name are affected by this effect.
+    ///
+    /// Layer effects only affect their listed materials, where the underscore material
+    /// or an empty list admits all materials. Density, export and wear effects affect
+    /// all entities. Surfel dumps do not operate on entities and never affect them.
+    pub fn affects_material(&self, material_name: &str) -> bool {
+        match self {
+            &EffectSpec::Layer { ref materials, .. } => {
+                materials.is_empty() || materials.iter().any(|m| m == "_" || m == material_name)
+            }
+            &EffectSpec::Density { .. } | &EffectSpec::Export { .. } | &EffectSpec::Wear { .. } => {
+                true
+            }
+            &EffectSpec::DumpSurfels { .. } => false,
+        }
+    }
+
+    /// Checks whether the entity with the given name and material name is affected by this
+    /// effect, which additionally requires layer effects with an entity filter to admit the
+    /// entity name.
+    pub fn affects_entity(&self, entity_name: &str, material_name: &str) -> bool {
+        let admitted = match self {
+            &EffectSpec::Layer {
+                entities: Some(ref filter),
+                ..
+            } => filter.admits(entity_name),
+            _ => true,
+        };
+        admitted && self.affects_material(material_name)
+    }
+
+    /// The entity filter of a layer effect, if any.
+    pub fn entities(&self) -> Option<&EntityFilter> {
+        match self {
+            &EffectSpec::Layer {
+                entities: Some(ref filter),
+                ..
+            } => Some(filter),
+            _ => None,
+        }
+    }
+
+    /// Patterns of all files written by this effect.
+    pub fn output_patterns(&self) -> Vec<&str> {
+        match self {
+            &EffectSpec::Density {
+                ref tex_pattern,
+                ref obj_pattern,
+                ref mtl_pattern,
+                ..
+            } => Some(tex_pattern)
+                .into_iter()
+                .chain(obj_pattern.as_ref())
+                .chain(mtl_pattern.as_ref())
+                .map(|p| p.as_str())
+                .collect(),
+            &EffectSpec::Export {
+                ref obj_pattern,
+                ref mtl_pattern,
+            } => obj_pattern
+                .iter()
+                .chain(mtl_pattern.iter())
+                .map(|p| p.as_str())
+                .collect(),
+            &EffectSpec::Layer {
+                ref normal,
+                ref displacement,
+                ref albedo,
+                ref metallicity,
+                ref roughness,
+                ref exr_pattern,
+                ..
+            } => [normal, displacement, albedo, metallicity, roughness]
+                .iter()
+                .filter_map(|b| b.as_ref())
+                .map(|b| b.tex_pattern.as_str())
+                .chain(exr_pattern.as_ref().map(|p| p.as_str()))
+                .collect(),
+            &EffectSpec::DumpSurfels { ref obj_pattern } => vec![obj_pattern.as_str()],
+            &EffectSpec::Wear {
+                ref tex_pattern, ..
+            } => vec![tex_pattern.as_str()],
+        }
+    }
+
+    /// Mutable access to the patterns of all files written by this effect,
+    /// in the same order as `output_patterns`.
+    pub fn output_patterns_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            &mut EffectSpec::Density {
+                ref mut tex_pattern,
+                ref mut obj_pattern,
+                ref mut mtl_pattern,
+                ..
+            } => Some(tex_pattern)
+                .into_iter()
+                .chain(obj_pattern.as_mut())
+                .chain(mtl_pattern.as_mut())
+                .collect(),
+            &mut EffectSpec::Export {
+                ref mut obj_pattern,
+                ref mut mtl_pattern,
+            } => obj_pattern.iter_mut().chain(mtl_pattern.iter_mut()).collect(),
+            &mut EffectSpec::Layer {
+                ref mut normal,
+                ref mut displacement,
+                ref mut albedo,
+                ref mut metallicity,
+                ref mut roughness,
+                ref mut exr_pattern,
+                ..
+            } => vec![normal, displacement, albedo, metallicity, roughness]
+                .into_iter()
+                .filter_map(|b| b.as_mut())
+                .map(|b| &mut b.tex_pattern)
+                .chain(exr_pattern.as_mut())
+                .collect(),
+            &mut EffectSpec::DumpSurfels {
+                ref mut obj_pattern,
+            } => vec![obj_pattern],
+            &mut EffectSpec::Wear {
+                ref mut tex_pattern,
+                ..
+            } => vec![tex_pattern],
+        }
+    }
+
+    /// Limits the textures synthesized by this effect to at most `max` texels
+    /// along each side, keeping the aspect ratio. Blends that use the size of
+    /// the original map or samples get `max` by `max` texels instead.
+    pub fn limit_resolution(&mut self, max: usize) {
+        match self {
+            &mut EffectSpec::Density {
+                ref mut width,
+                ref mut height,
+                ref mut adaptive_resolution,
+                ..
+            } => {
+                limit_size(width, height, max);
+                if let &mut Some(ref mut adaptive) = adaptive_resolution {
+                    adaptive.max = adaptive.max.min(max);
+                    adaptive.min = adaptive.min.min(adaptive.max);
+                }
+            }
+            &mut EffectSpec::Wear {
+                ref mut width,
+                ref mut height,
+                ..
+            } => limit_size(width, height, max),
+            &mut EffectSpec::Layer {
+                ref mut normal,
+                ref mut displacement,
+                ref mut albedo,
+                ref mut metallicity,
+                ref mut roughness,
+                ..
+            } => {
+                let blends = vec![normal, displacement, albedo, metallicity, roughness];
+                for blend in blends.into_iter().filter_map(|b| b.as_mut()) {
+                    let mut width = blend.width.or(blend.height).unwrap_or(max);
+                    let mut height = blend.height.or(blend.width).unwrap_or(max);
+                    limit_size(&mut width, &mut height, max);
+                    blend.width = Some(width);
+                    blend.height = Some(height);
+                }
+            }
+            &mut EffectSpec::Export { .. } | &mut EffectSpec::DumpSurfels { .. } => (),
+        }
+    }
+}
+
+/// Scales the given size down so neither side exceeds `max`, keeping at
+/// least one texel on each side.
+fn limit_size(width: &mut usize, height: &mut usize, max: usize) {
+    let longest = (*width).max(*height);
+    if longest > max {
+        *width = (*width * max / longest).max(1);
+        *height = (*height * max / longest).max(1);
+    }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Blend {
     /// If specified, use this output texture width instead
     /// of the width of the original map from the material or
@@ -85,21 +488,244 @@ pub struct Blend {
     /// Note that texture samples may also be partly transparent.
     #[serde(default = "default_influence")]
     pub influence: f32,
+    /// If specified, the guide is smeared along the flow direction before
+    /// blending, e.g. for drip marks below windowsills.
+    pub streaks: Option<Streaks>,
+    /// How the samples and the original map encode their values, sRGB for
+    /// albedo and linear for the other maps if unspecified. sRGB maps are
+    /// blended in linear light and encoded as sRGB again when written.
+    pub color_space: Option<ColorSpace>,
+    /// Block compression of the map if `tex_pattern` ends in `.ktx2`,
+    /// uncompressed if unspecified.
+    pub compression: Option<Compression>,
     /// {entity} {iteration} {id} {substance}
     pub tex_pattern: String,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+impl Blend {
+    /// Makes a blend without stops and with full influence that uses the
+    /// dimensions of the original map or the largest sample.
+    pub fn new<S: Into<String>>(tex_pattern: S) -> Self {
+        Blend {
+            width: None,
+            height: None,
+            stops: Vec::new(),
+            influence: default_influence(),
+            streaks: None,
+            color_space: None,
+            compression: None,
+            tex_pattern: tex_pattern.into(),
+        }
+    }
+
+    /// Adds a stop with the given sample, or with the original map if `None`.
+    pub fn stop(mut self, cenith: f32, sample: Option<PathBuf>) -> Self {
+        self.stops.push(Stop {
+            sample,
+            sha256: None,
+            cenith,
+        });
+        self
+    }
+
+    pub fn influence(mut self, influence: f32) -> Self {
+        self.influence = influence;
+        self
+    }
+
+    pub fn size(mut self, width: usize, height: usize) -> Self {
+        self.width = Some(width);
+        self.height = Some(height);
+        self
+    }
+
+    pub fn streaks(mut self, streaks: Streaks) -> Self {
+        self.streaks = Some(streaks);
+        self
+    }
+
+    pub fn color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = Some(color_space);
+        self
+    }
+
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Color space of the maps of the given layer channel, e.g. `albedo`,
+    /// unless the blend specifies one.
+    pub fn color_space_of(&self, channel: &str) -> ColorSpace {
+        self.color_space.unwrap_or_else(|| match channel {
+            "albedo" => ColorSpace::Srgb,
+            _ => ColorSpace::Linear,
+        })
+    }
+}
+
+/// Encoding of the values in a texture.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ColorSpace {
+    /// Gamma encoded color, as painted or photographed albedo usually is.
+    #[serde(rename = "srgb")]
+    Srgb,
+    /// Values proportional to what they express, e.g. roughness or height.
+    #[serde(rename = "linear")]
+    Linear,
+}
+
+/// Direction of the green channel of normal maps.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum NormalConvention {
+    /// Green points up in texture space, towards increasing V.
+    #[serde(rename = "opengl")]
+    OpenGl,
+    /// Green points down in texture space.
+    #[serde(rename = "directx")]
+    DirectX,
+}
+
+impl Default for NormalConvention {
+    fn default() -> Self {
+        NormalConvention::OpenGl
+    }
+}
+
+/// Block compression of textures written as KTX2, which GPUs sample without
+/// decompressing them first.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Compression {
+    /// Eight bits for each channel.
+    #[serde(rename = "none")]
+    None,
+    /// BC1 with four bits per texel, without alpha.
+    #[serde(rename = "bc1")]
+    Bc1,
+    /// BC3 with eight bits per texel, with alpha.
+    #[serde(rename = "bc3")]
+    Bc3,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::None
+    }
+}
+
+/// Smearing of a blend guide in texture space, so that weathering runs down
+/// from where it accumulated, in the direction of the flow projected onto
+/// the triangles of an entity.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Streaks {
+    /// Maximum length of a streak in texture coordinates, e.g. 0.1 for a
+    /// tenth of the texture.
+    pub length: f32,
+    /// Fraction between 0 and 1 that the length of individual streaks
+    /// randomly falls short of the maximum length.
+    #[serde(default)]
+    pub jitter: f32,
+    /// Exponent of how streaks fade out towards their end, 1 for linearly,
+    /// higher values for shorter visible streaks.
+    #[serde(default = "default_falloff")]
+    pub falloff: f32,
+    /// World space direction that streaks run in, downwards if unspecified.
+    #[serde(default = "default_streak_direction")]
+    pub direction: [f32; 3],
+}
+
+impl Streaks {
+    pub fn is_valid(&self) -> bool {
+        self.length >= 0.0
+            && self.jitter >= 0.0
+            && self.jitter <= 1.0
+            && self.falloff > 0.0
+            && self.direction.iter().any(|&c| c != 0.0)
+    }
+}
+
+/// Texture size of an entity derived from its surface area, so that small
+/// props do not get as many texels as whole facades.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct AdaptiveResolution {
+    /// Texels along one world space unit of the surface, if the texture
+    /// coordinates of the entity cover the whole texture.
+    pub texels_per_unit: f32,
+    /// Lower bound of width and height.
+    #[serde(default = "default_min_resolution")]
+    pub min: usize,
+    /// Upper bound of width and height.
+    #[serde(default = "default_max_resolution")]
+    pub max: usize,
+}
+
+impl AdaptiveResolution {
+    pub fn is_valid(&self) -> bool {
+        self.texels_per_unit > 0.0 && self.min > 0 && self.min <= self.max
+    }
+
+    /// Size of the texture for a surface with the given area in world space
+    /// and in texture space, keeping the aspect ratio of the given width and
+    /// height. Falls back to the given size for surfaces without area.
+    pub fn size(&self, width: usize, height: usize, area: f32, uv_area: f32) -> (usize, usize) {
+        if !(area > 0.0 && uv_area > 0.0) || width == 0 || height == 0 {
+            return (width, height);
+        }
+
+        let texels = self.texels_per_unit * self.texels_per_unit * area / uv_area;
+        let aspect = width as f32 / height as f32;
+        let clamped = |side: f32| (side.round() as usize).max(self.min).min(self.max);
+        (
+            clamped((texels * aspect).sqrt()),
+            clamped((texels / aspect).sqrt()),
+        )
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Stop {
     /// Path to the texture sample.
     pub sample: Option<PathBuf>,
+    /// If specified, the hex SHA-256 digest that the contents of the sample
+    /// need to have, verified before running. Samples matching a pattern
+    /// all need to have it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
     /// The concentration where this texture has maximum influence.
     /// To interpolate a given concentration, interpolation is performed
     /// between the textures at the cenith before and after.
     pub cenith: f32,
 }
 
-#[derive(Debug, Deserialize, Clone, Copy)]
+/// Mask of edges or cavities, derived from the surfels around each surfel.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct WearMask {
+    /// World space radius of the neighborhood of a surfel. Should be a few
+    /// times the surfel distance, larger radii find rounder edges.
+    pub radius: f32,
+    #[serde(default)]
+    pub kind: WearKind,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum WearKind {
+    /// Convex parts of the surface, where neighbors fall off below the
+    /// tangent plane of a surfel.
+    #[serde(rename = "edges")]
+    Edges,
+    /// Concave parts of the surface, where neighbors rise above the tangent
+    /// plane of a surfel.
+    #[serde(rename = "cavities")]
+    Cavities,
+}
+
+impl Default for WearKind {
+    fn default() -> Self {
+        WearKind::Edges
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 #[serde(untagged)]
 pub enum SurfelLookup {
     Nearest { count: usize },
@@ -114,6 +740,61 @@ fn default_influence() -> f32 {
     1.0
 }
 
+fn default_falloff() -> f32 {
+    1.0
+}
+
+fn default_streak_direction() -> [f32; 3] {
+    [0.0, -1.0, 0.0]
+}
+
 fn default_surfel_lookup() -> SurfelLookup {
     SurfelLookup::Nearest { count: 6 }
 }
+
+fn default_min_resolution() -> usize {
+    64
+}
+
+fn default_max_resolution() -> usize {
+    4096
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn adaptive_size_follows_area() {
+        let adaptive = AdaptiveResolution {
+            texels_per_unit: 100.0,
+            min: 16,
+            max: 1024,
+        };
+        assert!(adaptive.is_valid());
+
+        // Two square units covering half of the texture
+        assert_eq!((400, 200), adaptive.size(2, 1, 2.0, 0.25));
+        assert_eq!((200, 200), adaptive.size(512, 512, 2.0, 0.5));
+        assert_eq!((16, 16), adaptive.size(512, 512, 0.0001, 1.0));
+        assert_eq!((1024, 1024), adaptive.size(512, 512, 1000.0, 1.0));
+        assert_eq!((512, 512), adaptive.size(512, 512, 2.0, 0.0));
+    }
+
+    #[test]
+    fn limit_resolution_keeps_aspect() {
+        let mut density = EffectSpec::density(2048, 1024, "density.png", None, None);
+        density.limit_resolution(64);
+        match density {
+            EffectSpec::Density { width, height, .. } => assert_eq!((64, 32), (width, height)),
+            _ => unreachable!(),
+        }
+
+        let mut small = EffectSpec::density(16, 16, "density.png", None, None);
+        small.limit_resolution(64);
+        match small {
+            EffectSpec::Density { width, height, .. } => assert_eq!((16, 16), (width, height)),
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use glob::Pattern;
+
+/// Selects entities by their names with glob patterns, e.g. `"Wall*"` or
+/// `"Crate.00?"`, for scenes that group parts by object name rather than by
+/// material. Instances and separated UV islands are matched by their suffixed
+/// names, e.g. `Crate-2`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct EntityFilter {
+    /// Patterns of which an entity name has to match at least one, or any
+    /// name if empty.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Patterns of which an entity name may match none.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl EntityFilter {
+    pub fn include<S: Into<String>>(mut self, pattern: S) -> Self {
+        self.include.push(pattern.into());
+        self
+    }
+
+    pub fn exclude<S: Into<String>>(mut self, pattern: S) -> Self {
+        self.exclude.push(pattern.into());
+        self
+    }
+
+    /// Checks whether the entity with the given name passes the filter.
+    /// Invalid patterns match no name.
+    pub fn admits(&self, entity_name: &str) -> bool {
+        let matches = |pattern: &String| {
+            Pattern::new(pattern)
+                .map(|p| p.matches(entity_name))
+                .unwrap_or(false)
+        };
+
+        (self.include.is_empty() || self.include.iter().any(&matches))
+            && !self.exclude.iter().any(&matches)
+    }
+
+    /// Patterns that are no valid globs, e.g. with unclosed brackets.
+    pub fn invalid_patterns(&self) -> Vec<&str> {
+        self.include
+            .iter()
+            .chain(self.exclude.iter())
+            .filter(|p| Pattern::new(p).is_err())
+            .map(|p| p.as_str())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn include_and_exclude() {
+        let everything = EntityFilter::default();
+        assert!(everything.admits("Wall"));
+
+        let walls = EntityFilter::default()
+            .include("Wall*")
+            .include("Pillar")
+            .exclude("*_LOD?");
+        assert!(walls.admits("Wall.001"));
+        assert!(walls.admits("Pillar"));
+        assert!(!walls.admits("Pillar-2"));
+        assert!(!walls.admits("Wall_LOD1"));
+        assert!(!walls.admits("Floor"));
+
+        let invalid = EntityFilter::default().include("[Wall").exclude("Floor");
+        assert_eq!(vec!["[Wall"], invalid.invalid_patterns());
+        assert!(!invalid.admits("[Wall"));
+    }
+}
@@ -0,0 +1,23 @@
+/// Amount of a substance added to the surfels of the scene before tracing in
+/// an iteration, e.g. for a salt spray event, read from the CSV or JSON
+/// schedules listed in `injections`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct InjectionSpec {
+    pub iteration: u32,
+    pub substance: String,
+    /// Concentration added to each affected surfel. Negative amounts remove
+    /// the substance, but never below zero.
+    pub amount: f32,
+    /// Name of the material of the entities whose surfels get the substance,
+    /// all surfels get it if unspecified or `"_"`.
+    pub material: Option<String>,
+}
+
+impl InjectionSpec {
+    pub fn affects_material(&self, material_name: &str) -> bool {
+        match self.material {
+            Some(ref material) => material == "_" || material == material_name,
+            None => true,
+        }
+    }
+}
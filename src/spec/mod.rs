@@ -1,13 +1,46 @@
 mod bench;
+mod disk_space;
 mod effect;
+mod entity_filter;
+mod injection;
+mod notify;
+mod output_base;
+mod overwrite;
+mod placeholders;
+mod retry;
+mod sampling;
+mod scene;
+mod schedule;
 mod sim;
 mod source;
+mod substance;
 mod surfel;
+mod swap;
 mod transport;
+mod uv;
+mod wind;
 
-pub use self::bench::BenchSpec;
-pub use self::effect::{Blend, EffectSpec, Stop, SurfelLookup};
+pub use self::bench::{BenchFormat, BenchSpec};
+pub use self::disk_space::DiskSpaceCheck;
+pub use self::effect::{
+    AdaptiveResolution, Blend, ColorSpace, Compression, EffectSpec, NormalConvention, Stop,
+    Streaks, SurfelLookup, WearKind, WearMask,
+};
+pub use self::entity_filter::EntityFilter;
+pub use self::injection::InjectionSpec;
+pub use self::notify::Notify;
+pub use self::output_base::OutputBase;
+pub use self::overwrite::{Overwrite, OVERWRITE_NAMES};
+pub use self::placeholders::PLACEHOLDERS;
+pub use self::retry::OutputRetry;
+pub use self::sampling::SurfelSampling;
+pub use self::scene::{SceneLoading, SceneSpec, Transform};
+pub use self::schedule::ScheduleEntry;
 pub use self::sim::SimulationSpec;
-pub use self::source::TonSourceSpec;
+pub use self::source::{EmissionCount, EmitterShape, IterationSet, Keyframe, TonSourceSpec};
+pub use self::substance::SubstanceRange;
 pub use self::surfel::{SurfelRuleSpec, SurfelSpec};
+pub use self::swap::MaterialSwapSpec;
 pub use self::transport::Transport;
+pub use self::uv::{Unwrap, UvOverlaps};
+pub use self::wind::Wind;
@@ -0,0 +1,6 @@
+/// Where to send progress events of a run, e.g. to a dashboard or chat bot.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Notify {
+    /// HTTP or HTTPS URL that events are POSTed to as JSON.
+    pub url: String,
+}
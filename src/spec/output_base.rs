@@ -0,0 +1,18 @@
+/// What relative output patterns, the log and benchmark files are relative
+/// to when no `output_root` is set.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum OutputBase {
+    /// The working directory of the run.
+    #[serde(rename = "working_dir")]
+    WorkingDir,
+    /// The directory of the spec file that declares it, like paths of
+    /// inputs, so that specs can be moved along with their outputs.
+    #[serde(rename = "spec_dir")]
+    SpecDir,
+}
+
+impl Default for OutputBase {
+    fn default() -> Self {
+        OutputBase::WorkingDir
+    }
+}
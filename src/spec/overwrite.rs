@@ -0,0 +1,38 @@
+/// What to do when an output would be written to a path that exists already
+/// before the run, e.g. from a previous run with the same output patterns.
+///
+/// Outputs that are written more than once during a run, e.g. because their
+/// pattern has no `{iteration}`, are always overwritten by later writes.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Overwrite {
+    /// Fail instead of writing to an existing path.
+    #[serde(rename = "never")]
+    Never,
+    /// Replace the existing file.
+    #[serde(rename = "always")]
+    Always,
+    /// Write to the path with the lowest numeric suffix that does not exist
+    /// yet, e.g. `scene-2.obj`.
+    #[serde(rename = "version")]
+    Version,
+}
+
+/// Names of the policies as accepted by `Overwrite::from_name`.
+pub const OVERWRITE_NAMES: &'static [&'static str] = &["never", "always", "version"];
+
+impl Overwrite {
+    pub fn from_name(name: &str) -> Option<Overwrite> {
+        match name {
+            "never" => Some(Overwrite::Never),
+            "always" => Some(Overwrite::Always),
+            "version" => Some(Overwrite::Version),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Overwrite {
+    fn default() -> Self {
+        Overwrite::Always
+    }
+}
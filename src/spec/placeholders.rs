@@ -0,0 +1,45 @@
+/// Placeholders that get replaced in output file patterns, with a short
+/// description of what they are replaced with.
+///
+/// Not every placeholder is meaningful in every pattern, e.g. `{entity}`
+/// has no value in scene-wide OBJ patterns. Numbers and the date can also be
+/// formatted, e.g. `{iteration:04}` or `{datetime:%Y%m%d}`.
+pub const PLACEHOLDERS: &'static [(&'static str, &'static str)] = &[
+    (
+        "{datetime}",
+        "Filename-safe RFC3339 timestamp of the time the simulation was set up, or formatted with strftime syntax as in {datetime:%Y%m%d}. Also available in log and benchmark paths.",
+    ),
+    (
+        "{iteration}",
+        "Number of the iteration the output belongs to, starting at 0 for the unweathered reference. Zero-padded with a width as in {iteration:04}.",
+    ),
+    (
+        "{id}",
+        "Index of the entity in the loaded scene, for per-entity textures. Can be zero-padded like {iteration}.",
+    ),
+    ("{entity}", "Name of the entity, for per-entity textures."),
+    (
+        "{entity_dir}",
+        "Name of the entity made safe to use as a directory, suffixed with {id} if other entities have the same name, for per-entity textures.",
+    ),
+    (
+        "{material}",
+        "Name of the material of the entity, for per-entity textures.",
+    ),
+    (
+        "{substance}",
+        "Name of the substance that guided the output, or \"all\" for scene exports.",
+    ),
+    (
+        "{width}",
+        "Width of the texture in texels, which may be derived from the original map, for textures. Can be zero-padded like {iteration}.",
+    ),
+    (
+        "{height}",
+        "Height of the texture in texels, like {width}.",
+    ),
+    (
+        "{effect}",
+        "Kind and index of the effect in the spec, e.g. layer-1 for the second effect, to tell apart outputs of multiple effects of the same kind.",
+    ),
+];
@@ -0,0 +1,33 @@
+/// How often writing an output is attempted before the run fails, e.g. to
+/// ride out transient errors on network file systems.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct OutputRetry {
+    /// Attempts including the first one, so 1 never retries.
+    pub attempts: u32,
+    /// Seconds to wait before the first retry, doubling for each further
+    /// retry.
+    #[serde(default = "default_backoff")]
+    pub backoff: f32,
+}
+
+impl OutputRetry {
+    /// Whether there is at least one attempt and the backoff is a
+    /// non-negative amount of seconds.
+    pub fn is_valid(&self) -> bool {
+        self.attempts > 0 && self.backoff >= 0.0 && self.backoff.is_finite()
+    }
+}
+
+impl Default for OutputRetry {
+    /// Attempts writing once, without retrying.
+    fn default() -> Self {
+        OutputRetry {
+            attempts: 1,
+            backoff: default_backoff(),
+        }
+    }
+}
+
+fn default_backoff() -> f32 {
+    1.0
+}
@@ -0,0 +1,54 @@
+/// Strategy for distributing surfels over the surfaces of entities.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum SurfelSampling {
+    /// Dart throwing with at least the given distance between surfels, the
+    /// same as setting `surfel_distance`.
+    #[serde(rename = "minimum_distance")]
+    MinimumDistance(f32),
+    /// The given amount of surfels per square unit of surface area.
+    #[serde(rename = "per_sqr_unit")]
+    PerSqrUnit(usize),
+    /// About the given amount of surfels on every triangle, regardless of its
+    /// size, e.g. to sample finely tesselated areas more densely.
+    #[serde(rename = "per_triangle")]
+    PerTriangle(usize),
+    /// About one surfel per texel of textures with the given size, so the
+    /// surfels match the resolution of the output textures. Triangles without
+    /// texture coordinates are not sampled.
+    #[serde(rename = "per_texel")]
+    PerTexel { width: usize, height: usize },
+}
+
+impl SurfelSampling {
+    /// Whether the sampling yields any surfels at all.
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            SurfelSampling::MinimumDistance(distance) => distance > 0.0,
+            SurfelSampling::PerSqrUnit(count) | SurfelSampling::PerTriangle(count) => count > 0,
+            SurfelSampling::PerTexel { width, height } => width > 0 && height > 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_yaml;
+
+    #[test]
+    fn parse_sampling() {
+        let per_texel: SurfelSampling =
+            serde_yaml::from_str("per_texel: { width: 1024, height: 512 }").unwrap();
+        assert_eq!(
+            SurfelSampling::PerTexel {
+                width: 1024,
+                height: 512
+            },
+            per_texel
+        );
+
+        let per_triangle: SurfelSampling = serde_yaml::from_str("per_triangle: 4").unwrap();
+        assert!(per_triangle.is_valid());
+        assert!(!SurfelSampling::MinimumDistance(0.0).is_valid());
+    }
+}
@@ -0,0 +1,154 @@
+use std::path::{Path, PathBuf};
+
+/// An OBJ scene to load, either just its path, or its path with instances
+/// that each place a copy of the whole scene with a transform, e.g.
+/// `{ path: bench.obj, instances: [{ translate: [2, 0, 0] }, { rotate: [0, 90, 0] }] }`.
+///
+/// Instances share the loaded file, but get entities and surfels of their
+/// own, so each copy weathers individually.
+///
+/// Either form with a path can pin the contents of the file with a hex
+/// `sha256` digest, which is verified before loading.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum SceneSpec {
+    Path(PathBuf),
+    Instanced {
+        path: PathBuf,
+        instances: Vec<Transform>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sha256: Option<String>,
+    },
+    Pinned {
+        path: PathBuf,
+        sha256: String,
+    },
+}
+
+/// Placement of a scene instance, scaled uniformly first, then rotated around
+/// the x, y and z axes in that order by angles in degrees, then translated.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct Transform {
+    pub translate: Option<[f32; 3]>,
+    pub rotate: Option<[f32; 3]>,
+    pub scale: Option<f32>,
+}
+
+/// How the OBJ files of scenes are read.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SceneLoading {
+    /// Read each file as a whole before turning it into entities.
+    #[serde(rename = "full")]
+    Full,
+    /// Read files line by line and skip the faces of entities without a
+    /// surfel spec for their material while reading, for scenes too large
+    /// to hold in memory more than once.
+    #[serde(rename = "streaming")]
+    Streaming,
+    /// Like streaming, but also drop texture coordinates and material maps,
+    /// which only effects synthesizing textures need.
+    #[serde(rename = "geometry")]
+    Geometry,
+}
+
+impl Default for SceneLoading {
+    fn default() -> Self {
+        SceneLoading::Full
+    }
+}
+
+impl SceneSpec {
+    pub fn path(&self) -> &Path {
+        match self {
+            &SceneSpec::Path(ref path)
+            | &SceneSpec::Instanced { ref path, .. }
+            | &SceneSpec::Pinned { ref path, .. } => path,
+        }
+    }
+
+    pub fn path_mut(&mut self) -> &mut PathBuf {
+        match self {
+            &mut SceneSpec::Path(ref mut path)
+            | &mut SceneSpec::Instanced { ref mut path, .. }
+            | &mut SceneSpec::Pinned { ref mut path, .. } => path,
+        }
+    }
+
+    /// Transforms of the instances, or `None` if the scene is loaded once as
+    /// it is.
+    pub fn instances(&self) -> Option<&[Transform]> {
+        match self {
+            &SceneSpec::Path(_) | &SceneSpec::Pinned { .. } => None,
+            &SceneSpec::Instanced { ref instances, .. } => Some(instances),
+        }
+    }
+
+    /// Hex SHA-256 digest that the contents of the scene file need to have,
+    /// if pinned.
+    pub fn sha256(&self) -> Option<&str> {
+        match self {
+            &SceneSpec::Path(_) => None,
+            &SceneSpec::Instanced { ref sha256, .. } => sha256.as_ref().map(|s| s.as_str()),
+            &SceneSpec::Pinned { ref sha256, .. } => Some(sha256),
+        }
+    }
+
+    /// The same scene and instances at another path, e.g. a match of a glob.
+    pub fn with_path(&self, path: PathBuf) -> SceneSpec {
+        let mut scene = self.clone();
+        *scene.path_mut() = path;
+        scene
+    }
+}
+
+impl From<PathBuf> for SceneSpec {
+    fn from(path: PathBuf) -> Self {
+        SceneSpec::Path(path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_yaml;
+
+    #[test]
+    fn parse_scenes() {
+        let scenes: Vec<SceneSpec> = serde_yaml::from_str(
+            "- plaza.obj\n- { path: bench.obj, instances: [{ translate: [2, 0, 0] }, {}] }",
+        )
+        .unwrap();
+
+        assert_eq!(SceneSpec::Path(PathBuf::from("plaza.obj")), scenes[0]);
+        assert_eq!(None, scenes[0].instances());
+        assert_eq!(Path::new("bench.obj"), scenes[1].path());
+        assert_eq!(
+            Some(
+                &[
+                    Transform {
+                        translate: Some([2.0, 0.0, 0.0]),
+                        ..Transform::default()
+                    },
+                    Transform::default(),
+                ][..]
+            ),
+            scenes[1].instances()
+        );
+    }
+
+    #[test]
+    fn parse_pinned_scenes() {
+        let scenes: Vec<SceneSpec> = serde_yaml::from_str(
+            "- { path: plaza.obj, sha256: e3b0c442 }\n\
+             - { path: bench.obj, instances: [{}], sha256: 5feceb66 }",
+        )
+        .unwrap();
+
+        assert_eq!(Path::new("plaza.obj"), scenes[0].path());
+        assert_eq!(Some("e3b0c442"), scenes[0].sha256());
+        assert_eq!(None, scenes[0].instances());
+        assert_eq!(Some("5feceb66"), scenes[1].sha256());
+        assert_eq!(Some(&[Transform::default()][..]), scenes[1].instances());
+        assert_eq!(None, SceneSpec::Path(PathBuf::from("plaza.obj")).sha256());
+    }
+}
@@ -0,0 +1,90 @@
+/// Entry of `effects_at`, either an iteration or an expression. Expressions
+/// are either `"last"` for the last iteration, or a range of iterations like
+/// `"20..30"` that includes both ends, optionally with a step like
+/// `"20..30:2"`. Ranges without a start begin at iteration 1, ranges without
+/// an end stop at the last iteration, which can also be given as `"last"`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ScheduleEntry {
+    Iteration(u32),
+    Expression(String),
+}
+
+impl ScheduleEntry {
+    /// Whether the entry includes the given iteration of a simulation with the
+    /// given amount of iterations. Malformed expressions include nothing.
+    pub fn contains(&self, iteration: u32, iterations: u32) -> bool {
+        match self.range(iterations) {
+            Ok((from, to, step)) => {
+                iteration >= from && iteration <= to && (iteration - from) % step == 0
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// First and last iteration and the step between the iterations of the
+    /// entry, or the reason why the expression is malformed.
+    pub fn range(&self, iterations: u32) -> Result<(u32, u32, u32), String> {
+        let expression = match self {
+            &ScheduleEntry::Iteration(iteration) => return Ok((iteration, iteration, 1)),
+            &ScheduleEntry::Expression(ref expression) => expression.trim(),
+        };
+
+        let iteration = |text: &str, open: Option<u32>| match (text.trim(), open) {
+            ("", Some(open)) => Ok(open),
+            ("last", _) => Ok(iterations),
+            (text, _) => text
+                .parse()
+                .map_err(|_| format!("\"{}\" is neither an iteration nor \"last\"", text)),
+        };
+
+        let (range, step) = match expression.find(':') {
+            Some(colon) => {
+                let step = &expression[colon + 1..];
+                match step.trim().parse() {
+                    Ok(step) if step > 0 => (&expression[..colon], Some(step)),
+                    _ => return Err(format!("\"{}\" is not a positive step", step)),
+                }
+            }
+            None => (expression, None),
+        };
+
+        match (range.find(".."), step) {
+            (Some(dots), step) => Ok((
+                iteration(&range[..dots], Some(1))?,
+                iteration(&range[dots + 2..], Some(iterations))?,
+                step.unwrap_or(1),
+            )),
+            (None, None) => {
+                let single = iteration(range, None)?;
+                Ok((single, single, 1))
+            }
+            (None, Some(_)) => Err(String::from("Steps are only allowed on ranges")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_yaml;
+
+    #[test]
+    fn evaluate_schedule() {
+        let entries: Vec<ScheduleEntry> =
+            serde_yaml::from_str(r#"[1, 5, "20..30:2", "last", "..2", "38.."]"#).unwrap();
+        let scheduled: Vec<u32> = (1..41)
+            .filter(|&i| entries.iter().any(|e| e.contains(i, 40)))
+            .collect();
+
+        assert_eq!(vec![1, 2, 5, 20, 22, 24, 26, 28, 30, 38, 39, 40], scheduled);
+    }
+
+    #[test]
+    fn malformed_expressions() {
+        for expression in vec!["first", "1..x", "5:2", "1..10:0", "1..10:"] {
+            let entry = ScheduleEntry::Expression(String::from(expression));
+            assert!(entry.range(10).is_err(), "{} was accepted", expression);
+        }
+    }
+}
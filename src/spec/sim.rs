@@ -1,34 +1,125 @@
-use spec::{BenchSpec, EffectSpec, SurfelRuleSpec, Transport};
+use spec::{
+    BenchSpec, DiskSpaceCheck, EffectSpec, EntityFilter, MaterialSwapSpec, NormalConvention, Notify,
+    OutputBase, OutputRetry, Overwrite, SceneLoading, SceneSpec, ScheduleEntry, SubstanceRange,
+    SurfelRuleSpec, SurfelSampling, Transform, Transport, Unwrap, UvOverlaps, Wind,
+};
 use std::collections::HashMap;
 use std::default::Default;
 use std::path::PathBuf;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SimulationSpec {
     #[serde(default)]
     pub name: String,
     #[serde(default)]
     pub description: String,
     #[serde(default)]
-    pub scenes: Vec<PathBuf>,
+    pub scenes: Vec<SceneSpec>,
+    /// Scenes that block tons like other scenes, but are neither sampled
+    /// with surfels nor exported, e.g. an invisible roof over the scene.
+    #[serde(default)]
+    pub occluders: Vec<SceneSpec>,
+    /// Whether scenes are read as a whole or streamed, read as a whole if
+    /// unspecified.
+    pub scene_loading: Option<SceneLoading>,
     pub iterations: Option<u32>,
     /// Determines how often the effect pipeline is run.
     /// Iteration 0 and the last iteration will always be run,
     /// regardless of this setting.
     pub effect_interval: Option<u32>,
+    /// Iterations after which the effect pipeline is run, takes precedence
+    /// over `effect_interval`, e.g. `[1, 5, "20..30:2", "last"]`. Iteration 0
+    /// is always run.
+    pub effects_at: Option<Vec<ScheduleEntry>>,
     pub log: Option<PathBuf>,
+    /// JSON file recording what is needed to reproduce a run, e.g. the
+    /// arguments, the merged spec, the host and crate versions, along with
+    /// the outputs of the run. Supports `{datetime}`.
+    pub manifest: Option<PathBuf>,
+    /// Directory that output patterns, the log and benchmark files are
+    /// relative to. Relative roots are relative to the spec file they are
+    /// declared in. If unspecified, outputs are relative to the working
+    /// directory.
+    pub output_root: Option<PathBuf>,
+    /// With `spec_dir`, outputs are relative to the spec file that declares
+    /// it unless an `output_root` is set, like paths of inputs. Relative to
+    /// the working directory if unspecified.
+    pub output_base: Option<OutputBase>,
+    /// If specified, textures that effects write for each entity go into a
+    /// tree under this directory, e.g. `out/entity/Statue/albedo_3.png` for
+    /// `out`, instead of following the texture patterns of the effects, which
+    /// then only determine the file extension. Placeholders like `{datetime}`
+    /// only apply if they are part of this directory.
+    pub output_tree: Option<String>,
+    /// Retries writing textures, OBJ and MTL files that failed to be written,
+    /// instead of failing the run on the first error.
+    pub output_retry: Option<OutputRetry>,
+    /// Whether outputs replace existing files from before the run, fail the
+    /// run or are written next to them, replaces them if unspecified.
+    pub overwrite: Option<Overwrite>,
+    /// Whether to fail or only warn before running if the estimated size of
+    /// the outputs exceeds the free space where they are written, fails if
+    /// unspecified.
+    pub disk_space_check: Option<DiskSpaceCheck>,
     pub surfel_distance: Option<f32>,
+    /// How surfels are distributed, takes precedence over `surfel_distance`.
+    pub surfel_sampling: Option<SurfelSampling>,
     #[serde(default)]
     pub sources: Vec<PathBuf>,
     #[serde(default)]
     pub surfels_by_material: HashMap<String, String>,
+    /// New names for materials of the scenes by their names in the OBJ files,
+    /// applied after loading so that surfel specs and effects can use them.
+    #[serde(default)]
+    pub material_map: HashMap<String, String>,
+    /// If specified, only entities of the scenes whose names pass the filter
+    /// are simulated, others are dropped after loading like entities without
+    /// a surfel spec.
+    pub entities: Option<EntityFilter>,
+    /// Ranges of substance concentrations by substance name for density
+    /// maps and layer guides, zero to one for substances without a range.
+    #[serde(default)]
+    pub substances: HashMap<String, SubstanceRange>,
     #[serde(default)]
     pub effects: Vec<EffectSpec>,
     pub benchmark: Option<BenchSpec>,
     pub transport: Option<Transport>,
+    /// Biases the emission and flow directions of all sources.
+    pub wind: Option<Wind>,
     pub flat_filtering: Option<bool>,
+    /// Whether the smaller mip levels of synthesized textures are written
+    /// next to them, e.g. `albedo-mip1.png` for half the size, down to 1x1.
+    /// No mip levels if unspecified.
+    pub mipmaps: Option<bool>,
+    /// Convention of the original normal maps of the scene, which synthesized
+    /// normal maps are written in too. Blend samples are always in OpenGL
+    /// convention, which is also the default.
+    pub normal_convention: Option<NormalConvention>,
+    /// Whether to only warn about overlapping UV islands or to give them
+    /// separate textures, warns if unspecified.
+    pub uv_overlaps: Option<UvOverlaps>,
+    /// Generates texture coordinates for entities without usable ones, which
+    /// are otherwise only warned about.
+    pub auto_unwrap: Option<Unwrap>,
     #[serde(default)]
     pub rules: Vec<SurfelRuleSpec>,
+    #[serde(default)]
+    pub material_swaps: Vec<MaterialSwapSpec>,
+    /// CSV or JSON files with substances to add to surfels in certain
+    /// iterations, see `InjectionSpec`.
+    #[serde(default)]
+    pub injections: Vec<PathBuf>,
+    /// Surfel snapshot written by a `dump_surfels` effect to restore the
+    /// substance concentrations of the surfels from before iteration 1,
+    /// e.g. to branch several scenarios off a precomputed aging phase.
+    pub initial_surfels: Option<PathBuf>,
+    /// Seed for random decisions that are made outside of ton tracing, e.g.
+    /// whether a rule with a probability applies to a surfel. Zero if
+    /// unspecified, so repeated runs of a spec yield the same result.
+    pub seed: Option<u64>,
+    /// POSTs progress events of runs to a URL as JSON. Requires the http
+    /// feature.
+    pub notify: Option<Notify>,
 }
 
 impl Default for SimulationSpec {
@@ -37,25 +128,301 @@ impl Default for SimulationSpec {
             name: String::new(),
             description: String::new(),
             scenes: Vec::new(),
+            occluders: Vec::new(),
+            scene_loading: None,
             iterations: None,
             effect_interval: None,
+            effects_at: None,
             log: None,
+            manifest: None,
+            output_root: None,
+            output_base: None,
+            output_tree: None,
+            output_retry: None,
+            overwrite: None,
+            disk_space_check: None,
             surfel_distance: None,
+            surfel_sampling: None,
             sources: Vec::new(),
             surfels_by_material: HashMap::new(),
+            material_map: HashMap::new(),
+            entities: None,
+            substances: HashMap::new(),
             effects: Vec::new(),
             benchmark: None,
             transport: None,
+            wind: None,
             flat_filtering: None,
+            mipmaps: None,
+            normal_convention: None,
+            uv_overlaps: None,
+            auto_unwrap: None,
             rules: Vec::new(),
+            material_swaps: Vec::new(),
+            injections: Vec::new(),
+            initial_surfels: None,
+            seed: None,
+            notify: None,
         }
     }
 }
 
+impl SimulationSpec {
+    /// Whether the effect pipeline runs in the given iteration, which is
+    /// always the case in iteration 0. Afterwards, effects run in the
+    /// iterations listed in `effects_at`, or otherwise in every iteration
+    /// divisible by `effect_interval` and in the last iteration.
+    pub fn runs_effects(&self, iteration: u32) -> bool {
+        let iterations = self.iterations.unwrap_or(1);
+        match (&self.effects_at, self.effect_interval) {
+            _ if iteration == 0 => true,
+            // Effects only in the listed iterations, not necessarily the last one
+            (&Some(ref effects_at), _) => effects_at
+                .iter()
+                .any(|e| e.contains(iteration, iterations)),
+            // Interval is defined, 1-based iteration index must be divisible.
+            (&None, Some(interval)) if (iteration % interval) == 0 => true,
+            // Either no interval defined or defined and not divisible, skip effects,
+            // except for the last iteration.
+            _ => iteration == iterations,
+        }
+    }
+}
+
+/// Builder-style construction of specs in code, e.g. to pass them to
+/// `SimulationBuilder::append_spec_fragment`. Settings overwrite previous
+/// values, while scenes, sources, effects, rules, material swaps and
+/// injection schedules are appended.
+impl SimulationSpec {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn description<S: Into<String>>(mut self, description: S) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    pub fn scene<P: Into<PathBuf>>(mut self, scene: P) -> Self {
+        self.scenes.push(SceneSpec::Path(scene.into()));
+        self
+    }
+
+    pub fn occluder<P: Into<PathBuf>>(mut self, occluder: P) -> Self {
+        self.occluders.push(SceneSpec::Path(occluder.into()));
+        self
+    }
+
+    /// Adds a scene that is placed once for each of the given transforms.
+    pub fn instanced_scene<P: Into<PathBuf>>(
+        mut self,
+        scene: P,
+        instances: Vec<Transform>,
+    ) -> Self {
+        self.scenes.push(SceneSpec::Instanced {
+            path: scene.into(),
+            instances,
+            sha256: None,
+        });
+        self
+    }
+
+    pub fn scene_loading(mut self, scene_loading: SceneLoading) -> Self {
+        self.scene_loading = Some(scene_loading);
+        self
+    }
+
+    pub fn iterations(mut self, iterations: u32) -> Self {
+        self.iterations = Some(iterations);
+        self
+    }
+
+    pub fn effect_interval(mut self, effect_interval: u32) -> Self {
+        self.effect_interval = Some(effect_interval);
+        self
+    }
+
+    pub fn effects_at<I: IntoIterator<Item = ScheduleEntry>>(mut self, effects_at: I) -> Self {
+        self.effects_at = Some(effects_at.into_iter().collect());
+        self
+    }
+
+    pub fn log<P: Into<PathBuf>>(mut self, log: P) -> Self {
+        self.log = Some(log.into());
+        self
+    }
+
+    pub fn manifest<P: Into<PathBuf>>(mut self, manifest: P) -> Self {
+        self.manifest = Some(manifest.into());
+        self
+    }
+
+    pub fn output_root<P: Into<PathBuf>>(mut self, output_root: P) -> Self {
+        self.output_root = Some(output_root.into());
+        self
+    }
+
+    pub fn output_tree<S: Into<String>>(mut self, output_tree: S) -> Self {
+        self.output_tree = Some(output_tree.into());
+        self
+    }
+
+    pub fn output_base(mut self, output_base: OutputBase) -> Self {
+        self.output_base = Some(output_base);
+        self
+    }
+
+    pub fn output_retry(mut self, output_retry: OutputRetry) -> Self {
+        self.output_retry = Some(output_retry);
+        self
+    }
+
+    pub fn overwrite(mut self, overwrite: Overwrite) -> Self {
+        self.overwrite = Some(overwrite);
+        self
+    }
+
+    pub fn disk_space_check(mut self, disk_space_check: DiskSpaceCheck) -> Self {
+        self.disk_space_check = Some(disk_space_check);
+        self
+    }
+
+    pub fn surfel_distance(mut self, surfel_distance: f32) -> Self {
+        self.surfel_distance = Some(surfel_distance);
+        self
+    }
+
+    pub fn surfel_sampling(mut self, surfel_sampling: SurfelSampling) -> Self {
+        self.surfel_sampling = Some(surfel_sampling);
+        self
+    }
+
+    /// Adds the path to a ton source spec.
+    pub fn source<P: Into<PathBuf>>(mut self, source: P) -> Self {
+        self.sources.push(source.into());
+        self
+    }
+
+    /// Uses the surfel spec at the given path for entities with the given
+    /// material name, or for all unmapped materials if the name is `"_"`.
+    pub fn surfels_for_material<M, S>(mut self, material: M, surfel_spec: S) -> Self
+    where
+        M: Into<String>,
+        S: Into<String>,
+    {
+        self.surfels_by_material
+            .insert(material.into(), surfel_spec.into());
+        self
+    }
+
+    /// Renames the material with the given name in the scenes after loading.
+    pub fn rename_material<O, N>(mut self, old_name: O, new_name: N) -> Self
+    where
+        O: Into<String>,
+        N: Into<String>,
+    {
+        self.material_map.insert(old_name.into(), new_name.into());
+        self
+    }
+
+    pub fn entity_filter(mut self, filter: EntityFilter) -> Self {
+        self.entities = Some(filter);
+        self
+    }
+
+    pub fn substance_range<S: Into<String>>(mut self, substance: S, range: SubstanceRange) -> Self {
+        self.substances.insert(substance.into(), range);
+        self
+    }
+
+    pub fn effect(mut self, effect: EffectSpec) -> Self {
+        self.effects.push(effect);
+        self
+    }
+
+    pub fn benchmark(mut self, benchmark: BenchSpec) -> Self {
+        self.benchmark = Some(benchmark);
+        self
+    }
+
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    pub fn wind(mut self, wind: Wind) -> Self {
+        self.wind = Some(wind);
+        self
+    }
+
+    pub fn flat_filtering(mut self, flat_filtering: bool) -> Self {
+        self.flat_filtering = Some(flat_filtering);
+        self
+    }
+
+    pub fn mipmaps(mut self, mipmaps: bool) -> Self {
+        self.mipmaps = Some(mipmaps);
+        self
+    }
+
+    pub fn normal_convention(mut self, normal_convention: NormalConvention) -> Self {
+        self.normal_convention = Some(normal_convention);
+        self
+    }
+
+    pub fn uv_overlaps(mut self, uv_overlaps: UvOverlaps) -> Self {
+        self.uv_overlaps = Some(uv_overlaps);
+        self
+    }
+
+    pub fn auto_unwrap(mut self, auto_unwrap: Unwrap) -> Self {
+        self.auto_unwrap = Some(auto_unwrap);
+        self
+    }
+
+    pub fn rule(mut self, rule: SurfelRuleSpec) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    pub fn material_swap(mut self, material_swap: MaterialSwapSpec) -> Self {
+        self.material_swaps.push(material_swap);
+        self
+    }
+
+    /// Adds the path to a CSV or JSON schedule of substance injections.
+    pub fn injections<P: Into<PathBuf>>(mut self, injections: P) -> Self {
+        self.injections.push(injections.into());
+        self
+    }
+
+    /// Sets the surfel snapshot to start the simulation from.
+    pub fn initial_surfels<P: Into<PathBuf>>(mut self, snapshot: P) -> Self {
+        self.initial_surfels = Some(snapshot.into());
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn notify<S: Into<String>>(mut self, url: S) -> Self {
+        self.notify = Some(Notify { url: url.into() });
+        self
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use serde_yaml;
+    use spec::Blend;
     use std::fs::File;
 
     #[test]
@@ -71,7 +438,7 @@ mod test {
         assert!(
             spec.scenes
                 .iter()
-                .all(|scene| scene.file_name().unwrap().to_str().unwrap() == "buddha.obj"),
+                .all(|scene| scene.path().file_name().unwrap().to_str().unwrap() == "buddha.obj"),
         );
         assert_eq!(spec.iterations, Some(30));
         assert_eq!(spec.surfels_by_material.get("bronze").unwrap(), "iron.yml");
@@ -101,4 +468,50 @@ mod test {
             _ => (),
         }
     }
+
+    #[test]
+    fn yaml_round_trip() {
+        let spec = SimulationSpec::new()
+            .name("Park Scene")
+            .scene("tests/assets/buddha.obj")
+            .iterations(30)
+            .surfel_distance(0.1)
+            .source("tests/examples/rain.yml")
+            .surfels_for_material("_", "tests/examples/concrete.yml")
+            .transport(Transport::Conserving)
+            .effect(EffectSpec::export(Some(String::from("out/{iteration}.obj")), None))
+            .effect(
+                EffectSpec::layer(vec![], "rust").albedo(
+                    Blend::new("out/{iteration}-{entity}-albedo.png")
+                        .stop(0.5, Some(PathBuf::from("tests/examples/black_pixel.png"))),
+                ),
+            );
+
+        let yaml = serde_yaml::to_string(&spec).unwrap();
+        let parsed: SimulationSpec = serde_yaml::from_str(&yaml).unwrap();
+
+        assert_eq!("Park Scene", parsed.name);
+        assert_eq!(
+            vec![SceneSpec::Path(PathBuf::from("tests/assets/buddha.obj"))],
+            parsed.scenes
+        );
+        assert_eq!(Some(30), parsed.iterations);
+        assert_eq!(Some(0.1), parsed.surfel_distance);
+        assert_eq!("tests/examples/concrete.yml", parsed.surfels_by_material["_"]);
+        assert_eq!(Some(Transport::Conserving), parsed.transport);
+
+        match &parsed.effects[1] {
+            &EffectSpec::Layer {
+                ref substance,
+                albedo: Some(ref albedo),
+                roughness: None,
+                ..
+            } => {
+                assert_eq!("rust", substance);
+                assert_eq!(1, albedo.stops.len());
+                assert_eq!(1.0, albedo.influence);
+            }
+            _ => panic!("Expected layer effect with albedo only"),
+        }
+    }
 }
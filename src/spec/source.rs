@@ -1,20 +1,39 @@
+use spec::Transport;
 use std::collections::HashMap;
 use std::path::PathBuf;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TonSourceSpec {
-    name: String,
-    description: String,
-    pub mesh: PathBuf,
-    pub emission_count: usize,
+    pub name: String,
+    pub description: String,
+    /// OBJ to emit from, either this or `shape` must be set.
+    pub mesh: Option<PathBuf>,
+    /// Generated emission geometry for simple emitters without a mesh.
+    pub shape: Option<EmitterShape>,
+    /// Image whose luminance at the texture coordinates of the emission
+    /// geometry weights where tons are emitted, uniform if left out.
+    pub emission_map: Option<PathBuf>,
+    /// Tons per iteration, either this or `emission_per_area` must be set.
+    pub emission_count: Option<EmissionCount>,
+    /// Tons per iteration and unit of surface area of the emission geometry,
+    /// so the count grows with the emitter, e.g. for a sky over a scene.
+    pub emission_per_area: Option<f32>,
     #[serde(default = "is_diffuse_default")]
     pub diffuse: bool,
+    /// Spreads emission around the normals of the emission geometry, or the
+    /// direction of the shape, into directions up to this angle off them,
+    /// e.g. 90 for the whole hemisphere. Takes precedence over `diffuse`.
+    pub emission_cone_degrees: Option<f32>,
     pub p_straight: f32,
     pub p_parabolic: f32,
     pub p_flow: f32,
     /// Initial concentrations by material name
     pub initial: HashMap<String, f32>,
     /// When bouncing, not settling, indicates how much mateiral is absorbed from surfels
+    ///
+    /// Only flat rates are supported. A rate that falls off as a ton fills up
+    /// would need the amount each ton carries at every pickup, which tracing
+    /// in aitios-sim keeps to itself.
     pub absorb: HashMap<String, f32>,
     pub interaction_radius: f32,
     pub parabola_height: f32,
@@ -22,6 +41,116 @@ pub struct TonSourceSpec {
     /// If set, provides direction of flow that is projected onto triangles to obtain
     /// final flow direction. If left out, incoming direction will be projected.
     pub flow_direction: Option<[f32; 3]>,
+    /// Iterations in which the source emits tons, all iterations if left out.
+    pub active_iterations: Option<IterationSet>,
+    /// Transport for tons of this source, overriding the transport of the
+    /// simulation spec.
+    pub transport: Option<Transport>,
+    /// Splits the emission of each iteration into this many tracing passes,
+    /// with surfel rules applied after each of them. One if left out.
+    pub bursts: Option<u32>,
+}
+
+impl TonSourceSpec {
+    /// Tracing passes per iteration, one if unspecified.
+    pub fn bursts(&self) -> u32 {
+        self.bursts.unwrap_or(1)
+    }
+}
+
+/// Emitters that are set up without modelling emission geometry. Positions
+/// and directions are in world space, angles in degrees.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum EmitterShape {
+    /// Emits into all directions from a point, e.g. for a leaking pipe.
+    #[serde(rename = "point")]
+    Point { position: [f32; 3] },
+    /// Emits parallel rays into the direction from a disk with the given
+    /// radius around the position, e.g. for dust carried by the sun.
+    #[serde(rename = "directional")]
+    Directional {
+        position: [f32; 3],
+        direction: [f32; 3],
+        radius: f32,
+    },
+    /// Emits from a point into directions at most `angle` off the direction.
+    #[serde(rename = "cone")]
+    Cone {
+        position: [f32; 3],
+        direction: [f32; 3],
+        angle: f32,
+    },
+}
+
+/// Tons emitted per iteration, either a constant count, a linear ramp from the
+/// first to the last iteration, e.g. `{ from: 100000, to: 0 }`, or keyframes
+/// in ascending order of iteration that are linearly interpolated, e.g.
+/// `[{ iteration: 1, count: 100000 }, { iteration: 10, count: 0 }]`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum EmissionCount {
+    Constant(usize),
+    Ramp { from: usize, to: usize },
+    Keyframes(Vec<Keyframe>),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Keyframe {
+    pub iteration: u32,
+    pub count: usize,
+}
+
+impl EmissionCount {
+    /// Count in the given iteration of a simulation with the given amount of
+    /// iterations. Keyframe counts hold before the first and after the last
+    /// keyframe.
+    pub fn at(&self, iteration: u32, iterations: u32) -> usize {
+        match self {
+            &EmissionCount::Constant(count) => count,
+            &EmissionCount::Ramp { from, to } if iterations > 1 => {
+                let progress = f64::from(iteration.max(1) - 1) / f64::from(iterations - 1);
+                lerp(from, to, progress.min(1.0))
+            }
+            &EmissionCount::Ramp { from, .. } => from,
+            &EmissionCount::Keyframes(ref keyframes) => {
+                let next = keyframes.iter().position(|k| k.iteration > iteration);
+                match next {
+                    Some(0) => keyframes[0].count,
+                    Some(next) => {
+                        let (prev, next) = (&keyframes[next - 1], &keyframes[next]);
+                        let progress = f64::from(iteration - prev.iteration)
+                            / f64::from(next.iteration - prev.iteration);
+                        lerp(prev.count, next.count, progress)
+                    }
+                    None => keyframes.last().map(|k| k.count).unwrap_or(0),
+                }
+            }
+        }
+    }
+}
+
+fn lerp(from: usize, to: usize, progress: f64) -> usize {
+    (from as f64 + (to as f64 - from as f64) * progress).round() as usize
+}
+
+/// Iterations given as a list, e.g. `[1, 2, 5]`, or an inclusive range, e.g.
+/// `{ from: 11 }` or `{ from: 1, to: 10 }`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum IterationSet {
+    List(Vec<u32>),
+    Range { from: Option<u32>, to: Option<u32> },
+}
+
+impl IterationSet {
+    pub fn contains(&self, iteration: u32) -> bool {
+        match self {
+            &IterationSet::List(ref iterations) => iterations.contains(&iteration),
+            &IterationSet::Range { from, to } => {
+                from.map_or(true, |from| iteration >= from) && to.map_or(true, |to| iteration <= to)
+            }
+        }
+    }
 }
 
 fn is_diffuse_default() -> bool {
@@ -45,8 +174,14 @@ mod test {
 
         assert_eq!(spec.name, "Rain");
         assert_eq!(spec.description, "Rain dropping from the sky");
-        assert_eq!(spec.mesh.file_name().unwrap().to_str().unwrap(), "sky.obj");
-        assert_eq!(spec.emission_count, 100000);
+        assert_eq!(
+            spec.mesh.unwrap().file_name().unwrap().to_str().unwrap(),
+            "sky.obj"
+        );
+        assert_eq!(spec.shape, None);
+        assert_eq!(spec.emission_map, None);
+        assert_eq!(spec.emission_count, Some(EmissionCount::Constant(100000)));
+        assert_eq!(spec.emission_per_area, None);
         assert_eq!(spec.p_straight, 0.0);
         assert_eq!(spec.p_parabolic, 0.3);
         assert_eq!(spec.p_flow, 0.7);
@@ -58,5 +193,55 @@ mod test {
         assert_eq!(spec.parabola_height, 0.07);
         assert_eq!(spec.flow_distance, 0.17);
         assert_eq!(spec.flow_direction, Some([0.0, -1.0, 0.0]));
+        assert_eq!(spec.active_iterations, None);
+        assert_eq!(spec.transport, None);
+        assert_eq!(spec.emission_cone_degrees, None);
+        assert_eq!(spec.bursts, None);
+    }
+
+    #[test]
+    fn parse_emitter_shapes() {
+        let cone: EmitterShape = serde_yaml::from_str(
+            "cone: { position: [0.0, 2.0, 0.0], direction: [0.0, -1.0, 0.0], angle: 15.0 }",
+        ).unwrap();
+        assert_eq!(
+            EmitterShape::Cone {
+                position: [0.0, 2.0, 0.0],
+                direction: [0.0, -1.0, 0.0],
+                angle: 15.0,
+            },
+            cone
+        );
+    }
+
+    #[test]
+    fn varying_emission_counts() {
+        let ramp: EmissionCount = serde_yaml::from_str("{ from: 1000, to: 0 }").unwrap();
+        assert_eq!(1000, ramp.at(1, 11));
+        assert_eq!(500, ramp.at(6, 11));
+        assert_eq!(0, ramp.at(11, 11));
+
+        let keyframes: EmissionCount = serde_yaml::from_str(
+            "[{ iteration: 2, count: 100 }, { iteration: 4, count: 300 }, { iteration: 5, count: 0 }]",
+        ).unwrap();
+        assert_eq!(100, keyframes.at(1, 10));
+        assert_eq!(200, keyframes.at(3, 10));
+        assert_eq!(300, keyframes.at(4, 10));
+        assert_eq!(0, keyframes.at(10, 10));
+    }
+
+    #[test]
+    fn parse_iteration_sets() {
+        let list: IterationSet = serde_yaml::from_str("[1, 2, 5]").unwrap();
+        assert!(list.contains(5));
+        assert!(!list.contains(3));
+
+        let range: IterationSet = serde_yaml::from_str("from: 1\nto: 10").unwrap();
+        assert!(range.contains(1) && range.contains(10));
+        assert!(!range.contains(11));
+
+        let open: IterationSet = serde_yaml::from_str("from: 11").unwrap();
+        assert!(open.contains(1000));
+        assert!(!open.contains(10));
     }
 }
@@ -0,0 +1,67 @@
+/// Range of concentrations of a substance that density maps and layer guides
+/// span from their lowest to their highest value, clamping concentrations
+/// outside of it. Left out bounds are unbounded and follow the lowest or
+/// highest concentration on the surface at the time effects are run.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub struct SubstanceRange {
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+}
+
+impl SubstanceRange {
+    /// Lower and upper bound of the range, using the given concentrations of
+    /// the substance for unbounded ends. Empty ranges are widened to span at
+    /// least one unit, mapping all concentrations to the lower end.
+    pub fn bounds<I>(&self, concentrations: I) -> (f32, f32)
+    where
+        I: IntoIterator<Item = f32>,
+    {
+        let (min, max) = match (self.min, self.max) {
+            (Some(min), Some(max)) => (min, max),
+            (min, max) => {
+                let (lowest, highest) = concentrations.into_iter().fold(
+                    (None, None),
+                    |(lowest, highest): (Option<f32>, Option<f32>), c| {
+                        (
+                            Some(lowest.map_or(c, |l| l.min(c))),
+                            Some(highest.map_or(c, |h| h.max(c))),
+                        )
+                    },
+                );
+                (
+                    min.or(lowest).unwrap_or(0.0),
+                    max.or(highest).unwrap_or(1.0),
+                )
+            }
+        };
+
+        if max > min {
+            (min, max)
+        } else {
+            (min, min + 1.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unbounded_ends_follow_concentrations() {
+        let concentrations = vec![0.5, 3.0, 1.5];
+        let bounded = SubstanceRange {
+            min: Some(0.0),
+            max: Some(5.0),
+        };
+        let open_top = SubstanceRange {
+            min: Some(0.0),
+            max: None,
+        };
+
+        assert_eq!((0.0, 5.0), bounded.bounds(concentrations.clone()));
+        assert_eq!((0.0, 3.0), open_top.bounds(concentrations.clone()));
+        assert_eq!((0.5, 3.0), SubstanceRange::default().bounds(concentrations));
+        assert_eq!((2.0, 3.0), SubstanceRange::default().bounds(vec![2.0, 2.0]));
+    }
+}
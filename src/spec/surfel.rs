@@ -1,42 +1,67 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct SurfelSpec {
     pub name: String,
-    description: String,
+    pub description: String,
     pub reflectance: TonReflectance,
     pub initial: HashMap<String, f32>,
+    /// Grayscale textures by substance name, sampled at the texture
+    /// coordinates of each surfel for its initial concentration of the
+    /// substance, from zero for black to one for white. Takes precedence
+    /// over `initial`. Relative paths are relative to the surfel spec.
+    #[serde(default)]
+    pub initial_maps: HashMap<String, PathBuf>,
     pub deposit: HashMap<String, f32>,
     // TODO only global surfel rules allowed as of yet
     #[serde(default = "Vec::new")]
     pub rules: Vec<SurfelRuleSpec>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct TonReflectance {
     pub delta_straight: f32,
     pub delta_parabolic: f32,
     pub delta_flow: f32,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// Rules applied to surfels at the end of each iteration. With a
+/// `probability`, a rule only applies to each surfel in each iteration with
+/// that probability, for patchier weathering than uniform factors.
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(untagged)]
 pub enum SurfelRuleSpec {
     Transfer {
         from: String,
         to: String,
         factor: f32,
+        probability: Option<f32>,
     },
     Deteriorate {
         from: String,
         factor: f32,
+        probability: Option<f32>,
     },
     Deposit {
         to: String,
         amount: f32,
+        probability: Option<f32>,
     },
 }
 
+impl SurfelRuleSpec {
+    /// Probability of the rule applying to a surfel in an iteration, if the
+    /// rule is stochastic.
+    pub fn probability(&self) -> Option<f32> {
+        match self {
+            &SurfelRuleSpec::Transfer { probability, .. }
+            | &SurfelRuleSpec::Deteriorate { probability, .. }
+            | &SurfelRuleSpec::Deposit { probability, .. } => probability,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -61,7 +86,9 @@ mod test {
         assert_eq!(*spec.deposit.get("rust").unwrap(), 0.5);
 
         match &spec.rules[1] {
-            &SurfelRuleSpec::Deteriorate { ref from, factor } => {
+            &SurfelRuleSpec::Deteriorate {
+                ref from, factor, ..
+            } => {
                 assert_eq!(from, "humidity");
                 assert_eq!(factor, -0.5);
             }
@@ -73,6 +100,7 @@ mod test {
                 ref from,
                 ref to,
                 factor,
+                ..
             } => {
                 assert_eq!(from, "humidity");
                 assert_eq!(to, "rust");
@@ -80,5 +108,14 @@ mod test {
             }
             _ => assert!(false, "Did expect binary rule first"),
         }
+
+        assert_eq!(None, spec.rules[0].probability());
+    }
+
+    #[test]
+    fn parse_stochastic_rule() {
+        let rule: SurfelRuleSpec =
+            serde_yaml::from_str("{ from: humidity, factor: -0.5, probability: 0.25 }").unwrap();
+        assert_eq!(Some(0.25), rule.probability());
     }
 }
@@ -0,0 +1,29 @@
+/// Replaces the surfel spec of entities, and optionally their material, once
+/// the average concentration of a substance on them exceeds a threshold, e.g.
+/// when paint flakes off and exposes bare metal that weathers differently.
+///
+/// Swaps are checked at the end of each iteration and performed at most once
+/// per entity.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MaterialSwapSpec {
+    /// Names of the materials of entities to swap, an empty list or the
+    /// material name "_" match all materials.
+    #[serde(default)]
+    pub materials: Vec<String>,
+    pub substance: String,
+    /// Average concentration of the substance on an entity above which the
+    /// entity is swapped.
+    pub threshold: f32,
+    /// Surfel spec that replaces the reflectance, deposition rates and rules
+    /// of the surfels of swapped entities. Concentrations are kept.
+    pub surfels: String,
+    /// Name of a material in the loaded scenes that replaces the material of
+    /// swapped entities in effects and exports.
+    pub material: Option<String>,
+}
+
+impl MaterialSwapSpec {
+    pub fn affects_material(&self, material_name: &str) -> bool {
+        self.materials.is_empty() || self.materials.iter().any(|m| m == "_" || m == material_name)
+    }
+}
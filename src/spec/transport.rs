@@ -1,4 +1,4 @@
-#[derive(Debug, Copy, Clone, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Transport {
     #[serde(rename = "classic")]
     Classic,
@@ -0,0 +1,34 @@
+/// How to handle UV islands of an entity that overlap in texture space, so
+/// that surfels from different parts of the surface compete for the same
+/// texels.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum UvOverlaps {
+    /// Only warn about overlapping islands when building surfel tables.
+    #[serde(rename = "warn")]
+    Warn,
+    /// Split entities with overlapping islands into one entity for each set
+    /// of islands that do not overlap each other, so each gets its own
+    /// output textures.
+    #[serde(rename = "separate")]
+    Separate,
+}
+
+impl Default for UvOverlaps {
+    fn default() -> Self {
+        UvOverlaps::Warn
+    }
+}
+
+/// Texture coordinates generated for entities that have none that are usable,
+/// so that effects still produce meaningful textures for them.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Unwrap {
+    /// Each triangle gets its own half of a cell in a grid over the texture,
+    /// distorting its shape but never overlapping with others.
+    #[serde(rename = "per_triangle")]
+    PerTriangle,
+    /// Triangles are projected along the axis their normal is closest to,
+    /// each of the six directions into its own part of the texture.
+    #[serde(rename = "box")]
+    Box,
+}
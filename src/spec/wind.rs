@@ -0,0 +1,20 @@
+/// Wind over the whole scene that biases the emission and flow directions of
+/// all ton sources, e.g. for grime on the weather side of buildings.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Wind {
+    /// Direction the wind blows into, in world space.
+    pub direction: [f32; 3],
+    /// Weight of the wind direction relative to the emission and flow
+    /// directions of sources, so 1.0 blows emission perpendicular to the wind
+    /// into a 45° angle.
+    pub strength: f32,
+}
+
+impl Wind {
+    /// Whether the wind has a direction and a non-negative strength.
+    pub fn is_valid(&self) -> bool {
+        self.direction.iter().any(|&c| c != 0.0)
+            && self.strength >= 0.0
+            && self.strength.is_finite()
+    }
+}